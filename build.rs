@@ -1,4 +1,7 @@
 use glib_build_tools::compile_resources;
+use std::{env, fs, path::Path, process::Command};
+
+const SCHEMA: &str = "content/be.sourcery.ripperx4.gschema.xml";
 
 fn main() {
     compile_resources(
@@ -6,6 +9,37 @@ fn main() {
         "content/ripperx4.gresource.xml",
         "ripperx4.gresource",
     );
+    compile_schema();
     #[cfg(target_os = "macos")]
     println!("cargo:rustc-link-arg=-L/opt/homebrew/lib/");
+    // libdiscid has no pkg-config file in the MSVC builds for Windows, `DISCID_DIR` is the
+    // folder with its `lib\discid.lib`
+    println!("cargo:rerun-if-env-changed=DISCID_DIR");
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
+        if let Ok(dir) = env::var("DISCID_DIR") {
+            println!("cargo:rustc-link-search=native={dir}\\lib");
+        }
+    }
+}
+
+/// Compile the GSettings schema to `$OUT_DIR/schemas`, where `cargo run` finds it when it's not
+/// installed. Without `glib-compile-schemas` the settings are only kept in the config file
+fn compile_schema() {
+    println!("cargo:rerun-if-changed={SCHEMA}");
+    let dir = Path::new(&env::var("OUT_DIR").expect("OUT_DIR not set")).join("schemas");
+    let copied = fs::create_dir_all(&dir)
+        .and_then(|()| fs::copy(SCHEMA, dir.join("be.sourcery.ripperx4.gschema.xml")));
+    if let Err(e) = copied {
+        println!("cargo:warning=failed to copy the GSettings schema: {e}");
+        return;
+    }
+    match Command::new("glib-compile-schemas")
+        .args(["--strict"])
+        .arg(&dir)
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => println!("cargo:warning=glib-compile-schemas failed: {status}"),
+        Err(e) => println!("cargo:warning=glib-compile-schemas not found: {e}"),
+    }
 }
@@ -0,0 +1,274 @@
+use crate::duration::{sectors_to_samples, SAMPLES_PER_SECTOR};
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::io::Read;
+
+/// Samples left out at the start of the first and the end of the last track, drives with a read
+/// offset can't read all of them
+const SKIPPED_SAMPLES: u64 = 5 * SAMPLES_PER_SECTOR;
+/// Responses list a few pressings of the disc, they are much smaller than this
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+/// Path of the disc in the AccurateRip database, e.g. `7/6/f/dBAR-012-0010f267-00a8b45e-b10c0b0c.bin`
+/// `offsets` are the start sectors of the tracks and `leadout` the end of the disc as in the TOC,
+/// i.e. including the 2 seconds lead-in
+pub fn disc_id(offsets: &[i32], leadout: i32, freedb_id: &str) -> String {
+    let mut id1: u32 = 0;
+    let mut id2: u32 = 0;
+    // the lead-out counts as the track after the last one
+    for (number, offset) in (1_u32..).zip(offsets.iter().chain([&leadout])) {
+        let lba = u32::try_from(offset - 150).unwrap_or_default();
+        id1 = id1.wrapping_add(lba);
+        id2 = id2.wrapping_add(lba.max(1).wrapping_mul(number));
+    }
+    format!(
+        "{:x}/{:x}/{:x}/dBAR-{:03}-{id1:08x}-{id2:08x}-{freedb_id}.bin",
+        id1 & 0xf,
+        id1 >> 4 & 0xf,
+        id1 >> 8 & 0xf,
+        offsets.len()
+    )
+}
+
+/// AccurateRip v1 and v2 checksums of a track, updated with the raw audio of the track
+#[derive(Debug, Clone, Default)]
+pub struct Checksum {
+    /// 1 based number of the next sample
+    position: u64,
+    /// first and last sample that count
+    start: u64,
+    end: u64,
+    /// start of a sample that continues in the next buffer
+    partial: Vec<u8>,
+    v1: u32,
+    v2: u32,
+}
+
+impl Checksum {
+    /// `sectors` is the length of the track on the disc, the first track starts at the last
+    /// sample of its fifth sector and the last one stops 5 sectors before its end
+    pub fn new(sectors: u64, first: bool, last: bool) -> Checksum {
+        let samples = sectors_to_samples(sectors);
+        Checksum {
+            position: 1,
+            start: if first { SKIPPED_SAMPLES } else { 1 },
+            end: if last {
+                samples.saturating_sub(SKIPPED_SAMPLES)
+            } else {
+                samples
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Add 16 bit little endian stereo audio as read from the disc
+    pub fn update(&mut self, mut pcm: &[u8]) {
+        if !self.partial.is_empty() {
+            let missing = (4 - self.partial.len()).min(pcm.len());
+            self.partial.extend_from_slice(&pcm[..missing]);
+            pcm = &pcm[missing..];
+            if self.partial.len() < 4 {
+                return;
+            }
+            let sample = std::mem::take(&mut self.partial);
+            self.add(&sample);
+        }
+        let mut samples = pcm.chunks_exact(4);
+        for sample in &mut samples {
+            self.add(sample);
+        }
+        self.partial.extend_from_slice(samples.remainder());
+    }
+
+    fn add(&mut self, sample: &[u8]) {
+        let position = self.position;
+        self.position += 1;
+        if position < self.start || position > self.end {
+            return;
+        }
+        let value = u32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+        // a track has less than 2^32 samples
+        let multiplier = u32::try_from(position).unwrap_or_default();
+        self.v1 = self.v1.wrapping_add(value.wrapping_mul(multiplier));
+        let product = u64::from(value) * u64::from(multiplier);
+        let (high, low) = ((product >> 32) as u32, product as u32);
+        self.v2 = self.v2.wrapping_add(high).wrapping_add(low);
+    }
+
+    /// Whether all the samples the checksum covers were added
+    pub fn complete(&self) -> bool {
+        self.position > self.end
+    }
+
+    pub fn v1(&self) -> u32 {
+        self.v1
+    }
+
+    pub fn v2(&self) -> u32 {
+        self.v2
+    }
+}
+
+/// A checksum submitted for a track, with the number of rips that had it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub confidence: u32,
+    pub crc: u32,
+}
+
+/// How a track compares to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// matches the rips of this many others
+    Accurate(u32),
+    /// the disc is in the database, but nobody got these checksums
+    Inaccurate,
+    /// the disc is not in the database or it could not be reached
+    Unknown,
+}
+
+/// Download the checksums of the disc, one list of tracks per pressing
+/// A disc that is not in the database has none
+pub fn fetch(disc_id: &str) -> Result<Vec<Vec<Entry>>> {
+    let url = format!("http://www.accuraterip.com/accuraterip/{disc_id}");
+    debug!("AccurateRip url {url}");
+    match ureq::get(&url).call() {
+        Ok(response) => {
+            let mut body = Vec::new();
+            response
+                .into_reader()
+                .take(MAX_RESPONSE_SIZE)
+                .read_to_end(&mut body)?;
+            parse(&body)
+        }
+        Err(ureq::Error::Status(404, _)) => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse a response: per pressing a track count, the 3 disc ids and per track a confidence byte,
+/// the v1 or v2 checksum and the checksum of sector 450 (unused), all little endian
+fn parse(mut body: &[u8]) -> Result<Vec<Vec<Entry>>> {
+    let le32 = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut pressings = Vec::new();
+    while let Some(count) = body.first() {
+        let len = 13 + 9 * usize::from(*count);
+        if body.len() < len {
+            return Err(anyhow!("truncated AccurateRip response"));
+        }
+        let tracks = body[13..len]
+            .chunks_exact(9)
+            .map(|track| Entry {
+                confidence: u32::from(track[0]),
+                crc: le32(&track[1..5]),
+            })
+            .collect();
+        pressings.push(tracks);
+        body = &body[len..];
+    }
+    Ok(pressings)
+}
+
+/// Compare the checksum of track `number` with all pressings of the disc
+pub fn verify(pressings: &[Vec<Entry>], number: u32, checksum: &Checksum) -> Verdict {
+    if pressings.is_empty() {
+        return Verdict::Unknown;
+    }
+    let confidence: u32 = pressings
+        .iter()
+        .filter_map(|tracks| tracks.get(usize::try_from(number).ok()?.checked_sub(1)?))
+        .filter(|entry| entry.crc == checksum.v1() || entry.crc == checksum.v2())
+        .map(|entry| entry.confidence)
+        .sum();
+    if confidence > 0 {
+        Verdict::Accurate(confidence)
+    } else {
+        Verdict::Inaccurate
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disc_id, parse, verify, Checksum, Entry, Verdict};
+    use anyhow::Result;
+
+    #[test]
+    pub fn test_disc_id() {
+        // 3 tracks of 1000 sectors
+        let id = disc_id(&[150, 1150, 2150], 3150, "1a00b603");
+        assert_eq!("0/7/7/dBAR-003-00001770-00004e21-1a00b603.bin", id);
+    }
+
+    #[test]
+    pub fn test_checksum() {
+        let pcm: Vec<u8> = (1_u32..=6 * 588).flat_map(u32::to_le_bytes).collect();
+        let mut whole = Checksum::new(6, false, false);
+        whole.update(&pcm);
+        // sum of i * i
+        let expected = (1_u64..=6 * 588).map(|i| i * i).sum::<u64>();
+        assert_eq!(expected as u32, whole.v1());
+        assert_eq!(whole.v1(), whole.v2());
+
+        // samples split over buffers
+        let mut split = Checksum::new(6, false, false);
+        for chunk in pcm.chunks(7) {
+            split.update(chunk);
+        }
+        assert_eq!(whole.v1(), split.v1());
+
+        // the first track skips 5 sectors minus a sample, counting from sample 2940
+        let mut first = Checksum::new(6, true, false);
+        first.update(&pcm);
+        let expected = (2940_u64..=6 * 588).map(|i| i * i).sum::<u64>();
+        assert_eq!(expected as u32, first.v1());
+
+        // the last track skips its last 5 sectors
+        let mut last = Checksum::new(6, false, true);
+        last.update(&pcm);
+        let expected = (1_u64..=588).map(|i| i * i).sum::<u64>();
+        assert_eq!(expected as u32, last.v1());
+
+        let mut v2 = Checksum::new(1, false, false);
+        v2.update(&[0xff; 8]);
+        // 0xffffffff + 2 * 0xffffffff, with the high half of the second product added
+        assert_eq!(0xffff_fffd, v2.v1());
+        assert_eq!(
+            0xffff_ffff_u32.wrapping_add(0xffff_fffe).wrapping_add(1),
+            v2.v2()
+        );
+    }
+
+    #[test]
+    pub fn test_parse_verify() -> Result<()> {
+        let mut body = vec![2];
+        body.extend_from_slice(&[0; 12]);
+        for (confidence, crc) in [(7_u8, 0x1234_5678_u32), (3, 0xabcd)] {
+            body.push(confidence);
+            body.extend_from_slice(&crc.to_le_bytes());
+            body.extend_from_slice(&[0; 4]);
+        }
+        let pressings = parse(&body)?;
+        assert_eq!(
+            vec![vec![
+                Entry {
+                    confidence: 7,
+                    crc: 0x1234_5678
+                },
+                Entry {
+                    confidence: 3,
+                    crc: 0xabcd
+                }
+            ]],
+            pressings
+        );
+        assert!(parse(&body[..20]).is_err());
+
+        let mut checksum = Checksum::new(1, false, false);
+        checksum.update(&0xabcd_u32.to_le_bytes());
+        assert_eq!(Verdict::Accurate(3), verify(&pressings, 2, &checksum));
+        assert_eq!(Verdict::Inaccurate, verify(&pressings, 1, &checksum));
+        assert_eq!(Verdict::Inaccurate, verify(&pressings, 3, &checksum));
+        assert_eq!(Verdict::Unknown, verify(&[], 2, &checksum));
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use crate::data::{Config, Disc, Encoded, Track};
+use anyhow::{anyhow, Result};
+use log::{debug, error, info};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, FuncArgs, Scope, AST};
+use std::{
+    fs,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+/// Hooks run on the window's thread too, a script that takes more operations loops forever and
+/// is stopped
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// Deeper calls, e.g. runaway recursion, stop the script before they overflow the stack
+const MAX_CALL_LEVELS: usize = 32;
+
+/// The script compiled last, with the path and modification time of its file
+type Compiled = (String, Option<SystemTime>, Arc<Automation>);
+
+/// The Rhai script of `Config::automation_script`, with a function per hook it handles:
+/// `on_scan(discid)`, `on_lookup()` on the disc, `on_track(disc)` on a track before it's read,
+/// `on_encoded(disc)` on the file of a track and `on_album(files)` on the disc after the rip
+/// `this` is what the hook is about, changes to the disc and tracks are kept
+/// A failing hook is logged, it doesn't stop the rip
+pub struct Automation {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Automation {
+    /// The configured script, `None` when there is none
+    /// It's only compiled again when the file changed
+    pub fn load(config: &Config) -> Result<Option<Arc<Automation>>> {
+        static COMPILED: Mutex<Option<Compiled>> = Mutex::new(None);
+        let Some(path) = &config.automation_script else {
+            return Ok(None);
+        };
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut compiled = COMPILED.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, _, automation)) = compiled
+            .as_ref()
+            .filter(|(p, m, _)| p == path && *m == modified)
+        {
+            return Ok(Some(automation.clone()));
+        }
+        let source = fs::read_to_string(path).map_err(|e| anyhow!("{path}: {e}"))?;
+        let automation = Arc::new(Automation::new(&source)?);
+        *compiled = Some((path.clone(), modified, automation.clone()));
+        Ok(Some(automation))
+    }
+
+    /// The configured script, failures to compile it are logged
+    pub fn configured(config: &Config) -> Option<Arc<Automation>> {
+        Automation::load(config).unwrap_or_else(|e| {
+            error!("Failed to load the script: {e}");
+            None
+        })
+    }
+
+    fn new(source: &str) -> Result<Automation> {
+        let engine = engine();
+        let ast = engine.compile(source)?;
+        Ok(Automation { engine, ast })
+    }
+
+    /// A disc with this MusicBrainz disc id was scanned
+    pub fn on_scan(&self, discid: &str) {
+        let mut this = Dynamic::UNIT;
+        self.call("on_scan", &mut this, (discid.to_owned(),));
+    }
+
+    /// The metadata of the disc was looked up, e.g. to change titles before they are shown
+    pub fn on_lookup(&self, disc: &mut Disc) {
+        self.call_on("on_lookup", disc, ());
+    }
+
+    /// The track is about to be read, the script can rename it or skip it with `this.rip = false`
+    pub fn on_track(&self, disc: &Disc, track: &mut Track) {
+        self.call_on("on_track", track, (disc.clone(),));
+    }
+
+    /// The file of a track was written
+    pub fn on_encoded(&self, disc: &Disc, encoded: &Encoded) {
+        self.call_on("on_encoded", &mut encoded.clone(), (disc.clone(),));
+    }
+
+    /// The disc was ripped to these files
+    pub fn on_album(&self, disc: &Disc, report: &[Encoded]) {
+        let files: Array = report.iter().cloned().map(Dynamic::from).collect();
+        self.call_on("on_album", &mut disc.clone(), (files,));
+    }
+
+    /// Call the hook with `this` bound to the value, which gets the changes of the script
+    fn call_on<T: Clone + Send + Sync + 'static>(
+        &self,
+        hook: &str,
+        value: &mut T,
+        args: impl FuncArgs,
+    ) {
+        let mut this = Dynamic::from(value.clone());
+        if self.call(hook, &mut this, args) {
+            if let Some(changed) = this.try_cast::<T>() {
+                *value = changed;
+            }
+        }
+    }
+
+    /// Whether the script has the hook and it succeeded
+    fn call(&self, hook: &str, this: &mut Dynamic, args: impl FuncArgs) -> bool {
+        if !self.ast.iter_functions().any(|f| f.name == hook) {
+            return false;
+        }
+        debug!("running script hook {hook}");
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(this);
+        match self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            hook,
+            args,
+        ) {
+            Ok(_) => true,
+            Err(e) => {
+                error!("Script hook {hook} failed: {e}");
+                false
+            }
+        }
+    }
+}
+
+/// `()` for a value that isn't there
+fn optional<T: Clone + Send + Sync + 'static>(value: Option<T>) -> Dynamic {
+    value.map_or(Dynamic::UNIT, Dynamic::from)
+}
+
+/// The engine with the `Disc`, `Track` and `Encoded` types, `print` goes to the log
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS);
+    engine.on_print(|text| info!("script: {text}"));
+    engine.on_debug(|text, _, _| debug!("script: {text}"));
+    engine
+        .register_type_with_name::<Disc>("Disc")
+        .register_get_set(
+            "title",
+            |d: &mut Disc| d.title.clone(),
+            |d: &mut Disc, v: String| d.title = v,
+        )
+        .register_get_set(
+            "artist",
+            |d: &mut Disc| d.artist.clone(),
+            |d: &mut Disc, v: String| d.artist = v,
+        )
+        .register_get_set(
+            "genre",
+            |d: &mut Disc| optional(d.genre.clone()),
+            |d: &mut Disc, v: Dynamic| d.genre = v.into_string().ok(),
+        )
+        .register_get_set(
+            "comment",
+            |d: &mut Disc| optional(d.comment.clone()),
+            |d: &mut Disc, v: Dynamic| d.comment = v.into_string().ok(),
+        )
+        .register_get_set(
+            "year",
+            |d: &mut Disc| optional(d.year.map(i64::from)),
+            |d: &mut Disc, v: Dynamic| d.set_year(v.as_int().ok().and_then(|y| y.try_into().ok())),
+        )
+        // the tracks are those of the TOC, the script can change them but not add or remove any
+        .register_get_set(
+            "tracks",
+            |d: &mut Disc| {
+                d.tracks
+                    .iter()
+                    .cloned()
+                    .map(Dynamic::from)
+                    .collect::<Array>()
+            },
+            |d: &mut Disc, tracks: Array| {
+                for (track, changed) in d.tracks.iter_mut().zip(tracks) {
+                    if let Some(changed) = changed.try_cast::<Track>() {
+                        *track = changed;
+                    }
+                }
+            },
+        )
+        .register_get("discid", |d: &mut Disc| optional(d.discid.clone()))
+        .register_get("release_id", |d: &mut Disc| optional(d.release_id.clone()))
+        .register_get("barcode", |d: &mut Disc| optional(d.barcode.clone()))
+        .register_get("disc_number", |d: &mut Disc| {
+            optional(d.disc_number.map(i64::from))
+        });
+    engine
+        .register_type_with_name::<Track>("Track")
+        .register_get("number", |t: &mut Track| i64::from(t.number))
+        .register_get("duration", |t: &mut Track| {
+            i64::try_from(t.duration).unwrap_or_default()
+        })
+        .register_get_set(
+            "title",
+            |t: &mut Track| t.title.clone(),
+            |t: &mut Track, v: String| t.title = v,
+        )
+        .register_get_set(
+            "artist",
+            |t: &mut Track| t.artist.clone(),
+            |t: &mut Track, v: String| t.artist = v,
+        )
+        .register_get_set(
+            "composer",
+            |t: &mut Track| optional(t.composer.clone()),
+            |t: &mut Track, v: Dynamic| t.composer = v.into_string().ok(),
+        )
+        .register_get_set(
+            "comment",
+            |t: &mut Track| optional(t.comment.clone()),
+            |t: &mut Track, v: Dynamic| t.comment = v.into_string().ok(),
+        )
+        .register_get_set(
+            "rip",
+            |t: &mut Track| t.rip,
+            |t: &mut Track, v: bool| t.rip = v,
+        );
+    engine
+        .register_type_with_name::<Encoded>("Encoded")
+        .register_get("path", |e: &mut Encoded| {
+            e.path.to_string_lossy().into_owned()
+        })
+        .register_get("number", |e: &mut Encoded| i64::from(e.number))
+        .register_get("title", |e: &mut Encoded| e.title.clone())
+        .register_get("size", |e: &mut Encoded| {
+            i64::try_from(e.size).unwrap_or_default()
+        })
+        .register_get("bitrate", |e: &mut Encoded| {
+            optional(e.bitrate.and_then(|b| i64::try_from(b).ok()))
+        });
+    engine
+}
+
+#[cfg(test)]
+mod test {
+    use super::Automation;
+    use crate::data::{Disc, Encoded};
+    use std::path::PathBuf;
+
+    #[test]
+    pub fn test_hooks() {
+        let script = r#"
+            fn on_lookup() {
+                this.title = this.title.to_upper();
+                this.genre = ();
+                this.year = 1985;
+                this.tracks[1].title = "B-side";
+            }
+            fn on_track(disc) {
+                // skip the short tracks, e.g. of silence
+                if this.duration < 10 { this.rip = false; }
+                this.title = `${this.number}. ${this.title} (${disc.title})`;
+            }
+            fn on_encoded(disc) {
+                if this.size == 0 { throw "empty file"; }
+            }
+        "#;
+        let automation = Automation::new(script).unwrap();
+        let mut disc = Disc::with_tracks(2);
+        disc.title = "Brothers in Arms".to_owned();
+        disc.genre = Some("Rock".to_owned());
+        disc.date = Some("1988-11-30".to_owned());
+        disc.tracks[0].duration = 5;
+        automation.on_lookup(&mut disc);
+        assert_eq!("BROTHERS IN ARMS", disc.title);
+        assert_eq!(None, disc.genre);
+        assert_eq!(Some(1985), disc.year);
+        // the date of the release was of another year
+        assert_eq!(None, disc.date);
+        assert_eq!("B-side", disc.tracks[1].title);
+        let mut track = disc.tracks[0].clone();
+        automation.on_track(&disc, &mut track);
+        assert!(!track.rip);
+        assert_eq!("1. Unknown (BROTHERS IN ARMS)", track.title);
+        // a failing hook and hooks the script doesn't have only log
+        let empty = Encoded {
+            path: PathBuf::from("/music/01.flac"),
+            number: 1,
+            title: "Unknown".to_owned(),
+            size: 0,
+            bitrate: None,
+            expected_bitrate: 900,
+            read_errors: Default::default(),
+            accuraterip: None,
+        };
+        automation.on_encoded(&disc, &empty);
+        automation.on_album(&disc, &[]);
+        automation.on_scan("discid");
+        assert!(Automation::new("fn on_lookup( {").is_err());
+    }
+
+    #[test]
+    pub fn test_runaway_script() {
+        let script = r#"
+            fn on_lookup() {
+                this.title = "changed";
+                loop { }
+            }
+            fn deeper(n) { deeper(n + 1) }
+            fn on_scan(discid) { deeper(0); }
+        "#;
+        let automation = Automation::new(script).unwrap();
+        let mut disc = Disc::with_tracks(1);
+        // stopped, the changes of the failed hook are dropped
+        automation.on_lookup(&mut disc);
+        assert_eq!("Unknown", disc.title);
+        automation.on_scan("discid");
+    }
+}
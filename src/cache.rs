@@ -0,0 +1,302 @@
+use crate::data::{Config, Disc, APP_NAME};
+use anyhow::Result;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+/// Held while `metadata.toml` is loaded, changed and stored, so the rip thread (gnudb entries)
+/// and the window (edits, pinned releases) don't undo each other's changes
+static UPDATE: Mutex<()> = Mutex::new(());
+
+/// The metadata looked up or edited per disc id, so a disc inserted again gets it back without
+/// going online
+/// Stored as `metadata.toml` in the config directory
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct MetadataCache {
+    pub discs: BTreeMap<String, CachedDisc>,
+    /// gnudb entries per disc id, see `gnudb::lookup`
+    pub gnudb: BTreeMap<String, GnudbEntry>,
+    /// MusicBrainz release picked by the user per disc id, looked up instead of the releases
+    /// MusicBrainz lists for the disc id
+    pub releases: BTreeMap<String, String>,
+}
+
+/// A `cddb read` response as downloaded
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct GnudbEntry {
+    pub category: String,
+    /// id of the entry, differs from the disc's for inexact matches
+    pub id: String,
+    pub body: String,
+    pub etag: Option<String>,
+    /// seconds since the epoch
+    pub fetched_at: u64,
+}
+
+/// The parts of a `Disc` that come from the lookup or the user, not from the TOC
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CachedDisc {
+    pub title: String,
+    pub artist: String,
+    pub year: Option<u16>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub release_id: Option<String>,
+    pub release_group_id: Option<String>,
+    pub disc_number: Option<u32>,
+    pub disc_count: Option<u32>,
+    pub language: Option<String>,
+    pub script: Option<String>,
+    pub transliteration_id: Option<String>,
+    pub latin_title: Option<String>,
+    pub latin_artist: Option<String>,
+    pub barcode: Option<String>,
+    pub catalog_number: Option<String>,
+    pub comment: Option<String>,
+    pub tracks: Vec<CachedTrack>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CachedTrack {
+    pub number: u32,
+    pub title: String,
+    pub artist: String,
+    pub composer: Option<String>,
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    pub recording_id: Option<String>,
+    pub latin_title: Option<String>,
+    pub latin_artist: Option<String>,
+    pub length_ms: Option<u64>,
+    pub comment: Option<String>,
+}
+
+impl MetadataCache {
+    pub fn load() -> Self {
+        Config::load_file("metadata").unwrap_or_default()
+    }
+
+    /// Change the cache in `metadata.toml`, under the lock of the process
+    pub fn update<T>(change: impl FnOnce(&mut MetadataCache) -> T) -> Result<T> {
+        let path = confy::get_configuration_file_path(APP_NAME, Some("metadata"))?;
+        MetadataCache::update_path(&path, change)
+    }
+
+    fn update_path<T>(path: &Path, change: impl FnOnce(&mut MetadataCache) -> T) -> Result<T> {
+        let _lock = UPDATE.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut cache: MetadataCache = confy::load_path(path).unwrap_or_default();
+        let changed = change(&mut cache);
+        // written next to it and renamed, a reader never sees half a file
+        let written = path.with_extension("toml.part");
+        confy::store_path(&written, &cache)?;
+        fs::rename(&written, path)?;
+        Ok(changed)
+    }
+
+    /// The release the user picked for the disc id
+    pub fn release(&self, discid: &str) -> Option<&str> {
+        self.releases.get(discid).map(String::as_str)
+    }
+
+    /// The metadata of the disc with this id, as a disc without TOC information
+    pub fn get(&self, discid: &str) -> Option<Disc> {
+        let cached = self.discs.get(discid)?;
+        let mut disc = Disc::with_tracks(u32::try_from(cached.tracks.len()).ok()?);
+        disc.title.clone_from(&cached.title);
+        disc.artist.clone_from(&cached.artist);
+        disc.year = cached.year;
+        disc.date.clone_from(&cached.date);
+        disc.genre.clone_from(&cached.genre);
+        disc.release_id.clone_from(&cached.release_id);
+        disc.release_group_id.clone_from(&cached.release_group_id);
+        disc.disc_number = cached.disc_number;
+        disc.disc_count = cached.disc_count;
+        disc.language.clone_from(&cached.language);
+        disc.script.clone_from(&cached.script);
+        disc.transliteration_id
+            .clone_from(&cached.transliteration_id);
+        disc.latin_title.clone_from(&cached.latin_title);
+        disc.latin_artist.clone_from(&cached.latin_artist);
+        disc.barcode.clone_from(&cached.barcode);
+        disc.catalog_number.clone_from(&cached.catalog_number);
+        disc.comment.clone_from(&cached.comment);
+        for (track, cached) in disc.tracks.iter_mut().zip(&cached.tracks) {
+            track.number = cached.number;
+            track.title.clone_from(&cached.title);
+            track.artist.clone_from(&cached.artist);
+            track.composer.clone_from(&cached.composer);
+            track.work.clone_from(&cached.work);
+            track.movement.clone_from(&cached.movement);
+            track.recording_id.clone_from(&cached.recording_id);
+            track.latin_title.clone_from(&cached.latin_title);
+            track.latin_artist.clone_from(&cached.latin_artist);
+            track.length_ms = cached.length_ms;
+            track.comment.clone_from(&cached.comment);
+        }
+        Some(disc)
+    }
+
+    /// Remember the current metadata of the disc
+    pub fn remember(&mut self, disc: &Disc) {
+        let Some(id) = &disc.discid else {
+            return;
+        };
+        let tracks = disc
+            .tracks
+            .iter()
+            .map(|t| CachedTrack {
+                number: t.number,
+                title: t.title.clone(),
+                artist: t.artist.clone(),
+                composer: t.composer.clone(),
+                work: t.work.clone(),
+                movement: t.movement.clone(),
+                recording_id: t.recording_id.clone(),
+                latin_title: t.latin_title.clone(),
+                latin_artist: t.latin_artist.clone(),
+                length_ms: t.length_ms,
+                comment: t.comment.clone(),
+            })
+            .collect();
+        let cached = CachedDisc {
+            title: disc.title.clone(),
+            artist: disc.artist.clone(),
+            year: disc.year,
+            date: disc.date.clone(),
+            genre: disc.genre.clone(),
+            release_id: disc.release_id.clone(),
+            release_group_id: disc.release_group_id.clone(),
+            disc_number: disc.disc_number,
+            disc_count: disc.disc_count,
+            language: disc.language.clone(),
+            script: disc.script.clone(),
+            transliteration_id: disc.transliteration_id.clone(),
+            latin_title: disc.latin_title.clone(),
+            latin_artist: disc.latin_artist.clone(),
+            barcode: disc.barcode.clone(),
+            catalog_number: disc.catalog_number.clone(),
+            comment: disc.comment.clone(),
+            tracks,
+        };
+        self.discs.insert(id.clone(), cached);
+    }
+}
+
+/// Always look up the release for the disc id, failures are only logged
+pub fn pin_release(discid: &str, release_id: &str) {
+    let pinned = MetadataCache::update(|cache| {
+        cache
+            .releases
+            .insert(discid.to_owned(), release_id.to_owned());
+    });
+    if let Err(e) = pinned {
+        error!("Failed to store the release of the disc: {e}");
+    }
+}
+
+/// Look up the releases of the disc id again, failures are only logged
+pub fn unpin_release(discid: &str) {
+    if MetadataCache::load().release(discid).is_none() {
+        return;
+    }
+    if let Err(e) = MetadataCache::update(|cache| cache.releases.remove(discid)) {
+        error!("Failed to store the release of the disc: {e}");
+    }
+}
+
+/// Remember the metadata of the disc in `metadata.toml`, failures are only logged
+pub fn remember(disc: &Disc) {
+    if let Err(e) = MetadataCache::update(|cache| cache.remember(disc)) {
+        error!("Failed to store the metadata of the disc: {e}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MetadataCache;
+    use crate::data::Disc;
+    use anyhow::Result;
+    use std::{env, fs, sync::Arc, thread};
+
+    #[test]
+    pub fn test_remember_and_get() -> Result<()> {
+        let mut disc = Disc::with_tracks(2);
+        disc.discid = Some("xA3p59dQpJpDXZYHz1SSQ491oaU-".to_owned());
+        disc.title = "Money for Nothing".to_owned();
+        disc.artist = "Dire Straits".to_owned();
+        disc.year = Some(1988);
+        disc.tracks[0].title = "Sultans of Swing".to_owned();
+        disc.tracks[1].composer = Some("Mark Knopfler".to_owned());
+        disc.tracks[1].comment = Some("skips".to_owned());
+        disc.tracks[1].sectors = 1000;
+        let mut cache = MetadataCache::default();
+        cache.remember(&disc);
+        assert!(cache.get("unknown").is_none());
+
+        // survives a round trip through the file
+        let path = env::temp_dir().join("ripperx4_metadata.toml");
+        confy::store_path(&path, &cache)?;
+        cache.releases.insert(
+            "xA3p59dQpJpDXZYHz1SSQ491oaU-".to_owned(),
+            "a541c6e6".to_owned(),
+        );
+        confy::store_path(&path, &cache)?;
+        let cache: MetadataCache = confy::load_path(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(
+            Some("a541c6e6"),
+            cache.release("xA3p59dQpJpDXZYHz1SSQ491oaU-")
+        );
+        assert_eq!(None, cache.release("unknown"));
+        let cached = cache
+            .get("xA3p59dQpJpDXZYHz1SSQ491oaU-")
+            .expect("disc not cached");
+        assert_eq!("Money for Nothing", cached.title);
+        assert_eq!("Dire Straits", cached.artist);
+        assert_eq!(Some(1988), cached.year);
+        assert_eq!("Sultans of Swing", cached.tracks[0].title);
+        assert_eq!(Some("Mark Knopfler".to_owned()), cached.tracks[1].composer);
+        assert_eq!(Some("skips".to_owned()), cached.tracks[1].comment);
+        assert_eq!(2, cached.tracks[1].number);
+        // the TOC is filled in after the lookup
+        assert_eq!(0, cached.tracks[1].sectors);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_concurrent_updates() -> Result<()> {
+        let path = Arc::new(env::temp_dir().join("ripperx4_metadata_updates.toml"));
+        fs::remove_file(&*path).ok();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    MetadataCache::update_path(&path, |cache| {
+                        cache
+                            .releases
+                            .insert(format!("disc{i}"), format!("release{i}"))
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("thread panicked")?;
+        }
+        // no change is lost and nothing is left of the writes
+        let cache: MetadataCache = confy::load_path(&*path)?;
+        assert_eq!(8, cache.releases.len());
+        assert_eq!(Some("release5"), cache.release("disc5"));
+        assert!(!path.with_extension("toml.part").exists());
+        fs::remove_file(&*path)?;
+        Ok(())
+    }
+}
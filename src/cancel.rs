@@ -0,0 +1,126 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+type Callback = Box<dyn Fn() + Send + Sync>;
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    next_id: AtomicU64,
+    callbacks: Mutex<Vec<(u64, Callback)>>,
+}
+
+/// Shared between the UI and the ripping thread to stop a rip
+/// Whoever is blocked on the rip registers a callback with `on_cancel` so it is woken up right away
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation and notify the registered callbacks
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let callbacks = self
+            .inner
+            .callbacks
+            .lock()
+            .expect("failed to get callbacks");
+        for (_, callback) in callbacks.iter() {
+            callback();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Make the token usable for the next rip
+    pub fn reset(&self) {
+        self.inner.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    /// Call `callback` when the token is cancelled, or right away when it already is
+    /// The callback is unregistered when the returned guard is dropped
+    pub fn on_cancel(&self, callback: impl Fn() + Send + Sync + 'static) -> CancelGuard {
+        let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut callbacks = self
+            .inner
+            .callbacks
+            .lock()
+            .expect("failed to get callbacks");
+        if self.is_cancelled() {
+            callback();
+        }
+        callbacks.push((id, Box::new(callback)));
+        CancelGuard {
+            token: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Keeps a callback registered with `CancelToken::on_cancel`
+pub struct CancelGuard {
+    token: CancelToken,
+    id: u64,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Ok(mut callbacks) = self.token.inner.callbacks.lock() {
+            callbacks.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CancelToken;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    #[test]
+    pub fn test_cancel_notifies() {
+        let token = CancelToken::new();
+        let calls = Arc::new(AtomicU32::new(0));
+        let calls_clone = calls.clone();
+        let guard = token.on_cancel(move || {
+            calls_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(!token.is_cancelled());
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        token.cancel();
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        // dropped callbacks are not called after a reset
+        drop(guard);
+        token.reset();
+        assert!(!token.is_cancelled());
+        token.cancel();
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_already_cancelled() {
+        let token = CancelToken::new();
+        token.cancel();
+        let calls = Arc::new(AtomicU32::new(0));
+        let calls_clone = calls.clone();
+        let _guard = token.on_cancel(move || {
+            calls_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+}
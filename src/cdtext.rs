@@ -0,0 +1,168 @@
+use crate::{
+    data::{CdBackend, Config, Disc},
+    util::drive,
+};
+use anyhow::{anyhow, Result};
+use gstreamer::{
+    glib::GStr,
+    prelude::*,
+    tags::{Album, AlbumArtist, Artist, Tag, Title},
+    ClockTime, ElementFactory, MessageView, Pipeline, State, TagList, TagMergeMode, TocEntry,
+    TocEntryType,
+};
+
+/// The only CD source that reads CD-Text, it sends the texts downstream with the TOC
+const CDTEXT_SOURCE: &str = "cdiocddasrc";
+/// How long the drive gets to spin up and read the TOC
+const READ_TIMEOUT: ClockTime = ClockTime::from_seconds(60);
+
+/// Read the CD-Text of the disc in the configured drive into a `Disc` with `tracks` tracks
+/// Fails when the disc has none, most don't
+pub fn lookup(config: &Config, tracks: u32) -> Result<Disc> {
+    if config.cd_backend == CdBackend::Remote {
+        return Err(anyhow!("the CD-Text of a remote drive can't be read"));
+    }
+    if ElementFactory::find(CDTEXT_SOURCE).is_none() {
+        return Err(anyhow!(
+            "{CDTEXT_SOURCE} (gst-plugins-ugly) is needed to read CD-Text"
+        ));
+    }
+    let source = ElementFactory::make(CDTEXT_SOURCE)
+        .property("device", drive(config))
+        .property("track", 1_u32)
+        .build()?;
+    let sink = ElementFactory::make("fakesink")
+        .property("sync", false)
+        .build()?;
+    let pipeline = Pipeline::new();
+    pipeline.add_many([&source, &sink])?;
+    source.link(&sink)?;
+    let texts = read_texts(&pipeline);
+    pipeline.set_state(State::Null)?;
+    let (album, tracks_texts) = texts?;
+    disc_from_texts(&album, &tracks_texts, tracks)
+}
+
+/// Preroll the pipeline and collect the tags of the disc and those of each track from the TOC
+fn read_texts(pipeline: &Pipeline) -> Result<(TagList, Vec<TagList>)> {
+    let bus = pipeline.bus().ok_or(anyhow!("no bus".to_owned()))?;
+    pipeline.set_state(State::Paused)?;
+    let mut album = TagList::new();
+    let mut tracks = Vec::new();
+    for msg in bus.iter_timed(READ_TIMEOUT) {
+        match msg.view() {
+            MessageView::Toc(toc) => {
+                let (toc, _) = toc.toc();
+                if let Some(tags) = toc.tags() {
+                    album = album.merge(&tags, TagMergeMode::Keep);
+                }
+                tracks = track_entries(toc.entries())
+                    .iter()
+                    .map(|entry| entry.tags().unwrap_or_default())
+                    .collect();
+            }
+            // the tags of the first track, with the ones of the disc
+            MessageView::Tag(tag) => {
+                album = album.merge(&tag.tags(), TagMergeMode::Keep);
+            }
+            MessageView::AsyncDone(..) => break,
+            MessageView::Error(err) => {
+                return Err(anyhow!("Reading the CD-Text failed: {}", err.error()));
+            }
+            _ => (),
+        }
+    }
+    Ok((album, tracks))
+}
+
+/// The track entries of the TOC in order, also when they are grouped in an edition
+fn track_entries(entries: Vec<TocEntry>) -> Vec<TocEntry> {
+    entries
+        .into_iter()
+        .flat_map(|entry| {
+            if entry.entry_type() == TocEntryType::Track {
+                vec![entry]
+            } else {
+                track_entries(entry.sub_entries())
+            }
+        })
+        .collect()
+}
+
+/// A `Disc` with `count` tracks named after the CD-Text of the disc and its tracks
+fn disc_from_texts(album: &TagList, tracks: &[TagList], count: u32) -> Result<Disc> {
+    let titles: Vec<Option<String>> = tracks
+        .iter()
+        .map(|tags| text(tags, Title::TAG_NAME))
+        .collect();
+    let title = text(album, Album::TAG_NAME);
+    if title.is_none() && titles.iter().all(Option::is_none) {
+        return Err(anyhow!("the disc has no CD-Text"));
+    }
+    let mut disc = Disc::with_tracks(count);
+    if let Some(title) = title {
+        disc.title = title;
+    }
+    let artist = text(album, AlbumArtist::TAG_NAME)
+        .or_else(|| tracks.first().and_then(|tags| text(tags, Artist::TAG_NAME)));
+    if let Some(artist) = artist {
+        disc.artist = artist;
+    }
+    for ((track, tags), title) in disc.tracks.iter_mut().zip(tracks).zip(titles) {
+        if let Some(title) = title {
+            track.title = title;
+        }
+        track.artist = text(tags, Artist::TAG_NAME).unwrap_or_else(|| disc.artist.clone());
+    }
+    Ok(disc)
+}
+
+/// The text of a tag without the padding some discs have, `None` when it is empty
+fn text(tags: &TagList, name: &GStr) -> Option<String> {
+    tags.generic(name)
+        .and_then(|value| value.get::<String>().ok())
+        .map(|text| text.trim().to_owned())
+        .filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::disc_from_texts;
+    use anyhow::Result;
+    use gstreamer::{
+        tags::{Album, AlbumArtist, Artist, Title},
+        TagList, TagMergeMode,
+    };
+
+    #[test]
+    pub fn test_disc_from_texts() -> Result<()> {
+        gstreamer::init()?;
+        let mut album = TagList::new();
+        let mut tracks = vec![TagList::new(), TagList::new()];
+        assert!(disc_from_texts(&album, &tracks, 3).is_err());
+        {
+            let album = album.make_mut();
+            album.add::<Album>(&"Brothers in Arms", TagMergeMode::Replace);
+            album.add::<AlbumArtist>(&"Dire Straits", TagMergeMode::Replace);
+        }
+        {
+            let first = tracks[0].make_mut();
+            first.add::<Title>(&"So Far Away", TagMergeMode::Replace);
+        }
+        {
+            let second = tracks[1].make_mut();
+            second.add::<Title>(&" Money for Nothing ", TagMergeMode::Replace);
+            second.add::<Artist>(&"Dire Straits feat. Sting", TagMergeMode::Replace);
+        }
+        let disc = disc_from_texts(&album, &tracks, 3)?;
+        assert_eq!("Brothers in Arms", disc.title);
+        assert_eq!("Dire Straits", disc.artist);
+        assert_eq!("So Far Away", disc.tracks[0].title);
+        assert_eq!("Dire Straits", disc.tracks[0].artist);
+        assert_eq!("Money for Nothing", disc.tracks[1].title);
+        assert_eq!("Dire Straits feat. Sting", disc.tracks[1].artist);
+        // the TOC has more tracks than the CD-Text
+        assert_eq!("Unknown", disc.tracks[2].title);
+        Ok(())
+    }
+}
@@ -0,0 +1,647 @@
+use crate::{
+    accuraterip::Verdict,
+    cancel::CancelToken,
+    controller::{start_rip, STATUS_CAPACITY},
+    data::{Comparison, Config, Disc, Encoded, Outcome, Status, TrackState},
+    encoder::backends,
+    library::find_duplicates,
+    ripper::verify_rip,
+    selection::Selections,
+    util::{format_time, lookup_disc, scan_disc, scan_hook, toc_warnings},
+};
+use anyhow::{anyhow, Error, Result};
+use serde_json::{json, Value};
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    path::Path,
+    rc::Rc,
+    str::FromStr,
+    thread,
+};
+
+/// SIGINT and SIGTERM, the same numbers on all Unix systems
+#[cfg(unix)]
+const STOP_SIGNALS: [i32; 2] = [2, 15];
+
+/// How the headless rip reports its progress on stdout, problems always go to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// a line of text per update
+    Plain,
+    /// a JSON object per line, for scripts showing their own progress
+    Json,
+    /// nothing, the exit code tells whether the rip worked
+    Quiet,
+}
+
+impl FromStr for Progress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(Progress::Plain),
+            "json" => Ok(Progress::Json),
+            "quiet" => Ok(Progress::Quiet),
+            _ => Err(anyhow!(
+                "Unknown progress format {s}, use plain, json or quiet"
+            )),
+        }
+    }
+}
+
+/// How `--discid` prints the TOC of the disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocFormat {
+    /// as `cd-discid`: the FreeDB id, the number of tracks, their offsets and the length in
+    /// seconds
+    CdDiscid,
+    /// as `cd-discid --musicbrainz`: the number of tracks, their offsets and the lead-out
+    MusicBrainz,
+    /// the MusicBrainz disc id
+    Id,
+}
+
+impl FromStr for TocFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cd-discid" | "cddb" => Ok(TocFormat::CdDiscid),
+            "musicbrainz" => Ok(TocFormat::MusicBrainz),
+            "id" => Ok(TocFormat::Id),
+            _ => Err(anyhow!(
+                "Unknown disc id format {s}, use cd-discid, musicbrainz or id"
+            )),
+        }
+    }
+}
+
+/// What the user answered to the list of tracks
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    /// the tracks as listed
+    Rip,
+    /// only these track numbers
+    Tracks(Vec<u32>),
+    Cancel,
+}
+
+/// Rip the disc in the drive without the window, e.g. on a server without a display
+/// `encoder` and `output` override the configuration, returns the exit code of the process
+/// With `interactive` the tracks are listed and the user confirms them first, for ripperx4
+/// started without a display, e.g. over SSH
+pub fn run(
+    encoder: Option<&str>,
+    output: Option<String>,
+    progress: Progress,
+    interactive: bool,
+) -> i32 {
+    let mut config = Config::load().unwrap_or_default();
+    if let Some(name) = encoder {
+        let Some(backend) = backends()
+            .iter()
+            .find(|b| b.settings().name.eq_ignore_ascii_case(name))
+        else {
+            let names: Vec<&str> = backends().iter().map(|b| b.settings().name).collect();
+            eprintln!("Unknown encoder {name}, use one of {}", names.join(", "));
+            return 2;
+        };
+        config.encoder = backend.encoder();
+        // the format asked for wins over the profiles
+        config.profiles.clear();
+    }
+    if let Some(output) = output {
+        config.encode_path = output;
+    }
+
+    let discid = match scan_disc() {
+        Ok(discid) => discid,
+        Err(e) => {
+            eprintln!("Failed to scan disc: {e}");
+            return Outcome::NoDisc.exit_code();
+        }
+    };
+    scan_hook(&discid);
+    for warning in toc_warnings(&discid) {
+        eprintln!("Warning: {warning}");
+    }
+    let (mut disc, error) = lookup_disc(&discid);
+    let lookup_failed = disc.lookup_error.is_some();
+    if let Some(error) = error {
+        eprintln!("{error}");
+    }
+    if let Some(warning) = &disc.lookup_warning {
+        eprintln!("Warning: {warning}");
+    }
+    Selections::load().apply(&mut disc);
+    for duplicate in find_duplicates(&disc, &config) {
+        eprintln!("Warning: this disc seems to be ripped already: {duplicate}");
+    }
+    if interactive && !confirm_tracks(&mut disc) {
+        println!("Nothing ripped");
+        return Outcome::Success.exit_code();
+    }
+    match progress {
+        Progress::Plain => println!("Ripping {} - {}", disc.artist, disc.title),
+        Progress::Json => println!(
+            "{}",
+            json!({
+                "event": "start",
+                "artist": disc.artist,
+                "album": disc.title,
+                "tracks": disc.tracks.iter().filter(|t| t.rip).count(),
+            })
+        ),
+        Progress::Quiet => (),
+    }
+
+    let order = (0..disc.tracks.len()).collect();
+    let cancel = CancelToken::new();
+    let signal = Rc::new(Cell::new(None));
+    stop_on_signals(&cancel, &signal);
+    let rx = start_rip(disc, order, config, cancel);
+    // the signal handlers run in the main context while waiting
+    let context = glib::MainContext::default();
+    while let Ok(status) = context.block_on(rx.recv()) {
+        if progress == Progress::Json {
+            let mut line = status_json(&status);
+            if let Status::Done(report) = &status {
+                line["outcome"] = json!(Outcome::of(report, false, lookup_failed).name());
+            }
+            println!("{line}");
+        }
+        match status {
+            Status::Done(report) => {
+                if progress == Progress::Plain {
+                    for encoded in &report {
+                        println!("{encoded}");
+                    }
+                }
+                // the mail, the post rip hooks and the eject
+                while context.block_on(rx.recv()).is_ok() {}
+                return Outcome::of(&report, false, lookup_failed).exit_code();
+            }
+            Status::Aborted => {
+                eprintln!("Rip aborted");
+                while context.block_on(rx.recv()).is_ok() {}
+                return exit_code(signal.get());
+            }
+            status @ (Status::Progress { .. } | Status::Recovering { .. } | Status::Resumed) => {
+                if progress == Progress::Plain {
+                    println!("{status}");
+                }
+            }
+            paused @ Status::Paused(_) => eprintln!("{paused}"),
+            // listed with the others when the rip is done
+            Status::Encoded(_) => (),
+        }
+    }
+    1
+}
+
+/// Print the TOC of the disc in the drive without ripping it, for scripts written for
+/// `cd-discid`; the drive is the configured one, also a remote drive
+/// Returns the exit code
+pub fn print_toc(format: TocFormat) -> i32 {
+    let discid = match scan_disc() {
+        Ok(discid) => discid,
+        Err(e) => {
+            eprintln!("Failed to scan disc: {e}");
+            return Outcome::NoDisc.exit_code();
+        }
+    };
+    let offsets: Vec<i32> = discid.tracks().map(|t| t.offset).collect();
+    println!(
+        "{}",
+        toc_line(
+            format,
+            &discid.id(),
+            &discid.freedb_id(),
+            &offsets,
+            discid.sectors()
+        )
+    );
+    Outcome::Success.exit_code()
+}
+
+/// The line `--discid` prints, `offsets` and `leadout` are in sectors from the start of the
+/// disc, with the 2 seconds before the first track
+fn toc_line(format: TocFormat, id: &str, freedb_id: &str, offsets: &[i32], leadout: i32) -> String {
+    let offsets: Vec<String> = offsets.iter().map(ToString::to_string).collect();
+    match format {
+        TocFormat::CdDiscid => format!(
+            "{freedb_id} {} {} {}",
+            offsets.len(),
+            offsets.join(" "),
+            leadout / 75
+        ),
+        TocFormat::MusicBrainz => {
+            format!("{} {} {leadout}", offsets.len(), offsets.join(" "))
+        }
+        TocFormat::Id => id.to_owned(),
+    }
+}
+
+/// Read the disc in the drive and compare it to the JSON `report` of an earlier rip
+/// Returns the exit code, a verification failure when a track differs or doesn't match
+/// AccurateRip
+pub fn verify(report: &str, progress: Progress) -> i32 {
+    let config = Config::load().unwrap_or_default();
+    let discid = match scan_disc() {
+        Ok(discid) => discid,
+        Err(e) => {
+            eprintln!("Failed to scan disc: {e}");
+            return Outcome::NoDisc.exit_code();
+        }
+    };
+    let (disc, _) = lookup_disc(&discid);
+    if progress == Progress::Plain {
+        println!("Verifying {} - {}", disc.artist, disc.title);
+    }
+    let cancel = CancelToken::new();
+    let signal = Rc::new(Cell::new(None));
+    stop_on_signals(&cancel, &signal);
+    let (tx, rx) = async_channel::bounded(STATUS_CAPACITY);
+    let report = report.to_owned();
+    let reader = {
+        let cancel = cancel.clone();
+        thread::spawn(move || verify_rip(&disc, Path::new(&report), &config, &tx, &cancel))
+    };
+    // the sender is dropped when the last track was read
+    let context = glib::MainContext::default();
+    while let Ok(status) = context.block_on(rx.recv()) {
+        match progress {
+            Progress::Json => println!("{}", status_json(&status)),
+            Progress::Plain => println!("{status}"),
+            Progress::Quiet => (),
+        }
+    }
+    let comparisons = match reader.join() {
+        Ok(Ok(comparisons)) => comparisons,
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            return exit_code(signal.get());
+        }
+        Err(_) => return Outcome::RipFailed.exit_code(),
+    };
+    for comparison in &comparisons {
+        match progress {
+            Progress::Json => println!("{}", comparison_json(comparison)),
+            Progress::Plain => println!("{comparison}"),
+            Progress::Quiet => (),
+        }
+    }
+    verify_outcome(&comparisons).exit_code()
+}
+
+/// Success when all tracks read the same as before and none contradicts AccurateRip
+fn verify_outcome(comparisons: &[Comparison]) -> Outcome {
+    if comparisons
+        .iter()
+        .all(|c| c.matches && c.accuraterip != Verdict::Inaccurate)
+    {
+        Outcome::Success
+    } else {
+        Outcome::VerificationFailed
+    }
+}
+
+fn comparison_json(comparison: &Comparison) -> Value {
+    json!({
+        "event": "compared",
+        "track": comparison.number,
+        "title": comparison.title,
+        "matches": comparison.matches,
+        "accuraterip": match comparison.accuraterip {
+            Verdict::Accurate(_) => "accurate",
+            Verdict::Inaccurate => "inaccurate",
+            Verdict::Unknown => "unknown",
+        },
+        "rereads": comparison.read_errors.rereads,
+        "skipped": comparison.read_errors.skipped,
+    })
+}
+
+/// List the tracks and ask which to rip, until the answer makes sense
+/// Returns false when the user doesn't want to rip or stdin is closed
+fn confirm_tracks(disc: &mut Disc) -> bool {
+    println!("{} - {}", disc.artist, disc.title);
+    for track in &disc.tracks {
+        let mark = if track.rip { '*' } else { ' ' };
+        let artist = if track.artist == disc.artist {
+            String::new()
+        } else {
+            format!(" ({})", track.artist)
+        };
+        println!(
+            "{mark} {:2}. {}{artist} [{}]",
+            track.number,
+            track.title,
+            format_time(track.duration)
+        );
+    }
+    loop {
+        print!("Rip the tracks marked with *? [Y/n, or the numbers, e.g. 1-3 5] ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).unwrap_or_default() == 0 {
+            return false;
+        }
+        let numbers: Vec<u32> = disc.tracks.iter().map(|t| t.number).collect();
+        match parse_answer(&line, &numbers) {
+            Ok(Answer::Rip) => return true,
+            Ok(Answer::Cancel) => return false,
+            Ok(Answer::Tracks(numbers)) => {
+                for track in &mut disc.tracks {
+                    track.rip = numbers.contains(&track.number);
+                }
+                return true;
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// Yes, no, or track numbers and ranges separated by spaces or commas
+/// The numbers and the ends of the ranges must be in `tracks`, the track numbers of the disc
+fn parse_answer(answer: &str, tracks: &[u32]) -> Result<Answer> {
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "" | "y" | "yes" => return Ok(Answer::Rip),
+        "n" | "no" | "q" | "quit" => return Ok(Answer::Cancel),
+        _ => (),
+    }
+    let mut numbers = Vec::new();
+    for part in answer
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+    {
+        let number = |n: &str| {
+            let n = n
+                .parse::<u32>()
+                .map_err(|_| anyhow!("{part} is not a track number or range"))?;
+            if tracks.contains(&n) {
+                Ok(n)
+            } else {
+                Err(anyhow!("There is no track {n}"))
+            }
+        };
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if first > last {
+                    return Err(anyhow!("{part} starts after it ends"));
+                }
+                numbers.extend(tracks.iter().filter(|n| (first..=last).contains(*n)));
+            }
+            None => numbers.push(number(part)?),
+        }
+    }
+    if numbers.is_empty() {
+        return Err(anyhow!("Pick at least one track"));
+    }
+    Ok(Answer::Tracks(numbers))
+}
+
+/// Stop the rip on SIGINT or SIGTERM like the Stop button, the track being ripped is aborted,
+/// its partial file removed or kept as `Config::keep_aborted_tracks` says and the report
+/// written; a second signal exits right away
+/// The signal is stored in `signal` for the exit code
+#[cfg(unix)]
+fn stop_on_signals(cancel: &CancelToken, signal: &Rc<Cell<Option<i32>>>) {
+    for signum in STOP_SIGNALS {
+        let (cancel, signal) = (cancel.clone(), signal.clone());
+        glib::unix_signal_add_local(signum, move || {
+            if signal.get().is_some() {
+                std::process::exit(exit_code(Some(signum)));
+            }
+            eprintln!("Stopping the rip, signal again to exit right away");
+            signal.set(Some(signum));
+            cancel.cancel();
+            glib::ControlFlow::Continue
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn stop_on_signals(_cancel: &CancelToken, _signal: &Rc<Cell<Option<i32>>>) {}
+
+/// The exit code of an aborted rip, as a shell reports a process killed by the signal
+fn exit_code(signal: Option<i32>) -> i32 {
+    signal.map_or(Outcome::RipFailed.exit_code(), |signum| 128 + signum)
+}
+
+/// The status as a line of `--progress json`, the `event` tells which
+fn status_json(status: &Status) -> Value {
+    match status {
+        Status::Progress {
+            number,
+            title,
+            percent,
+            state,
+        } => json!({
+            "event": "progress",
+            "track": number,
+            "title": title,
+            "percent": (percent * 10.0).round() / 10.0,
+            "state": match state {
+                TrackState::Reading => "reading",
+                TrackState::Encoding => "encoding",
+                TrackState::Done => "done",
+                TrackState::Failed => "failed",
+            },
+        }),
+        Status::Recovering {
+            number,
+            title,
+            sector,
+            attempt,
+        } => json!({
+            "event": "recovering",
+            "track": number,
+            "title": title,
+            "sector": sector,
+            "attempt": attempt,
+        }),
+        Status::Encoded(encoded) => {
+            let mut value = encoded_json(encoded);
+            value["event"] = json!("encoded");
+            value
+        }
+        Status::Paused(reason) => json!({ "event": "paused", "reason": reason }),
+        Status::Resumed => json!({ "event": "resumed" }),
+        Status::Done(report) => json!({
+            "event": "done",
+            "files": report.iter().map(encoded_json).collect::<Vec<_>>(),
+        }),
+        Status::Aborted => json!({ "event": "aborted" }),
+    }
+}
+
+fn encoded_json(encoded: &Encoded) -> Value {
+    json!({
+        "track": encoded.number,
+        "title": encoded.title,
+        "path": encoded.path,
+        "size": encoded.size,
+        "bitrate": encoded.bitrate,
+        "rereads": encoded.read_errors.rereads,
+        "skipped": encoded.read_errors.skipped,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        exit_code, parse_answer, status_json, toc_line, verify_outcome, Answer, Progress, TocFormat,
+    };
+    use crate::{
+        accuraterip::Verdict,
+        data::{Comparison, Encoded, Outcome, ReadErrors, Status, TrackState},
+    };
+    use std::path::PathBuf;
+
+    #[test]
+    pub fn test_progress_format() {
+        assert_eq!(Progress::Json, "JSON".parse::<Progress>().unwrap());
+        assert_eq!(Progress::Quiet, "quiet".parse::<Progress>().unwrap());
+        assert!("xml".parse::<Progress>().is_err());
+    }
+
+    #[test]
+    pub fn test_parse_answer() -> anyhow::Result<()> {
+        let tracks: Vec<u32> = (1..=10).collect();
+        assert_eq!(Answer::Rip, parse_answer("\n", &tracks)?);
+        assert_eq!(Answer::Rip, parse_answer(" Yes\n", &tracks)?);
+        assert_eq!(Answer::Cancel, parse_answer("n", &tracks)?);
+        assert_eq!(
+            Answer::Tracks(vec![1, 2, 3, 5, 9]),
+            parse_answer("1-3, 5 9\n", &tracks)?
+        );
+        assert!(parse_answer("1-x", &tracks).is_err());
+        assert!(parse_answer("maybe", &tracks).is_err());
+        assert!(parse_answer("5-3", &tracks).is_err());
+        assert!(parse_answer("1-999999999", &tracks).is_err());
+        assert!(parse_answer("11", &tracks).is_err());
+        assert!(parse_answer(",", &tracks).is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_exit_code() {
+        assert_eq!(1, exit_code(None));
+        // SIGINT, SIGTERM
+        assert_eq!(130, exit_code(Some(2)));
+        assert_eq!(143, exit_code(Some(15)));
+    }
+
+    #[test]
+    pub fn test_toc_line() {
+        let offsets = [150, 18901, 39738];
+        let line = |format| {
+            toc_line(
+                format,
+                "lwHl8fGzJyLXQR33ug60E8jhf4k-",
+                "1a00b603",
+                &offsets,
+                60340,
+            )
+        };
+        assert_eq!("1a00b603 3 150 18901 39738 804", line(TocFormat::CdDiscid));
+        assert_eq!("3 150 18901 39738 60340", line(TocFormat::MusicBrainz));
+        assert_eq!("lwHl8fGzJyLXQR33ug60E8jhf4k-", line(TocFormat::Id));
+        assert_eq!(
+            TocFormat::CdDiscid,
+            "cd-discid".parse::<TocFormat>().unwrap()
+        );
+        assert!("cdtext".parse::<TocFormat>().is_err());
+    }
+
+    #[test]
+    pub fn test_verify_outcome() {
+        let mut comparisons = vec![Comparison {
+            number: 1,
+            title: "Intro".to_owned(),
+            matches: true,
+            read_errors: ReadErrors::default(),
+            accuraterip: Verdict::Unknown,
+        }];
+        assert_eq!(Outcome::Success, verify_outcome(&comparisons));
+        assert_eq!(
+            "1. Intro: same audio as the earlier rip",
+            comparisons[0].to_string()
+        );
+        comparisons[0].accuraterip = Verdict::Inaccurate;
+        assert_eq!(Outcome::VerificationFailed, verify_outcome(&comparisons));
+        comparisons[0].accuraterip = Verdict::Accurate(3);
+        comparisons[0].matches = false;
+        assert_eq!(Outcome::VerificationFailed, verify_outcome(&comparisons));
+    }
+
+    #[test]
+    pub fn test_outcome() {
+        let mut encoded = Encoded {
+            path: PathBuf::from("/music/01.flac"),
+            number: 1,
+            title: "Intro".to_owned(),
+            size: 1000,
+            bitrate: None,
+            expected_bitrate: 900,
+            read_errors: ReadErrors::default(),
+            accuraterip: Some(Verdict::Accurate(3)),
+        };
+        let ok = [encoded.clone()];
+        assert_eq!(Outcome::Success, Outcome::of(&ok, false, false));
+        assert_eq!(0, Outcome::of(&ok, false, false).exit_code());
+        assert_eq!(Outcome::LookupFailed, Outcome::of(&ok, false, true));
+        assert_eq!(Outcome::RipFailed, Outcome::of(&ok, true, true));
+        encoded.accuraterip = Some(Verdict::Inaccurate);
+        let inaccurate = [encoded.clone()];
+        assert_eq!(
+            Outcome::VerificationFailed,
+            Outcome::of(&inaccurate, false, true)
+        );
+        // a disc that is not in the database is not a failure, sectors not corrected are
+        encoded.accuraterip = Some(Verdict::Unknown);
+        assert_eq!(
+            Outcome::Success,
+            Outcome::of(&[encoded.clone()], false, false)
+        );
+        encoded.read_errors.skipped = 2;
+        let skipped = Outcome::of(&[encoded], false, false);
+        assert_eq!("verification_failed", skipped.name());
+        assert_eq!(5, skipped.exit_code());
+    }
+
+    #[test]
+    pub fn test_status_json() {
+        let progress = Status::Progress {
+            number: 3,
+            title: "Walk of Life".to_owned(),
+            percent: 42.25,
+            state: TrackState::Encoding,
+        };
+        assert_eq!(
+            r#"{"event":"progress","percent":42.3,"state":"encoding","title":"Walk of Life","track":3}"#,
+            status_json(&progress).to_string()
+        );
+        let encoded = Encoded {
+            path: PathBuf::from("/music/03.flac"),
+            number: 3,
+            title: "Walk of Life".to_owned(),
+            size: 30_000_000,
+            bitrate: Some(950),
+            expected_bitrate: 900,
+            read_errors: Default::default(),
+            accuraterip: None,
+        };
+        let done = status_json(&Status::Done(vec![encoded]));
+        assert_eq!("done", done["event"]);
+        assert_eq!("/music/03.flac", done["files"][0]["path"]);
+        assert_eq!(950, done["files"][0]["bitrate"]);
+        assert_eq!(
+            r#"{"event":"paused","reason":"the disk is full"}"#,
+            status_json(&Status::Paused("the disk is full".to_owned())).to_string()
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{data::Config, gsettings};
+use anyhow::Result;
+use log::{debug, error};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Changes saved with `ConfigManager::update_later` are written this long after the first one,
+/// e.g. while the user goes through the format drop down
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+/// The one copy of the configuration the threads of the process share
+/// The window and the rip thread load and change it at the same time, so every access goes
+/// through the lock, and a change is applied to the latest configuration instead of storing a
+/// copy loaded before, which would undo the changes made in between
+/// The file is read again when it was changed outside ripperx4, e.g. edited by hand
+/// With the GSettings schema installed, the values set there win over the file and the changes
+/// are written to both
+pub struct ConfigManager {
+    path: PathBuf,
+    gsettings: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    config: Option<Config>,
+    /// modification time of the file when it was last read or written
+    modified: Option<SystemTime>,
+    /// a change waits to be written
+    pending: bool,
+}
+
+/// The manager of the configuration file of ripperx4
+pub fn manager() -> &'static ConfigManager {
+    static MANAGER: OnceLock<ConfigManager> = OnceLock::new();
+    MANAGER.get_or_init(|| {
+        let path = confy::get_configuration_file_path(crate::data::APP_NAME, None)
+            .unwrap_or_else(|_| PathBuf::from("ripperx4.toml"));
+        ConfigManager {
+            gsettings: true,
+            ..ConfigManager::new(path)
+        }
+    })
+}
+
+impl ConfigManager {
+    fn new(path: PathBuf) -> ConfigManager {
+        ConfigManager {
+            path,
+            gsettings: false,
+            state: Mutex::default(),
+        }
+    }
+
+    /// The state is consistent after every change, a thread that panicked doesn't matter
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A copy of the current configuration, read from the file the first time and after it
+    /// was changed outside ripperx4
+    pub fn load(&self) -> Result<Config> {
+        let mut state = self.lock();
+        self.current(&mut state).cloned()
+    }
+
+    fn current<'a>(&self, state: &'a mut State) -> Result<&'a mut Config> {
+        // a change that waits is newer than the file
+        let fresh =
+            state.pending || (state.config.is_some() && state.modified == modified(&self.path));
+        if !fresh {
+            debug!("reading {}", self.path.display());
+            state.config = Some(confy::load_path(&self.path)?);
+            // confy writes the file when it doesn't exist
+            state.modified = modified(&self.path);
+        }
+        let config = state.config.get_or_insert_with(Config::default);
+        // dconf may have changed since, it keeps its values in memory so this is cheap
+        if let Some(settings) = self.settings() {
+            if let Err(e) = gsettings::apply(&settings, config) {
+                error!("Failed to apply the GSettings values: {e}");
+            }
+        }
+        Ok(config)
+    }
+
+    fn settings(&self) -> Option<gtk::gio::Settings> {
+        self.gsettings.then(gsettings::open).flatten()
+    }
+
+    /// Change the current configuration and write it right away
+    pub fn update(&self, change: impl FnOnce(&mut Config)) -> Result<()> {
+        let mut state = self.lock();
+        change(self.current(&mut state)?);
+        self.write(&mut state)
+    }
+
+    /// Replace the whole configuration and write it right away, without reading the file first,
+    /// so a file that doesn't parse (edited by hand, from a newer version) is replaced as well
+    pub fn replace(&self, config: Config) -> Result<()> {
+        let mut state = self.lock();
+        state.config = Some(config);
+        self.write(&mut state)
+    }
+
+    /// Change the current configuration and write it a moment later, together with the changes
+    /// made in the meantime
+    pub fn update_later(&'static self, change: impl FnOnce(&mut Config)) -> Result<()> {
+        let mut state = self.lock();
+        change(self.current(&mut state)?);
+        if !state.pending {
+            state.pending = true;
+            thread::spawn(move || {
+                thread::sleep(SAVE_DELAY);
+                if let Err(e) = self.flush() {
+                    error!("Failed to store the configuration: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the changes that wait, before the process exits
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.lock();
+        if state.pending {
+            self.write(&mut state)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, state: &mut State) -> Result<()> {
+        if let Some(config) = &state.config {
+            confy::store_path(&self.path, config)?;
+            if let Some(settings) = self.settings() {
+                gsettings::store(&settings, config)?;
+            }
+        }
+        state.pending = false;
+        state.modified = modified(&self.path);
+        Ok(())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigManager;
+    use crate::data::{Config, Encoder};
+    use anyhow::Result;
+    use std::{
+        env,
+        fs::{self, File},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    #[test]
+    pub fn test_config_manager() -> Result<()> {
+        let path = env::temp_dir().join("ripperx4_manager.toml");
+        fs::remove_file(&path).ok();
+        let manager: &'static ConfigManager = Box::leak(Box::new(ConfigManager::new(path.clone())));
+        // the file is made with the defaults
+        assert_eq!(Encoder::MP3, manager.load()?.encoder);
+        assert!(path.exists());
+        manager.update(|config| config.encoder = Encoder::FLAC)?;
+        assert!(fs::read_to_string(&path)?.contains("encoder = \"FLAC\""));
+        // two changes from different threads, neither is lost
+        let other = thread::spawn(move || manager.update(|config| config.secure_rip = true));
+        manager.update_later(|config| config.encode_path = "/music".to_owned())?;
+        other.join().expect("thread panicked")?;
+        let config = manager.load()?;
+        assert_eq!(Encoder::FLAC, config.encoder);
+        assert!(config.secure_rip);
+        assert_eq!("/music", config.encode_path);
+        manager.flush()?;
+        assert!(fs::read_to_string(&path)?.contains("encode_path = \"/music\""));
+        // edited by hand, with another modification time
+        let text = fs::read_to_string(&path)?.replace("\"FLAC\"", "\"OPUS\"");
+        fs::write(&path, text)?;
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        assert_eq!(Encoder::OPUS, manager.load()?.encoder);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_replace_invalid() -> Result<()> {
+        let path = env::temp_dir().join("ripperx4_invalid.toml");
+        fs::write(&path, "encoder = \"FLAC\"\nsecure_rip = maybe\n")?;
+        let manager = ConfigManager::new(path.clone());
+        assert!(manager.load().is_err());
+        assert!(manager.update(|config| config.secure_rip = true).is_err());
+        // the broken file is overwritten with the defaults
+        manager.replace(Config::default())?;
+        assert_eq!(Encoder::MP3, manager.load()?.encoder);
+        assert!(fs::read_to_string(&path)?.contains("encoder = \"MP3\""));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
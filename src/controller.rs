@@ -0,0 +1,181 @@
+use crate::{
+    cancel::CancelToken,
+    data::{Config, Disc, Status, TrackState},
+    hooks::run_post_rip_hooks,
+    mail,
+    naming::name_rules,
+    profile,
+    ripper::extract,
+    udisks::eject_disc,
+    util::drive,
+};
+use async_channel::{Receiver, Sender};
+use log::{debug, error};
+use std::thread;
+
+/// Apply what is decided at the start of a rip: the library of the disc, the settings of its
+/// folder, the profile for the free space and the file name rules of the encode path
+pub fn prepare_config(config: &mut Config, disc: &Disc) {
+    profile::apply_route(config, disc);
+    profile::apply_folder(config);
+    profile::apply(config);
+    // looking up the file system can block on a network share, not for every file
+    config.name_rules = name_rules(config);
+}
+
+/// Status updates a rip can be ahead of the window or the terminal showing them
+pub const STATUS_CAPACITY: usize = 64;
+
+/// Progress of a track being read or encoded and sectors read again, the next update replaces
+/// them
+fn is_transient(update: &Status) -> bool {
+    matches!(
+        update,
+        Status::Progress {
+            state: TrackState::Reading | TrackState::Encoding,
+            ..
+        } | Status::Recovering { .. }
+    )
+}
+
+/// Send a status update of a rip on a channel of `STATUS_CAPACITY`
+/// When the receiver falls behind, e.g. a window that doesn't redraw, new transient updates are
+/// dropped rather than hold up the drive: the queued ones stay, older than the rip, until the
+/// receiver makes room again; the others (tracks done or failed, files, pauses, the end) wait
+/// for room, so they are never lost
+pub fn send_status(to: &Sender<Status>, update: Status) {
+    if is_transient(&update) {
+        to.try_send(update).ok();
+    } else {
+        to.send_blocking(update).ok();
+    }
+}
+
+/// Rip the disc in a thread, shared by the window and the command line
+/// The thread owns its copy of the disc, the progress and the outcome (`Status::Done` or
+/// `Status::Aborted`) are sent on the returned channel
+/// The outcome is sent as soon as the tracks are ripped, the channel is closed when the summary
+/// mail, the post rip hooks and the eject that follow are done too
+pub fn start_rip(
+    disc: Disc,
+    order: Vec<usize>,
+    mut config: Config,
+    cancel: CancelToken,
+) -> Receiver<Status> {
+    let (tx, rx) = async_channel::bounded(STATUS_CAPACITY);
+    thread::spawn(move || {
+        prepare_config(&mut config, &disc);
+        let result = extract(&disc, &order, &config, &tx, &cancel);
+        match &result {
+            Ok(report) => {
+                debug!("done");
+                send_status(&tx, Status::Done(report.clone()));
+            }
+            Err(e) => {
+                error!("Rip failed: {e}");
+                send_status(&tx, Status::Aborted);
+            }
+        }
+        if let (Some(smtp), false) = (&config.smtp, cancel.is_cancelled()) {
+            let (subject, body) = mail::summary(&disc, &result, config.write_checksums);
+            if let Err(e) = mail::send(smtp, &subject, &body) {
+                error!("Failed to send the summary mail: {e}");
+            }
+        }
+        if let Ok(report) = result {
+            for e in run_post_rip_hooks(&disc, &report, &config) {
+                error!("Post rip hook failed: {e}");
+            }
+            if config.eject_when_done {
+                if let Err(e) = eject_disc(&drive(&config)) {
+                    error!("Failed to eject the disc: {e}");
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Where the window is with a rip, the buttons follow it
+/// After Stop the rip is `Finishing` until its thread reports the end, so a second rip can't
+/// start on the same drive before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RipState {
+    #[default]
+    Idle,
+    Ripping,
+    Finishing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RipEvent {
+    /// the Go button was clicked
+    Start,
+    /// the Stop button was clicked
+    Stop,
+    /// the rip thread sent `Status::Done` or `Status::Aborted`
+    Ended,
+}
+
+impl RipState {
+    /// The state after the event, `None` when it doesn't apply, e.g. a second Go while ripping
+    pub fn next(self, event: RipEvent) -> Option<RipState> {
+        match (self, event) {
+            (RipState::Idle, RipEvent::Start) => Some(RipState::Ripping),
+            (RipState::Ripping, RipEvent::Stop) => Some(RipState::Finishing),
+            (RipState::Ripping | RipState::Finishing, RipEvent::Ended) => Some(RipState::Idle),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{send_status, RipEvent, RipState};
+    use crate::data::{Status, TrackState};
+
+    #[test]
+    pub fn test_send_status() {
+        let (tx, rx) = async_channel::bounded(2);
+        let progress = |percent, state| Status::Progress {
+            number: 1,
+            title: "Intro".to_owned(),
+            percent,
+            state,
+        };
+        send_status(&tx, progress(10.0, TrackState::Reading));
+        send_status(&tx, progress(20.0, TrackState::Reading));
+        // the channel is full, the progress is dropped instead of waiting
+        send_status(&tx, progress(30.0, TrackState::Encoding));
+        assert_eq!(2, rx.len());
+        assert_eq!(Ok(progress(10.0, TrackState::Reading)), rx.try_recv());
+        send_status(&tx, progress(100.0, TrackState::Done));
+        assert_eq!(Ok(progress(20.0, TrackState::Reading)), rx.try_recv());
+        assert_eq!(Ok(progress(100.0, TrackState::Done)), rx.try_recv());
+    }
+
+    #[test]
+    pub fn test_rip_state() {
+        let ripping = RipState::Idle.next(RipEvent::Start);
+        assert_eq!(Some(RipState::Ripping), ripping);
+        // a double click doesn't start a second rip
+        assert_eq!(None, RipState::Ripping.next(RipEvent::Start));
+        assert_eq!(
+            Some(RipState::Finishing),
+            RipState::Ripping.next(RipEvent::Stop)
+        );
+        // nor does Go while the stopped rip is finishing
+        assert_eq!(None, RipState::Finishing.next(RipEvent::Start));
+        assert_eq!(None, RipState::Finishing.next(RipEvent::Stop));
+        assert_eq!(
+            Some(RipState::Idle),
+            RipState::Finishing.next(RipEvent::Ended)
+        );
+        assert_eq!(
+            Some(RipState::Idle),
+            RipState::Ripping.next(RipEvent::Ended)
+        );
+        assert_eq!(None, RipState::Idle.next(RipEvent::Stop));
+        assert_eq!(None, RipState::Idle.next(RipEvent::Ended));
+    }
+}
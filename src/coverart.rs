@@ -0,0 +1,353 @@
+use crate::data::{Config, Disc};
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use serde_json::Value;
+use std::io::Read;
+
+/// Covers larger than this are not downloaded
+const MAX_COVER_SIZE: u64 = 20 * 1024 * 1024;
+
+/// A source of album covers
+/// To add one, implement this trait and add it to `PROVIDERS`, it's used when its id is listed in
+/// `Config::cover_providers`
+pub trait CoverArtProvider: Sync {
+    /// Id used in the config file
+    fn id(&self) -> &'static str;
+
+    /// Whether the provider can be used, e.g. it has the API key it needs
+    fn enabled(&self, disc: &Disc, config: &Config) -> bool;
+
+    /// Image URLs for the front cover of the disc, best first
+    fn candidates(&self, disc: &Disc, config: &Config) -> Result<Vec<String>>;
+}
+
+/// coverartarchive.org, for discs found on MusicBrainz
+struct CoverArtArchive;
+/// The iTunes search API, matches on artist and album title, only when it's listed
+struct ITunes;
+/// fanart.tv, needs an API key and the MusicBrainz release group
+struct FanartTv;
+
+static PROVIDERS: &[&dyn CoverArtProvider] = &[&CoverArtArchive, &ITunes, &FanartTv];
+
+/// The providers in the configured order
+fn configured_providers(config: &Config) -> Vec<&'static dyn CoverArtProvider> {
+    config
+        .cover_providers
+        .iter()
+        .filter_map(|id| PROVIDERS.iter().find(|p| p.id() == id.as_str()).copied())
+        .collect()
+}
+
+/// Find the front cover of the disc, trying the providers in the configured order
+/// Returns the image data of the first candidate that passes the quality checks
+pub fn fetch_cover(disc: &Disc, config: &Config) -> Result<Vec<u8>> {
+    let mut rejected = Vec::new();
+    for provider in configured_providers(config) {
+        if !provider.enabled(disc, config) {
+            continue;
+        }
+        let candidates = match provider.candidates(disc, config) {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                debug!("no cover from {}: {e}", provider.id());
+                continue;
+            }
+        };
+        for url in candidates {
+            let image = match download(&url) {
+                Ok(image) => image,
+                Err(e) => {
+                    debug!("failed to download cover {url}: {e}");
+                    continue;
+                }
+            };
+            match check_cover(&image, config) {
+                Ok(()) => {
+                    debug!("cover from {}: {url}", provider.id());
+                    return Ok(image);
+                }
+                Err(reason) => {
+                    warn!("rejected cover {url}: {reason}");
+                    rejected.push(format!("{}: {reason}", provider.id()));
+                }
+            }
+        }
+    }
+    if rejected.is_empty() {
+        Err(anyhow!("no cover found"))
+    } else {
+        Err(anyhow!(
+            "all covers were rejected ({})",
+            rejected.join(", ")
+        ))
+    }
+}
+
+/// Reject covers that are too small or too far from square, they'd look bad in every player
+fn check_cover(image: &[u8], config: &Config) -> Result<(), String> {
+    let (width, height) = image_size(image).ok_or("not a JPEG or PNG image")?;
+    if width.min(height) < config.cover_min_size {
+        return Err(format!(
+            "{width}x{height} is smaller than {0}x{0}",
+            config.cover_min_size
+        ));
+    }
+    let aspect = f64::from(width.max(height)) / f64::from(width.min(height));
+    if aspect > config.cover_max_aspect {
+        return Err(format!("{width}x{height} is not square enough"));
+    }
+    Ok(())
+}
+
+/// Width and height of a JPEG or PNG image, read from its header
+fn image_size(image: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| {
+        Some(u32::from(u16::from_be_bytes(
+            image.get(at..at + 2)?.try_into().ok()?,
+        )))
+    };
+    match image_extension(image)? {
+        "png" => {
+            // the IHDR chunk always comes first
+            let be32 =
+                |at: usize| Some(u32::from_be_bytes(image.get(at..at + 4)?.try_into().ok()?));
+            Some((be32(16)?, be32(20)?))
+        }
+        _ => {
+            // walk the segments up to the start of frame
+            let mut at = 2;
+            loop {
+                if *image.get(at)? != 0xff {
+                    return None;
+                }
+                let marker = *image.get(at + 1)?;
+                match marker {
+                    0xff => at += 1,
+                    0xd0..=0xd9 | 0x01 => at += 2,
+                    0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                        return Some((be16(at + 7)?, be16(at + 5)?));
+                    }
+                    _ => at += 2 + usize::try_from(be16(at + 2)?).ok()?,
+                }
+            }
+        }
+    }
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let mut image = Vec::new();
+    ureq::get(url)
+        .call()?
+        .into_reader()
+        .take(MAX_COVER_SIZE)
+        .read_to_end(&mut image)?;
+    Ok(image)
+}
+
+/// File extension for the image data, `None` when it's not a JPEG or PNG
+pub fn image_extension(image: &[u8]) -> Option<&'static str> {
+    if image.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("jpg")
+    } else if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else {
+        None
+    }
+}
+
+impl CoverArtProvider for CoverArtArchive {
+    fn id(&self) -> &'static str {
+        "coverartarchive"
+    }
+
+    fn enabled(&self, disc: &Disc, _config: &Config) -> bool {
+        disc.release_id.is_some()
+    }
+
+    fn candidates(&self, disc: &Disc, _config: &Config) -> Result<Vec<String>> {
+        let release = disc.release_id.as_ref().ok_or(anyhow!("no release id"))?;
+        Ok(vec![format!(
+            "https://coverartarchive.org/release/{release}/front"
+        )])
+    }
+}
+
+/// Artwork URLs from an iTunes search result, at the largest size iTunes serves
+/// Only the albums of the artist of the disc with its title are kept, an edition like
+/// "Title (Remastered)" is the same album
+fn itunes_artwork(results: &Value, disc: &Disc) -> Vec<String> {
+    let artist = words(&disc.artist);
+    let title = words(&disc.title);
+    results["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|album| words(album["artistName"].as_str().unwrap_or_default()) == artist)
+        .filter(|album| {
+            let collection = words(album["collectionName"].as_str().unwrap_or_default());
+            collection == title || collection.starts_with(&format!("{title} "))
+        })
+        .filter_map(|album| album["artworkUrl100"].as_str())
+        .map(|url| url.replace("100x100bb", "1200x1200bb"))
+        .collect()
+}
+
+/// The words of a name in lower case, without the punctuation
+fn words(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl CoverArtProvider for ITunes {
+    fn id(&self) -> &'static str {
+        "itunes"
+    }
+
+    fn enabled(&self, _disc: &Disc, _config: &Config) -> bool {
+        true
+    }
+
+    fn candidates(&self, disc: &Disc, _config: &Config) -> Result<Vec<String>> {
+        let term = format!("{} {}", disc.artist, disc.title);
+        let results: Value = ureq::get("https://itunes.apple.com/search")
+            .query("term", &term)
+            .query("entity", "album")
+            .query("limit", "5")
+            .call()?
+            .into_json()?;
+        Ok(itunes_artwork(&results, disc))
+    }
+}
+
+/// Album cover URLs from a fanart.tv album response
+fn fanart_covers(response: &Value, release_group: &str) -> Vec<String> {
+    response["albums"][release_group]["albumcover"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|cover| cover["url"].as_str())
+        .map(str::to_owned)
+        .collect()
+}
+
+impl CoverArtProvider for FanartTv {
+    fn id(&self) -> &'static str {
+        "fanart.tv"
+    }
+
+    fn enabled(&self, disc: &Disc, config: &Config) -> bool {
+        config.fanart_api_key.is_some() && disc.release_group_id.is_some()
+    }
+
+    fn candidates(&self, disc: &Disc, config: &Config) -> Result<Vec<String>> {
+        let (Some(key), Some(release_group)) = (&config.fanart_api_key, &disc.release_group_id)
+        else {
+            return Ok(Vec::new());
+        };
+        let response: Value = ureq::get(&format!(
+            "https://webservice.fanart.tv/v3/music/albums/{release_group}"
+        ))
+        .query("api_key", key)
+        .call()?
+        .into_json()?;
+        Ok(fanart_covers(&response, release_group))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        check_cover, configured_providers, fanart_covers, image_extension, image_size,
+        itunes_artwork,
+    };
+    use crate::data::{Config, Disc};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_configured_providers() {
+        let config = Config {
+            cover_providers: vec!["itunes".to_owned(), "unknown".to_owned()],
+            ..Default::default()
+        };
+        let ids: Vec<&str> = configured_providers(&config)
+            .iter()
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(vec!["itunes"], ids);
+    }
+
+    #[test]
+    pub fn test_parse_candidates() {
+        let mut disc = Disc::with_tracks(1);
+        disc.artist = "Dire Straits".to_owned();
+        disc.title = "Brothers in Arms".to_owned();
+        let results = json!({"results": [
+            {
+                "artistName": "Dire Straits",
+                "collectionName": "Brothers In Arms (Remastered)",
+                "artworkUrl100": "https://is1.mzstatic.com/image/a/100x100bb.jpg",
+            },
+            {"artistName": "Dire Straits", "collectionName": "Brothers in Arms"},
+            {
+                "artistName": "Dire Straits",
+                "collectionName": "Brothers in Armstrong",
+                "artworkUrl100": "https://is1.mzstatic.com/image/b/100x100bb.jpg",
+            },
+            {
+                "artistName": "Tribute Band",
+                "collectionName": "Brothers in Arms",
+                "artworkUrl100": "https://is1.mzstatic.com/image/c/100x100bb.jpg",
+            },
+        ]});
+        assert_eq!(
+            vec!["https://is1.mzstatic.com/image/a/1200x1200bb.jpg"],
+            itunes_artwork(&results, &disc)
+        );
+        let response =
+            json!({"albums": {"rg": {"albumcover": [{"url": "https://fanart.tv/a.jpg"}]}}});
+        assert_eq!(
+            vec!["https://fanart.tv/a.jpg"],
+            fanart_covers(&response, "rg")
+        );
+        assert!(fanart_covers(&response, "other").is_empty());
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&width.to_be_bytes());
+        png.extend_from_slice(&height.to_be_bytes());
+        png
+    }
+
+    #[test]
+    pub fn test_image_size() {
+        assert_eq!(Some((600, 500)), image_size(&png(600, 500)));
+        // SOI, an APP0 segment of 4 bytes, then a baseline SOF0 of 300 high and 400 wide
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xc0, 0, 11, 8, 0x01, 0x2c, 0x01, 0x90,
+        ];
+        assert_eq!(Some((400, 300)), image_size(&jpeg));
+        assert_eq!(None, image_size(&[0xff, 0xd8, 0xff, 0xe0, 0, 4]));
+        assert_eq!(None, image_size(b"GIF89a"));
+    }
+
+    #[test]
+    pub fn test_check_cover() {
+        let config = Config::default();
+        assert!(check_cover(&png(1200, 1200), &config).is_ok());
+        assert!(check_cover(&png(100, 100), &config).is_err());
+        assert!(check_cover(&png(1200, 600), &config).is_err());
+        assert!(check_cover(b"<html>", &config).is_err());
+    }
+
+    #[test]
+    pub fn test_image_extension() {
+        assert_eq!(Some("jpg"), image_extension(&[0xff, 0xd8, 0xff, 0xe0]));
+        assert_eq!(Some("png"), image_extension(b"\x89PNG\r\n\x1a\n...."));
+        assert_eq!(None, image_extension(b"<html>"));
+    }
+}
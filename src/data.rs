@@ -1,22 +1,110 @@
-use serde::{Deserialize, Serialize};
+use crate::{
+    accuraterip::Verdict,
+    config::manager,
+    naming::{COMPILATION_TEMPLATE, DEFAULT_TEMPLATE},
+};
+use confy::ConfyError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::{self, Display},
+    path::PathBuf,
+};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Disc {
     pub title: String,
     pub artist: String,
     pub year: Option<u16>,
+    /// release date as on MusicBrainz: YYYY, YYYY-MM or YYYY-MM-DD
+    pub date: Option<String>,
     pub genre: Option<String>,
     pub tracks: Vec<Track>,
+    /// MusicBrainz release id, when the metadata came from MusicBrainz
+    pub release_id: Option<String>,
+    /// MusicBrainz release group id, used to find cover art
+    pub release_group_id: Option<String>,
+    /// MusicBrainz disc id of the scanned CD
+    pub discid: Option<String>,
+    /// position of the CD in a set of several, and the number of CDs in the set
+    pub disc_number: Option<u32>,
+    pub disc_count: Option<u32>,
+    /// path of the disc in the AccurateRip database, see `accuraterip::disc_id`
+    pub accuraterip_id: Option<String>,
+    /// language (ISO 639-3, e.g. "jpn") and script (ISO 15924, e.g. "Jpan") of the titles
+    pub language: Option<String>,
+    pub script: Option<String>,
+    /// MusicBrainz pseudo-release with the titles transliterated or translated
+    pub transliteration_id: Option<String>,
+    /// album title and artist in the Latin alphabet, for discs in other scripts
+    pub latin_title: Option<String>,
+    pub latin_artist: Option<String>,
+    /// barcode (EAN/UPC) and catalog number of the release, they tell editions apart
+    pub barcode: Option<String>,
+    pub catalog_number: Option<String>,
+    /// note of the user, e.g. on the condition of the copy, in the comment of every track
+    pub comment: Option<String>,
+    /// the front cover (JPEG or PNG), embedded in the files
+    pub cover: Option<Vec<u8>>,
+    /// why no metadata was found, the disc has placeholder titles
+    pub lookup_error: Option<String>,
+    /// why the metadata found may be of another release than the disc
+    pub lookup_warning: Option<String>,
 }
 
 impl Disc {
+    /// Whether most tracks are by other artists than the disc, e.g. a "Various Artists" sampler
+    /// Tracks that all have the same artist are not, the album artist may just have been edited
+    pub fn is_compilation(&self) -> bool {
+        let artists: Vec<&str> = self
+            .tracks
+            .iter()
+            .map(|t| t.artist.as_str())
+            .filter(|artist| !artist.is_empty())
+            .collect();
+        let others: HashSet<&str> = artists
+            .iter()
+            .copied()
+            .filter(|artist| *artist != self.artist)
+            .collect();
+        let on_others = artists.iter().filter(|a| others.contains(*a)).count();
+        others.len() > 1 && on_others * 2 > artists.len()
+    }
+
+    /// Change the year of the disc, the full release date is only kept when it's in that year
+    pub fn set_year(&mut self, year: Option<u16>) {
+        self.year = year;
+        let same_year = |date: &String| date.get(..4).and_then(|y| y.parse().ok()) == year;
+        if !self.date.as_ref().is_some_and(same_year) {
+            self.date = None;
+        }
+    }
+
     pub(crate) fn with_tracks(num: u32) -> Disc {
         let mut d = Disc {
             title: "Unknown".to_string(),
             artist: "Unknown".to_string(),
             year: None,
+            date: None,
             genre: None,
             tracks: Vec::new(),
+            release_id: None,
+            release_group_id: None,
+            discid: None,
+            disc_number: None,
+            disc_count: None,
+            accuraterip_id: None,
+            language: None,
+            script: None,
+            transliteration_id: None,
+            latin_title: None,
+            latin_artist: None,
+            barcode: None,
+            catalog_number: None,
+            comment: None,
+            cover: None,
+            lookup_error: None,
+            lookup_warning: None,
         };
         for i in 1..=num {
             d.tracks.push(Track {
@@ -24,61 +112,731 @@ impl Disc {
                 title: "Unknown".to_string(),
                 artist: "Unknown".to_string(),
                 duration: 0,
+                sectors: 0,
                 composer: None,
-                rip: false,
+                work: None,
+                movement: None,
+                segments: Vec::new(),
+                rip: true,
+                encoder: None,
+                recording_id: None,
+                latin_title: None,
+                latin_artist: None,
+                length_ms: None,
+                comment: None,
             });
         }
         d
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Track {
     pub number: u32,
     pub title: String,
     pub artist: String,
     pub duration: u64,
+    /// length in CD frames (1/75 s) from the TOC, including the pregap of the next track
+    pub sectors: u64,
     pub composer: Option<String>,
+    /// the work this track is (a part of), from the MusicBrainz work relationships
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    /// when not empty, these parts are ripped to separate files instead of the whole track
+    pub segments: Vec<Segment>,
     pub rip: bool,
+    /// format of this track instead of the configured one
+    pub encoder: Option<Encoder>,
+    /// MusicBrainz recording id
+    pub recording_id: Option<String>,
+    /// title and artist in the Latin alphabet, for discs in other scripts
+    pub latin_title: Option<String>,
+    pub latin_artist: Option<String>,
+    /// length the metadata has, in milliseconds, to check the release matches the disc
+    pub length_ms: Option<u64>,
+    /// note of the user on this track, in its comment after the one of the disc
+    pub comment: Option<String>,
+}
+
+/// A part of a track, the times are in seconds from the start of the track
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub title: String,
+    pub start: u64,
+    /// `None` runs to the end of the track
+    pub end: Option<u64>,
+}
+
+impl Track {
+    /// The "Work: Movement" title used in classical mode, the plain title when the work is unknown
+    pub fn classical_title(&self) -> String {
+        match (&self.work, &self.movement) {
+            (Some(work), Some(movement)) => format!("{work}: {movement}"),
+            (Some(work), None) => work.clone(),
+            _ => self.title.clone(),
+        }
+    }
+
+    /// The format the track is encoded to
+    pub fn encoder(&self, config: &Config) -> Encoder {
+        self.encoder.unwrap_or(config.encoder)
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Data {
     pub disc: Option<Disc>,
+    /// format and folder of the scanned disc only, they replace the configured ones
+    pub disc_settings: Option<DiscSettings>,
+    /// name of the library picked for the disc, `None` to route it by genre
+    pub route: Option<String>,
+}
+
+/// Format and folder for one disc, e.g. an audiobook while the library is FLAC, without
+/// changing the saved configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscSettings {
+    pub encoder: Encoder,
+    pub quality: Quality,
+    pub encode_path: String,
+}
+
+impl DiscSettings {
+    /// Replace the format and folder of the config, the profiles and routes no longer apply
+    pub fn apply(&self, config: &mut Config) {
+        config.encoder = self.encoder;
+        config.quality = self.quality;
+        config.encode_path.clone_from(&self.encode_path);
+        config.profiles.clear();
+        config.routes.clear();
+    }
+}
+
+/// Naming and format for the rips to one folder, from a `.ripperx4.toml` in it, e.g. the
+/// audiobooks folder always using the audiobook profile
+/// Only what the file sets replaces the configuration
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FolderSettings {
+    /// name of a profile of the configuration, its format is used whatever the free space
+    pub profile: Option<String>,
+    pub encoder: Option<Encoder>,
+    pub quality: Option<Quality>,
+    pub name_template: Option<String>,
+    pub compilation_template: Option<String>,
+    pub classical: Option<bool>,
+    pub disc_image: Option<bool>,
+    pub image_format: Option<ImageFormat>,
+}
+
+/// Progress of a rip, sent from the ripping thread to the UI
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// progress of the track being ripped, or a track that finished
+    Progress {
+        number: u32,
+        title: String,
+        percent: f64,
+        state: TrackState,
+    },
+    /// cdparanoia reads a damaged sector of the track again, the progress stands still meanwhile
+    Recovering {
+        number: u32,
+        title: String,
+        sector: i32,
+        attempt: u32,
+    },
+    /// the file of a track is ready, sent after its `TrackState::Done` progress
+    Encoded(Encoded),
+    /// the rip waits for the encode path to come back, with the reason
+    Paused(String),
+    Resumed,
+    Done(Vec<Encoded>),
+    Aborted,
+}
+
+/// Where a track is in the rip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackState {
+    /// being read from the disc, before it's encoded
+    Reading,
+    Encoding,
+    Done,
+    Failed,
+}
+
+/// A track that was encoded, listed in the summary after the rip
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoded {
+    /// where the file was stored
+    pub path: PathBuf,
+    pub number: u32,
+    pub title: String,
+    /// size of the file in bytes
+    pub size: u64,
+    /// actual average bitrate in kbit/s, unknown when the duration is
+    pub bitrate: Option<u64>,
+    /// typical bitrate in kbit/s of the encoder settings
+    pub expected_bitrate: u64,
+    pub read_errors: ReadErrors,
+    /// how the track compares to AccurateRip, known after the rip when it was verified
+    pub accuraterip: Option<Verdict>,
+}
+
+/// A track read again to check an earlier rip, compared to the checksums in its report
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub number: u32,
+    pub title: String,
+    /// the audio read now has the checksums of the earlier rip
+    pub matches: bool,
+    pub read_errors: ReadErrors,
+    /// how the audio read now compares to AccurateRip
+    pub accuraterip: Verdict,
+}
+
+/// How a rip ended, the exit code of `--no-gui` and the `outcome` of the JSON report
+/// A rip that failed in several ways is classified by the first one listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// a track failed or the rip was stopped
+    RipFailed,
+    /// there is no disc in the drive, or its TOC can't be read
+    NoDisc,
+    /// all tracks were ripped, but sectors were not corrected or AccurateRip has other checksums
+    VerificationFailed,
+    /// all tracks were ripped, with placeholder titles
+    LookupFailed,
+    Success,
+}
+
+impl Outcome {
+    /// The outcome of a rip that produced the `encoded` files
+    pub fn of(encoded: &[Encoded], failed: bool, lookup_failed: bool) -> Outcome {
+        let unverified =
+            |e: &Encoded| e.read_errors.skipped > 0 || e.accuraterip == Some(Verdict::Inaccurate);
+        if failed {
+            Outcome::RipFailed
+        } else if encoded.iter().any(unverified) {
+            Outcome::VerificationFailed
+        } else if lookup_failed {
+            Outcome::LookupFailed
+        } else {
+            Outcome::Success
+        }
+    }
+
+    /// 2 is left for invalid options, 130 and 143 for SIGINT and SIGTERM
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::RipFailed => 1,
+            Outcome::NoDisc => 3,
+            Outcome::LookupFailed => 4,
+            Outcome::VerificationFailed => 5,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::RipFailed => "rip_failed",
+            Outcome::NoDisc => "no_disc",
+            Outcome::LookupFailed => "lookup_failed",
+            Outcome::VerificationFailed => "verification_failed",
+        }
+    }
+}
+
+/// Problems reading a track, only cdparanoiasrc reports them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadErrors {
+    /// sectors the drive failed to read, they were read again
+    pub rereads: u32,
+    /// reads of those sectors that failed, more than `rereads` when a sector took several
+    pub retries: u32,
+    /// sectors cdparanoia could not correct, they may hold silence or noise
+    pub skipped: u32,
+}
+
+impl Display for Encoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}. {}: {:.1} MB",
+            self.number,
+            self.title,
+            self.size as f64 / 1_000_000.0
+        )?;
+        match self.bitrate {
+            Some(bitrate) => write!(
+                f,
+                ", {bitrate} kbit/s (expected ≈ {})",
+                self.expected_bitrate
+            ),
+            None => Ok(()),
+        }?;
+        let errors = self.read_errors;
+        if errors != ReadErrors::default() {
+            write!(
+                f,
+                ", {} sectors re-read, {} not corrected",
+                errors.rereads, errors.skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rip = if self.matches {
+            "same audio as the earlier rip"
+        } else {
+            "differs from the earlier rip"
+        };
+        write!(f, "{}. {}: {rip}", self.number, self.title)?;
+        match self.accuraterip {
+            Verdict::Accurate(confidence) => {
+                write!(f, ", accurate (confidence {confidence})")
+            }
+            Verdict::Inaccurate => write!(f, ", not matching AccurateRip"),
+            Verdict::Unknown => Ok(()),
+        }?;
+        let errors = self.read_errors;
+        if errors != ReadErrors::default() {
+            write!(
+                f,
+                ", {} sectors re-read, {} not corrected",
+                errors.rereads, errors.skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Progress {
+                title,
+                percent,
+                state,
+                ..
+            } => match state {
+                TrackState::Reading => write!(f, "Reading {title} : {percent:.0} %"),
+                TrackState::Encoding => write!(f, "Encoding {title} : {percent:.0} %"),
+                TrackState::Done => write!(f, "Encoded {title}"),
+                TrackState::Failed => write!(f, "Failed to encode {title}"),
+            },
+            Status::Recovering {
+                title,
+                sector,
+                attempt,
+                ..
+            } => write!(
+                f,
+                "Reading {title} : recovering sector {sector} (attempt {attempt})"
+            ),
+            Status::Encoded(encoded) => write!(f, "{encoded}"),
+            Status::Paused(reason) => write!(f, "paused, {reason}"),
+            Status::Resumed => write!(f, "resumed"),
+            Status::Done(_) => write!(f, "done"),
+            Status::Aborted => write!(f, "aborted"),
+        }
+    }
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Encoder {
     MP3,
     OGG,
     FLAC,
     OPUS,
+    /// AAC in an MP4 container
+    AAC,
+    /// Apple Lossless in an MP4 container
+    ALAC,
+    WAVPACK,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Quality {
     Low,
     Medium,
     High,
 }
-#[derive(Serialize, Deserialize)]
+/// Format of the file in disc image mode
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    Flac,
+    Wav,
+    /// an audiobook, with a chapter per track
+    Opus,
+    /// an AAC audiobook, mp4mux can't write chapters so only the CUE sheet lists them
+    M4b,
+}
+
+impl ImageFormat {
+    /// File extension of the image, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Flac => "flac",
+            ImageFormat::Wav => "wav",
+            ImageFormat::Opus => "opus",
+            ImageFormat::M4b => "m4b",
+        }
+    }
+}
+
+/// The characters file names can't have, after the file system of the encode path
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NameRules {
+    /// FAT and NTFS get the Windows rules, the others the portable ones
+    Auto,
+    /// safe on all platforms and to copy between them
+    Portable,
+    /// FAT32, exFAT, NTFS and Windows shares: also no names ending in a dot or a space and no
+    /// device names like CON
+    Windows,
+    /// ext4, Btrfs and the other Linux file systems: only `/` is replaced
+    Unix,
+    /// APFS and HFS+: `/` and `:` are replaced
+    Mac,
+}
+
+/// How hard cdparanoiasrc works to correct read errors, other CD sources don't
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorCorrection {
+    Off,
+    /// verify overlapping reads, cdparanoia's default
+    Fast,
+    /// also repair scratches
+    Full,
+}
+
+/// What is done with the loudness measured by `rganalysis` after a track is read
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplayGain {
+    /// no analysis
+    Off,
+    /// write REPLAYGAIN_TRACK_GAIN and REPLAYGAIN_TRACK_PEAK tags
+    Tags,
+    /// also lower the volume of lossy files with peaks above -1 dBFS, for players that ignore
+    /// the tags
+    Limit,
+}
+
+/// The GStreamer element used to read audio from the CD
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CdBackend {
+    /// use the first available backend
+    Auto,
+    /// whatever element handles `cdda://` URIs
+    CddaUri,
+    /// `cdiocddasrc` from gst-plugins-ugly
+    Cdio,
+    /// `cdparanoiasrc` from gst-plugins-base
+    Cdparanoia,
+    /// the drive of another machine running `ripperx4 --serve-drive`, at `Config::remote_drive`
+    Remote,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
     pub encode_path: String,
+    /// path of the files below `encode_path`, see `naming::PLACEHOLDERS`
+    pub name_template: String,
+    /// used instead of `name_template` for discs with tracks by various artists
+    pub compilation_template: String,
     pub encoder: Encoder,
     pub quality: Quality,
     pub fake_cdrom: bool,
+    pub cd_backend: CdBackend,
+    pub error_correction: ErrorCorrection,
+    pub replaygain: ReplayGain,
+    /// CD drive to rip from, e.g. "/dev/sr1", the system's default drive when not set
+    pub device: Option<String>,
+    /// `host` or `host:port` of the drive served by `ripperx4 --serve-drive`, for the
+    /// `Remote` CD source
+    pub remote_drive: Option<String>,
+    /// shared secret of `--serve-drive`: the server requires it when set, clients send it
+    pub remote_token: Option<String>,
+    /// octal mode bits for created files, e.g. "0644"
+    pub file_mode: Option<String>,
+    /// octal mode bits for created directories, e.g. "0755"
+    pub dir_mode: Option<String>,
+    /// group name or gid to assign to created files and directories
+    pub group: Option<String>,
+    /// maintain "By Genre" and "By Year" symlink trees pointing at the ripped albums
+    pub symlink_views: bool,
+    /// write a `checksums.sha256` manifest of the encoded files in each album folder
+    pub write_checksums: bool,
+    /// name the files of discs in other scripts after the Latin titles from MusicBrainz
+    pub latin_file_names: bool,
+    /// the characters replaced in file names, `Auto` picks them after the file system
+    pub name_rules: NameRules,
+    /// ID3v2 version of MP3 and FLAC tags, 3 for players that don't read 4, which only have the year
+    /// 4 writes the text in UTF-8, 3 in UTF-16
+    pub id3v2_version: u32,
+    /// also write an ID3v1 tag at the end of MP3 files, needs id3mux from gst-plugins-bad
+    pub id3v1_tags: bool,
+    /// milliseconds between progress updates while encoding a track
+    pub progress_interval_ms: u64,
+    /// keep the part of the track that was being encoded when the rip was stopped
+    pub keep_aborted_tracks: bool,
+    /// closing the window during a rip hides it, a notification is sent when the rip is finished
+    pub background: bool,
+    /// MusicBrainz OAuth access token (scope `collection`), used to add ripped releases to a collection
+    pub musicbrainz_token: Option<String>,
+    /// id of the MusicBrainz release collection ripped discs are added to
+    pub musicbrainz_collection: Option<String>,
+    /// metadata sources, the first one in order that knows the disc is used: "musicbrainz",
+    /// "gnudb" and "cdtext", the texts on the disc itself
+    pub metadata_sources: Vec<String>,
+    /// languages (ISO 639-3, e.g. "eng") of the MusicBrainz releases picked first when several
+    /// match the disc
+    pub preferred_languages: Vec<String>,
+    /// release countries (e.g. "XE" for Europe, "GB") picked first, after the languages
+    pub preferred_countries: Vec<String>,
+    /// CDDB server queried by the "gnudb" source, through its HTTP interface
+    pub gnudb_url: String,
+    /// hours a gnudb entry is used from the cache before asking gnudb again
+    pub gnudb_cache_hours: u64,
+    /// append a JSON line per ripped disc to this file, as an inventory of the collection
+    pub inventory_file: Option<String>,
+    /// ListenBrainz user token, the ripped tracks are submitted as listens when set
+    pub listenbrainz_token: Option<String>,
+    /// ListenBrainz compatible service to submit to
+    pub listenbrainz_url: String,
+    /// warn after scanning when the disc seems to be in the library below this folder already
+    pub library_root: Option<String>,
+    /// eject the disc through udisks2 when the rip succeeded
+    pub eject_when_done: bool,
+    /// send a desktop notification with the outcome of each rip, not only in the background
+    pub notify_when_done: bool,
+    /// run after a successful rip with the album folder as last argument, e.g. a library rescan
+    pub post_rip_command: Option<String>,
+    /// Rhai script with functions called while scanning, looking up and ripping, see `automation`
+    pub automation_script: Option<String>,
+    /// title tracks "Work: Movement", tag work and movement and store the files per composer
+    pub classical: bool,
+    /// rip the selected tracks to one file with a CUE sheet instead of a file per track
+    pub disc_image: bool,
+    pub image_format: ImageFormat,
+    /// verify the tracks with AccurateRip and write a `rip.log` next to them
+    pub secure_rip: bool,
+    /// add how each track read, e.g. "heavy corrections, 120 sectors re-read", to its comment tag
+    pub condition_comment: bool,
+    /// write a JSON report of each rip, for archival tooling
+    pub json_report: bool,
+    /// folder for the JSON reports, `rip.json` in the album folder when not set
+    pub report_dir: Option<String>,
+    /// tracks encoded at the same time, while the next one is read from the disc
+    pub encoder_workers: usize,
+    /// times a track is encoded again from its raw audio when the encoder fails
+    pub encode_retries: u32,
+    /// format a track is encoded to when the retries failed, e.g. when a plugin is missing
+    pub fallback_encoder: Option<Encoder>,
+    /// points of the text of the track list, 0 for the size of the theme, e.g. 16 on a TV
+    pub track_list_font_size: u32,
+    /// less room around the rows of the track list, to see more tracks on a small screen
+    pub compact_track_list: bool,
+    /// large buttons and only scan, confirm and rip, for a touch screen
+    pub touch_mode: bool,
+    /// encode to this folder on a fast local disk (e.g. /tmp, in memory on most systems) and
+    /// move the files to a slow encode path in the background, e.g. a network share
+    pub spool_dir: Option<String>,
+    /// download the front cover to show, embed and save in the album folder
+    pub cover_art: bool,
+    /// cover art sources to try in order: "coverartarchive", "itunes" and "fanart.tv"
+    /// iTunes is left out by default, it's searched by name
+    pub cover_providers: Vec<String>,
+    /// covers smaller than this many pixels on either side are rejected
+    pub cover_min_size: u32,
+    /// covers with a longer side more than this many times the shorter one are rejected
+    pub cover_max_aspect: f64,
+    /// fanart.tv personal API key, the fanart.tv provider is skipped without one
+    pub fanart_api_key: Option<String>,
+    /// picked at the start of a rip instead of `encoder` and `quality`, see `profile::select`
+    pub profiles: Vec<Profile>,
+    /// libraries discs are ripped to instead of `encode_path`, by genre or picked for the disc
+    pub routes: Vec<Route>,
+    /// file extension per format name, replacing the default one, e.g. `ogg = "oga"`
+    pub extensions: BTreeMap<String, String>,
+    /// mail a summary after each rip through this server
+    pub smtp: Option<SmtpConfig>,
+}
+
+/// Format for a use case, e.g. lossless while the library has room
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub encoder: Encoder,
+    pub quality: Quality,
+    /// only used when the file system of the encode path has this many GB free
+    pub min_free_gb: Option<f64>,
+}
+
+/// A library of its own, e.g. audiobooks or music for the kids
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Route {
+    pub name: String,
+    /// used as the encode path
+    pub path: String,
+    /// discs of these genres go here, compared ignoring case
+    #[serde(default)]
+    pub genres: Vec<String>,
+}
+
+/// SMTP server used to mail rip summaries, the connection uses STARTTLS
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub server: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: String,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            server: "localhost".to_owned(),
+            port: 587,
+            username: None,
+            password: None,
+            from: "ripperx4@localhost".to_owned(),
+            to: "root@localhost".to_owned(),
+        }
+    }
+}
+
+/// confy application name, the config lives in `~/.config/ripperx4/`
+pub(crate) const APP_NAME: &str = "ripperx4";
+
+/// All settings are loaded and stored through here, as TOML files in the config directory
+impl Config {
+    /// The configuration shared by the whole process, see `ConfigManager`
+    pub fn load() -> anyhow::Result<Config> {
+        manager().load()
+    }
+
+    /// Replace the whole configuration, `ConfigManager::update` only changes what it sets
+    pub fn store(&self) -> anyhow::Result<()> {
+        manager().replace(self.clone())
+    }
+
+    /// Load a separate settings file from the config directory, e.g. `genres.toml`
+    pub fn load_file<T: Serialize + DeserializeOwned + Default>(
+        name: &str,
+    ) -> Result<T, ConfyError> {
+        confy::load(APP_NAME, Some(name))
+    }
+
+    /// Store a separate settings file in the config directory
+    pub fn store_file<T: Serialize>(name: &str, value: T) -> Result<(), ConfyError> {
+        confy::store(APP_NAME, Some(name), value)
+    }
+}
+
+/// The default encode path, `~/Music/` on Linux
+#[cfg(target_os = "linux")]
+fn music_folder() -> String {
+    let home = home::home_dir().expect("Failed to get home dir!");
+    format!("{}/Music/", home.display())
+}
+
+/// The default encode path, the platform's Music folder (e.g. the Windows known folder),
+/// falling back to `~/Music`
+#[cfg(not(target_os = "linux"))]
+fn music_folder() -> String {
+    glib::user_special_dir(glib::UserDirectory::Music)
+        .unwrap_or_else(|| {
+            home::home_dir()
+                .expect("Failed to get home dir!")
+                .join("Music")
+        })
+        .display()
+        .to_string()
 }
 
 impl Default for Config {
     fn default() -> Self {
-        let home = home::home_dir().expect("Failed to get home dir!");
-        let path = format!("{}/Music/", home.display());
         Config {
-            encode_path: path,
+            encode_path: music_folder(),
+            name_template: DEFAULT_TEMPLATE.to_owned(),
+            compilation_template: COMPILATION_TEMPLATE.to_owned(),
             encoder: Encoder::MP3,
             quality: Quality::Medium,
             fake_cdrom: false,
+            cd_backend: CdBackend::Auto,
+            error_correction: ErrorCorrection::Fast,
+            replaygain: ReplayGain::Off,
+            device: None,
+            remote_drive: None,
+            remote_token: None,
+            file_mode: None,
+            dir_mode: None,
+            group: None,
+            symlink_views: false,
+            write_checksums: false,
+            id3v2_version: 4,
+            id3v1_tags: false,
+            latin_file_names: false,
+            name_rules: NameRules::Auto,
+            progress_interval_ms: 1000,
+            keep_aborted_tracks: false,
+            background: false,
+            musicbrainz_token: None,
+            musicbrainz_collection: None,
+            metadata_sources: vec![
+                "musicbrainz".to_owned(),
+                "gnudb".to_owned(),
+                "cdtext".to_owned(),
+            ],
+            preferred_languages: Vec::new(),
+            preferred_countries: Vec::new(),
+            gnudb_url: "https://gnudb.gnudb.org/~cddb/cddb.cgi".to_owned(),
+            gnudb_cache_hours: 24,
+            inventory_file: None,
+            listenbrainz_token: None,
+            listenbrainz_url: "https://api.listenbrainz.org".to_owned(),
+            library_root: None,
+            eject_when_done: false,
+            notify_when_done: false,
+            post_rip_command: None,
+            automation_script: None,
+            classical: false,
+            disc_image: false,
+            image_format: ImageFormat::Flac,
+            secure_rip: false,
+            condition_comment: false,
+            json_report: false,
+            report_dir: None,
+            encoder_workers: 2,
+            encode_retries: 1,
+            fallback_encoder: None,
+            spool_dir: None,
+            track_list_font_size: 0,
+            compact_track_list: false,
+            touch_mode: false,
+            cover_art: true,
+            cover_providers: ["coverartarchive", "fanart.tv"]
+                .iter()
+                .map(|id| (*id).to_owned())
+                .collect(),
+            cover_min_size: 500,
+            cover_max_aspect: 1.2,
+            fanart_api_key: None,
+            profiles: Vec::new(),
+            routes: Vec::new(),
+            extensions: BTreeMap::new(),
+            smtp: None,
         }
     }
 }
@@ -0,0 +1,122 @@
+use crate::data::Status;
+use anyhow::{anyhow, Result};
+use gtk::{
+    gio::{DBusConnection, DBusNodeInfo},
+    glib::Variant,
+    prelude::*,
+};
+use log::debug;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+const OBJECT_PATH: &str = "/be/sourcery/ripperx4/Status";
+const INTERFACE_NAME: &str = "be.sourcery.ripperx4.Status";
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="be.sourcery.ripperx4.Status">
+    <property name="Ripping" type="b" access="read"/>
+    <property name="Album" type="s" access="read"/>
+    <property name="Track" type="s" access="read"/>
+    <property name="TrackNumber" type="u" access="read"/>
+    <property name="Percent" type="d" access="read"/>
+  </interface>
+</node>"#;
+
+#[derive(Default)]
+struct State {
+    ripping: bool,
+    album: String,
+    track: String,
+    number: u32,
+    percent: f64,
+}
+
+impl State {
+    fn properties(&self) -> HashMap<String, Variant> {
+        HashMap::from([
+            ("Ripping".to_owned(), self.ripping.to_variant()),
+            ("Album".to_owned(), self.album.to_variant()),
+            ("Track".to_owned(), self.track.to_variant()),
+            ("TrackNumber".to_owned(), self.number.to_variant()),
+            ("Percent".to_owned(), self.percent.to_variant()),
+        ])
+    }
+}
+
+/// Publishes the rip status on the session bus as `be.sourcery.ripperx4.Status`
+/// Status bars and shell extensions can read the properties or listen to `PropertiesChanged`
+#[derive(Clone)]
+pub struct StatusExporter {
+    connection: DBusConnection,
+    state: Arc<RwLock<State>>,
+}
+
+impl StatusExporter {
+    /// Register the status object on the connection of the application
+    pub fn register(connection: DBusConnection) -> Result<Self> {
+        let node = DBusNodeInfo::for_xml(INTERFACE_XML)?;
+        let interface = node
+            .lookup_interface(INTERFACE_NAME)
+            .ok_or(anyhow!("failed to get interface"))?;
+        let state = Arc::new(RwLock::new(State::default()));
+        let state_clone = state.clone();
+        connection.register_object(
+            OBJECT_PATH,
+            &interface,
+            |_, _, _, _, method, _, invocation| {
+                invocation.return_dbus_error(
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    &format!("unknown method {method}"),
+                );
+            },
+            move |_, _, _, _, property| {
+                state_clone
+                    .read()
+                    .expect("failed to get state")
+                    .properties()
+                    .remove(property)
+                    .unwrap_or_else(|| false.to_variant())
+            },
+            |_, _, _, _, _, _| false,
+        )?;
+        Ok(Self { connection, state })
+    }
+
+    /// Publish a status update for the album being ripped
+    pub fn update(&self, album: &str, status: &Status) {
+        let changed = {
+            let mut state = self.state.write().expect("failed to get state");
+            match status {
+                Status::Progress {
+                    number,
+                    title,
+                    percent,
+                    ..
+                } => {
+                    state.ripping = true;
+                    state.album = album.to_owned();
+                    state.track.clone_from(title);
+                    state.number = *number;
+                    state.percent = *percent;
+                }
+                Status::Recovering { .. }
+                | Status::Encoded(_)
+                | Status::Paused(_)
+                | Status::Resumed => return,
+                Status::Done(_) | Status::Aborted => *state = State::default(),
+            }
+            (INTERFACE_NAME, state.properties(), Vec::<String>::new()).to_variant()
+        };
+        if let Err(e) = self.connection.emit_signal(
+            None,
+            OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            Some(&changed),
+        ) {
+            debug!("failed to publish status: {e}");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use gstreamer::ClockTime;
+
+/// CD audio has 75 sectors a second
+pub const SECTORS_PER_SECOND: u64 = 75;
+/// Samples (16 bit stereo, 4 bytes) in a CD sector
+pub const SAMPLES_PER_SECTOR: u64 = 588;
+/// Samples a second, 44.1 kHz
+pub const SAMPLE_RATE: u64 = SECTORS_PER_SECOND * SAMPLES_PER_SECTOR;
+/// The end of the longest disc, a TOC addresses up to 99:59:74, which 90 and 99 minute and
+/// overburned CD-Rs get close to
+pub const MAX_SECTORS: u64 = 100 * 60 * SECTORS_PER_SECOND - 1;
+
+/// The length of a track as the TOC has it, `None` for a length no disc can have, e.g. of a
+/// corrupt TOC
+pub fn toc_sectors(sectors: i32) -> Option<u64> {
+    u64::try_from(sectors)
+        .ok()
+        .filter(|sectors| *sectors <= MAX_SECTORS)
+}
+
+pub fn sectors_to_samples(sectors: u64) -> u64 {
+    sectors.saturating_mul(SAMPLES_PER_SECTOR)
+}
+
+/// Whole seconds, as `Track::duration` has them
+pub fn sectors_to_seconds(sectors: u64) -> u64 {
+    sectors / SECTORS_PER_SECOND
+}
+
+pub fn sectors_to_time(sectors: u64) -> ClockTime {
+    samples_to_time(sectors_to_samples(sectors))
+}
+
+/// The time of a sample, i.e. the timestamp of a buffer starting with it, rounded down
+/// Saturates instead of wrapping around for lengths no disc has
+pub fn samples_to_time(samples: u64) -> ClockTime {
+    let nanos =
+        u128::from(samples) * u128::from(ClockTime::SECOND.nseconds()) / u128::from(SAMPLE_RATE);
+    ClockTime::from_nseconds(u64::try_from(nanos).map_or(ClockTime::MAX.nseconds(), |n| {
+        n.min(ClockTime::MAX.nseconds())
+    }))
+}
+
+/// Minutes, seconds and frames (sectors) as in a CUE sheet or TOC
+pub fn sectors_to_msf(sectors: u64) -> (u64, u64, u64) {
+    let seconds = sectors_to_seconds(sectors);
+    (seconds / 60, seconds % 60, sectors % SECTORS_PER_SECOND)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        samples_to_time, sectors_to_msf, sectors_to_samples, sectors_to_seconds, sectors_to_time,
+        toc_sectors, MAX_SECTORS,
+    };
+    use gstreamer::ClockTime;
+
+    #[test]
+    pub fn test_conversions() {
+        assert_eq!(44_100, sectors_to_samples(75));
+        assert_eq!(346, sectors_to_seconds(26_000));
+        assert_eq!(ClockTime::from_seconds(2), sectors_to_time(150));
+        assert_eq!(13_333_333, sectors_to_time(1).nseconds());
+        assert_eq!(ClockTime::from_nseconds(22_675), samples_to_time(1));
+        // the longest CD, 80 minutes, doesn't overflow
+        assert_eq!(ClockTime::from_seconds(4800), sectors_to_time(360_000));
+        assert_eq!((5, 46, 50), sectors_to_msf(26_000));
+        // a 99 minute CD-R
+        assert_eq!((99, 0, 0), sectors_to_msf(99 * 60 * 75));
+        assert_eq!((99, 59, 74), sectors_to_msf(MAX_SECTORS));
+    }
+
+    #[test]
+    pub fn test_corrupt_lengths() {
+        assert_eq!(Some(445_500), toc_sectors(445_500));
+        assert_eq!(Some(MAX_SECTORS), toc_sectors(449_999));
+        assert_eq!(None, toc_sectors(450_000));
+        assert_eq!(None, toc_sectors(-150));
+        assert_eq!(None, toc_sectors(i32::MAX));
+        // saturate instead of wrapping around
+        assert_eq!(u64::MAX, sectors_to_samples(u64::MAX));
+        assert_eq!(ClockTime::MAX, sectors_to_time(u64::MAX));
+        assert!(sectors_to_time(MAX_SECTORS) < ClockTime::from_seconds(100 * 60));
+    }
+}
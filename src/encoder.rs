@@ -0,0 +1,524 @@
+use crate::data::{Config, Encoder, Quality};
+use anyhow::Result;
+use gstreamer::{prelude::*, Element, ElementFactory};
+use log::warn;
+
+/// Where the tags of a track are written
+pub enum TagStrategy {
+    /// an `id3v2mux` is linked after the encoder elements
+    Id3v2Mux,
+    /// the first element of the chain implementing `TagSetter` writes them
+    TagSetter,
+    /// an `apev2mux` is linked after the encoder elements
+    Apev2Mux,
+}
+
+impl TagStrategy {
+    /// The tag muxer linked after the encoder elements, if any
+    pub fn muxer(&self) -> Option<&'static str> {
+        match self {
+            TagStrategy::Id3v2Mux => Some("id3v2mux"),
+            TagStrategy::TagSetter => None,
+            TagStrategy::Apev2Mux => Some("apev2mux"),
+        }
+    }
+}
+
+/// What the configuration dialog shows for a backend
+pub struct Settings {
+    /// name in the format drop down
+    pub name: &'static str,
+    /// description of the low, medium and high quality
+    pub qualities: [&'static str; 3],
+}
+
+/// An output format
+/// To add a format, implement this trait and add it to `BACKENDS`
+pub trait EncoderBackend: Sync {
+    /// The config value selecting this backend
+    fn encoder(&self) -> Encoder;
+
+    fn settings(&self) -> Settings;
+
+    /// Default file extension, including the dot
+    fn extension(&self) -> &'static str;
+
+    /// The elements between the CD source and the tagger/sink, in link order
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>>;
+
+    fn tag_strategy(&self) -> TagStrategy;
+
+    /// Whether the tags keep the precision of the release date (a `DateTime` tag), the others
+    /// get a `Date` tag
+    fn full_date(&self) -> bool {
+        true
+    }
+
+    /// Whether the format drops part of the audio, the peaks of those are limited with
+    /// `ReplayGain::Limit`
+    fn lossy(&self) -> bool {
+        true
+    }
+
+    /// The GStreamer elements needed to encode and the plugin package providing them
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)>;
+
+    /// Approximate average bitrate in kbit/s
+    fn estimated_bitrate(&self, quality: &Quality) -> u64;
+}
+
+struct Mp3;
+struct Vorbis;
+struct Flac;
+struct Opus;
+struct Aac;
+struct Alac;
+struct WavPack;
+
+static BACKENDS: &[&dyn EncoderBackend] = &[&Mp3, &Vorbis, &Flac, &Opus, &Aac, &Alac, &WavPack];
+
+/// All registered backends, in the order they are shown to the user
+pub fn backends() -> &'static [&'static dyn EncoderBackend] {
+    BACKENDS
+}
+
+/// The backend for the configured `Encoder`
+pub fn backend(encoder: &Encoder) -> &'static dyn EncoderBackend {
+    *BACKENDS
+        .iter()
+        .find(|b| b.encoder() == *encoder)
+        .expect("every encoder has a backend")
+}
+
+/// File extension for the format, including the dot
+/// An entry for the format name in `Config::extensions` overrides the default, e.g. `ogg = "oga"`
+pub fn file_extension(encoder: &Encoder, config: &Config) -> String {
+    let backend = backend(encoder);
+    match config.extensions.get(backend.settings().name) {
+        Some(extension) => format!(".{}", extension.trim_start_matches('.')),
+        None => backend.extension().to_owned(),
+    }
+}
+
+impl EncoderBackend for Mp3 {
+    fn encoder(&self) -> Encoder {
+        Encoder::MP3
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "mp3",
+            qualities: ["low (V9)", "medium (V5)", "high (V0)"],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".mp3"
+    }
+
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>> {
+        let enc = ElementFactory::make("lamemp3enc").build()?;
+        let quality = match quality {
+            Quality::Low => 9_f32,
+            Quality::Medium => 5_f32,
+            Quality::High => 0_f32,
+        };
+        enc.set_property("quality", quality);
+        let mut elements = vec![enc];
+        // the Xing frame holds the frame count, which players need for the exact duration and
+        // to play consecutive tracks without a gap, xingmux comes with gst-plugins-ugly
+        match ElementFactory::make("xingmux").build() {
+            Ok(xing) => elements.push(xing),
+            Err(_) => warn!("xingmux is not installed, MP3 files get no Xing frame"),
+        }
+        Ok(elements)
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::Id3v2Mux
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("lamemp3enc", "gst-plugins-good"),
+            ("id3v2mux", "gst-plugins-good"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, quality: &Quality) -> u64 {
+        match quality {
+            Quality::Low => 65,
+            Quality::Medium => 130,
+            Quality::High => 245,
+        }
+    }
+}
+
+impl EncoderBackend for Vorbis {
+    fn encoder(&self) -> Encoder {
+        Encoder::OGG
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "ogg",
+            qualities: ["low (q2)", "medium (q5)", "high (q9)"],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".ogg"
+    }
+
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>> {
+        let convert = ElementFactory::make("audioconvert").build()?;
+        let vorbis = ElementFactory::make("vorbisenc").build()?;
+        let quality = match quality {
+            Quality::Low => 0.2_f32,
+            Quality::Medium => 0.5_f32,
+            Quality::High => 0.9_f32,
+        };
+        vorbis.set_property("quality", quality);
+        let mux = ElementFactory::make("oggmux").build()?;
+        Ok(vec![convert, vorbis, mux])
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::TagSetter
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("audioconvert", "gst-plugins-base"),
+            ("vorbisenc", "gst-plugins-base"),
+            ("oggmux", "gst-plugins-base"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, quality: &Quality) -> u64 {
+        match quality {
+            Quality::Low => 96,
+            Quality::Medium => 160,
+            Quality::High => 320,
+        }
+    }
+}
+
+impl EncoderBackend for Flac {
+    fn encoder(&self) -> Encoder {
+        Encoder::FLAC
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "flac",
+            qualities: ["low (level 2)", "medium (level 5)", "high (level 8)"],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".flac"
+    }
+
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>> {
+        let enc = ElementFactory::make("flacenc").build()?;
+        let quality = match quality {
+            Quality::Low => "2",
+            Quality::Medium => "5",
+            Quality::High => "8",
+        };
+        enc.set_property_from_str("quality", quality);
+        Ok(vec![enc])
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::Id3v2Mux
+    }
+
+    fn lossy(&self) -> bool {
+        false
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("flacenc", "gst-plugins-good"),
+            ("id3v2mux", "gst-plugins-good"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, _quality: &Quality) -> u64 {
+        // lossless, the compression level hardly matters for the size
+        850
+    }
+}
+
+impl EncoderBackend for Opus {
+    fn encoder(&self) -> Encoder {
+        Encoder::OPUS
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "opus",
+            qualities: [
+                "low (64 kbit/s)",
+                "medium (128 kbit/s)",
+                "high (256 kbit/s)",
+            ],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".opus"
+    }
+
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>> {
+        let convert = ElementFactory::make("audioconvert").build()?;
+        let resample = ElementFactory::make("audioresample").build()?;
+        let opus = ElementFactory::make("opusenc").build()?;
+        let bitrate = match quality {
+            Quality::Low => 64_000_i32,
+            Quality::Medium => 128_000_i32,
+            Quality::High => 256_000_i32,
+        };
+        opus.set_property("bitrate", bitrate);
+        let mux = ElementFactory::make("oggmux").build()?;
+        Ok(vec![convert, resample, opus, mux])
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::TagSetter
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("audioconvert", "gst-plugins-base"),
+            ("audioresample", "gst-plugins-base"),
+            ("opusenc", "gst-plugins-base"),
+            ("oggmux", "gst-plugins-base"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, quality: &Quality) -> u64 {
+        match quality {
+            Quality::Low => 64,
+            Quality::Medium => 128,
+            Quality::High => 256,
+        }
+    }
+}
+
+/// The AAC encoder to use: `faac` when it's installed, the FFmpeg encoder otherwise
+fn aac_encoder() -> (&'static str, &'static str) {
+    if ElementFactory::find("faac").is_some() {
+        ("faac", "gst-plugins-bad")
+    } else {
+        ("avenc_aac", "gst-libav")
+    }
+}
+
+impl EncoderBackend for Aac {
+    fn encoder(&self) -> Encoder {
+        Encoder::AAC
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "aac",
+            qualities: [
+                "low (96 kbit/s)",
+                "medium (160 kbit/s)",
+                "high (256 kbit/s)",
+            ],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".m4a"
+    }
+
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>> {
+        let convert = ElementFactory::make("audioconvert").build()?;
+        let aac = ElementFactory::make(aac_encoder().0).build()?;
+        let bitrate = match quality {
+            Quality::Low => "96000",
+            Quality::Medium => "160000",
+            Quality::High => "256000",
+        };
+        // an int for faac, an int64 for avenc_aac
+        aac.set_property_from_str("bitrate", bitrate);
+        // writes the tags, including the cover, in the iTunes metadata atoms
+        let mux = ElementFactory::make("mp4mux").build()?;
+        Ok(vec![convert, aac, mux])
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::TagSetter
+    }
+
+    // mp4mux only writes the date from a `Date` tag
+    fn full_date(&self) -> bool {
+        false
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("audioconvert", "gst-plugins-base"),
+            aac_encoder(),
+            ("mp4mux", "gst-plugins-good"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, quality: &Quality) -> u64 {
+        match quality {
+            Quality::Low => 96,
+            Quality::Medium => 160,
+            Quality::High => 256,
+        }
+    }
+}
+
+impl EncoderBackend for Alac {
+    fn encoder(&self) -> Encoder {
+        Encoder::ALAC
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "alac",
+            qualities: ["lossless", "lossless", "lossless"],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".m4a"
+    }
+
+    fn elements(&self, _quality: &Quality) -> Result<Vec<Element>> {
+        let convert = ElementFactory::make("audioconvert").build()?;
+        let alac = ElementFactory::make("avenc_alac").build()?;
+        let mux = ElementFactory::make("mp4mux").build()?;
+        Ok(vec![convert, alac, mux])
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::TagSetter
+    }
+
+    fn full_date(&self) -> bool {
+        false
+    }
+
+    fn lossy(&self) -> bool {
+        false
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("audioconvert", "gst-plugins-base"),
+            ("avenc_alac", "gst-libav"),
+            ("mp4mux", "gst-plugins-good"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, _quality: &Quality) -> u64 {
+        // a little bigger than FLAC
+        900
+    }
+}
+
+impl EncoderBackend for WavPack {
+    fn encoder(&self) -> Encoder {
+        Encoder::WAVPACK
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            name: "wavpack",
+            qualities: ["low (fast)", "medium (normal)", "high (high)"],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        ".wv"
+    }
+
+    fn elements(&self, quality: &Quality) -> Result<Vec<Element>> {
+        let enc = ElementFactory::make("wavpackenc").build()?;
+        let mode = match quality {
+            Quality::Low => "fast",
+            Quality::Medium => "normal",
+            Quality::High => "high",
+        };
+        enc.set_property_from_str("mode", mode);
+        Ok(vec![enc])
+    }
+
+    fn tag_strategy(&self) -> TagStrategy {
+        TagStrategy::Apev2Mux
+    }
+
+    // APEv2 has a Year item only
+    fn full_date(&self) -> bool {
+        false
+    }
+
+    fn lossy(&self) -> bool {
+        false
+    }
+
+    fn required_elements(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("wavpackenc", "gst-plugins-good"),
+            ("apev2mux", "gst-plugins-good"),
+        ]
+    }
+
+    fn estimated_bitrate(&self, _quality: &Quality) -> u64 {
+        850
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backend, backends, file_extension};
+    use crate::data::{Config, Encoder};
+
+    #[test]
+    pub fn test_every_encoder_has_one_backend() {
+        for encoder in [
+            Encoder::MP3,
+            Encoder::OGG,
+            Encoder::FLAC,
+            Encoder::OPUS,
+            Encoder::AAC,
+            Encoder::ALAC,
+            Encoder::WAVPACK,
+        ] {
+            assert_eq!(encoder, backend(&encoder).encoder());
+            assert_eq!(
+                1,
+                backends().iter().filter(|b| b.encoder() == encoder).count()
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_file_extension() {
+        let mut config = Config {
+            encoder: Encoder::OPUS,
+            ..Default::default()
+        };
+        assert_eq!(".opus", file_extension(&config.encoder, &config));
+        config.encoder = Encoder::OGG;
+        config.extensions.insert("ogg".to_owned(), "oga".to_owned());
+        config
+            .extensions
+            .insert("mp3".to_owned(), ".MP3".to_owned());
+        assert_eq!(".oga", file_extension(&config.encoder, &config));
+        config.encoder = Encoder::MP3;
+        assert_eq!(".MP3", file_extension(&config.encoder, &config));
+        assert_eq!(".m4a", file_extension(&Encoder::ALAC, &config));
+        assert_eq!(".wv", file_extension(&Encoder::WAVPACK, &config));
+    }
+}
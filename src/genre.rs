@@ -0,0 +1,57 @@
+use crate::data::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// User editable mapping applied to genres coming from metadata lookups
+/// Stored as `genres.toml` in the config directory, an empty value removes the genre
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct GenreMap {
+    pub genres: BTreeMap<String, String>,
+}
+
+impl Default for GenreMap {
+    fn default() -> Self {
+        // freedb categories that are not genres
+        let genres = [("misc", ""), ("data", "")]
+            .iter()
+            .map(|(from, to)| ((*from).to_owned(), (*to).to_owned()))
+            .collect();
+        GenreMap { genres }
+    }
+}
+
+impl GenreMap {
+    /// Load the mapping, writing the default one the first time so there is a file to edit
+    pub fn load() -> Self {
+        Config::load_file("genres").unwrap_or_default()
+    }
+
+    /// Map a genre, the keys are matched case insensitively
+    /// Returns `None` when the genre is blank or mapped to blank
+    pub fn normalize(&self, genre: &str) -> Option<String> {
+        let genre = genre.trim();
+        let mapped = self
+            .genres
+            .iter()
+            .find(|(from, _)| from.trim().eq_ignore_ascii_case(genre))
+            .map_or(genre, |(_, to)| to.trim());
+        (!mapped.is_empty()).then(|| mapped.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GenreMap;
+
+    #[test]
+    pub fn test_normalize() {
+        let mut map = GenreMap::default();
+        map.genres
+            .insert("blues/rock".to_owned(), "Blues Rock".to_owned());
+        assert_eq!(Some("Blues Rock".to_owned()), map.normalize("Blues/Rock "));
+        assert_eq!(None, map.normalize("misc"));
+        assert_eq!(None, map.normalize("  "));
+        assert_eq!(Some("Jazz".to_owned()), map.normalize("Jazz"));
+    }
+}
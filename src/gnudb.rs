@@ -0,0 +1,438 @@
+use crate::{
+    cache::{GnudbEntry, MetadataCache},
+    data::{Config, Disc},
+    duration::sectors_to_seconds,
+};
+use anyhow::{anyhow, Result};
+use discid::DiscId;
+use log::{debug, error};
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Entries are a few KB, stop reading runaway responses long before this
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+/// Close matches read to find one with the tracks of the disc, the best ones come first
+const MAX_CLOSE_MATCHES: usize = 5;
+/// Difference between the length of a track in an entry and on the disc that is still the same
+/// track, in frames (5 seconds)
+const LENGTH_TOLERANCE: i32 = 5 * 75;
+
+/// A disc in the CDDB database matching the TOC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// freedb category, e.g. "rock" or "misc"
+    pub category: String,
+    pub id: String,
+    pub title: String,
+    /// the id matched, not only the length of the disc
+    pub exact: bool,
+}
+
+/// The body of a response, with its ETag if the server sent one
+struct Response {
+    body: String,
+    etag: Option<String>,
+}
+
+/// Look up the disc on gnudb (or another CDDB server) through its HTTP interface
+/// Uses protocol level 6, so the entries are UTF-8; the first match with the track lengths of
+/// the disc is returned
+/// Entries are kept in the metadata cache for `Config::gnudb_cache_hours`, an outdated one is
+/// used when the server can't be reached
+/// They are cached per MusicBrainz disc id, discs with another TOC often share a freedb id
+pub fn lookup(discid: &DiscId, config: &Config) -> Result<Disc> {
+    let id = discid.id();
+    let tracks = u32::try_from(discid.tracks().count())?;
+    let cached = MetadataCache::load().gnudb.remove(&id);
+    let entry = match cached {
+        Some(entry) if fresh(&entry, now(), config.gnudb_cache_hours) => {
+            debug!("gnudb entry of {id} from the cache");
+            entry
+        }
+        cached => match fetch(discid, &config.gnudb_url, cached.clone()) {
+            Ok(entry) => {
+                // error responses are not cached
+                let disc = parse_entry(&entry.body, &entry.category, tracks)?;
+                if let Err(e) = MetadataCache::update(|cache| cache.gnudb.insert(id, entry)) {
+                    error!("Failed to store the gnudb entry: {e}");
+                }
+                return Ok(disc);
+            }
+            Err(e) => cached.ok_or(e)?,
+        },
+    };
+    parse_entry(&entry.body, &entry.category, tracks)
+}
+
+/// Whether the entry was fetched less than `hours` ago
+fn fresh(entry: &GnudbEntry, now: u64, hours: u64) -> bool {
+    now.saturating_sub(entry.fetched_at) < hours * 3600
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Download the entry of the disc, an outdated entry with an ETag is only downloaded again when
+/// it changed on the server
+fn fetch(discid: &DiscId, url: &str, cached: Option<GnudbEntry>) -> Result<GnudbEntry> {
+    if let Some(mut cached) = cached.filter(|entry| entry.etag.is_some()) {
+        match read(url, &cached.category, &cached.id, cached.etag.as_deref()) {
+            Ok(Some(entry)) => return Ok(entry),
+            Ok(None) => {
+                debug!("gnudb entry {} did not change", cached.id);
+                cached.fetched_at = now();
+                return Ok(cached);
+            }
+            Err(e) => debug!("failed to revalidate gnudb entry {}: {e}", cached.id),
+        }
+    }
+    let offsets: Vec<i32> = discid.tracks().map(|t| t.offset).collect();
+    let query = query_command(&discid.freedb_id(), &offsets, discid.sectors());
+    let response = command(url, &query, None)?.ok_or(anyhow!("unexpected gnudb response"))?;
+    let matches = parse_query(&response.body)?;
+    for found in matches.iter().take(MAX_CLOSE_MATCHES) {
+        let entry = read(url, &found.category, &found.id, None)?
+            .ok_or(anyhow!("unexpected gnudb response"))?;
+        if fits_toc(&entry.body, &offsets, discid.sectors(), found.exact) {
+            debug!("gnudb match {found:?}");
+            return Ok(entry);
+        }
+        debug!("gnudb match {found:?} has other tracks than the disc");
+    }
+    Err(anyhow!("no gnudb match has the tracks of the disc"))
+}
+
+/// Whether the entry is of a disc with the TOC: as many tracks, each about as long
+/// Entries without the offsets of their tracks only fit when the id matched
+fn fits_toc(body: &str, offsets: &[i32], leadout: i32, exact: bool) -> bool {
+    let Some((entry_offsets, seconds)) = entry_toc(body) else {
+        return exact;
+    };
+    entry_offsets.len() == offsets.len()
+        && track_lengths(&entry_offsets, seconds * 75)
+            .zip(track_lengths(offsets, leadout))
+            .all(|(entry, disc)| (entry - disc).abs() <= LENGTH_TOLERANCE)
+}
+
+/// The length of each track in frames, up to the next track or the lead-out
+fn track_lengths(offsets: &[i32], leadout: i32) -> impl Iterator<Item = i32> + '_ {
+    let ends = offsets
+        .iter()
+        .skip(1)
+        .copied()
+        .chain(std::iter::once(leadout));
+    offsets.iter().zip(ends).map(|(start, end)| end - start)
+}
+
+/// The track offsets and the length in seconds of the disc in the comments of an xmcd entry
+fn entry_toc(body: &str) -> Option<(Vec<i32>, i32)> {
+    let mut offsets = Vec::new();
+    let mut seconds = None;
+    let mut in_offsets = false;
+    for line in body.lines() {
+        let Some(comment) = line.strip_prefix('#').map(str::trim) else {
+            continue;
+        };
+        if comment.starts_with("Track frame offsets") {
+            in_offsets = true;
+        } else if let (true, Ok(offset)) = (in_offsets, comment.parse()) {
+            offsets.push(offset);
+        } else {
+            in_offsets = false;
+            if let Some(length) = comment.strip_prefix("Disc length:") {
+                seconds = length
+                    .split_whitespace()
+                    .next()
+                    .and_then(|s| s.parse().ok());
+            }
+        }
+    }
+    Some((offsets, seconds?)).filter(|(offsets, _)| !offsets.is_empty())
+}
+
+/// Read the entry of a match, `None` when it still has the `etag`
+fn read(url: &str, category: &str, id: &str, etag: Option<&str>) -> Result<Option<GnudbEntry>> {
+    let read = format!("cddb read {category} {id}");
+    Ok(command(url, &read, etag)?.map(|response| GnudbEntry {
+        category: category.to_owned(),
+        id: id.to_owned(),
+        body: response.body,
+        etag: response.etag,
+        fetched_at: now(),
+    }))
+}
+
+/// The `cddb query` command for the TOC: the track offsets in frames (including the lead-in) and
+/// the length of the disc in seconds
+fn query_command(freedb_id: &str, offsets: &[i32], leadout: i32) -> String {
+    let offsets: Vec<String> = offsets.iter().map(i32::to_string).collect();
+    format!(
+        "cddb query {freedb_id} {} {} {}",
+        offsets.len(),
+        offsets.join(" "),
+        sectors_to_seconds(u64::try_from(leadout).unwrap_or_default())
+    )
+}
+
+/// Send a CDDB command, `None` when the server answers that the response with `etag` is current
+fn command(url: &str, command: &str, etag: Option<&str>) -> Result<Option<Response>> {
+    let hello = format!("anonymous localhost ripperx4 {}", env!("CARGO_PKG_VERSION"));
+    // the HTTP interface works through proxies, unlike CDDBP on port 8880
+    let agent = ureq::AgentBuilder::new().try_proxy_from_env(true).build();
+    let mut request = agent
+        .get(url)
+        .query("cmd", command)
+        .query("hello", &hello)
+        .query("proto", "6");
+    if let Some(etag) = etag {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = request.call()?;
+    if response.status() == 304 {
+        return Ok(None);
+    }
+    let etag = response.header("ETag").map(str::to_owned);
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_RESPONSE_SIZE)
+        .read_to_end(&mut body)?;
+    Ok(Some(Response {
+        body: decode(body),
+        etag,
+    }))
+}
+
+/// UTF-8, or Latin-1 for servers that ignore the protocol level
+fn decode(body: Vec<u8>) -> String {
+    String::from_utf8(body)
+        .unwrap_or_else(|e| e.into_bytes().iter().map(|&b| char::from(b)).collect())
+}
+
+/// The status code and the rest of the first line of a response, and the lines after it up to
+/// the terminating "."
+fn split_response(body: &str) -> Result<(u32, &str, Vec<&str>)> {
+    let mut lines = body.lines().map(|line| line.trim_end_matches('\r'));
+    let status = lines.next().ok_or(anyhow!("empty response from gnudb"))?;
+    let (code, text) = status.split_once(' ').unwrap_or((status, ""));
+    let code = code
+        .parse()
+        .map_err(|_| anyhow!("unexpected response from gnudb: {status}"))?;
+    let data = lines.take_while(|line| *line != ".").collect();
+    Ok((code, text, data))
+}
+
+/// Parse the response to `cddb query`, a single match or a list of exact or inexact ones
+fn parse_query(body: &str) -> Result<Vec<Match>> {
+    let (code, text, data) = split_response(body)?;
+    // 211 lists close matches, of discs with about the same length
+    let exact = code != 211;
+    let parse_match = |line: &str| {
+        let mut fields = line.splitn(3, ' ');
+        Some(Match {
+            category: fields.next()?.to_owned(),
+            id: fields.next()?.to_owned(),
+            title: fields.next().unwrap_or_default().to_owned(),
+            exact,
+        })
+    };
+    let matches: Vec<Match> = match code {
+        200 => parse_match(text).into_iter().collect(),
+        210 | 211 => data.into_iter().filter_map(parse_match).collect(),
+        202 => return Err(anyhow!("disc not found on gnudb")),
+        _ => return Err(anyhow!("gnudb query failed: {code} {text}")),
+    };
+    if matches.is_empty() {
+        return Err(anyhow!("malformed gnudb query response"));
+    }
+    Ok(matches)
+}
+
+/// Parse the xmcd entry returned by `cddb read` into a `Disc` with `tracks` tracks
+/// `category` is the genre when the entry has no `DGENRE`
+fn parse_entry(body: &str, category: &str, tracks: u32) -> Result<Disc> {
+    let (code, text, data) = split_response(body)?;
+    if code != 210 {
+        return Err(anyhow!("gnudb read failed: {code} {text}"));
+    }
+    // long values are continued on lines with the same key
+    let mut values: BTreeMap<&str, String> = BTreeMap::new();
+    for line in data.into_iter().filter(|line| !line.starts_with('#')) {
+        if let Some((key, value)) = line.split_once('=') {
+            values.entry(key.trim()).or_default().push_str(value);
+        }
+    }
+    let value = |key: &str| {
+        values
+            .get(key)
+            .map(|value| unescape(value.trim()))
+            .filter(|value| !value.is_empty())
+    };
+    let title = value("DTITLE").ok_or(anyhow!("gnudb entry without DTITLE"))?;
+    let mut disc = Disc::with_tracks(tracks);
+    let (artist, album) = split_artist(&title);
+    disc.artist = artist.unwrap_or(album).to_owned();
+    disc.title = album.to_owned();
+    disc.genre = value("DGENRE").or_else(|| Some(category.to_owned()));
+    disc.year = value("DYEAR")
+        .or_else(|| value("EXTD").and_then(|extd| extended_year(&extd)))
+        .and_then(|year| year.parse().ok());
+    for (index, track) in disc.tracks.iter_mut().enumerate() {
+        let Some(title) = value(&format!("TTITLE{index}")) else {
+            continue;
+        };
+        // compilations have "artist / title" track titles
+        let (artist, title) = split_artist(&title);
+        track.artist = artist.unwrap_or(&disc.artist).to_owned();
+        track.title = title.to_owned();
+    }
+    Ok(disc)
+}
+
+/// Split "artist / title", there is no artist when there is no separator
+fn split_artist(value: &str) -> (Option<&str>, &str) {
+    match value.split_once(" / ") {
+        Some((artist, title)) => (Some(artist.trim()), title.trim()),
+        None => (None, value),
+    }
+}
+
+/// The year some taggers put in the extended data before DYEAR existed, e.g. "YEAR: 1988"
+fn extended_year(extd: &str) -> Option<String> {
+    let (_, rest) = extd.split_once("YEAR:")?;
+    rest.split_whitespace().next().map(str::to_owned)
+}
+
+/// Undo the escapes of xmcd values
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, fits_toc, fresh, parse_entry, parse_query, query_command, Match};
+    use crate::cache::GnudbEntry;
+    use anyhow::Result;
+
+    #[test]
+    pub fn test_parse_query() -> Result<()> {
+        assert_eq!(
+            "cddb query 1a00b603 3 150 1150 2150 42",
+            query_command("1a00b603", &[150, 1150, 2150], 3150)
+        );
+        let single = parse_query("200 rock 940aac0d Dire Straits / Money for Nothing\r\n")?;
+        assert_eq!(
+            vec![Match {
+                category: "rock".to_owned(),
+                id: "940aac0d".to_owned(),
+                title: "Dire Straits / Money for Nothing".to_owned(),
+                exact: true,
+            }],
+            single
+        );
+        let several = parse_query(
+            "211 close matches found\r\nrock 940aac0d Dire Straits / Money\r\nmisc 940aac0e Other\r\n.\r\n",
+        )?;
+        assert_eq!(2, several.len());
+        assert_eq!("misc", several[1].category);
+        assert!(!several[0].exact);
+        assert!(parse_query("202 No match found\r\n").is_err());
+        assert!(parse_query("500 Command syntax error\r\n").is_err());
+        assert!(parse_query("").is_err());
+        assert!(parse_query("garbage").is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_parse_entry() -> Result<()> {
+        let body = "210 misc 940aac0d CD database entry follows (until terminating `.')\r\n\
+            # xmcd\r\n\
+            DISCID=940aac0d\r\n\
+            DTITLE=Various / Best of the 80\r\n\
+            DTITLE=s\r\n\
+            DYEAR=\r\n\
+            DGENRE=\r\n\
+            TTITLE0=Dire Straits / Sultans of Swing\r\n\
+            TTITLE1=Intro\\tpart\r\n\
+            EXTD= YEAR: 1988 ID3G: 17\r\n\
+            .\r\n";
+        let disc = parse_entry(body, "misc", 3)?;
+        assert_eq!("Various", disc.artist);
+        assert_eq!("Best of the 80s", disc.title);
+        assert_eq!(Some(1988), disc.year);
+        assert_eq!(Some("misc".to_owned()), disc.genre);
+        assert_eq!("Dire Straits", disc.tracks[0].artist);
+        assert_eq!("Sultans of Swing", disc.tracks[0].title);
+        assert_eq!("Various", disc.tracks[1].artist);
+        assert_eq!("Intro\tpart", disc.tracks[1].title);
+        // a track missing from the entry
+        assert_eq!("Unknown", disc.tracks[2].title);
+
+        assert!(parse_entry("401 entry not found\r\n", "misc", 3).is_err());
+        assert!(parse_entry("210 misc 940aac0d\r\n.\r\n", "misc", 3).is_err());
+
+        // Latin-1 from servers that don't do protocol level 6
+        assert_eq!("Björk", decode(b"Bj\xf6rk".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_fits_toc() {
+        let body = "210 rock 1a00b603 CD database entry follows\r\n\
+            # xmcd\r\n\
+            #\r\n\
+            # Track frame offsets:\r\n\
+            #\t150\r\n\
+            #\t1150\r\n\
+            #\t2150\r\n\
+            #\r\n\
+            # Disc length: 42 seconds\r\n\
+            DTITLE=Dire Straits / Money for Nothing\r\n\
+            .\r\n";
+        let offsets = [150, 1150, 2150];
+        assert!(fits_toc(body, &offsets, 3150, false));
+        // a few frames off, the lead-out is rounded to seconds
+        assert!(fits_toc(body, &[150, 1200, 2100], 3150, false));
+        // another disc of about the same length
+        assert!(!fits_toc(body, &[150, 2150, 2650], 3150, false));
+        assert!(!fits_toc(body, &[150, 1150, 2150, 2650], 3150, true));
+        // without the offsets only the id tells
+        let bare = "210 rock 1a00b603\r\nDTITLE=Dire Straits / Money\r\n.\r\n";
+        assert!(fits_toc(bare, &offsets, 3150, true));
+        assert!(!fits_toc(bare, &offsets, 3150, false));
+    }
+
+    #[test]
+    pub fn test_fresh() {
+        let entry = GnudbEntry {
+            fetched_at: 1000,
+            ..Default::default()
+        };
+        assert!(fresh(&entry, 1000 + 3599, 1));
+        assert!(!fresh(&entry, 1000 + 3600, 1));
+        // a clock that went back
+        assert!(fresh(&entry, 10, 1));
+        assert!(!fresh(&entry, 1000, 0));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::data::Config;
+use anyhow::{anyhow, Result};
+use gtk::{
+    gio::{prelude::*, Settings, SettingsBackend, SettingsSchema, SettingsSchemaSource},
+    glib::{Variant, VariantClass, VariantTy},
+};
+use serde_json::Value;
+
+/// Id of the schema in `content/be.sourcery.ripperx4.gschema.xml`, the id of the application
+pub const SCHEMA_ID: &str = "be.sourcery.ripperx4";
+
+/// The schema installed on the system (or found in `GSETTINGS_SCHEMA_DIR`), else the one compiled
+/// by the build, so `cargo run` uses it as well
+pub fn schema() -> Option<SettingsSchema> {
+    let installed = SettingsSchemaSource::default();
+    if let Some(schema) = installed.as_ref().and_then(|s| s.lookup(SCHEMA_ID, true)) {
+        return Some(schema);
+    }
+    let built = concat!(env!("OUT_DIR"), "/schemas");
+    SettingsSchemaSource::from_directory(built, installed.as_ref(), false)
+        .ok()?
+        .lookup(SCHEMA_ID, false)
+}
+
+/// The settings of ripperx4 in the default backend (dconf), `None` without the schema: then the
+/// configuration file is all there is
+pub fn open() -> Option<Settings> {
+    schema().map(|schema| Settings::new_full(&schema, None::<&SettingsBackend>, None))
+}
+
+/// The GSettings key of a field of `Config`
+pub fn key(field: &str) -> String {
+    field.replace('_', "-")
+}
+
+fn field(key: &str) -> String {
+    key.replace('-', "_")
+}
+
+fn keys(settings: &Settings) -> Vec<String> {
+    settings
+        .settings_schema()
+        .map(|schema| schema.list_keys().iter().map(ToString::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Put the values set in GSettings over the ones of the configuration file: the ones the user
+/// changed (e.g. with dconf-editor) and the ones the administrator locked
+pub fn apply(settings: &Settings, config: &mut Config) -> Result<()> {
+    let mut fields = serde_json::to_value(&*config)?;
+    for key in keys(settings) {
+        if settings.user_value(&key).is_some() || !settings.is_writable(&key) {
+            fields[field(&key)] = json(&settings.value(&key));
+        }
+    }
+    *config = serde_json::from_value(fields)?;
+    Ok(())
+}
+
+/// Write the values of the configuration that aren't in GSettings yet, except the locked ones
+pub fn store(settings: &Settings, config: &Config) -> Result<()> {
+    let fields = serde_json::to_value(config)?;
+    for key in keys(settings) {
+        let current = settings.value(&key);
+        let value = variant(&fields[field(&key)], current.type_())
+            .ok_or_else(|| anyhow!("{key} doesn't fit its GSettings type"))?;
+        if value != current && settings.is_writable(&key) {
+            settings.set_value(&key, &value)?;
+        }
+    }
+    Settings::sync();
+    Ok(())
+}
+
+/// The value of the GSettings key in the configuration
+pub fn value(config: &Config, key: &str, ty: &VariantTy) -> Option<Variant> {
+    variant(&serde_json::to_value(config).ok()?[field(key)], ty)
+}
+
+/// The configuration with the value of the GSettings key
+pub fn with_value(config: &Config, key: &str, value: &Variant) -> Option<Config> {
+    let mut fields = serde_json::to_value(config).ok()?;
+    fields[field(key)] = json(value);
+    serde_json::from_value(fields).ok()
+}
+
+fn json(value: &Variant) -> Value {
+    match value.classify() {
+        VariantClass::Boolean => value.get::<bool>().into(),
+        VariantClass::Uint32 => value.get::<u32>().into(),
+        VariantClass::Int32 => value.get::<i32>().into(),
+        VariantClass::Uint64 => value.get::<u64>().into(),
+        VariantClass::Int64 => value.get::<i64>().into(),
+        VariantClass::Double => value.get::<f64>().into(),
+        VariantClass::String => value.str().into(),
+        VariantClass::Maybe => value.as_maybe().map_or(Value::Null, |value| json(&value)),
+        VariantClass::Array => value.iter().map(|value| json(&value)).collect(),
+        _ => Value::Null,
+    }
+}
+
+fn variant(value: &Value, ty: &VariantTy) -> Option<Variant> {
+    if ty.is_maybe() {
+        return match value {
+            Value::Null => Some(Variant::from_none(ty.element())),
+            value => variant(value, ty.element()).map(|value| Variant::from_some(&value)),
+        };
+    }
+    if ty.is_array() {
+        let items = value
+            .as_array()?
+            .iter()
+            .map(|value| variant(value, ty.element()))
+            .collect::<Option<Vec<_>>>()?;
+        return Some(Variant::array_from_iter_with_type(ty.element(), items));
+    }
+    Some(match ty.as_str() {
+        "b" => value.as_bool()?.to_variant(),
+        "s" => value.as_str()?.to_variant(),
+        "u" => u32::try_from(value.as_u64()?).ok()?.to_variant(),
+        "i" => i32::try_from(value.as_i64()?).ok()?.to_variant(),
+        "t" => value.as_u64()?.to_variant(),
+        "x" => value.as_i64()?.to_variant(),
+        "d" => value.as_f64()?.to_variant(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, key, schema, store, value, with_value};
+    use crate::{
+        data::{CdBackend, Config, Encoder},
+        preferences,
+    };
+    use anyhow::Result;
+    use gtk::{
+        gio::{self, prelude::*, Settings},
+        glib::VariantTy,
+    };
+
+    fn memory_settings() -> Settings {
+        let schema = schema().expect("the build compiles the schema");
+        Settings::new_full(&schema, Some(&gio::memory_settings_backend_new()), None)
+    }
+
+    #[test]
+    pub fn test_schema_defaults() {
+        let settings = memory_settings();
+        let mut config = Config {
+            encode_path: String::new(),
+            ..Config::default()
+        };
+        let defaults = serde_json::to_value(&config).expect("config serializes");
+        // with the defaults of the schema, as if they were all locked
+        for key in settings.settings_schema().expect("schema").list_keys() {
+            config = with_value(&config, &key, &settings.value(&key)).expect(&key);
+        }
+        assert_eq!(
+            defaults,
+            serde_json::to_value(&config).expect("config serializes")
+        );
+        // the preferences window binds all its settings
+        let schema = settings.settings_schema().expect("schema");
+        for setting in preferences::settings(&config, &[], &[]) {
+            assert!(schema.has_key(&key(setting.key)), "{}", setting.key);
+        }
+    }
+
+    #[test]
+    pub fn test_apply_store() -> Result<()> {
+        let settings = memory_settings();
+        // nothing set yet, the file wins
+        let mut config = Config {
+            encoder: Encoder::FLAC,
+            ..Config::default()
+        };
+        apply(&settings, &mut config)?;
+        assert_eq!(Encoder::FLAC, config.encoder);
+        settings.set_string("cd-backend", "Remote")?;
+        settings.set_value("remote-drive", &Some("laptop:4646").to_variant())?;
+        apply(&settings, &mut config)?;
+        assert_eq!(CdBackend::Remote, config.cd_backend);
+        assert_eq!(Some("laptop:4646"), config.remote_drive.as_deref());
+        assert_eq!(Encoder::FLAC, config.encoder);
+        config.preferred_languages = vec!["deu".to_owned(), "eng".to_owned()];
+        config.remote_drive = None;
+        store(&settings, &config)?;
+        assert_eq!("FLAC", settings.string("encoder"));
+        assert_eq!(
+            vec!["deu", "eng"],
+            settings.get::<Vec<String>>("preferred-languages")
+        );
+        assert!(settings.value("remote-drive").as_maybe().is_none());
+        let ty = VariantTy::new("ms")?;
+        assert_eq!(
+            Some(None::<String>.to_variant()),
+            value(&config, "remote-drive", ty)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,505 @@
+use crate::{
+    accuraterip::Verdict,
+    automation::Automation,
+    data::{Config, Disc, Encoded},
+    encoder::backend,
+    musicbrainz::add_to_collection,
+    ripper::{modified_at, write_safely},
+};
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde_json::{json, Value};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How long the `post_rip_command` may run before it's killed, the next disc waits for it
+const POST_RIP_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How often a running command is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Something to do after a disc was ripped successfully
+/// To add one, implement this trait and add it to `HOOKS`
+pub trait PostRipHook: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether the hook is configured
+    fn enabled(&self, config: &Config) -> bool;
+
+    fn run(&self, disc: &Disc, report: &[Encoded], config: &Config) -> Result<()>;
+}
+
+/// Adds the release to the configured MusicBrainz collection
+struct MusicBrainzCollection;
+/// Appends a line per ripped disc to a local JSON lines file
+struct InventoryLog;
+/// Submits the ripped tracks to ListenBrainz or a compatible service
+struct ListenBrainz;
+/// Runs the configured command with the album folder, e.g. to rescan the music library
+struct PostRipCommand;
+/// Calls `on_album` of the automation script
+struct Script;
+
+static HOOKS: &[&dyn PostRipHook] = &[
+    &MusicBrainzCollection,
+    &InventoryLog,
+    &ListenBrainz,
+    &PostRipCommand,
+    &Script,
+];
+
+/// Run the configured hooks
+/// A failing hook doesn't stop the others, returns a message per failure
+pub fn run_post_rip_hooks(disc: &Disc, report: &[Encoded], config: &Config) -> Vec<String> {
+    HOOKS
+        .iter()
+        .filter(|hook| hook.enabled(config))
+        .filter_map(|hook| {
+            debug!("running post rip hook {}", hook.name());
+            hook.run(disc, report, config)
+                .err()
+                .map(|e| format!("{}: {e}", hook.name()))
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl PostRipHook for MusicBrainzCollection {
+    fn name(&self) -> &'static str {
+        "MusicBrainz collection"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.musicbrainz_token.is_some() && config.musicbrainz_collection.is_some()
+    }
+
+    fn run(&self, disc: &Disc, _report: &[Encoded], config: &Config) -> Result<()> {
+        add_to_collection(disc, config)
+    }
+}
+
+/// The format the disc was ripped to, e.g. "flac", after the extensions of the files
+/// A profile with several encoders lists them all, e.g. "flac+mp3"
+fn format_name(config: &Config, report: &[Encoded]) -> String {
+    let mut formats: Vec<String> = Vec::new();
+    for encoded in report {
+        let Some(extension) = encoded.path.extension() else {
+            continue;
+        };
+        let extension = extension.to_string_lossy().to_lowercase();
+        if !formats.contains(&extension) {
+            formats.push(extension);
+        }
+    }
+    if !formats.is_empty() {
+        formats.join("+")
+    } else if config.disc_image {
+        config.image_format.extension().to_owned()
+    } else {
+        backend(&config.encoder).settings().name.to_owned()
+    }
+}
+
+/// How the disc compares to AccurateRip, by its worst track, `None` when it wasn't verified
+fn verification(report: &[Encoded]) -> Option<&'static str> {
+    let verdicts: Vec<Option<Verdict>> = report.iter().map(|e| e.accuraterip).collect();
+    if verdicts.iter().all(Option::is_none) {
+        None
+    } else if verdicts.contains(&Some(Verdict::Inaccurate)) {
+        Some("inaccurate")
+    } else if verdicts
+        .iter()
+        .all(|verdict| matches!(verdict, Some(Verdict::Accurate(_))))
+    {
+        Some("accurate")
+    } else {
+        Some("unknown")
+    }
+}
+
+/// The files are listed with the values of the naming template, so the library can be
+/// reorganized when the template changes
+fn inventory_entry(disc: &Disc, report: &[Encoded], format: &str, ripped_at: u64) -> Value {
+    let files: Vec<Value> = report
+        .iter()
+        .map(|encoded| {
+            let track = disc.tracks.iter().find(|t| t.number == encoded.number);
+            let mut file = json!({
+                "path": encoded.path,
+                "number": encoded.number,
+                "title": encoded.title,
+                "artist": track.map_or(disc.artist.as_str(), |t| t.artist.as_str()),
+                "composer": track.and_then(|t| t.composer.as_deref()),
+                // to tell whether the file was changed after the rip
+                "size": encoded.size,
+                "modified": fs::metadata(&encoded.path).ok().as_ref().and_then(modified_at),
+            });
+            // only for discs in other scripts, to reorganize them after the Latin names
+            if let Some(track) = track.filter(|t| t.latin_title.is_some()) {
+                file["latin_title"] = json!(track.latin_title);
+                file["latin_artist"] = json!(track.latin_artist);
+            }
+            file
+        })
+        .collect();
+    let mut entry = json!({
+        "ripped_at": ripped_at,
+        "artist": disc.artist,
+        "album": disc.title,
+        "year": disc.year,
+        "genre": disc.genre,
+        "release_id": disc.release_id,
+        "discid": disc.discid,
+        "format": format,
+        "verification": verification(report),
+        "tracks": report.len(),
+        // for the statistics
+        "seconds": disc.tracks.iter().filter(|t| t.rip).map(|t| t.duration).sum::<u64>(),
+        "bytes": report.iter().map(|e| e.size).sum::<u64>(),
+        "rereads": report.iter().map(|e| e.read_errors.rereads).sum::<u32>(),
+        "skipped": report.iter().map(|e| e.read_errors.skipped).sum::<u32>(),
+        "files": files,
+    });
+    if disc.latin_title.is_some() {
+        entry["latin_album"] = json!(disc.latin_title);
+        entry["latin_artist"] = json!(disc.latin_artist);
+    }
+    entry
+}
+
+impl PostRipHook for InventoryLog {
+    fn name(&self) -> &'static str {
+        "inventory log"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.inventory_file.is_some()
+    }
+
+    fn run(&self, disc: &Disc, report: &[Encoded], config: &Config) -> Result<()> {
+        let Some(path) = &config.inventory_file else {
+            return Ok(());
+        };
+        let entry = inventory_entry(disc, report, &format_name(config, report), now());
+        log_entry(Path::new(path), disc, &entry)
+    }
+}
+
+/// Write the tracks of the disc encoded so far to the inventory, marked as incomplete, so a
+/// crash or a power loss in the middle of the disc leaves a record of them
+/// The line is replaced with each track and when the disc is done
+pub fn record_tracks(disc: &Disc, done: &[Encoded], config: &Config) -> Result<()> {
+    let Some(path) = &config.inventory_file else {
+        return Ok(());
+    };
+    let mut entry = inventory_entry(disc, done, &format_name(config, done), now());
+    entry["incomplete"] = json!(true);
+    log_entry(Path::new(path), disc, &entry)
+}
+
+/// Add the entry to the inventory in place of the line of an incomplete rip of the disc
+fn log_entry(path: &Path, disc: &Disc, entry: &Value) -> Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let lines: Vec<&str> = contents.lines().collect();
+    let kept: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| !incomplete_rip(line, disc))
+        .collect();
+    if kept.len() == lines.len() {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // in one write and on the disk before the hook is done, a crash can't leave half a line
+        // in the middle of the file
+        file.write_all(format!("{entry}\n").as_bytes())?;
+        file.sync_data()?;
+        return Ok(());
+    }
+    let mut contents: String = kept.iter().map(|line| format!("{line}\n")).collect();
+    contents.push_str(&format!("{entry}\n"));
+    write_safely(path, &contents)
+}
+
+/// Whether the inventory line is the record of the tracks done so far of the disc
+fn incomplete_rip(line: &str, disc: &Disc) -> bool {
+    serde_json::from_str::<Value>(line).is_ok_and(|entry| {
+        entry["incomplete"] == json!(true)
+            && entry["discid"] == json!(disc.discid)
+            && entry["album"] == json!(disc.title)
+    })
+}
+
+fn listens(disc: &Disc, report: &[Encoded], listened_at: u64) -> Value {
+    let payload: Vec<Value> = report
+        .iter()
+        .map(|encoded| {
+            let artist = disc
+                .tracks
+                .iter()
+                .find(|t| t.number == encoded.number)
+                .map_or(disc.artist.as_str(), |t| t.artist.as_str());
+            json!({
+                "listened_at": listened_at,
+                "track_metadata": {
+                    "artist_name": artist,
+                    "track_name": encoded.title,
+                    "release_name": disc.title,
+                    "additional_info": {
+                        "tracknumber": encoded.number,
+                        "release_mbid": disc.release_id,
+                        "submission_client": "ripperx4",
+                    },
+                },
+            })
+        })
+        .collect();
+    json!({ "listen_type": "import", "payload": payload })
+}
+
+impl PostRipHook for ListenBrainz {
+    fn name(&self) -> &'static str {
+        "ListenBrainz"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.listenbrainz_token.is_some()
+    }
+
+    fn run(&self, disc: &Disc, report: &[Encoded], config: &Config) -> Result<()> {
+        let Some(token) = &config.listenbrainz_token else {
+            return Ok(());
+        };
+        if report.is_empty() {
+            return Ok(());
+        }
+        let url = format!(
+            "{}/1/submit-listens",
+            config.listenbrainz_url.trim_end_matches('/')
+        );
+        ureq::post(&url)
+            .set("Authorization", &format!("Token {token}"))
+            .send_json(listens(disc, report, now()))?;
+        Ok(())
+    }
+}
+
+/// The command split on white space, with the folder as last argument
+fn command_line(command: &str, folder: &Path) -> Result<Command> {
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or(anyhow!("the command is empty"))?;
+    let mut command = Command::new(program);
+    // nobody answers a question, e.g. of `beet import` without `-q`
+    command.args(words).arg(folder).stdin(Stdio::null());
+    Ok(command)
+}
+
+/// Run the command, it's killed when it runs longer than `timeout`
+fn run_with_timeout(command: &mut Command, timeout: Duration) -> Result<ExitStatus> {
+    let mut child = command.spawn()?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(anyhow!("killed after {} seconds", timeout.as_secs()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+impl PostRipHook for PostRipCommand {
+    fn name(&self) -> &'static str {
+        "post rip command"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.post_rip_command.is_some()
+    }
+
+    fn run(&self, _disc: &Disc, report: &[Encoded], config: &Config) -> Result<()> {
+        let Some(command) = &config.post_rip_command else {
+            return Ok(());
+        };
+        let Some(folder) = report.first().and_then(|encoded| encoded.path.parent()) else {
+            return Ok(());
+        };
+        let status = run_with_timeout(&mut command_line(command, folder)?, POST_RIP_TIMEOUT)
+            .map_err(|e| anyhow!("{command}: {e}"))?;
+        if !status.success() {
+            return Err(anyhow!("{command} failed: {status}"));
+        }
+        Ok(())
+    }
+}
+
+impl PostRipHook for Script {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.automation_script.is_some()
+    }
+
+    fn run(&self, disc: &Disc, report: &[Encoded], config: &Config) -> Result<()> {
+        if let Some(automation) = Automation::load(config)? {
+            automation.on_album(disc, report);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        command_line, format_name, inventory_entry, listens, log_entry, run_with_timeout,
+        verification,
+    };
+    use crate::{
+        accuraterip::Verdict,
+        data::{Config, Disc, Encoded, Encoder, ReadErrors},
+    };
+    use anyhow::Result;
+    use serde_json::{json, Value};
+    use std::{
+        ffi::OsStr,
+        fs,
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    };
+
+    fn ripped() -> (Disc, Vec<Encoded>) {
+        let mut disc = Disc::with_tracks(2);
+        disc.title = "Money for Nothing".to_owned();
+        disc.artist = "Dire Straits".to_owned();
+        disc.tracks[1].artist = "Mark Knopfler".to_owned();
+        let report = vec![Encoded {
+            path: PathBuf::from("/music/Dire Straits-Money for Nothing/Down to the Waterline.mp3"),
+            number: 2,
+            title: "Down to the Waterline".to_owned(),
+            size: 1000,
+            bitrate: None,
+            expected_bitrate: 128,
+            read_errors: ReadErrors::default(),
+            accuraterip: None,
+        }];
+        (disc, report)
+    }
+
+    #[test]
+    pub fn test_inventory_entry() {
+        let (disc, report) = ripped();
+        assert_eq!(
+            r#"{"album":"Money for Nothing","artist":"Dire Straits","bytes":1000,"discid":null,"files":[{"artist":"Mark Knopfler","composer":null,"modified":null,"number":2,"path":"/music/Dire Straits-Money for Nothing/Down to the Waterline.mp3","size":1000,"title":"Down to the Waterline"}],"format":"mp3","genre":null,"release_id":null,"rereads":0,"ripped_at":42,"seconds":0,"skipped":0,"tracks":1,"verification":null,"year":null}"#,
+            inventory_entry(&disc, &report, "mp3", 42).to_string()
+        );
+        let mut verified = report.clone();
+        verified.push(report[0].clone());
+        verified[0].accuraterip = Some(Verdict::Accurate(5));
+        assert_eq!(None, verification(&report));
+        assert_eq!(Some("unknown"), verification(&verified));
+        verified[1].accuraterip = Some(Verdict::Accurate(2));
+        assert_eq!(Some("accurate"), verification(&verified));
+        verified[1].accuraterip = Some(Verdict::Inaccurate);
+        assert_eq!(Some("inaccurate"), verification(&verified));
+    }
+
+    #[test]
+    pub fn test_format_name() {
+        let (_, mut report) = ripped();
+        let config = Config {
+            encoder: Encoder::OPUS,
+            ..Default::default()
+        };
+        assert_eq!("opus", format_name(&config, &[]));
+        assert_eq!("mp3", format_name(&config, &report));
+        report.push(report[0].clone());
+        report[1].path.set_extension("FLAC");
+        assert_eq!("mp3+flac", format_name(&config, &report));
+    }
+
+    #[test]
+    pub fn test_log_entry() -> Result<()> {
+        let (disc, report) = ripped();
+        let inventory = Path::new("/tmp/ripperx4_inventory.jsonl");
+        fs::write(inventory, "{\"album\":\"Brothers in Arms\"}\n")?;
+        let mut incomplete = inventory_entry(&disc, &report[..0], "mp3", 42);
+        incomplete["incomplete"] = json!(true);
+        log_entry(inventory, &disc, &incomplete)?;
+        let mut incomplete = inventory_entry(&disc, &report, "mp3", 42);
+        incomplete["incomplete"] = json!(true);
+        log_entry(inventory, &disc, &incomplete)?;
+        let lines: Vec<Value> = fs::read_to_string(inventory)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        // the tracks done so far replace the line before
+        assert_eq!(2, lines.len());
+        assert_eq!(1, lines[1]["tracks"]);
+        log_entry(
+            inventory,
+            &disc,
+            &inventory_entry(&disc, &report, "mp3", 43),
+        )?;
+        let contents = fs::read_to_string(inventory)?;
+        fs::remove_file(inventory)?;
+        assert_eq!(2, contents.lines().count());
+        assert!(!contents.contains("incomplete"));
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_listens() {
+        let (disc, report) = ripped();
+        let listens = listens(&disc, &report, 42);
+        assert_eq!("import", listens["listen_type"]);
+        let metadata = &listens["payload"][0]["track_metadata"];
+        assert_eq!("Mark Knopfler", metadata["artist_name"]);
+        assert_eq!("Down to the Waterline", metadata["track_name"]);
+        assert_eq!("Money for Nothing", metadata["release_name"]);
+        assert_eq!(2, metadata["additional_info"]["tracknumber"]);
+    }
+
+    #[test]
+    pub fn test_command_line() -> Result<()> {
+        let folder = Path::new("/music/Dire Straits-Money for Nothing");
+        let command = command_line("beet  import -q", folder)?;
+        assert_eq!("beet", command.get_program());
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            vec![OsStr::new("import"), OsStr::new("-q"), folder.as_os_str()],
+            args
+        );
+        assert!(command_line("  ", folder).is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_run_with_timeout() -> Result<()> {
+        let timeout = Duration::from_secs(1);
+        let status = run_with_timeout(&mut command_line("true", Path::new("/"))?, timeout)?;
+        assert!(status.success());
+        let started = Instant::now();
+        // the folder is the last argument, here the seconds
+        assert!(run_with_timeout(&mut command_line("sleep", Path::new("60"))?, timeout).is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+use gtk::{
+    gio::{self, BusType, Cancellable, DBusCallFlags},
+    glib::Variant,
+    prelude::*,
+};
+use log::debug;
+use std::path::Path;
+
+const FILE_MANAGER: &str = "org.freedesktop.FileManager1";
+const TIMEOUT_MS: i32 = 5000;
+
+/// Open the folder in the file manager
+/// Inside a sandbox GIO goes through the OpenURI portal
+pub fn open_folder(folder: &Path) -> Result<()> {
+    let uri = gio::File::for_path(folder).uri();
+    gio::AppInfo::launch_default_for_uri(&uri, None::<&gio::AppLaunchContext>)?;
+    Ok(())
+}
+
+/// Show the file selected in its folder, or just open the folder when the file manager can't
+pub fn reveal_file(file: &Path) -> Result<()> {
+    match show_items(file) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            debug!("FileManager1 failed, opening the folder: {e}");
+            open_folder(file.parent().ok_or(anyhow!("the file has no folder"))?)
+        }
+    }
+}
+
+/// Ask the file manager on the session bus to select the file
+fn show_items(file: &Path) -> Result<()> {
+    let bus = gio::bus_get_sync(BusType::Session, Cancellable::NONE)?;
+    bus.call_sync(
+        Some(FILE_MANAGER),
+        "/org/freedesktop/FileManager1",
+        FILE_MANAGER,
+        "ShowItems",
+        Some(&show_items_args(file)),
+        None,
+        DBusCallFlags::NONE,
+        TIMEOUT_MS,
+        Cancellable::NONE,
+    )?;
+    Ok(())
+}
+
+/// The URIs of the files and the startup notification id, none
+fn show_items_args(file: &Path) -> Variant {
+    let uris = vec![gio::File::for_path(file).uri().to_string()];
+    (uris, "").to_variant()
+}
+
+#[cfg(test)]
+mod test {
+    use super::show_items_args;
+    use std::path::Path;
+
+    #[test]
+    pub fn test_show_items_args() {
+        let args = show_items_args(Path::new("/music/Dire Straits-Money for Nothing/01.flac"));
+        assert_eq!("(ass)", args.type_().as_str());
+        assert_eq!(
+            "(['file:///music/Dire%20Straits-Money%20for%20Nothing/01.flac'], '')",
+            args.print(false).as_str()
+        );
+    }
+}
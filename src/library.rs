@@ -0,0 +1,610 @@
+use crate::{
+    data::{Config, Disc, Track},
+    naming::{expand_template, name_rules, template},
+};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// How deep below the library root album folders are looked for, e.g. `Genre/Artist/Album`
+const MAX_DEPTH: usize = 3;
+
+/// Look for the disc in the existing library, so the user doesn't rip an album twice
+/// Matches album folders named `Artist-Album` or `Artist/Album` below `library_root` and the
+/// release id in the inventory file, returns a description of every match
+pub fn find_duplicates(disc: &Disc, config: &Config) -> Vec<String> {
+    let mut found = Vec::new();
+    if let Some(root) = &config.library_root {
+        if disc.title != "Unknown" {
+            find_album_folders(disc, Path::new(root), 0, &mut found);
+        }
+    }
+    if let (Some(inventory), Some(release)) = (&config.inventory_file, &disc.release_id) {
+        if in_inventory(inventory, release) {
+            found.push(format!("the release is listed in {inventory}"));
+        }
+    }
+    found
+}
+
+/// Only letters and digits, lower case, so `Artist - Album` matches `artist-album`
+fn simplify(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn find_album_folders(disc: &Disc, folder: &Path, depth: usize, found: &mut Vec<String>) {
+    if depth == MAX_DEPTH {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+    let album = simplify(&disc.title);
+    let artist_album = simplify(&format!("{}-{}", disc.artist, disc.title));
+    let parent = folder
+        .file_name()
+        .map(|name| simplify(&name.to_string_lossy()));
+    // the entry's file type doesn't follow symlinks, so the symlink views are skipped
+    for entry in entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+    {
+        let name = simplify(&entry.file_name().to_string_lossy());
+        let by_artist = name == album && parent.as_deref() == Some(&simplify(&disc.artist));
+        if name == artist_album || by_artist {
+            found.push(entry.path().display().to_string());
+        } else {
+            find_album_folders(disc, &entry.path(), depth + 1, found);
+        }
+    }
+}
+
+fn in_inventory(inventory: &str, release: &str) -> bool {
+    fs::read_to_string(inventory)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .any(|entry| entry["release_id"].as_str() == Some(release))
+}
+
+/// A ripped file that isn't named after the current template
+#[derive(Debug, PartialEq)]
+pub struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Where the files listed in the inventory belong with the current naming template
+/// Files that are in place already or no longer exist are left out, as are discs ripped before
+/// the inventory listed the files
+pub fn plan_reorganize(config: &Config) -> Result<Vec<Move>> {
+    let inventory = config
+        .inventory_file
+        .as_ref()
+        .ok_or(anyhow!("there is no inventory_file with the ripped discs"))?;
+    let rules = name_rules(config);
+    let mut moves = Vec::new();
+    for entry in fs::read_to_string(inventory)?
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+    {
+        let disc = inventory_disc(&entry);
+        for file in entry["files"].as_array().into_iter().flatten() {
+            let Some(from) = file["path"].as_str().map(PathBuf::from) else {
+                continue;
+            };
+            if !from.is_file() {
+                continue;
+            }
+            let mut to = PathBuf::from(format!(
+                "{}/{}",
+                config.encode_path,
+                expand_template(
+                    template(config, &disc),
+                    &inventory_track(file),
+                    &disc,
+                    config.latin_file_names,
+                    rules
+                )
+            ));
+            // the extension of the file is kept, it may have been ripped to another format
+            if let Some(extension) = from.extension() {
+                let mut name = to.into_os_string();
+                name.push(".");
+                name.push(extension);
+                to = PathBuf::from(name);
+            }
+            if to != from {
+                moves.push(Move { from, to });
+            }
+        }
+    }
+    Ok(moves)
+}
+
+fn inventory_disc(entry: &Value) -> Disc {
+    let text = |key: &str| entry[key].as_str().unwrap_or_default().to_owned();
+    Disc {
+        title: text("album"),
+        artist: text("artist"),
+        year: entry["year"]
+            .as_u64()
+            .and_then(|year| u16::try_from(year).ok()),
+        genre: entry["genre"].as_str().map(str::to_owned),
+        latin_title: entry["latin_album"].as_str().map(str::to_owned),
+        latin_artist: entry["latin_artist"].as_str().map(str::to_owned),
+        // the artists of the files tell compilations apart
+        tracks: entry["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(inventory_track)
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn inventory_track(file: &Value) -> Track {
+    let text = |key: &str| file[key].as_str().unwrap_or_default().to_owned();
+    Track {
+        number: file["number"]
+            .as_u64()
+            .and_then(|number| u32::try_from(number).ok())
+            .unwrap_or_default(),
+        title: text("title"),
+        artist: text("artist"),
+        composer: file["composer"].as_str().map(str::to_owned),
+        latin_title: file["latin_title"].as_str().map(str::to_owned),
+        latin_artist: file["latin_artist"].as_str().map(str::to_owned),
+        ..Default::default()
+    }
+}
+
+/// Move the files and point the inventory to the new locations
+/// A cover left behind goes along with the files, emptied album folders are removed
+/// Stops at the first file that can't be moved, the inventory is updated for the files moved
+pub fn reorganize(moves: &[Move], config: &Config) -> Result<()> {
+    let mut moved = HashMap::new();
+    let result = moves.iter().try_for_each(|m| {
+        if m.to.exists() {
+            return Err(anyhow!("{} exists already", m.to.display()));
+        }
+        if let Some(folder) = m.to.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::rename(&m.from, &m.to)?;
+        moved.insert(m.from.display().to_string(), m.to.display().to_string());
+        Ok(())
+    });
+    for m in moves {
+        if let (Some(old), Some(new)) = (m.from.parent(), m.to.parent()) {
+            move_covers(old, new);
+            fs::remove_dir(old).ok();
+        }
+    }
+    if let Some(inventory) = &config.inventory_file {
+        update_inventory(inventory, &moved)?;
+    }
+    result
+}
+
+fn move_covers(old: &Path, new: &Path) {
+    let Ok(entries) = fs::read_dir(old) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_cover = entry.file_name().to_string_lossy().starts_with("cover.");
+        // only when the tracks are gone, an album split over several folders keeps its cover
+        if is_cover && fs::read_dir(old).map_or(0, Iterator::count) == 1 {
+            fs::rename(entry.path(), new.join(entry.file_name())).ok();
+        }
+    }
+}
+
+/// Replace the moved paths in the inventory, lines that are not JSON are kept as they are
+fn update_inventory(inventory: &str, moved: &HashMap<String, String>) -> Result<()> {
+    if moved.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<String> = fs::read_to_string(inventory)?
+        .lines()
+        .map(|line| {
+            let Ok(mut entry) = serde_json::from_str::<Value>(line) else {
+                return line.to_owned();
+            };
+            for file in entry["files"].as_array_mut().into_iter().flatten() {
+                if let Some(to) = file["path"].as_str().and_then(|from| moved.get(from)) {
+                    file["path"] = Value::from(to.as_str());
+                }
+            }
+            entry.to_string()
+        })
+        .collect();
+    fs::write(inventory, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// A disc of the inventory ripped to FLAC, to be encoded to another format without the disc
+#[derive(Debug, Clone)]
+pub struct Archive {
+    /// the metadata of the rip, a track per file
+    pub disc: Disc,
+    pub files: Vec<PathBuf>,
+}
+
+impl Display for Archive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} - {}", self.disc.artist, self.disc.title)
+    }
+}
+
+/// The discs of the inventory whose FLAC files are all still there, sorted by name
+/// A disc ripped to FLAC again is listed once
+pub fn flac_archives(config: &Config) -> Result<Vec<Archive>> {
+    let inventory = config
+        .inventory_file
+        .as_ref()
+        .ok_or(anyhow!("there is no inventory_file with the ripped discs"))?;
+    Ok(inventory_archives(&fs::read_to_string(inventory)?))
+}
+
+fn inventory_archives(inventory: &str) -> Vec<Archive> {
+    let mut archives: Vec<Archive> = Vec::new();
+    for entry in inventory
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+    {
+        let files: Vec<PathBuf> = entry["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|file| file["path"].as_str().map(PathBuf::from))
+            .collect();
+        let flac = |path: &PathBuf| {
+            path.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("flac"))
+                && path.is_file()
+        };
+        if files.is_empty() || !files.iter().all(flac) {
+            continue;
+        }
+        archives.retain(|archive| archive.files != files);
+        archives.push(Archive {
+            disc: inventory_disc(&entry),
+            files,
+        });
+    }
+    archives.sort_by_cached_key(ToString::to_string);
+    archives
+}
+
+/// Write the discs of the inventory to a CSV file for a spreadsheet, returns how many
+pub fn export_csv(config: &Config, path: &Path) -> Result<usize> {
+    let inventory = config
+        .inventory_file
+        .as_ref()
+        .ok_or(anyhow!("there is no inventory_file with the ripped discs"))?;
+    let (csv, discs) = inventory_csv(&fs::read_to_string(inventory)?);
+    fs::write(path, csv)?;
+    Ok(discs)
+}
+
+/// A row per disc of the inventory after a header, discs ripped before the inventory had a
+/// column leave it empty
+fn inventory_csv(inventory: &str) -> (String, usize) {
+    let mut csv = String::from(
+        "Artist,Album,Year,Disc ID,Ripped,Format,Verification,Tracks,MusicBrainz release\r\n",
+    );
+    let mut discs = 0;
+    for entry in inventory
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+    {
+        let text = |key: &str| match &entry[key] {
+            Value::String(text) => text.clone(),
+            Value::Number(number) => number.to_string(),
+            _ => String::new(),
+        };
+        let ripped = entry["ripped_at"]
+            .as_i64()
+            .and_then(|time| glib::DateTime::from_unix_local(time).ok())
+            .and_then(|date| date.format("%Y-%m-%d %H:%M").ok())
+            .map(String::from)
+            .unwrap_or_default();
+        let row = [
+            text("artist"),
+            text("album"),
+            text("year"),
+            text("discid"),
+            ripped,
+            text("format"),
+            text("verification"),
+            text("tracks"),
+            text("release_id"),
+        ];
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+        discs += 1;
+    }
+    (csv, discs)
+}
+
+/// Totals of the discs in the inventory, the counts of discs ripped before it had them are left
+/// out
+#[derive(Debug, Default, PartialEq)]
+pub struct Statistics {
+    pub discs: usize,
+    pub tracks: u64,
+    /// length of the ripped audio
+    pub seconds: u64,
+    /// size of the files written
+    pub bytes: u64,
+    /// sectors that were read again or skipped
+    pub read_errors: u64,
+    /// discs ripped per local date, e.g. "2024-03-01"
+    pub per_day: BTreeMap<String, usize>,
+}
+
+/// Days listed after the totals
+const RECENT_DAYS: usize = 7;
+
+impl Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} discs, {} tracks", self.discs, self.tracks)?;
+        writeln!(f, "{:.1} hours of audio", self.seconds as f64 / 3600.0)?;
+        writeln!(f, "{:.1} GB written", self.bytes as f64 / 1_000_000_000.0)?;
+        let errors = self.read_errors as f64 / self.discs.max(1) as f64;
+        write!(f, "{errors:.1} read errors per disc")?;
+        if let Some((day, discs)) = self.per_day.iter().max_by_key(|(_, discs)| **discs) {
+            write!(f, "\nMost in a day: {discs} on {day}")?;
+        }
+        for (day, discs) in self.per_day.iter().rev().take(RECENT_DAYS) {
+            write!(f, "\n{day}: {discs}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The statistics of the discs in the inventory
+pub fn statistics(config: &Config) -> Result<Statistics> {
+    let inventory = config
+        .inventory_file
+        .as_ref()
+        .ok_or(anyhow!("there is no inventory_file with the ripped discs"))?;
+    Ok(inventory_statistics(&fs::read_to_string(inventory)?))
+}
+
+fn inventory_statistics(inventory: &str) -> Statistics {
+    let mut statistics = Statistics::default();
+    for entry in inventory
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+    {
+        let count = |key: &str| entry[key].as_u64().unwrap_or_default();
+        statistics.discs += 1;
+        statistics.tracks += count("tracks");
+        statistics.seconds += count("seconds");
+        statistics.bytes += count("bytes");
+        statistics.read_errors += count("rereads") + count("skipped");
+        if let Some(day) = entry["ripped_at"]
+            .as_i64()
+            .and_then(|time| glib::DateTime::from_unix_local(time).ok())
+            .and_then(|date| date.format("%Y-%m-%d").ok())
+        {
+            *statistics.per_day.entry(day.into()).or_default() += 1;
+        }
+    }
+    statistics
+}
+
+/// Quote a field with a separator, quote or line break in it, as RFC 4180 has it
+/// A field a spreadsheet would take for a formula gets a `'` in front, so an album named
+/// `=HYPERLINK(...)` stays text
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{field}")
+    } else {
+        field.to_owned()
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        find_duplicates, inventory_archives, inventory_csv, inventory_statistics, plan_reorganize,
+        reorganize, Move,
+    };
+    use crate::data::{Config, Disc};
+    use anyhow::Result;
+    use std::{fs, path::Path};
+
+    #[test]
+    pub fn test_inventory_archives() -> Result<()> {
+        let root = Path::new("/tmp/ripperx4_archives");
+        fs::create_dir_all(root)?;
+        for name in ["1.flac", "2.flac", "1.mp3"] {
+            fs::write(root.join(name), name)?;
+        }
+        let entry = |album: &str, files: &[&str]| {
+            let files: Vec<String> = files
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    format!(
+                        r#"{{"path":"{}","number":{},"title":"Track {}"}}"#,
+                        root.join(name).display(),
+                        i + 1,
+                        i + 1
+                    )
+                })
+                .collect();
+            format!(
+                r#"{{"artist":"Band","album":"{album}","files":[{}]}}"#,
+                files.join(",")
+            )
+        };
+        let inventory = [
+            entry("Lossless", &["1.flac", "2.flac"]),
+            entry("Lossy", &["1.mp3"]),
+            entry("Gone", &["1.flac", "3.flac"]),
+            "not json".to_owned(),
+            // ripped again, replaces the first
+            entry("Again", &["1.flac", "2.flac"]),
+        ]
+        .join("\n");
+        let archives = inventory_archives(&inventory);
+        fs::remove_dir_all(root)?;
+        assert_eq!(1, archives.len());
+        assert_eq!("Band - Again", archives[0].to_string());
+        assert_eq!(root.join("2.flac"), archives[0].files[1]);
+        assert_eq!("Track 2", archives[0].disc.tracks[1].title);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_inventory_csv() {
+        let inventory = concat!(
+            r#"{"artist":"Dire Straits","album":"Money for Nothing","year":1988,"discid":"nRsO3sgfWCeG3kSMcVBD5hbmrw4-","ripped_at":1699956800,"format":"flac","verification":"accurate","tracks":12,"release_id":"a541c6e6"}"#,
+            "\nnot json\n",
+            r#"{"artist":"Crosby, Stills & Nash","album":"\"CSN\"","tracks":10}"#,
+            "\n",
+            r#"{"artist":"=HYPERLINK(\"http://example.com\")","album":"-","tracks":1}"#,
+        );
+        let (csv, discs) = inventory_csv(inventory);
+        assert_eq!(3, discs);
+        let rows: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(
+            "Artist,Album,Year,Disc ID,Ripped,Format,Verification,Tracks,MusicBrainz release",
+            rows[0]
+        );
+        // the local date of the rip, this one is around noon UTC
+        assert!(rows[1].starts_with(
+            "Dire Straits,Money for Nothing,1988,nRsO3sgfWCeG3kSMcVBD5hbmrw4-,2023-11-14 "
+        ));
+        assert!(rows[1].ends_with(",flac,accurate,12,a541c6e6"));
+        assert_eq!(r#""Crosby, Stills & Nash","""CSN""",,,,,,10,"#, rows[2]);
+        assert_eq!(
+            r#""'=HYPERLINK(""http://example.com"")",'-,,,,,,1,"#,
+            rows[3]
+        );
+        assert_eq!("", rows[4]);
+    }
+
+    #[test]
+    pub fn test_statistics() {
+        let inventory = concat!(
+            r#"{"ripped_at":1699956800,"tracks":12,"seconds":3600,"bytes":400000000,"rereads":3,"skipped":1}"#,
+            "\n",
+            r#"{"ripped_at":1699960400,"tracks":10,"seconds":1800,"bytes":200000000,"rereads":0,"skipped":0}"#,
+            "\nnot json\n",
+            // ripped before the inventory had the statistics
+            r#"{"ripped_at":1700129600,"tracks":8}"#,
+        );
+        let statistics = inventory_statistics(inventory);
+        assert_eq!(3, statistics.discs);
+        assert_eq!(30, statistics.tracks);
+        assert_eq!(5400, statistics.seconds);
+        assert_eq!(600_000_000, statistics.bytes);
+        assert_eq!(4, statistics.read_errors);
+        // an hour apart in the morning UTC, the same day in every time zone
+        assert_eq!(
+            vec![2, 1],
+            statistics.per_day.values().copied().collect::<Vec<_>>()
+        );
+        assert!(statistics.to_string().starts_with(
+            "3 discs, 30 tracks\n1.5 hours of audio\n0.6 GB written\n1.3 read errors per disc\nMost in a day: 2 on "
+        ));
+    }
+
+    #[test]
+    pub fn test_find_duplicates() -> Result<()> {
+        let root = Path::new("/tmp/ripperx4_library");
+        fs::create_dir_all(root.join("Rock/Dire Straits/Money For Nothing"))?;
+        fs::create_dir_all(root.join("Dire Straits - Brothers in Arms"))?;
+        let inventory = root.join("inventory.jsonl");
+        fs::write(&inventory, "{\"release_id\":\"a541c6e6\"}\nnot json\n")?;
+        let config = Config {
+            library_root: Some(root.display().to_string()),
+            inventory_file: Some(inventory.display().to_string()),
+            ..Default::default()
+        };
+        let mut disc = Disc::with_tracks(1);
+        disc.artist = "Dire Straits".to_owned();
+        disc.title = "Money for Nothing".to_owned();
+        disc.release_id = Some("a541c6e6".to_owned());
+        let found = find_duplicates(&disc, &config);
+        assert_eq!(2, found.len());
+        assert!(found[0].ends_with("Rock/Dire Straits/Money For Nothing"));
+        assert!(found[1].contains("inventory.jsonl"));
+
+        disc.title = "Brothers in Arms".to_owned();
+        disc.release_id = None;
+        let found = find_duplicates(&disc, &config);
+        assert_eq!(1, found.len());
+        assert!(found[0].ends_with("Dire Straits - Brothers in Arms"));
+
+        disc.title = "Love over Gold".to_owned();
+        assert!(find_duplicates(&disc, &config).is_empty());
+        fs::remove_dir_all(root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_reorganize() -> Result<()> {
+        let root = Path::new("/tmp/ripperx4_reorganize");
+        let album = root.join("Dire Straits-Money for Nothing");
+        fs::create_dir_all(&album)?;
+        fs::write(album.join("Sultans of Swing.mp3"), "mp3")?;
+        fs::write(album.join("cover.jpg"), "jpg")?;
+        let inventory = root.join("inventory.jsonl");
+        let entry = format!(
+            r#"{{"artist":"Dire Straits","album":"Money for Nothing","year":1988,"files":[{{"path":"{0}/Sultans of Swing.mp3","number":1,"title":"Sultans of Swing","artist":"Dire Straits"}},{{"path":"{0}/Gone.mp3","number":2,"title":"Gone"}}]}}"#,
+            album.display()
+        );
+        fs::write(
+            &inventory,
+            format!("{{\"artist\":\"Old\"}}\n{entry}\nnot json\n"),
+        )?;
+        let mut config = Config {
+            encode_path: root.display().to_string(),
+            inventory_file: Some(inventory.display().to_string()),
+            ..Default::default()
+        };
+        assert!(plan_reorganize(&config)?.is_empty());
+
+        config.name_template = "%artist/%year - %album/%tracknum %title".to_owned();
+        let moves = plan_reorganize(&config)?;
+        let new_album = root.join("Dire Straits/1988 - Money for Nothing");
+        assert_eq!(
+            vec![Move {
+                from: album.join("Sultans of Swing.mp3"),
+                to: new_album.join("01 Sultans of Swing.mp3"),
+            }],
+            moves
+        );
+        reorganize(&moves, &config)?;
+        assert!(new_album.join("01 Sultans of Swing.mp3").is_file());
+        assert!(new_album.join("cover.jpg").is_file());
+        assert!(!album.exists());
+        let lines = fs::read_to_string(&inventory)?;
+        assert!(lines.contains("01 Sultans of Swing.mp3"));
+        assert!(lines.ends_with("not json\n"));
+        assert!(plan_reorganize(&config)?.is_empty());
+        fs::remove_dir_all(root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::data::{Disc, Encoded, SmtpConfig};
+use anyhow::Result;
+use lettre::{transport::smtp::authentication::Credentials, Message, SmtpTransport, Transport};
+
+/// Subject and body of the summary mail for a rip session
+pub fn summary(disc: &Disc, result: &Result<Vec<Encoded>>, checksums: bool) -> (String, String) {
+    let album = format!("{} - {}", disc.artist, disc.title);
+    match result {
+        Ok(report) => {
+            let mut body = format!("Ripped {album}\n\n");
+            for encoded in report {
+                body.push_str(&format!("{encoded}\n"));
+            }
+            if checksums {
+                body.push_str("\nChecksums were written to checksums.sha256\n");
+            }
+            (format!("ripperx4: ripped {album}"), body)
+        }
+        Err(e) => (
+            format!("ripperx4: failed to rip {album}"),
+            format!("Ripping {album} failed: {e}\n"),
+        ),
+    }
+}
+
+/// Send a mail through the configured SMTP server, using STARTTLS
+pub fn send(smtp: &SmtpConfig, subject: &str, body: &str) -> Result<()> {
+    let message = Message::builder()
+        .from(smtp.from.parse()?)
+        .to(smtp.to.parse()?)
+        .subject(subject)
+        .body(body.to_owned())?;
+    let mut transport = SmtpTransport::starttls_relay(&smtp.server)?.port(smtp.port);
+    if let Some(username) = &smtp.username {
+        transport = transport.credentials(Credentials::new(
+            username.clone(),
+            smtp.password.clone().unwrap_or_default(),
+        ));
+    }
+    transport.build().send(&message)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::summary;
+    use crate::data::{Disc, Encoded, ReadErrors};
+    use anyhow::anyhow;
+    use std::path::PathBuf;
+
+    #[test]
+    pub fn test_summary() {
+        let mut disc = Disc::with_tracks(1);
+        disc.artist = "Dire Straits".to_owned();
+        disc.title = "Money for Nothing".to_owned();
+        let report = vec![Encoded {
+            path: PathBuf::from("/tmp/Dire Straits-Money for Nothing/track.mp3"),
+            number: 1,
+            title: "Sultans of Swing".to_owned(),
+            size: 5_000_000,
+            bitrate: Some(130),
+            expected_bitrate: 130,
+            read_errors: ReadErrors {
+                rereads: 3,
+                retries: 3,
+                skipped: 1,
+            },
+            accuraterip: None,
+        }];
+        let (subject, body) = summary(&disc, &Ok(report), true);
+        assert_eq!("ripperx4: ripped Dire Straits - Money for Nothing", subject);
+        assert!(body.contains("1. Sultans of Swing: 5.0 MB, 130 kbit/s"));
+        assert!(body.contains("3 sectors re-read, 1 not corrected"));
+        assert!(body.contains("checksums.sha256"));
+
+        let (subject, body) = summary(&disc, &Err(anyhow!("no disc")), false);
+        assert_eq!(
+            "ripperx4: failed to rip Dire Straits - Money for Nothing",
+            subject
+        );
+        assert!(body.contains("no disc"));
+    }
+}
@@ -1,10 +1,45 @@
-use confy::ConfyError;
 use data::Config;
-use gtk::{gio::resources_register_include, prelude::*, Application};
+use gtk::{
+    gio::{resources_register_include, ApplicationFlags},
+    glib::{Char, OptionArg, OptionFlags},
+    prelude::*,
+    Application,
+};
+use std::io::{self, IsTerminal};
 
+mod accuraterip;
+mod automation;
+mod cache;
+mod cancel;
+mod cdtext;
+mod cli;
+mod config;
+mod controller;
+mod coverart;
 mod data;
+mod dbus;
+mod duration;
+mod encoder;
+mod genre;
+mod gnudb;
+mod gsettings;
+mod hooks;
+mod launcher;
+mod library;
+mod mail;
 mod musicbrainz;
+mod naming;
+mod player;
+mod preferences;
+mod profile;
+mod remote;
+mod replaygain;
 mod ripper;
+mod script;
+mod selection;
+mod silence;
+mod tags;
+mod udisks;
 mod ui;
 mod util;
 
@@ -12,22 +47,162 @@ pub fn main() {
     simplelog::TermLogger::init(
         simplelog::LevelFilter::Debug,
         simplelog::Config::default(),
-        simplelog::TerminalMode::Mixed,
+        // stdout is for the output of the command line, e.g. the TOC of `--discid`
+        simplelog::TerminalMode::Stderr,
         simplelog::ColorChoice::Auto,
     )
     .expect("Failed to initialize logger.");
     resources_register_include!("ripperx4.gresource").expect("Failed to register resources.");
 
-    let cfg: Result<Config, ConfyError> = confy::load("ripperx4", None);
-    if cfg.is_err() {
+    // once for the whole process, before any pipeline or element lookup
+    let gstreamer_error = gstreamer::init().err().map(|e| e.to_string());
+
+    if Config::load().is_err() {
         // make sure config exists
-        let config = Config::default();
-        confy::store("ripperx4", None, config).expect("failed to create config");
+        Config::default().store().expect("failed to create config");
     }
 
+    // a second launch (e.g. from a disc-inserted autostart) is forwarded to the running instance
     let app = Application::builder()
         .application_id("be.sourcery.ripperx4")
+        .flags(ApplicationFlags::HANDLES_COMMAND_LINE)
         .build();
-    app.connect_activate(ui::build);
+    app.add_main_option(
+        "scan",
+        Char::from(b's'),
+        OptionFlags::NONE,
+        OptionArg::None,
+        "Scan the disc in the drive",
+        None,
+    );
+    app.add_main_option(
+        "no-gui",
+        Char::from(b'n'),
+        OptionFlags::NONE,
+        OptionArg::None,
+        "Rip the disc in the drive without opening a window",
+        None,
+    );
+    app.add_main_option(
+        "encoder",
+        Char::from(b'e'),
+        OptionFlags::NONE,
+        OptionArg::String,
+        "Format to rip to with --no-gui: mp3, ogg, flac or opus",
+        Some("FORMAT"),
+    );
+    app.add_main_option(
+        "output",
+        Char::from(b'o'),
+        OptionFlags::NONE,
+        OptionArg::String,
+        "Folder to rip to with --no-gui",
+        Some("FOLDER"),
+    );
+    app.add_main_option(
+        "progress",
+        Char::from(b'p'),
+        OptionFlags::NONE,
+        OptionArg::String,
+        "How --no-gui reports the progress: plain, json (a line per event) or quiet",
+        Some("FORMAT"),
+    );
+    app.add_main_option(
+        "discid",
+        Char::from(0),
+        OptionFlags::NONE,
+        OptionArg::String,
+        "Print the TOC of the disc without ripping: cd-discid, musicbrainz (as cd-discid --musicbrainz) or id",
+        Some("FORMAT"),
+    );
+    app.add_main_option(
+        "verify",
+        Char::from(0),
+        OptionFlags::NONE,
+        OptionArg::String,
+        "Read the disc again and compare it to the JSON report of an earlier rip, without writing files",
+        Some("REPORT"),
+    );
+    app.add_main_option(
+        "serve-drive",
+        Char::from(0),
+        OptionFlags::NONE,
+        OptionArg::Int,
+        "Serve the CD drive to ripperx4 on other machines on this port, e.g. 4646",
+        Some("PORT"),
+    );
+    // runs in the launched process, so a headless rip never ends up in a running window
+    let error = gstreamer_error.clone();
+    app.connect_handle_local_options(move |_, options| {
+        let serve: Option<i32> = options.lookup("serve-drive").ok().flatten();
+        let verify: Option<String> = options.lookup("verify").ok().flatten();
+        let discid: Option<String> = options.lookup("discid").ok().flatten();
+        if let Some(format) = discid {
+            return match format.parse() {
+                Ok(format) => cli::print_toc(format),
+                Err(e) => {
+                    eprintln!("{e}");
+                    2
+                }
+            };
+        }
+        // without a display, e.g. over SSH to the rip box, the tracks are confirmed on the terminal
+        let interactive = !options.contains("no-gui") && serve.is_none() && verify.is_none();
+        if interactive && gtk::init().is_ok() {
+            return -1;
+        }
+        if interactive && !io::stdin().is_terminal() {
+            eprintln!("Can't open a display, use --no-gui to rip without one");
+            return 1;
+        }
+        if let Some(e) = &error {
+            eprintln!("GStreamer could not be initialized: {e}");
+            return 1;
+        }
+        if let Some(port) = serve {
+            let Ok(port) = u16::try_from(port) else {
+                eprintln!("Invalid port {port}");
+                return 2;
+            };
+            return match remote::serve(port) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Failed to serve the drive: {e}");
+                    1
+                }
+            };
+        }
+        let encoder: Option<String> = options.lookup("encoder").ok().flatten();
+        let output: Option<String> = options.lookup("output").ok().flatten();
+        let progress: Option<String> = options.lookup("progress").ok().flatten();
+        let progress = match progress
+            .as_deref()
+            .map_or(Ok(cli::Progress::Plain), str::parse)
+        {
+            Ok(progress) => progress,
+            Err(e) => {
+                eprintln!("{e}");
+                return 2;
+            }
+        };
+        if let Some(report) = verify {
+            return cli::verify(&report, progress);
+        }
+        if interactive {
+            eprintln!("Can't open a display, ripping on the terminal");
+        }
+        cli::run(encoder.as_deref(), output, progress, interactive)
+    });
+    app.connect_activate(move |app| ui::activate(app, gstreamer_error.as_deref()));
+    app.connect_command_line(|app, command_line| {
+        app.activate();
+        if command_line.options_dict().contains("scan") {
+            app.activate_action("scan", None);
+        }
+        0
+    });
     app.run();
+    if let Err(e) = config::manager().flush() {
+        eprintln!("Failed to store the configuration: {e}");
+    }
 }
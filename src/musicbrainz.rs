@@ -1,6 +1,45 @@
-use crate::data::{Disc, Track};
+use crate::{
+    cache::MetadataCache,
+    data::{Config, Disc, Track},
+};
 use anyhow::{anyhow, Result};
+use log::debug;
 use minidom::Element;
+use std::{
+    fmt::{self, Display},
+    thread,
+    time::Duration,
+};
+
+/// How many times a request is retried while MusicBrainz answers 503 (busy)
+const RETRIES: u32 = 3;
+/// ISO 639-3 codes of `Config::preferred_languages` and the ISO 639-1 codes of alias locales
+const LANGUAGE_CODES: &[(&str, &str)] = &[
+    ("ara", "ar"),
+    ("ces", "cs"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hun", "hu"),
+    ("ita", "it"),
+    ("jpn", "ja"),
+    ("kor", "ko"),
+    ("nld", "nl"),
+    ("nor", "no"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("rus", "ru"),
+    ("spa", "es"),
+    ("swe", "sv"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("zho", "zh"),
+];
 
 macro_rules! get_child {
     ($parent:ident, $child:literal) => {
@@ -20,56 +59,358 @@ macro_rules! get_first_child {
     };
 }
 
+/// A release matching a disc id, one disc id can belong to several releases (countries,
+/// reissues, compilations)
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub date: Option<String>,
+    pub country: Option<String>,
+    /// ISO 639-3 code of the language of the titles, e.g. "eng"
+    pub language: Option<String>,
+}
+
+impl Display for Release {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} - {}", self.artist, self.title)?;
+        let details: Vec<&str> = [&self.date, &self.country]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
 /// Lookup a disc by discid on musicbrainz
-/// Returns a `Disc` if a disc was found and parsing metadata succeeds
-pub fn lookup(discid: &str) -> Result<Disc> {
-    let lookup = format!("https://musicbrainz.org/ws/2/discid/{discid}");
-    let body: String = ureq::get(&lookup).call()?.into_string()?;
-    let release = get_release_url(&body)?;
-    let body: String = ureq::get(&release).call()?.into_string()?;
-    parse_metadata(&body)
-}
-
-/// Return an URL to a release for the given disc
-/// Parses the XML returned by the query on discid
-fn get_release_url(body: &str) -> Result<String> {
+/// Returns a `Disc` for the release the user picked for the disc id, or else the preferred
+/// matching release, if parsing metadata succeeds
+pub fn lookup(discid: &str, config: &Config) -> Result<Disc> {
+    if let Some(release) = MetadataCache::load().release(discid) {
+        debug!("using release {release} picked for {discid}");
+        return lookup_release(release, discid, config);
+    }
+    let releases = releases(discid, config)?;
+    let release = releases.first().ok_or(anyhow!("failed to get release"))?;
+    lookup_release(&release.id, discid, config)
+}
+
+/// All releases the disc id belongs to, the preferred ones first, see `prefer_releases`
+pub fn releases(discid: &str, config: &Config) -> Result<Vec<Release>> {
+    let lookup = format!("https://musicbrainz.org/ws/2/discid/{discid}?inc=artist-credits");
+    let body = get(&lookup)?;
+    let mut releases = parse_releases(&body)?;
+    prefer_releases(&mut releases, config);
+    Ok(releases)
+}
+
+/// Move the releases in the preferred languages, then those from the preferred countries, to
+/// the front, e.g. the European edition before the Japanese one
+/// Otherwise the order of MusicBrainz is kept
+fn prefer_releases(releases: &mut [Release], config: &Config) {
+    let rank = |preferred: &[String], value: &Option<String>| {
+        value
+            .as_ref()
+            .and_then(|value| preferred.iter().position(|p| p.eq_ignore_ascii_case(value)))
+            .unwrap_or(preferred.len())
+    };
+    releases.sort_by_key(|release| {
+        (
+            rank(&config.preferred_languages, &release.language),
+            rank(&config.preferred_countries, &release.country),
+        )
+    });
+}
+
+/// Search the releases with the barcode and/or catalog number, these usually pin down the edition
+/// better than the disc id, which many pressings share
+pub fn search_releases(
+    barcode: Option<&str>,
+    catalog_number: Option<&str>,
+) -> Result<Vec<Release>> {
+    let query = search_query(barcode, catalog_number)
+        .ok_or(anyhow!("enter a barcode or a catalog number"))?;
+    let search = format!(
+        "https://musicbrainz.org/ws/2/release/?query={}&limit=25",
+        encode(&query)
+    );
+    let body = get(&search)?;
+    let metadata: Element = body.parse()?;
+    let release_list = get_child!(metadata, "release-list", "failed to get release list")?;
+    let releases = parse_release_list(release_list);
+    if releases.is_empty() {
+        return Err(anyhow!("no release with {query}"));
+    }
+    Ok(releases)
+}
+
+/// The Lucene query for the search, `None` without anything to search for
+fn search_query(barcode: Option<&str>, catalog_number: Option<&str>) -> Option<String> {
+    let terms: Vec<String> = [("barcode", barcode), ("catno", catalog_number)]
+        .into_iter()
+        .filter_map(|(field, value)| {
+            // barcodes are printed with spaces, MusicBrainz has them without
+            let value = match field {
+                "barcode" => value?.replace([' ', '-'], ""),
+                _ => value?.trim().replace('"', ""),
+            };
+            (!value.is_empty()).then(|| format!("{field}:\"{value}\""))
+        })
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" AND "))
+}
+
+/// Percent-encode a query parameter
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Get the metadata of the CD with the disc id in a release, named in the preferred languages
+pub fn lookup_release(release_id: &str, discid: &str, config: &Config) -> Result<Disc> {
+    let body = get(&release_url(release_id))?;
+    parse_metadata(&body, discid, &config.preferred_languages)
+}
+
+/// Fill in the Latin titles and artists of a disc in another script from the pseudo-release
+/// with its transliterated track list
+pub fn latin_names(disc: &mut Disc) -> Result<()> {
+    let id = disc.transliteration_id.as_deref().ok_or(anyhow!(
+        "there is no transliterated track list on MusicBrainz"
+    ))?;
+    let body = get(&release_url(id))?;
+    // pseudo-releases have no disc ids, the medium is found by its position in the set
+    let latin = parse_medium(&body, "", disc.disc_number, &[])?;
+    disc.latin_title = Some(latin.title);
+    disc.latin_artist = Some(latin.artist);
+    for (track, latin) in disc.tracks.iter_mut().zip(latin.tracks) {
+        track.latin_title = Some(latin.title);
+        track.latin_artist = Some(latin.artist);
+    }
+    Ok(())
+}
+
+/// Add the release of the ripped disc to the configured collection
+/// Does nothing unless a token and a collection are configured
+pub fn add_to_collection(disc: &Disc, config: &Config) -> Result<()> {
+    let (Some(token), Some(collection), Some(release)) = (
+        config.musicbrainz_token.as_deref(),
+        config.musicbrainz_collection.as_deref(),
+        disc.release_id.as_deref(),
+    ) else {
+        return Ok(());
+    };
+    let url = format!(
+        "https://musicbrainz.org/ws/2/collection/{collection}/releases/{release}?client=ripperx4-{}",
+        env!("CARGO_PKG_VERSION")
+    );
+    ureq::put(&url)
+        .set("Authorization", &format!("Bearer {token}"))
+        .call()?;
+    debug!("added {release} to collection {collection}");
+    Ok(())
+}
+
+/// GET the body of the URL, retrying with backoff while MusicBrainz is busy
+fn get(url: &str) -> Result<String> {
+    let mut attempt = 0;
+    loop {
+        match ureq::get(url).call() {
+            Ok(response) => return Ok(response.into_string()?),
+            Err(ureq::Error::Status(503, response)) => {
+                if attempt == RETRIES {
+                    return Err(anyhow!(
+                        "MusicBrainz is busy, gave up after {RETRIES} retries"
+                    ));
+                }
+                let delay = retry_delay(attempt, response.header("Retry-After"));
+                debug!("MusicBrainz is busy, retrying in {delay:?}");
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Honour the Retry-After header (in seconds, capped at 10), otherwise back off 1, 2, 4... seconds
+fn retry_delay(attempt: u32, retry_after: Option<&str>) -> Duration {
+    retry_after
+        .and_then(|secs| secs.trim().parse::<u64>().ok())
+        .map_or(Duration::from_secs(1 << attempt), |secs| {
+            Duration::from_secs(secs.min(10))
+        })
+}
+
+/// The release id in a MusicBrainz id or the URL of a release page, e.g.
+/// `https://musicbrainz.org/release/a541c6e6-…/discids`
+pub fn parse_release_id(text: &str) -> Option<String> {
+    let text = text.trim();
+    let id = match text.split_once("/release/") {
+        Some((_, path)) => path.split(['/', '?', '#']).next()?,
+        None => text,
+    };
+    let groups: Vec<&str> = id.split('-').collect();
+    let valid = groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+        && groups
+            .iter()
+            .all(|g| g.chars().all(|c| c.is_ascii_hexdigit()));
+    valid.then(|| id.to_ascii_lowercase())
+}
+
+/// Return an URL to the metadata of a release
+fn release_url(release_id: &str) -> String {
+    format!(
+        "https://musicbrainz.org/ws/2/release/{release_id}?inc=%20recordings+artist-credits+release-groups+recording-level-rels+work-rels+work-level-rels+artist-rels+release-rels+labels+discids+genres+tags+aliases"
+    )
+}
+
+/// Parse the releases for the given disc
+/// Parses the XML returned by the query on discid, fails if there are none
+fn parse_releases(body: &str) -> Result<Vec<Release>> {
     let metadata: Element = body.parse()?;
     let disc = get_first_child!(metadata, "failed to get disc")?;
     let release_list = get_child!(disc, "release-list", "failed to get release list")?;
-    let release = get_child!(release_list, "release", "failed to get release")?;
-    let release_id = release
-        .attr("id")
-        .ok_or(anyhow!("failed to get release id"))?;
-    Ok(format!(
-        "https://musicbrainz.org/ws/2/release/{release_id}?inc=%20recordings+artist-credits"
-    ))
+    let releases = parse_release_list(release_list);
+    if releases.is_empty() {
+        return Err(anyhow!("failed to get release"));
+    }
+    Ok(releases)
+}
+
+/// The releases in a `release-list` of a disc id lookup or a search
+fn parse_release_list(release_list: &Element) -> Vec<Release> {
+    release_list
+        .children()
+        .filter(|release| release.name() == "release")
+        .filter_map(|release| {
+            Some(Release {
+                id: release.attr("id")?.to_owned(),
+                title: get_child!(release, "title").map(Element::text)?,
+                artist: get_artist(release, &[]).unwrap_or_default(),
+                date: get_child!(release, "date").map(Element::text),
+                country: get_child!(release, "country").map(Element::text),
+                language: get_child!(release, "text-representation")
+                    .and_then(|text| get_child!(text, "language"))
+                    .map(Element::text),
+            })
+        })
+        .collect()
 }
 
 /// Parse the metadata for the given release
+/// The tracks are those of the medium with the disc id, the first one if none has it
+/// Titles and artists with an alias in one of the `languages` get the alias of the first one
 /// Returns a `Disc` if parsing succeeds
-fn parse_metadata(xml: &str) -> Result<Disc> {
+fn parse_metadata(xml: &str, discid: &str, languages: &[String]) -> Result<Disc> {
+    parse_medium(xml, discid, None, languages)
+}
+
+/// Parse the metadata of the medium with the disc id, or else the one at `position` or the first
+fn parse_medium(
+    xml: &str,
+    discid: &str,
+    position: Option<u32>,
+    languages: &[String],
+) -> Result<Disc> {
     let metadata: Element = xml.parse()?;
     let release = get_first_child!(metadata, "failed to get release")?;
-    let mut disc = Disc::default();
+    let release_group = get_child!(release, "release-group");
+    let mut disc = Disc {
+        release_id: release.attr("id").map(str::to_owned),
+        release_group_id: release_group
+            .and_then(|group| group.attr("id"))
+            .map(str::to_owned),
+        year: get_child!(release, "date").and_then(|date| date.text().get(..4)?.parse().ok()),
+        date: get_child!(release, "date")
+            .map(Element::text)
+            .filter(|date| !date.is_empty()),
+        genre: top_genre(release).or_else(|| release_group.and_then(top_genre)),
+        transliteration_id: transliteration(release),
+        barcode: get_child!(release, "barcode")
+            .map(Element::text)
+            .filter(|barcode| !barcode.is_empty()),
+        catalog_number: get_child!(release, "label-info-list")
+            .into_iter()
+            .flat_map(Element::children)
+            .find_map(|info| get_child!(info, "catalog-number").map(Element::text)),
+        ..Default::default()
+    };
+    if let Some(representation) = get_child!(release, "text-representation") {
+        disc.language = get_child!(representation, "language").map(Element::text);
+        disc.script = get_child!(representation, "script").map(Element::text);
+    }
     if let Some(title) = get_child!(release, "title") {
-        disc.title = title.text();
+        disc.title = localized(release, languages).unwrap_or(title.text());
     }
 
-    disc.artist = get_artist(release)?;
+    disc.artist = get_artist(release, languages)?;
 
     let medium_list = get_child!(release, "medium-list", "failed to get medium list")?;
-    let medium = get_first_child!(medium_list, "failed to get medium")?;
+    let media: Vec<&Element> = medium_list
+        .children()
+        .filter(|medium| medium.name() == "medium")
+        .collect();
+    let medium = media
+        .iter()
+        .find(|medium| {
+            get_child!(medium, "disc-list")
+                .is_some_and(|discs| discs.children().any(|d| d.attr("id") == Some(discid)))
+        })
+        .or_else(|| {
+            let position = position?.to_string();
+            media
+                .iter()
+                .find(|medium| get_child!(medium, "position").is_some_and(|p| p.text() == position))
+        })
+        .or(media.first())
+        .ok_or(anyhow!("failed to get medium"))?;
+    if media.len() > 1 {
+        disc.disc_number = get_child!(medium, "position").and_then(|p| p.text().parse().ok());
+        disc.disc_count = u32::try_from(media.len()).ok();
+    }
     let track_list = get_child!(medium, "track-list", "failed to get track list")?;
     for (i, track) in track_list.children().enumerate() {
         let mut dtrack = Track::default();
         let num: Option<u32> = get_child!(track, "number").and_then(|num| num.text().parse().ok());
         dtrack.number = num.unwrap_or(u32::try_from(i)?);
 
+        // the length of the track on this release, or else of the recording
+        dtrack.length_ms = [Some(track), get_child!(track, "recording")]
+            .into_iter()
+            .flatten()
+            .find_map(|element| get_child!(element, "length")?.text().parse().ok());
         if let Some(recording) = get_child!(track, "recording") {
-            if let Some(title) = get_child!(recording, "title") {
-                dtrack.title = title.text();
+            dtrack.recording_id = recording.attr("id").map(str::to_owned);
+            // the track has a title of its own when it differs from the recording, e.g. on a
+            // transliterated pseudo-release
+            if let Some(title) = get_child!(track, "title").or(get_child!(recording, "title")) {
+                dtrack.title = localized(recording, languages).unwrap_or(title.text());
+            }
+            dtrack.artist = get_artist(recording, languages).unwrap_or_default();
+            if let Some(work) = relations(recording, "work", "performance").next() {
+                if let Some(title) = get_child!(work, "title") {
+                    let title = localized(work, languages).unwrap_or(title.text());
+                    let (work_title, movement) = split_work(&title, &dtrack.title);
+                    dtrack.work = Some(work_title);
+                    dtrack.movement = movement;
+                }
+                dtrack.composer = relations(work, "artist", "composer")
+                    .find_map(|artist| get_child!(artist, "name"))
+                    .map(Element::text);
             }
-            dtrack.artist = get_artist(recording).unwrap_or_default();
         }
         dtrack.rip = true;
         disc.tracks.push(dtrack);
@@ -77,24 +418,117 @@ fn parse_metadata(xml: &str) -> Result<Disc> {
     Ok(disc)
 }
 
-/// Parse out the Artist name from a `artist-credit` XML element
-fn get_artist(element: &Element) -> Result<String> {
+/// The pseudo-release with the track list of the release transliterated or translated
+fn transliteration(release: &Element) -> Option<String> {
+    release
+        .children()
+        .filter(|list| {
+            list.name() == "relation-list" && list.attr("target-type") == Some("release")
+        })
+        .flat_map(Element::children)
+        .filter(|relation| relation.attr("type") == Some("transl-tracklisting"))
+        .filter(|relation| {
+            get_child!(relation, "direction").map_or(true, |d| d.text() != "backward")
+        })
+        .find_map(|relation| get_child!(relation, "target").map(Element::text))
+}
+
+/// The genre most voted for, or the most used tag when there are no genres
+fn top_genre(element: &Element) -> Option<String> {
+    ["genre-list", "tag-list"].into_iter().find_map(|list| {
+        let list = element.children().find(|e| e.name() == list)?;
+        list.children()
+            .filter_map(|genre| {
+                let count: u32 = genre.attr("count")?.parse().ok()?;
+                Some((count, get_child!(genre, "name")?.text()))
+            })
+            .max_by_key(|(count, _)| *count)
+            .map(|(_, name)| name)
+    })
+}
+
+/// The targets of the relations of type `kind` to `target_type` entities
+fn relations<'a>(
+    element: &'a Element,
+    target_type: &'a str,
+    kind: &'a str,
+) -> impl Iterator<Item = &'a Element> {
+    element
+        .children()
+        .filter(move |list| {
+            list.name() == "relation-list" && list.attr("target-type") == Some(target_type)
+        })
+        .flat_map(Element::children)
+        .filter(move |relation| relation.attr("type") == Some(kind))
+        .filter_map(move |relation| relation.children().find(|e| e.name() == target_type))
+}
+
+/// Split a work title in the work and the movement
+/// Parts of a work are named "Work: Movement" on MusicBrainz, when the performed work is the whole
+/// work the movement is taken from the recording title if that follows the same convention
+fn split_work(work: &str, recording: &str) -> (String, Option<String>) {
+    if let Some((work, movement)) = work.split_once(": ") {
+        return (work.to_owned(), Some(movement.to_owned()));
+    }
+    let movement = recording
+        .strip_prefix(work)
+        .and_then(|rest| rest.strip_prefix(": "))
+        .map(str::to_owned);
+    (work.to_owned(), movement)
+}
+
+/// Parse out the Artist name from a `artist-credit` XML element, its alias in the first of the
+/// `languages` that has one
+fn get_artist(element: &Element, languages: &[String]) -> Result<String> {
     let artist_credit = get_child!(element, "artist-credit", "failed to get artist credit")?;
     let name_credit = get_child!(artist_credit, "name-credit", "failed to get name credit")?;
     let artist = get_child!(name_credit, "artist", "failed to get artist")?;
-    Ok(get_child!(artist, "name", "failed to get artist name")?.text())
+    let name = get_child!(artist, "name", "failed to get artist name")?.text();
+    Ok(localized(artist, languages).unwrap_or(name))
+}
+
+/// The alias of the entity for the first of the `languages` (ISO 639-3) with one, the primary
+/// alias of the locale when it has several
+fn localized(element: &Element, languages: &[String]) -> Option<String> {
+    let aliases: Vec<(&str, bool, String)> = get_child!(element, "alias-list")?
+        .children()
+        .filter_map(|alias| {
+            let primary = alias.attr("primary") == Some("primary");
+            Some((alias.attr("locale")?, primary, alias.text()))
+        })
+        .collect();
+    languages.iter().find_map(|language| {
+        aliases
+            .iter()
+            .filter(|(locale, ..)| locale_matches(language, locale))
+            .max_by_key(|(_, primary, _)| *primary)
+            .map(|(.., name)| name.clone())
+    })
+}
+
+/// Whether an alias locale, e.g. "en" or "en_GB", is in the language, e.g. "eng"
+fn locale_matches(language: &str, locale: &str) -> bool {
+    let locale = locale.split(['_', '-']).next().unwrap_or(locale);
+    language.eq_ignore_ascii_case(locale)
+        || LANGUAGE_CODES
+            .iter()
+            .any(|(code, short)| code.eq_ignore_ascii_case(language) && *short == locale)
 }
 
 #[cfg(test)]
 mod test {
-    use std::{env, fs};
+    use std::{env, fs, time::Duration};
 
-    use super::{get_release_url, lookup, parse_metadata};
+    use super::{
+        encode, locale_matches, lookup, parse_medium, parse_metadata, parse_release_id,
+        parse_releases, prefer_releases, retry_delay, search_query, split_work, Release,
+    };
+    use crate::data::Config;
     use anyhow::Result;
 
     #[test]
     fn test_good_net() -> Result<()> {
-        let disc = lookup("xA3p59dQpJpDXZYHz1SSQ491oaU-")?;
+        let disc = lookup("xA3p59dQpJpDXZYHz1SSQ491oaU-", &Config::default())?;
         assert_eq!("Dire Straits", disc.artist);
         assert_eq!("Money for Nothing", disc.title);
         assert_eq!(12, disc.tracks.len());
@@ -109,34 +543,262 @@ mod test {
         let mut path = env::var("CARGO_MANIFEST_DIR")?;
         path.push_str("/resources/test/direstraits-releases-metadata.xml");
         let contents = fs::read_to_string(path)?;
-        let disc = parse_metadata(&contents)?;
+        let disc = parse_metadata(&contents, "xA3p59dQpJpDXZYHz1SSQ491oaU-", &[])?;
         assert_eq!("Dire Straits", disc.artist);
         assert_eq!("Money for Nothing", disc.title);
         assert_eq!(12, disc.tracks.len());
         assert_eq!("Sultans of Swing", disc.tracks[0].title);
         assert_eq!("Dire Straits", disc.tracks[0].artist);
         assert_eq!(1, disc.tracks[0].number);
+        assert_eq!(Some(346_293), disc.tracks[0].length_ms);
+        assert_eq!(
+            Some("a541c6e6-eb8c-4fb2-b0bb-5c07e89c2182"),
+            disc.release_id.as_deref()
+        );
+        assert_eq!(Some(1988), disc.year);
+        assert_eq!(None, disc.disc_number);
+        assert_eq!(Some("042283641921"), disc.barcode.as_deref());
+        assert_eq!(
+            Some("5e26f77d-73ee-4085-a7c8-d75d6a5cdfbf"),
+            disc.tracks[0].recording_id.as_deref()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_metadata_set() -> Result<()> {
+        let medium = |position: u32, discid: &str, title: &str| {
+            format!(
+                r#"<medium><position>{position}</position><disc-list><disc id="{discid}"/></disc-list>
+                <track-list count="1"><track><number>1</number><recording id="rec-{position}">
+                    <title>{title}</title></recording></track></track-list></medium>"#
+            )
+        };
+        let xml = format!(
+            r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"><release id="r">
+            <title>The Wall</title><date>1979-11-30</date>
+            <artist-credit><name-credit><artist><name>Pink Floyd</name></artist></name-credit></artist-credit>
+            <release-group id="rg"><genre-list><genre count="2"><name>rock</name></genre>
+                <genre count="5"><name>progressive rock</name></genre></genre-list></release-group>
+            <medium-list count="2">{}{}</medium-list>
+        </release></metadata>"#,
+            medium(1, "disc-one", "In the Flesh?"),
+            medium(2, "disc-two", "Hey You")
+        );
+        let disc = parse_metadata(&xml, "disc-two", &[])?;
+        assert_eq!("Hey You", disc.tracks[0].title);
+        assert_eq!(Some("rec-2"), disc.tracks[0].recording_id.as_deref());
+        assert_eq!((Some(2), Some(2)), (disc.disc_number, disc.disc_count));
+        assert_eq!(Some(1979), disc.year);
+        assert_eq!(Some("1979-11-30".to_owned()), disc.date);
+        assert_eq!(Some("progressive rock"), disc.genre.as_deref());
+        // a disc id that is not in the release gets the first medium
+        let disc = parse_metadata(&xml, "other", &[])?;
+        assert_eq!("In the Flesh?", disc.tracks[0].title);
+        // or the one at the position asked for
+        let disc = parse_medium(&xml, "other", Some(2), &[])?;
+        assert_eq!("Hey You", disc.tracks[0].title);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_releases() -> Result<()> {
+        let mut path = env::var("CARGO_MANIFEST_DIR")?;
+        path.push_str("/resources/test/direstraits-disc-metadata.xml");
+        let contents = fs::read_to_string(path)?;
+        let releases = parse_releases(&contents)?;
+        assert_eq!(1, releases.len());
+        assert_eq!("a541c6e6-eb8c-4fb2-b0bb-5c07e89c2182", releases[0].id);
+        assert_eq!(
+            " - Money for Nothing (1988-10, XE)",
+            releases[0].to_string()
+        );
+
+        let releases = parse_releases(
+            r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"><disc><release-list count="2">
+                <release id="1"><title>Album</title><artist-credit><name-credit><artist>
+                    <name>Band</name></artist></name-credit></artist-credit></release>
+                <release id="2"><title>Album (Remaster)</title><date>2001</date></release>
+            </release-list></disc></metadata>"#,
+        )?;
+        assert_eq!(
+            vec!["Band - Album", " - Album (Remaster) (2001)"],
+            releases.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefer_releases() {
+        let release = |id: &str, country: Option<&str>, language: Option<&str>| Release {
+            id: id.to_owned(),
+            title: "Album".to_owned(),
+            artist: "Band".to_owned(),
+            date: None,
+            country: country.map(str::to_owned),
+            language: language.map(str::to_owned),
+        };
+        let mut releases = vec![
+            release("jp", Some("JP"), Some("jpn")),
+            release("us", Some("US"), Some("eng")),
+            release("unknown", None, None),
+            release("eu", Some("XE"), Some("eng")),
+        ];
+        let ids = |releases: &[Release]| releases.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+        // nothing preferred, the MusicBrainz order
+        prefer_releases(&mut releases, &Config::default());
+        assert_eq!(vec!["jp", "us", "unknown", "eu"], ids(&releases));
+
+        let config = Config {
+            preferred_languages: vec!["eng".to_owned()],
+            preferred_countries: vec!["xe".to_owned(), "GB".to_owned()],
+            ..Default::default()
+        };
+        prefer_releases(&mut releases, &config);
+        assert_eq!(vec!["eu", "us", "jp", "unknown"], ids(&releases));
+    }
+
+    #[test]
+    fn test_parse_metadata_work() -> Result<()> {
+        let xml = r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"><release id="r">
+            <title>Symphonies 5 &amp; 7</title>
+            <artist-credit><name-credit><artist><name>Carlos Kleiber</name></artist></name-credit></artist-credit>
+            <medium-list><medium><track-list><track><number>1</number><recording>
+                <title>Symphony no. 5: I. Allegro con brio</title>
+                <artist-credit><name-credit><artist><name>Wiener Philharmoniker</name></artist></name-credit></artist-credit>
+                <relation-list target-type="work"><relation type="performance"><work>
+                    <title>Symphony no. 5 in C minor, op. 67: I. Allegro con brio</title>
+                    <relation-list target-type="artist"><relation type="composer"><artist>
+                        <name>Ludwig van Beethoven</name>
+                    </artist></relation></relation-list>
+                </work></relation></relation-list>
+            </recording></track></track-list></medium></medium-list>
+        </release></metadata>"#;
+        let disc = parse_metadata(xml, "", &[])?;
+        let track = &disc.tracks[0];
+        assert_eq!(Some("Ludwig van Beethoven"), track.composer.as_deref());
+        assert_eq!(
+            Some("Symphony no. 5 in C minor, op. 67"),
+            track.work.as_deref()
+        );
+        assert_eq!(Some("I. Allegro con brio"), track.movement.as_deref());
+        assert_eq!(
+            "Symphony no. 5 in C minor, op. 67: I. Allegro con brio",
+            track.classical_title()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_metadata_script() -> Result<()> {
+        let xml = r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"><release id="r">
+            <title>初恋</title>
+            <text-representation><language>jpn</language><script>Jpan</script></text-representation>
+            <label-info-list><label-info><catalog-number>TOCT-24067</catalog-number></label-info></label-info-list>
+            <artist-credit><name-credit><artist><name>宇多田ヒカル</name></artist></name-credit></artist-credit>
+            <relation-list target-type="release">
+                <relation type="transl-tracklisting"><target>old</target><direction>backward</direction></relation>
+                <relation type="transl-tracklisting"><target>latin</target></relation>
+            </relation-list>
+            <medium-list><medium><track-list><track><number>1</number><title>初恋</title>
+                <recording><title>Hatsukoi</title></recording>
+            </track></track-list></medium></medium-list>
+        </release></metadata>"#;
+        let disc = parse_metadata(xml, "", &[])?;
+        assert_eq!(Some("jpn"), disc.language.as_deref());
+        assert_eq!(Some("Jpan"), disc.script.as_deref());
+        assert_eq!(Some("latin"), disc.transliteration_id.as_deref());
+        assert_eq!(Some("TOCT-24067"), disc.catalog_number.as_deref());
+        // the title of the track, not of the recording
+        assert_eq!("初恋", disc.tracks[0].title);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_metadata_aliases() -> Result<()> {
+        let xml = r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"><release id="r">
+            <title>Песни</title>
+            <alias-list><alias locale="de">Lieder</alias></alias-list>
+            <artist-credit><name-credit><artist><name>Леонид Утёсов</name>
+                <alias-list>
+                    <alias locale="en">L. Utyosov</alias>
+                    <alias locale="en" primary="primary">Leonid Utyosov</alias>
+                    <alias locale="de_DE">Leonid Utjossow</alias>
+                </alias-list>
+            </artist></name-credit></artist-credit>
+            <medium-list><medium><track-list><track><number>1</number>
+                <recording><title>Сердце</title>
+                    <alias-list><alias locale="en">Heart</alias></alias-list>
+                </recording>
+            </track></track-list></medium></medium-list>
+        </release></metadata>"#;
+        let languages = ["deu".to_owned(), "eng".to_owned()];
+        let disc = parse_metadata(xml, "", &languages)?;
+        // German first, falling back to English where there is no German alias
+        assert_eq!("Lieder", disc.title);
+        assert_eq!("Leonid Utjossow", disc.artist);
+        assert_eq!("Heart", disc.tracks[0].title);
+        let disc = parse_metadata(xml, "", &languages[1..])?;
+        assert_eq!("Песни", disc.title);
+        assert_eq!("Leonid Utyosov", disc.artist);
+        // no preferred languages, the names as released
+        let disc = parse_metadata(xml, "", &[])?;
+        assert_eq!("Леонид Утёсов", disc.artist);
+        assert_eq!("Сердце", disc.tracks[0].title);
+        assert!(locale_matches("eng", "en_GB"));
+        assert!(locale_matches("en", "en"));
+        assert!(!locale_matches("eng", "de"));
         Ok(())
     }
 
+    #[test]
+    fn test_search_query() {
+        assert_eq!(None, search_query(None, Some(" ")));
+        assert_eq!(
+            Some(r#"barcode:"042283641921""#.to_owned()),
+            search_query(Some("0 42283 64192 1"), None)
+        );
+        let query = search_query(Some("042283641921"), Some("824 499-2"));
+        assert_eq!(
+            Some(r#"barcode:"042283641921" AND catno:"824 499-2""#.to_owned()),
+            query
+        );
+        assert_eq!(
+            "barcode%3A%22042283641921%22%20AND%20catno%3A%22824%20499-2%22",
+            encode(&query.unwrap_or_default())
+        );
+    }
+
+    #[test]
+    fn test_split_work() {
+        assert_eq!(
+            ("Goldberg Variations".to_owned(), Some("Aria".to_owned())),
+            split_work("Goldberg Variations", "Goldberg Variations: Aria")
+        );
+        assert_eq!(("Bolero".to_owned(), None), split_work("Bolero", "Boléro"));
+    }
+
     #[test]
     fn parse_metadata_bad_non_xml() -> Result<()> {
-        let e = parse_metadata("brol");
+        let e = parse_metadata("brol", "", &[]);
         assert!(e.is_err());
         Ok(())
     }
 
     #[test]
     fn parse_metadata_bad_xml_no_releases() -> Result<()> {
-        let e =
-            parse_metadata(r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"></metadata>"#);
+        let e = parse_metadata(
+            r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"></metadata>"#,
+            "",
+            &[],
+        );
         assert!(e.is_err());
         Ok(())
     }
 
     #[test]
     fn parse_disc_bad_non_xml() -> Result<()> {
-        let e = get_release_url("brol");
+        let e = parse_releases("brol");
         assert!(e.is_err());
         Ok(())
     }
@@ -144,7 +806,7 @@ mod test {
     #[test]
     fn parse_disc_bad_xml_no_discs() -> Result<()> {
         let e =
-            get_release_url(r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"></metadata>"#);
+            parse_releases(r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"></metadata>"#);
         assert!(e.is_err());
         Ok(())
     }
@@ -152,15 +814,51 @@ mod test {
     #[test]
     fn parse_disc_bad_xml_discs() -> Result<()> {
         let e =
-            get_release_url(r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"></metadata>"#);
+            parse_releases(r#"<metadata xmlns="http://musicbrainz.org/ns/mmd-2.0#"></metadata>"#);
         assert!(e.is_err());
         Ok(())
     }
 
     #[test]
     fn test_bad_discid() -> Result<()> {
-        let disc = lookup("dees besta zeker ni");
+        let disc = lookup("dees besta zeker ni", &Config::default());
         assert!(disc.is_err());
         Ok(())
     }
+
+    #[test]
+    fn test_parse_release_id() {
+        let id = "a541c6e6-4f8c-4c8b-9d5b-5ea0e1e6a3f1";
+        assert_eq!(Some(id.to_owned()), parse_release_id(id));
+        assert_eq!(
+            Some(id.to_owned()),
+            parse_release_id(&format!(" https://musicbrainz.org/release/{id}/discids\n"))
+        );
+        assert_eq!(
+            Some(id.to_owned()),
+            parse_release_id(&format!(
+                "https://musicbrainz.org/release/{}",
+                id.to_uppercase()
+            ))
+        );
+        assert_eq!(None, parse_release_id("a541c6e6"));
+        assert_eq!(
+            None,
+            parse_release_id("https://musicbrainz.org/artist/614e3804-7d34-41ba-857f-811bad7c2b7a")
+        );
+        assert_eq!(None, parse_release_id(""));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(Duration::from_secs(1), retry_delay(0, None));
+        assert_eq!(Duration::from_secs(4), retry_delay(2, None));
+        assert_eq!(Duration::from_secs(3), retry_delay(0, Some("3")));
+        assert_eq!(Duration::from_secs(10), retry_delay(0, Some("120")));
+        // HTTP dates are not supported, fall back to the backoff
+        assert_eq!(
+            Duration::from_secs(2),
+            retry_delay(1, Some("Wed, 21 Oct 2015 07:28:00 GMT"))
+        );
+    }
 }
@@ -0,0 +1,378 @@
+use crate::data::{Config, Disc, NameRules, Track};
+use std::path::Path;
+
+/// The default layout, `Artist-Album/Title`
+pub const DEFAULT_TEMPLATE: &str = "%artist-%album/%title";
+/// Used in classical mode, the albums are grouped per composer
+pub const CLASSICAL_TEMPLATE: &str = "%composer/%artist-%album/%title";
+/// The default layout of compilations, the track artist is part of the file name
+pub const COMPILATION_TEMPLATE: &str = "%albumartist-%album/%tracknum - %trackartist - %title";
+
+/// The placeholders a template can use, shown in the config dialog
+pub const PLACEHOLDERS: &[&str] = &[
+    "%artist",
+    "%albumartist",
+    "%album",
+    "%title",
+    "%trackartist",
+    "%tracknum",
+    "%year",
+    "%genre",
+    "%composer",
+];
+
+/// The template files of the disc are named after, in classical mode the albums are grouped per
+/// composer and compilations have a template of their own
+/// A `name_template` of the user's own is kept for compilations, unless the user also changed
+/// the `compilation_template`
+pub fn template<'a>(config: &'a Config, disc: &Disc) -> &'a str {
+    let own_template = config.name_template != DEFAULT_TEMPLATE
+        && config.compilation_template == COMPILATION_TEMPLATE;
+    if config.classical {
+        CLASSICAL_TEMPLATE
+    } else if disc.is_compilation() && !own_template {
+        &config.compilation_template
+    } else {
+        &config.name_template
+    }
+}
+
+/// Expand the placeholders of the template into a relative path, without the extension
+/// Every value is sanitized with the `rules`, so only the `/` in the template itself create
+/// folders
+/// `%artist` and `%albumartist` are both the artist of the disc
+/// With `latin`, the titles and artists in the Latin alphabet are used where there are any
+pub fn expand_template(
+    template: &str,
+    track: &Track,
+    disc: &Disc,
+    latin: bool,
+    rules: NameRules,
+) -> String {
+    let pick = |value, latin_value| {
+        if latin {
+            latin_or(value, latin_value)
+        } else {
+            value
+        }
+    };
+    let artist = pick(&disc.artist, &disc.latin_artist);
+    let year = disc.year.map(|year| year.to_string()).unwrap_or_default();
+    let composer = track.composer.as_deref().unwrap_or(artist);
+    // longest first, so %trackartist isn't taken for %track followed by "artist"
+    let values = [
+        ("%trackartist", pick(&track.artist, &track.latin_artist)),
+        ("%albumartist", artist),
+        ("%tracknum", &format!("{:02}", track.number)),
+        ("%composer", composer),
+        ("%artist", artist),
+        ("%album", pick(&disc.title, &disc.latin_title)),
+        ("%title", pick(&track.title, &track.latin_title)),
+        ("%genre", disc.genre.as_deref().unwrap_or_default()),
+        ("%year", &year),
+    ];
+    let mut path = String::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('%') {
+        path.push_str(&rest[..at]);
+        rest = &rest[at..];
+        match values.iter().find(|(name, _)| rest.starts_with(name)) {
+            Some((name, value)) => {
+                path.push_str(&sanitize_with(value, rules));
+                rest = &rest[name.len()..];
+            }
+            None => {
+                path.push('%');
+                rest = &rest[1..];
+            }
+        }
+    }
+    path.push_str(rest);
+    if rules == NameRules::Windows {
+        // the text of the template around the values counts as well
+        let parts: Vec<String> = path.split('/').map(windows_name).collect();
+        return parts.join("/");
+    }
+    path
+}
+
+fn latin_or<'a>(value: &'a str, latin_value: &'a Option<String>) -> &'a str {
+    latin_value.as_deref().unwrap_or(value)
+}
+
+/// Make a value safe to use as (part of) a file name on all platforms
+/// Path separators and characters Windows and Samba reject are replaced, leading dots removed
+pub fn sanitize(value: &str) -> String {
+    sanitize_with(value, NameRules::Portable)
+}
+
+/// Make a value safe to use as (part of) a file name with the `rules` of a file system,
+/// `NameRules::Auto` is taken as portable
+pub fn sanitize_with(value: &str, rules: NameRules) -> String {
+    let forbidden: &[char] = match rules {
+        NameRules::Auto | NameRules::Portable | NameRules::Windows => {
+            &['/', '\\', ':', '*', '?', '"', '<', '>', '|']
+        }
+        NameRules::Unix => &['/'],
+        NameRules::Mac => &['/', ':'],
+    };
+    let value = if forbidden.contains(&':') {
+        value.replace(": ", " - ")
+    } else {
+        value.to_owned()
+    };
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            c if forbidden.contains(&c) => '-',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    value.trim().trim_start_matches('.').trim().to_owned()
+}
+
+/// Names Windows reserves for devices, also with an extension
+const DEVICE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A file or folder name FAT and NTFS accept: without dots or spaces at the end, and device
+/// names get a `_`
+fn windows_name(name: &str) -> String {
+    let mut name = name.trim_end_matches(['.', ' ']).to_owned();
+    let stem = name.split('.').next().unwrap_or_default();
+    if DEVICE_NAMES
+        .iter()
+        .any(|device| stem.eq_ignore_ascii_case(device))
+    {
+        name.insert(stem.len(), '_');
+    }
+    name
+}
+
+/// The rules for the file names, `Config::name_rules` or the ones of the file system of the
+/// encode path
+pub fn name_rules(config: &Config) -> NameRules {
+    match config.name_rules {
+        NameRules::Auto => filesystem(Path::new(&config.encode_path))
+            .map_or(NameRules::Portable, |fs| filesystem_rules(&fs)),
+        rules => rules,
+    }
+}
+
+/// The rules of a file system type as Linux names it
+/// Only FAT and NTFS get rules of their own, files on the others keep the portable names
+/// ripperx4 always gave them, so existing libraries aren't named differently
+fn filesystem_rules(fs_type: &str) -> NameRules {
+    match fs_type {
+        // fuseblk is ntfs-3g or exfat-fuse
+        "vfat" | "msdos" | "exfat" | "ntfs" | "ntfs3" | "fuseblk" | "cifs" | "smb3" => {
+            NameRules::Windows
+        }
+        _ => NameRules::Portable,
+    }
+}
+
+/// The type of the file system the path is on, from the mount table
+#[cfg(target_os = "linux")]
+fn filesystem(path: &Path) -> Option<String> {
+    // the folders of the encode path may not exist yet
+    let existing = path.ancestors().find(|p| p.exists())?.canonicalize().ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    mount_type(&mounts, &existing)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem(_path: &Path) -> Option<String> {
+    None
+}
+
+/// The type of the innermost mount of `path` in a mount table like `/proc/self/mounts`
+fn mount_type(mounts: &str, path: &Path) -> Option<String> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (_, point, fs_type) = (fields.next()?, fields.next()?, fields.next()?);
+            // spaces in the mount point are written as \040
+            let point = point.replace("\\040", " ");
+            path.starts_with(&point)
+                .then(|| (point, fs_type.to_owned()))
+        })
+        .max_by_key(|(point, _)| point.len())
+        .map(|(_, fs_type)| fs_type)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        expand_template, filesystem_rules, mount_type, sanitize, sanitize_with, template,
+        CLASSICAL_TEMPLATE, COMPILATION_TEMPLATE, DEFAULT_TEMPLATE,
+    };
+    use crate::data::{Config, Disc, NameRules};
+    use std::path::Path;
+
+    #[test]
+    pub fn test_expand_template() {
+        let mut disc = Disc::with_tracks(3);
+        disc.artist = "AC/DC".to_owned();
+        disc.title = "Back in Black".to_owned();
+        disc.year = Some(1980);
+        let track = &mut disc.tracks[2];
+        track.title = "What Do You Do for Money Honey?".to_owned();
+        track.artist = "AC/DC".to_owned();
+        let track = disc.tracks[2].clone();
+        assert_eq!(
+            "AC-DC-Back in Black/What Do You Do for Money Honey-",
+            expand_template(DEFAULT_TEMPLATE, &track, &disc, false, NameRules::Portable)
+        );
+        assert_eq!(
+            "1980/AC-DC/03 - What Do You Do for Money Honey- (AC-DC) 100%",
+            expand_template(
+                "%year/%artist/%tracknum - %title (%trackartist) 100%",
+                &track,
+                &disc,
+                false,
+                NameRules::Portable
+            )
+        );
+        assert_eq!(
+            "AC-DC/AC-DC-Back in Black/What Do You Do for Money Honey-",
+            expand_template(
+                CLASSICAL_TEMPLATE,
+                &track,
+                &disc,
+                false,
+                NameRules::Portable
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_latin_names() {
+        let mut disc = Disc::with_tracks(1);
+        disc.artist = "宇多田ヒカル".to_owned();
+        disc.title = "初恋".to_owned();
+        disc.tracks[0].title = "Play A Love Song".to_owned();
+        disc.tracks[0].artist = "宇多田ヒカル".to_owned();
+        assert_eq!(
+            "宇多田ヒカル-初恋/Play A Love Song",
+            expand_template(
+                DEFAULT_TEMPLATE,
+                &disc.tracks[0],
+                &disc,
+                true,
+                NameRules::Portable
+            )
+        );
+        disc.latin_artist = Some("Utada Hikaru".to_owned());
+        disc.latin_title = Some("Hatsukoi".to_owned());
+        assert_eq!(
+            "Utada Hikaru-Hatsukoi/Play A Love Song",
+            expand_template(
+                DEFAULT_TEMPLATE,
+                &disc.tracks[0],
+                &disc,
+                true,
+                NameRules::Portable
+            )
+        );
+        assert_eq!(
+            "宇多田ヒカル-初恋/Play A Love Song",
+            expand_template(
+                DEFAULT_TEMPLATE,
+                &disc.tracks[0],
+                &disc,
+                false,
+                NameRules::Portable
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_compilation_template() {
+        let mut disc = Disc::with_tracks(3);
+        disc.artist = "Various Artists".to_owned();
+        disc.title = "Now 80s".to_owned();
+        for (track, artist) in disc
+            .tracks
+            .iter_mut()
+            .zip(["Queen", "Toto", "Various Artists"])
+        {
+            track.artist = artist.to_owned();
+            track.title = "Song".to_owned();
+        }
+        let mut config = Config::default();
+        assert_eq!(COMPILATION_TEMPLATE, template(&config, &disc));
+        assert_eq!(
+            "Various Artists-Now 80s/02 - Toto - Song",
+            expand_template(
+                template(&config, &disc),
+                &disc.tracks[1],
+                &disc,
+                false,
+                NameRules::Portable
+            )
+        );
+
+        // the user's own template, unless there is an own one for compilations too
+        config.name_template = "%artist/%album/%title".to_owned();
+        assert_eq!("%artist/%album/%title", template(&config, &disc));
+        config.compilation_template = "Compilations/%album/%title".to_owned();
+        assert_eq!("Compilations/%album/%title", template(&config, &disc));
+        config = Config::default();
+
+        // a guest on one track doesn't make a compilation
+        disc.artist = "Queen".to_owned();
+        disc.tracks[1].artist = "Queen".to_owned();
+        assert_eq!(DEFAULT_TEMPLATE, template(&config, &disc));
+
+        // nor do tracks that all have another artist than the album
+        for track in &mut disc.tracks {
+            track.artist = "Unknown".to_owned();
+        }
+        assert_eq!(DEFAULT_TEMPLATE, template(&config, &disc));
+    }
+
+    #[test]
+    pub fn test_sanitize() {
+        assert_eq!(
+            "Symphony no. 5 - I. Allegro",
+            sanitize("Symphony no. 5: I. Allegro")
+        );
+        assert_eq!("-etc-passwd", sanitize("/etc/passwd"));
+        assert_eq!("", sanitize(".."));
+        assert_eq!("hidden", sanitize(".hidden"));
+    }
+
+    #[test]
+    pub fn test_name_rules() {
+        assert_eq!(
+            "AC-DC: Live?",
+            sanitize_with("AC/DC: Live?", NameRules::Unix)
+        );
+        assert_eq!(
+            "AC-DC - Live?",
+            sanitize_with("AC/DC: Live?", NameRules::Mac)
+        );
+        let mut disc = Disc::with_tracks(1);
+        disc.artist = "Con".to_owned();
+        disc.title = "Vol. 2...".to_owned();
+        disc.tracks[0].title = "Aux.mp3 ".to_owned();
+        let track = disc.tracks[0].clone();
+        let expand = |rules| expand_template("%artist/%album/%title", &track, &disc, false, rules);
+        assert_eq!("Con/Vol. 2.../Aux.mp3", expand(NameRules::Portable));
+        assert_eq!("Con_/Vol. 2/Aux_.mp3", expand(NameRules::Windows));
+
+        let mounts = "/dev/sda2 / ext4 rw 0 0\n\
+                      /dev/sdb1 /media/usb\\040stick vfat rw 0 0\n";
+        let usb = mount_type(mounts, Path::new("/media/usb stick/Music"));
+        assert_eq!(Some("vfat"), usb.as_deref());
+        assert_eq!(NameRules::Windows, filesystem_rules("vfat"));
+        let home = mount_type(mounts, Path::new("/home/user/Music"));
+        assert_eq!(Some("ext4"), home.as_deref());
+        assert_eq!(NameRules::Portable, filesystem_rules("ext4"));
+    }
+}
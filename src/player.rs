@@ -0,0 +1,208 @@
+use crate::{
+    data::{Config, Track},
+    ripper::make_source,
+};
+use anyhow::{anyhow, Result};
+use gstreamer::{
+    bus::BusWatchGuard,
+    glib::{self, ControlFlow},
+    prelude::*,
+    Element, ElementFactory, MessageView, Pipeline, State,
+};
+use log::{debug, error};
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+/// Peaks from -0.1 dBFS up are shown as clipping, CD audio at full scale is 32767/32768
+const CLIP_DB: f64 = -0.1;
+
+/// Plays encoded files, to check the encoder settings without leaving the app, or previews
+/// tracks from the CD
+pub struct Player {
+    playbin: Element,
+    _watch: BusWatchGuard,
+    /// reads the track from the CD with the source and error correction of the rip
+    preview: RefCell<Option<(Pipeline, BusWatchGuard)>>,
+    callbacks: Rc<Callbacks>,
+}
+
+struct Callbacks {
+    on_stopped: Box<dyn Fn()>,
+    on_level: Box<dyn Fn(f64)>,
+}
+
+impl Player {
+    /// `on_stopped` is called in the main loop when a file played to the end or failed,
+    /// `on_level` with the peak in dBFS of the audio played, when the `level` element is installed
+    pub fn new(
+        on_stopped: impl Fn() + 'static,
+        on_level: impl Fn(f64) + 'static,
+    ) -> Result<Player> {
+        let playbin = ElementFactory::make("playbin").build()?;
+        match make_level() {
+            Ok(level) => playbin.set_property("audio-filter", &level),
+            Err(e) => debug!("No level meter: {e}"),
+        }
+        let callbacks = Rc::new(Callbacks {
+            on_stopped: Box::new(on_stopped),
+            on_level: Box::new(on_level),
+        });
+        Ok(Player {
+            _watch: watch(&playbin, callbacks.clone())?,
+            playbin,
+            preview: RefCell::default(),
+            callbacks,
+        })
+    }
+
+    /// Play the file from the start, stopping what was playing
+    pub fn play(&self, path: &Path) -> Result<()> {
+        self.play_uri(&glib::filename_to_uri(path, None)?)
+    }
+
+    /// Play the track of the disc in the drive, or of the remote drive, read the way the rip
+    /// reads it, stopping what was playing
+    pub fn preview(&self, track: &Track, config: &Config) -> Result<()> {
+        self.stop();
+        let pipeline = Pipeline::new();
+        let mut elements = vec![
+            make_source(track, config)?,
+            ElementFactory::make("audioconvert").build()?,
+            ElementFactory::make("audioresample").build()?,
+        ];
+        if let Ok(level) = make_level() {
+            elements.push(level);
+        }
+        elements.push(ElementFactory::make("autoaudiosink").build()?);
+        pipeline.add_many(&elements)?;
+        Element::link_many(&elements)?;
+        let watch = watch(pipeline.upcast_ref(), self.callbacks.clone())?;
+        pipeline.set_state(State::Playing)?;
+        self.preview.replace(Some((pipeline, watch)));
+        Ok(())
+    }
+
+    fn play_uri(&self, uri: &str) -> Result<()> {
+        self.stop();
+        self.playbin.set_property("uri", uri);
+        self.playbin.set_state(State::Playing)?;
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.playbin.set_state(State::Null).ok();
+        if let Some((pipeline, _)) = self.preview.take() {
+            pipeline.set_state(State::Null).ok();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        let playing = |element: &Element| {
+            element.current_state() == State::Playing || element.pending_state() == State::Playing
+        };
+        playing(&self.playbin)
+            || self
+                .preview
+                .borrow()
+                .as_ref()
+                .is_some_and(|(pipeline, _)| playing(pipeline.upcast_ref()))
+    }
+}
+
+/// Posts the peaks of the audio played 20 times a second
+fn make_level() -> Result<Element> {
+    Ok(ElementFactory::make("level")
+        .property("interval", 50_000_000u64)
+        .build()?)
+}
+
+/// Pass the levels of the pipeline to the callbacks and stop it at the end or on an error
+fn watch(pipeline: &Element, callbacks: Rc<Callbacks>) -> Result<BusWatchGuard> {
+    let bus = pipeline.bus().ok_or(anyhow!("no bus"))?;
+    let weak = pipeline.downgrade();
+    Ok(bus.add_watch_local(move |_, msg| {
+        let done = match msg.view() {
+            MessageView::Element(element) => {
+                if let Some(peak) = element.structure().and_then(peak_db) {
+                    (callbacks.on_level)(peak);
+                }
+                false
+            }
+            MessageView::Eos(..) => true,
+            MessageView::Error(err) => {
+                error!("Playback failed: {} ({:?})", err.error(), err.debug());
+                true
+            }
+            _ => false,
+        };
+        if done {
+            if let Some(pipeline) = weak.upgrade() {
+                pipeline.set_state(State::Null).ok();
+            }
+            (callbacks.on_stopped)();
+        }
+        ControlFlow::Continue
+    })?)
+}
+
+/// The highest peak of the channels in a message of the `level` element
+fn peak_db(structure: &gstreamer::StructureRef) -> Option<f64> {
+    loudest_db(structure, "peak")
+}
+
+/// The loudest channel in a message of the `level` element, `field` is `peak` or `rms`
+pub fn loudest_db(structure: &gstreamer::StructureRef, field: &str) -> Option<f64> {
+    if structure.name() != "level" {
+        return None;
+    }
+    // a GValueArray, newer GStreamer versions may send a GstValueArray
+    let levels: Vec<f64> = match structure.get::<glib::ValueArray>(field) {
+        Ok(levels) => levels.iter().filter_map(|l| l.get().ok()).collect(),
+        Err(_) => structure
+            .get::<gstreamer::Array>(field)
+            .ok()?
+            .iter()
+            .filter_map(|l| l.get().ok())
+            .collect(),
+    };
+    levels.into_iter().reduce(f64::max)
+}
+
+/// The peak as a fraction of full scale, for a level bar
+pub fn meter_value(peak_db: f64) -> f64 {
+    10f64.powf(peak_db / 20.0).clamp(0.0, 1.0)
+}
+
+/// Whether the peak reaches full scale, the decoder or the DAC may clip it
+pub fn is_clipping(peak_db: f64) -> bool {
+    peak_db >= CLIP_DB
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_clipping, meter_value, peak_db};
+    use gstreamer::Structure;
+
+    #[test]
+    pub fn test_levels() {
+        assert!((meter_value(0.0) - 1.0).abs() < 1e-9);
+        assert!((meter_value(-6.0) - 0.501).abs() < 0.001);
+        assert!(meter_value(f64::NEG_INFINITY).abs() < 1e-9);
+        // lossy files decode to peaks over full scale
+        assert!((meter_value(1.5) - 1.0).abs() < 1e-9);
+        assert!(is_clipping(0.0));
+        assert!(is_clipping(-0.0003));
+        assert!(!is_clipping(-1.0));
+    }
+
+    #[test]
+    pub fn test_peak_db() {
+        gstreamer::init().expect("gstreamer");
+        // as posted by the level element for a stereo stream
+        let level: Structure = "level, peak=(GValueArray)< (double)-3.0, (double)-1.5 >"
+            .parse()
+            .expect("structure");
+        assert_eq!(Some(-1.5), peak_db(&level));
+        let other = Structure::builder("progress").build();
+        assert_eq!(None, peak_db(&other));
+    }
+}
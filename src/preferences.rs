@@ -0,0 +1,565 @@
+use crate::{
+    data::{CdBackend, Config, ErrorCorrection, ImageFormat, NameRules, Quality, ReplayGain},
+    encoder::{backend, backends},
+    naming::{COMPILATION_TEMPLATE, DEFAULT_TEMPLATE, PLACEHOLDERS},
+};
+
+/// The pages of the preferences window, in the order they are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Page {
+    General,
+    Encoding,
+    Naming,
+    Metadata,
+    Drives,
+    Hooks,
+}
+
+impl Page {
+    pub const ALL: [Page; 6] = [
+        Page::General,
+        Page::Encoding,
+        Page::Naming,
+        Page::Metadata,
+        Page::Drives,
+        Page::Hooks,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Page::General => "General",
+            Page::Encoding => "Encoding",
+            Page::Naming => "Naming",
+            Page::Metadata => "Metadata",
+            Page::Drives => "Drives",
+            Page::Hooks => "Hooks",
+        }
+    }
+}
+
+type Get<T> = Box<dyn Fn(&Config) -> T>;
+type Set<T> = Box<dyn Fn(&mut Config, T)>;
+type SetText = Box<dyn Fn(&mut Config, &str)>;
+/// The options of a drop down for the option selected in another one
+type Options = fn(usize) -> Vec<String>;
+
+/// How a setting is edited, with how it's read from and written to the configuration
+pub enum Editor {
+    /// a check box
+    Check(Get<bool>, Set<bool>),
+    /// a drop down, by the index of the option
+    Choice {
+        options: Vec<String>,
+        get: Get<usize>,
+        set: Set<usize>,
+    },
+    /// a line of text
+    Line(Get<String>, SetText),
+    /// several lines of text
+    Text(Get<String>, SetText),
+}
+
+/// A setting of the preferences window, the window is built from the list of `settings`
+pub struct Setting {
+    /// names the setting for the others depending on it
+    pub key: &'static str,
+    pub page: Page,
+    pub label: String,
+    /// placeholder of a line of text, tooltip of the others
+    pub hint: Option<&'static str>,
+    pub editor: Editor,
+    /// only editable while the check box of this setting is ticked
+    pub enabled_by: Option<&'static str>,
+    /// the options of the drop down follow the option selected in this setting
+    pub options_by: Option<(&'static str, Options)>,
+}
+
+impl Setting {
+    fn new(key: &'static str, page: Page, label: impl Into<String>, editor: Editor) -> Setting {
+        Setting {
+            key,
+            page,
+            label: label.into(),
+            hint: None,
+            editor,
+            enabled_by: None,
+            options_by: None,
+        }
+    }
+
+    fn hint(mut self, hint: &'static str) -> Setting {
+        self.hint = Some(hint);
+        self
+    }
+
+    fn enabled_by(mut self, key: &'static str) -> Setting {
+        self.enabled_by = Some(key);
+        self
+    }
+
+    fn options_by(mut self, key: &'static str, options: Options) -> Setting {
+        self.options_by = Some((key, options));
+        self
+    }
+}
+
+fn check(get: fn(&Config) -> bool, set: fn(&mut Config, bool)) -> Editor {
+    Editor::Check(Box::new(get), Box::new(set))
+}
+
+/// A drop down picking one of the `values`, each with its label
+fn choice<T: Clone + PartialEq + 'static>(
+    values: Vec<(T, String)>,
+    get: fn(&Config) -> T,
+    set: fn(&mut Config, T),
+) -> Editor {
+    let options = values.iter().map(|(_, label)| label.clone()).collect();
+    let values: Vec<T> = values.into_iter().map(|(value, _)| value).collect();
+    let read = values.clone();
+    Editor::Choice {
+        options,
+        get: Box::new(move |config| {
+            let value = get(config);
+            read.iter().position(|v| *v == value).unwrap_or_default()
+        }),
+        set: Box::new(move |config, index| {
+            if let Some(value) = values.get(index) {
+                set(config, value.clone());
+            }
+        }),
+    }
+}
+
+fn line(get: fn(&Config) -> String, set: fn(&mut Config, &str)) -> Editor {
+    Editor::Line(Box::new(get), Box::new(set))
+}
+
+fn text(get: fn(&Config) -> String, set: fn(&mut Config, &str)) -> Editor {
+    Editor::Text(Box::new(get), Box::new(set))
+}
+
+fn labelled<T: Copy>(values: &[(T, &str)]) -> Vec<(T, String)> {
+    values
+        .iter()
+        .map(|(value, label)| (*value, (*label).to_owned()))
+        .collect()
+}
+
+/// `None` for an empty text
+fn optional(text: &str) -> Option<String> {
+    Some(text.trim().to_owned()).filter(|text| !text.is_empty())
+}
+
+/// The labels of the qualities of the format at `index` of the encoder drop down
+fn quality_labels(index: usize) -> Vec<String> {
+    backends()
+        .get(index)
+        .map(|b| b.settings().qualities.map(str::to_owned).to_vec())
+        .unwrap_or_default()
+}
+
+/// The languages entered, separated by commas or spaces
+fn parse_languages(text: &str) -> Vec<String> {
+    text.split([',', ' '])
+        .map(str::trim)
+        .filter(|language| !language.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+/// Everything the preferences window edits
+/// `devices` are the CD drives to pick from, `available` the CD sources that are installed
+pub fn settings(config: &Config, devices: &[String], available: &[CdBackend]) -> Vec<Setting> {
+    let formats = backends()
+        .iter()
+        .map(|b| (b.encoder(), b.settings().name.to_owned()))
+        .collect();
+    let fallbacks = std::iter::once((None, "none".to_owned()))
+        .chain(
+            backends()
+                .iter()
+                .map(|b| (Some(b.encoder()), b.settings().name.to_owned())),
+        )
+        .collect();
+    let qualities = [Quality::Low, Quality::Medium, Quality::High]
+        .into_iter()
+        .zip(
+            backend(&config.encoder)
+                .settings()
+                .qualities
+                .map(str::to_owned),
+        )
+        .collect();
+    let sources = [
+        (CdBackend::Auto, "auto"),
+        (CdBackend::CddaUri, "cdda:// URI"),
+        (CdBackend::Cdio, "cdio"),
+        (CdBackend::Cdparanoia, "cdparanoia"),
+        (CdBackend::Remote, "remote drive"),
+    ]
+    .into_iter()
+    .map(|(source, name)| {
+        if [CdBackend::Auto, CdBackend::Remote].contains(&source) || available.contains(&source) {
+            (source, name.to_owned())
+        } else {
+            (source, format!("{name} (not installed)"))
+        }
+    })
+    .collect();
+    // the configured drive is listed even when it's not connected
+    let mut drives: Vec<(Option<String>, String)> = vec![(None, "default".to_owned())];
+    drives.extend(devices.iter().map(|d| (Some(d.clone()), d.clone())));
+    if let Some(device) = config.device.as_ref().filter(|d| !devices.contains(d)) {
+        drives.push((Some(device.clone()), device.clone()));
+    }
+    vec![
+        Setting::new(
+            "encode_path",
+            Page::General,
+            "Folder",
+            line(
+                |c| c.encode_path.clone(),
+                |c, v| c.encode_path = v.to_owned(),
+            ),
+        ),
+        Setting::new(
+            "eject_when_done",
+            Page::General,
+            "Eject the disc when done",
+            check(|c| c.eject_when_done, |c, v| c.eject_when_done = v),
+        ),
+        Setting::new(
+            "notify_when_done",
+            Page::General,
+            "Show a notification when done",
+            check(|c| c.notify_when_done, |c, v| c.notify_when_done = v),
+        ),
+        Setting::new(
+            "track_list_font_size",
+            Page::General,
+            "Text size of the track list",
+            choice(
+                labelled(&[
+                    (0, "as the theme"),
+                    (10, "10 pt"),
+                    (12, "12 pt"),
+                    (14, "14 pt"),
+                    (16, "16 pt"),
+                    (20, "20 pt"),
+                    (24, "24 pt"),
+                ]),
+                |c| c.track_list_font_size,
+                |c, v| c.track_list_font_size = v,
+            ),
+        ),
+        Setting::new(
+            "compact_track_list",
+            Page::General,
+            "Compact track list",
+            check(|c| c.compact_track_list, |c, v| c.compact_track_list = v),
+        ),
+        Setting::new(
+            "touch_mode",
+            Page::General,
+            "Touch screen mode",
+            check(|c| c.touch_mode, |c, v| c.touch_mode = v),
+        ),
+        Setting::new(
+            "encoder",
+            Page::Encoding,
+            "Format",
+            choice(formats, |c| c.encoder, |c, v| c.encoder = v),
+        ),
+        Setting::new(
+            "quality",
+            Page::Encoding,
+            "Quality",
+            choice(qualities, |c| c.quality, |c, v| c.quality = v),
+        )
+        .options_by("encoder", quality_labels),
+        Setting::new(
+            "replaygain",
+            Page::Encoding,
+            "ReplayGain",
+            choice(
+                labelled(&[
+                    (ReplayGain::Off, "off"),
+                    (ReplayGain::Tags, "tags only"),
+                    (ReplayGain::Limit, "tags, limit the peaks of lossy files"),
+                ]),
+                |c| c.replaygain,
+                |c, v| c.replaygain = v,
+            ),
+        ),
+        Setting::new(
+            "encode_retries",
+            Page::Encoding,
+            "Encode a track again when the encoder fails",
+            choice(
+                labelled(&[(0, "never"), (1, "once"), (2, "twice"), (3, "3 times")]),
+                |c| c.encode_retries,
+                |c, v| c.encode_retries = v,
+            ),
+        ),
+        Setting::new(
+            "fallback_encoder",
+            Page::Encoding,
+            "Then encode it to",
+            choice(
+                fallbacks,
+                |c| c.fallback_encoder,
+                |c, v| c.fallback_encoder = v,
+            ),
+        ),
+        Setting::new(
+            "id3v2_version",
+            Page::Encoding,
+            "ID3 tags of MP3 and FLAC",
+            choice(
+                labelled(&[
+                    (4, "ID3v2.4 (UTF-8)"),
+                    (3, "ID3v2.3 (UTF-16, for older players and car stereos)"),
+                ]),
+                |c| c.id3v2_version,
+                |c, v| c.id3v2_version = v,
+            ),
+        ),
+        Setting::new(
+            "id3v1_tags",
+            Page::Encoding,
+            "Also write ID3v1 tags to MP3 files",
+            check(|c| c.id3v1_tags, |c, v| c.id3v1_tags = v),
+        ),
+        Setting::new(
+            "disc_image",
+            Page::Encoding,
+            "Rip to one disc image with a CUE sheet",
+            check(|c| c.disc_image, |c, v| c.disc_image = v),
+        ),
+        Setting::new(
+            "image_format",
+            Page::Encoding,
+            "Disc image",
+            choice(
+                labelled(&[
+                    (ImageFormat::Flac, "FLAC"),
+                    (ImageFormat::Wav, "WAV"),
+                    (ImageFormat::Opus, "Opus audiobook with chapters"),
+                    (ImageFormat::M4b, "M4B audiobook"),
+                ]),
+                |c| c.image_format,
+                |c, v| c.image_format = v,
+            ),
+        )
+        .enabled_by("disc_image"),
+        Setting::new(
+            "secure_rip",
+            Page::Encoding,
+            "Secure rip (verify with AccurateRip, write a rip log)",
+            check(|c| c.secure_rip, |c, v| c.secure_rip = v),
+        ),
+        Setting::new(
+            "condition_comment",
+            Page::Encoding,
+            "Note the read errors of each track in its comment",
+            check(|c| c.condition_comment, |c, v| c.condition_comment = v),
+        ),
+        Setting::new(
+            "json_report",
+            Page::Encoding,
+            "Write a JSON report of each rip",
+            check(|c| c.json_report, |c, v| c.json_report = v),
+        ),
+        Setting::new(
+            "name_template",
+            Page::Naming,
+            format!("File names, using {}", PLACEHOLDERS.join(" ")),
+            text(
+                |c| c.name_template.clone(),
+                |c, v| c.name_template = optional(v).unwrap_or(DEFAULT_TEMPLATE.to_owned()),
+            ),
+        ),
+        Setting::new(
+            "compilation_template",
+            Page::Naming,
+            "File names of compilations (tracks by various artists)",
+            text(
+                |c| c.compilation_template.clone(),
+                |c, v| {
+                    c.compilation_template = optional(v).unwrap_or(COMPILATION_TEMPLATE.to_owned());
+                },
+            ),
+        ),
+        Setting::new(
+            "classical",
+            Page::Naming,
+            "Classical mode (\"Work: Movement\" titles, folders per composer)",
+            check(|c| c.classical, |c, v| c.classical = v),
+        ),
+        Setting::new(
+            "latin_file_names",
+            Page::Naming,
+            "Name the files of discs in other scripts in the Latin alphabet",
+            check(|c| c.latin_file_names, |c, v| c.latin_file_names = v),
+        ),
+        Setting::new(
+            "name_rules",
+            Page::Naming,
+            "Characters allowed in file names",
+            choice(
+                labelled(&[
+                    (
+                        NameRules::Auto,
+                        "After the file system (FAT, NTFS: Windows)",
+                    ),
+                    (NameRules::Portable, "Portable"),
+                    (NameRules::Windows, "Windows, FAT32, exFAT, NTFS"),
+                    (NameRules::Unix, "Linux (only / is replaced)"),
+                    (NameRules::Mac, "macOS (/ and : are replaced)"),
+                ]),
+                |c| c.name_rules,
+                |c, v| c.name_rules = v,
+            ),
+        ),
+        Setting::new(
+            "preferred_languages",
+            Page::Metadata,
+            "Preferred languages",
+            line(
+                |c| c.preferred_languages.join(", "),
+                |c, v| c.preferred_languages = parse_languages(v),
+            ),
+        )
+        .hint("e.g. deu, eng: releases, titles and artists in the first one MusicBrainz has"),
+        Setting::new(
+            "cover_art",
+            Page::Metadata,
+            "Download the cover art",
+            check(|c| c.cover_art, |c, v| c.cover_art = v),
+        ),
+        Setting::new(
+            "device",
+            Page::Drives,
+            "Drive",
+            choice(drives, |c| c.device.clone(), |c, v| c.device = v),
+        ),
+        Setting::new(
+            "cd_backend",
+            Page::Drives,
+            "CD source",
+            choice(sources, |c| c.cd_backend, |c, v| c.cd_backend = v),
+        ),
+        Setting::new(
+            "remote_drive",
+            Page::Drives,
+            "Remote drive",
+            line(
+                |c| c.remote_drive.clone().unwrap_or_default(),
+                |c, v| c.remote_drive = optional(v),
+            ),
+        )
+        .hint("host or host:port"),
+        // only cdparanoia corrects errors
+        Setting::new(
+            "error_correction",
+            Page::Drives,
+            "Error correction",
+            choice(
+                labelled(&[
+                    (ErrorCorrection::Off, "off"),
+                    (ErrorCorrection::Fast, "fast"),
+                    (ErrorCorrection::Full, "full"),
+                ]),
+                |c| c.error_correction,
+                |c, v| c.error_correction = v,
+            ),
+        ),
+        Setting::new(
+            "post_rip_command",
+            Page::Hooks,
+            "Command to run after a rip, with the album folder as argument",
+            text(
+                |c| c.post_rip_command.clone().unwrap_or_default(),
+                |c, v| c.post_rip_command = optional(v),
+            ),
+        ),
+        Setting::new(
+            "automation_script",
+            Page::Hooks,
+            "Script",
+            line(
+                |c| c.automation_script.clone().unwrap_or_default(),
+                |c, v| c.automation_script = optional(v),
+            ),
+        )
+        .hint("path of a Rhai script"),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_languages, quality_labels, settings, Editor, Page};
+    use crate::{
+        data::{CdBackend, Config, ImageFormat, Quality},
+        encoder::backends,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    pub fn test_settings() {
+        let config = Config {
+            device: Some("/dev/sr3".to_owned()),
+            ..Default::default()
+        };
+        let settings = settings(&config, &["/dev/sr0".to_owned()], &[CdBackend::Cdio]);
+        let keys: HashSet<&str> = settings.iter().map(|s| s.key).collect();
+        assert_eq!(settings.len(), keys.len());
+        for page in Page::ALL {
+            assert!(settings.iter().any(|s| s.page == page), "{page:?} is empty");
+        }
+        // the settings others depend on exist
+        for setting in &settings {
+            let depends = setting
+                .enabled_by
+                .or(setting.options_by.map(|(key, _)| key));
+            assert!(depends.is_none_or(|key| keys.contains(key)));
+        }
+        // every setting writes back what it read
+        let mut written = Config::default();
+        for setting in &settings {
+            match &setting.editor {
+                Editor::Check(get, set) => set(&mut written, get(&config)),
+                Editor::Choice { get, set, .. } => set(&mut written, get(&config)),
+                Editor::Line(get, set) | Editor::Text(get, set) => set(&mut written, &get(&config)),
+            }
+        }
+        assert_eq!(config.name_template, written.name_template);
+        assert_eq!(config.device, written.device);
+        assert_eq!(config.remote_drive, written.remote_drive);
+        assert_eq!(config.post_rip_command, written.post_rip_command);
+    }
+
+    #[test]
+    pub fn test_choices() {
+        let config = Config::default();
+        let settings = settings(&config, &[], &[]);
+        let mut written = Config::default();
+        let choose = |key: &str, index: usize, config: &mut Config| {
+            let setting = settings.iter().find(|s| s.key == key).unwrap();
+            let Editor::Choice { options, set, .. } = &setting.editor else {
+                panic!("{key} is no drop down");
+            };
+            assert!(index < options.len());
+            set(config, index);
+        };
+        choose("encoder", 1, &mut written);
+        choose("quality", 0, &mut written);
+        choose("image_format", 2, &mut written);
+        assert_eq!(backends()[1].encoder(), written.encoder);
+        assert_eq!(Quality::Low, written.quality);
+        assert_eq!(ImageFormat::Opus, written.image_format);
+        assert_eq!(3, quality_labels(0).len());
+        assert!(quality_labels(99).is_empty());
+        assert_eq!(vec!["deu", "eng"], parse_languages("DEU, eng"));
+    }
+}
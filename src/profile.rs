@@ -0,0 +1,335 @@
+use crate::{
+    data::{Config, Disc, Encoder, FolderSettings, Profile, Route},
+    encoder::backends,
+};
+use anyhow::Result;
+use gtk::gio::{self, prelude::*};
+use log::{debug, error, info, warn};
+use std::path::Path;
+
+const GB: f64 = 1_000_000_000.0;
+/// Settings for the rips to the folder it's in
+pub const FOLDER_SETTINGS: &str = ".ripperx4.toml";
+
+/// The first profile whose rule holds for the free space (in bytes) of the encode path
+/// A rule on free space doesn't hold when it's unknown
+pub fn select(profiles: &[Profile], free: Option<u64>) -> Option<&Profile> {
+    profiles.iter().find(|profile| match profile.min_free_gb {
+        Some(min) => free.is_some_and(|free| free as f64 >= min * GB),
+        None => true,
+    })
+}
+
+/// Use the format of the profile selected for the encode path, evaluated at the start of a rip
+/// Returns the name of the profile, the configured format is kept when none is selected
+pub fn apply(config: &mut Config) -> Option<String> {
+    if config.profiles.is_empty() {
+        return None;
+    }
+    let free = free_space(Path::new(&config.encode_path));
+    debug!("{free:?} bytes free in {}", config.encode_path);
+    let profile = select(&config.profiles, free)?.clone();
+    info!("using profile {}", profile.name);
+    config.encoder = profile.encoder;
+    config.quality = profile.quality;
+    Some(profile.name)
+}
+
+/// What a disc ripped to FLAC can be encoded to: the profiles, or every format at the configured
+/// quality when there are none, FLAC left out
+pub fn transcode_targets(config: &Config) -> Vec<Profile> {
+    let profiles = if config.profiles.is_empty() {
+        backends()
+            .iter()
+            .map(|backend| Profile {
+                name: backend.settings().name.to_owned(),
+                encoder: backend.encoder(),
+                quality: config.quality,
+                min_free_gb: None,
+            })
+            .collect()
+    } else {
+        config.profiles.clone()
+    };
+    profiles
+        .into_iter()
+        .filter(|profile| profile.encoder != Encoder::FLAC)
+        .collect()
+}
+
+/// The route of the first library for the genre
+fn route_for<'a>(routes: &'a [Route], genre: Option<&str>) -> Option<&'a Route> {
+    let genre = genre?;
+    routes
+        .iter()
+        .find(|route| route.genres.iter().any(|g| g.eq_ignore_ascii_case(genre)))
+}
+
+/// Rip to the library of the genre of the disc, evaluated at the start of a rip before the
+/// folder settings, so those of the library apply
+/// Returns the name of the library, the encode path is kept when no route has the genre
+pub fn apply_route(config: &mut Config, disc: &Disc) -> Option<String> {
+    let route = route_for(&config.routes, disc.genre.as_deref())?.clone();
+    info!("ripping to {} ({})", route.name, route.path);
+    config.encode_path = route.path;
+    config.routes.clear();
+    Some(route.name)
+}
+
+/// Rip to the library picked for the disc instead of the one of its genre
+pub fn choose_route(config: &mut Config, name: &str) {
+    match config.routes.iter().find(|route| route.name == name) {
+        Some(route) => {
+            config.encode_path.clone_from(&route.path);
+            config.routes.clear();
+        }
+        None => warn!("there is no library {name}"),
+    }
+}
+
+/// Use the settings of the `.ripperx4.toml` in the encode path, read at the start of a rip
+/// They win over the configuration, and a profile named in it over the other profiles
+pub fn apply_folder(config: &mut Config) {
+    let path = Path::new(&config.encode_path).join(FOLDER_SETTINGS);
+    // confy would create a missing file
+    if !path.is_file() {
+        return;
+    }
+    match load_folder(&path) {
+        Ok(settings) => {
+            info!("using the settings of {}", path.display());
+            apply_folder_settings(&settings, config);
+        }
+        Err(e) => error!("Failed to read {}: {e}", path.display()),
+    }
+}
+
+fn load_folder(path: &Path) -> Result<FolderSettings> {
+    Ok(confy::load_path(path)?)
+}
+
+fn apply_folder_settings(settings: &FolderSettings, config: &mut Config) {
+    if let Some(name) = &settings.profile {
+        match config.profiles.iter().find(|p| &p.name == name) {
+            Some(profile) => {
+                config.encoder = profile.encoder;
+                config.quality = profile.quality;
+                config.profiles.clear();
+            }
+            None => warn!("the folder settings name an unknown profile {name}"),
+        }
+    }
+    if let Some(encoder) = settings.encoder {
+        config.encoder = encoder;
+        config.profiles.clear();
+    }
+    if let Some(quality) = settings.quality {
+        config.quality = quality;
+        config.profiles.clear();
+    }
+    if let Some(template) = &settings.name_template {
+        config.name_template.clone_from(template);
+    }
+    if let Some(template) = &settings.compilation_template {
+        config.compilation_template.clone_from(template);
+    }
+    config.classical = settings.classical.unwrap_or(config.classical);
+    config.disc_image = settings.disc_image.unwrap_or(config.disc_image);
+    config.image_format = settings.image_format.unwrap_or(config.image_format);
+}
+
+/// Free bytes on the file system of `path`, or of the nearest folder above it that exists
+fn free_space(path: &Path) -> Option<u64> {
+    free_space_of(path, filesystem_free)
+}
+
+/// What `free` tells for `path`, or for the nearest folder above it that exists
+fn free_space_of(path: &Path, free: impl Fn(&Path) -> Option<u64>) -> Option<u64> {
+    free(path.ancestors().find(|p| p.exists())?)
+}
+
+/// Free bytes on the file system of the folder, as gio has it
+fn filesystem_free(existing: &Path) -> Option<u64> {
+    let info = gio::File::for_path(existing)
+        .query_filesystem_info(
+            gio::FILE_ATTRIBUTE_FILESYSTEM_FREE,
+            None::<&gio::Cancellable>,
+        )
+        .ok()?;
+    info.has_attribute(gio::FILE_ATTRIBUTE_FILESYSTEM_FREE)
+        .then(|| info.attribute_uint64(gio::FILE_ATTRIBUTE_FILESYSTEM_FREE))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply, apply_folder, apply_route, choose_route, free_space, free_space_of, select,
+        transcode_targets, FOLDER_SETTINGS,
+    };
+    use crate::data::{Config, Disc, DiscSettings, Encoder, ImageFormat, Profile, Quality, Route};
+    use anyhow::Result;
+    use std::{env, path::Path};
+
+    fn profiles() -> Vec<Profile> {
+        vec![
+            Profile {
+                name: "archive".to_owned(),
+                encoder: Encoder::FLAC,
+                quality: Quality::High,
+                min_free_gb: Some(20.0),
+            },
+            Profile {
+                name: "portable".to_owned(),
+                encoder: Encoder::OPUS,
+                quality: Quality::Medium,
+                min_free_gb: None,
+            },
+        ]
+    }
+
+    #[test]
+    pub fn test_select() {
+        let profiles = profiles();
+        let name = |free| select(&profiles, free).map(|p| p.name.as_str());
+        assert_eq!(Some("archive"), name(Some(25_000_000_000)));
+        assert_eq!(Some("portable"), name(Some(5_000_000_000)));
+        assert_eq!(Some("portable"), name(None));
+        assert_eq!(None, select(&profiles[..1], Some(0)));
+    }
+
+    #[test]
+    pub fn test_transcode_targets() {
+        let mut config = Config {
+            quality: Quality::Low,
+            ..Default::default()
+        };
+        let targets = transcode_targets(&config);
+        assert!(targets.iter().all(|t| t.quality == Quality::Low));
+        assert!(targets.iter().any(|t| t.encoder == Encoder::OPUS));
+        assert!(!targets.iter().any(|t| t.encoder == Encoder::FLAC));
+        config.profiles = profiles();
+        let names: Vec<String> = transcode_targets(&config)
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(vec!["portable"], names);
+    }
+
+    #[test]
+    pub fn test_disc_settings() {
+        let mut config = Config {
+            profiles: profiles(),
+            ..Default::default()
+        };
+        let settings = DiscSettings {
+            encoder: Encoder::OPUS,
+            quality: Quality::Low,
+            encode_path: "/audiobooks".to_owned(),
+        };
+        settings.apply(&mut config);
+        // the profiles don't replace the format of the disc
+        assert_eq!(None, apply(&mut config));
+        assert_eq!(Encoder::OPUS, config.encoder);
+        assert_eq!(Quality::Low, config.quality);
+        assert_eq!("/audiobooks", config.encode_path);
+    }
+
+    #[test]
+    pub fn test_routes() {
+        let routes = vec![
+            Route {
+                name: "Audiobooks".to_owned(),
+                path: "/audiobooks".to_owned(),
+                genres: vec!["Audiobook".to_owned(), "Spoken Word".to_owned()],
+            },
+            Route {
+                name: "Kids".to_owned(),
+                path: "/kids".to_owned(),
+                genres: Vec::new(),
+            },
+        ];
+        let config = || Config {
+            encode_path: "/music".to_owned(),
+            routes: routes.clone(),
+            ..Default::default()
+        };
+        let mut disc = Disc::with_tracks(1);
+        disc.genre = Some("spoken word".to_owned());
+        let mut routed = config();
+        assert_eq!(
+            Some("Audiobooks".to_owned()),
+            apply_route(&mut routed, &disc)
+        );
+        assert_eq!("/audiobooks", routed.encode_path);
+        // the library picked for the disc wins over the genre
+        let mut chosen = config();
+        choose_route(&mut chosen, "Kids");
+        assert_eq!(None, apply_route(&mut chosen, &disc));
+        assert_eq!("/kids", chosen.encode_path);
+        disc.genre = Some("Rock".to_owned());
+        let mut music = config();
+        assert_eq!(None, apply_route(&mut music, &disc));
+        disc.genre = None;
+        assert_eq!(None, apply_route(&mut music, &disc));
+        assert_eq!("/music", music.encode_path);
+    }
+
+    #[test]
+    pub fn test_free_space() {
+        let temp = env::temp_dir();
+        let free = |path: &Path| (path == temp).then_some(42_000_000_000);
+        // a folder that doesn't exist yet is on the file system of its parent
+        let missing = temp.join("ripperx4_free_space/album");
+        assert_eq!(Some(42_000_000_000), free_space_of(&missing, free));
+        assert_eq!(Some(42_000_000_000), free_space_of(&temp, free));
+        assert_eq!(None, free_space_of(Path::new(""), free));
+        assert!(free_space(&temp).is_some());
+    }
+
+    #[test]
+    pub fn test_store_profiles() -> Result<()> {
+        let path = env::temp_dir().join("ripperx4_profiles.toml");
+        let config = Config {
+            profiles: profiles(),
+            ..Default::default()
+        };
+        confy::store_path(&path, &config)?;
+        let loaded: Config = confy::load_path(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(profiles(), loaded.profiles);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_folder_settings() -> Result<()> {
+        let folder = env::temp_dir().join("ripperx4_audiobooks");
+        std::fs::create_dir_all(&folder)?;
+        let mut config = Config {
+            profiles: profiles(),
+            encode_path: folder.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        // no file, the configuration stays as it is and no file is created
+        let before = Config::default();
+        apply_folder(&mut config);
+        assert_eq!(before.name_template, config.name_template);
+        assert_eq!(2, config.profiles.len());
+        assert!(!folder.join(FOLDER_SETTINGS).exists());
+        std::fs::write(
+            folder.join(FOLDER_SETTINGS),
+            "profile = \"portable\"\nname_template = \"%A/%d\"\ndisc_image = true\nimage_format = \"Opus\"\n",
+        )?;
+        apply_folder(&mut config);
+        std::fs::remove_dir_all(&folder)?;
+        assert_eq!(Encoder::OPUS, config.encoder);
+        assert_eq!(Quality::Medium, config.quality);
+        // the free space no longer picks the profile
+        assert!(config.profiles.is_empty());
+        assert_eq!("%A/%d", config.name_template);
+        assert!(config.disc_image);
+        assert_eq!(ImageFormat::Opus, config.image_format);
+        assert_eq!(before.compilation_template, config.compilation_template);
+        assert_eq!(before.classical, config.classical);
+        Ok(())
+    }
+}
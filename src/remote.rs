@@ -0,0 +1,473 @@
+use crate::{
+    data::{CdBackend, Config, Track},
+    duration::toc_sectors,
+    ripper::{make_source, raw_audio},
+    util::drive,
+};
+use anyhow::{anyhow, Result};
+use discid::DiscId;
+use gstreamer::{
+    prelude::*, Bin, ClockTime, Element, MessageView, Pipeline, State, Structure, URIType,
+};
+use log::{debug, error};
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// The port `--serve-drive` listens on, and the one of a `Config::remote_drive` without one
+pub const DEFAULT_PORT: u16 = 4646;
+/// Bytes of 16 bit stereo audio in a CD sector
+const BYTES_PER_SECTOR: u64 = 2352;
+/// A client that sends nothing for this long is dropped, the server answers one at a time
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// The time a client has to send the whole request line and headers, so sending a byte now
+/// and then doesn't hold up the server
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Limits of the request line and headers read before the token is checked
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 32;
+
+/// Why the request line and headers of a client were refused
+#[derive(Debug, PartialEq, Eq)]
+enum Refused {
+    /// more than `MAX_HEAD_BYTES` or `MAX_HEADERS`
+    TooLarge,
+    /// not ended by an empty line, or not text
+    Malformed,
+    /// not sent within `REQUEST_TIMEOUT`
+    TimedOut,
+}
+
+impl Refused {
+    fn response(&self) -> &'static [u8] {
+        match self {
+            Refused::TooLarge => b"HTTP/1.0 431 Request Header Fields Too Large\r\n\r\n",
+            Refused::Malformed => b"HTTP/1.0 400 Bad Request\r\n\r\n",
+            Refused::TimedOut => b"HTTP/1.0 408 Request Timeout\r\n\r\n",
+        }
+    }
+}
+
+/// Reads from a client until a deadline, each read waits at most `READ_TIMEOUT`
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "the request took too long",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left.min(READ_TIMEOUT)))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// What a client of `--serve-drive` asks for
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    /// the TOC of the disc, `GET /toc`
+    Toc,
+    /// the raw audio of the track, counting from 1, `GET /track/3`
+    Track(u32),
+}
+
+/// Serve the drive of this machine to the `Remote` CD source of ripperx4 on others, until
+/// the process is stopped
+/// The TOC and the raw audio of the tracks (16 bit little endian stereo at 44.1 kHz) are
+/// served over HTTP, read with the CD source and error correction of the configuration
+/// With `Config::remote_token` set, requests without it are refused; without it the drive is
+/// only served to this machine
+pub fn serve(port: u16) -> Result<()> {
+    let config = Config::load()?;
+    if config.cd_backend == CdBackend::Remote {
+        return Err(anyhow!(
+            "The served drive must be a local one, choose another CD source in the configuration"
+        ));
+    }
+    let address = if config.remote_token.is_some() {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        Ipv4Addr::LOCALHOST
+    };
+    let listener = TcpListener::bind((address, port))?;
+    println!("Serving {} on port {port}", drive(&config));
+    if config.remote_token.is_none() {
+        eprintln!(
+            "Warning: no remote_token is configured, the drive is only served to this machine"
+        );
+    }
+    // one drive, so one client at a time
+    for stream in listener.incoming() {
+        let served = stream
+            .map_err(Into::into)
+            .and_then(|stream| respond(stream, &config));
+        if let Err(e) = served {
+            error!("Failed to serve the drive: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, config: &Config) -> Result<()> {
+    let deadline = Deadline {
+        stream: &stream,
+        until: Instant::now() + REQUEST_TIMEOUT,
+    };
+    let (line, token) = match read_head(BufReader::new(deadline)) {
+        Ok(head) => head,
+        Err(refused) => {
+            debug!("remote request refused: {refused:?}");
+            stream.write_all(refused.response())?;
+            return Ok(());
+        }
+    };
+    let Some(request) = parse_request(&line) else {
+        stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n")?;
+        return Ok(());
+    };
+    debug!("remote request {request:?}");
+    if !authorized(config.remote_token.as_deref(), token.as_deref()) {
+        stream.write_all(b"HTTP/1.0 401 Unauthorized\r\n\r\n")?;
+        return Ok(());
+    }
+    let discid = match DiscId::read(Some(&drive(config))) {
+        Ok(discid) => discid,
+        Err(e) => {
+            stream.write_all(format!("HTTP/1.0 503 Service Unavailable\r\n\r\n{e}").as_bytes())?;
+            return Ok(());
+        }
+    };
+    match request {
+        Request::Toc => {
+            let offsets: Vec<i32> = discid.tracks().map(|t| t.offset).collect();
+            let body = toc_body(discid.first_track_num(), discid.sectors(), &offsets);
+            stream.write_all(ok_header("text/plain", body.len() as u64).as_bytes())?;
+            stream.write_all(body.as_bytes())?;
+        }
+        Request::Track(number) => {
+            let Some(sectors) = discid
+                .tracks()
+                .find(|t| u32::try_from(t.number) == Ok(number))
+                .and_then(|t| toc_sectors(t.sectors))
+            else {
+                stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n")?;
+                return Ok(());
+            };
+            // with the length the client knows the duration, for its progress
+            let length = sectors * BYTES_PER_SECTOR;
+            stream.write_all(ok_header("audio/x-raw", length).as_bytes())?;
+            stream_track(number, config, &stream)?;
+        }
+    }
+    Ok(())
+}
+
+fn ok_header(content_type: &str, length: u64) -> String {
+    format!("HTTP/1.0 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {length}\r\n\r\n")
+}
+
+/// Read the track from the drive and write its raw audio to the client
+#[cfg(unix)]
+fn stream_track(number: u32, config: &Config, stream: &TcpStream) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let track = Track {
+        number,
+        ..Default::default()
+    };
+    let source = make_source(&track, config)?;
+    let sink = gstreamer::ElementFactory::make("fdsink")
+        .property("fd", stream.as_raw_fd())
+        .build()?;
+    let pipeline = Pipeline::new();
+    pipeline.add_many([&source, &sink])?;
+    source.link(&sink)?;
+    let result = play_to_end(&pipeline);
+    pipeline.set_state(State::Null)?;
+    result
+}
+
+#[cfg(not(unix))]
+fn stream_track(_number: u32, _config: &Config, _stream: &TcpStream) -> Result<()> {
+    Err(anyhow!("Serving the drive needs a Unix system"))
+}
+
+fn play_to_end(pipeline: &Pipeline) -> Result<()> {
+    let bus = pipeline.bus().ok_or(anyhow!("no bus".to_owned()))?;
+    pipeline.set_state(State::Playing)?;
+    for msg in bus.iter_timed(ClockTime::NONE) {
+        match msg.view() {
+            MessageView::Eos(..) => return Ok(()),
+            // e.g. the client went away
+            MessageView::Error(err) => return Err(anyhow!("Reading failed: {}", err.error())),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// The request line and the bearer token of the headers, read up to the empty line that ends
+/// them, within `MAX_HEAD_BYTES` and `MAX_HEADERS`
+fn read_head(reader: impl BufRead) -> std::result::Result<(String, Option<String>), Refused> {
+    let mut reader = reader.take(MAX_HEAD_BYTES);
+    let mut read_line = |line: &mut String| {
+        line.clear();
+        match reader.read_line(line) {
+            Ok(_) if line.ends_with('\n') => Ok(()),
+            Ok(_) if reader.limit() == 0 => Err(Refused::TooLarge),
+            // the client closed the connection in the middle
+            Ok(_) => Err(Refused::Malformed),
+            Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                Err(Refused::TimedOut)
+            }
+            Err(_) => Err(Refused::Malformed),
+        }
+    };
+    let mut line = String::new();
+    read_line(&mut line)?;
+    let mut token = None;
+    let mut header = String::new();
+    for _ in 0..=MAX_HEADERS {
+        read_line(&mut header)?;
+        if header.trim().is_empty() {
+            return Ok((line, token));
+        }
+        token = token.or_else(|| bearer_token(&header));
+    }
+    Err(Refused::TooLarge)
+}
+
+/// The request of the first line of an HTTP request, e.g. `GET /track/3 HTTP/1.1`
+fn parse_request(line: &str) -> Option<Request> {
+    let mut parts = line.split_whitespace();
+    if parts.next() != Some("GET") {
+        return None;
+    }
+    let target = parts.next()?;
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let path: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match path.as_slice() {
+        ["toc"] => Some(Request::Toc),
+        ["track", number] => number.parse().ok().map(Request::Track),
+        _ => None,
+    }
+}
+
+/// The token of an `Authorization: Bearer secret` header line
+fn bearer_token(header: &str) -> Option<String> {
+    let (name, value) = header.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case("authorization") {
+        return None;
+    }
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim().to_owned())
+}
+
+/// The `Authorization` header the clients send the token in
+fn authorization(token: &str) -> String {
+    format!("Bearer {token}")
+}
+
+/// Whether the client sent the configured token, compared in constant time so the time of the
+/// answer doesn't tell how much of it was right
+fn authorized(expected: Option<&str>, given: Option<&str>) -> bool {
+    let Some(expected) = expected else {
+        return true;
+    };
+    let given = given.unwrap_or_default();
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The TOC as served: the first track, the sectors of the disc and the offset of each track
+fn toc_body(first: i32, sectors: i32, offsets: &[i32]) -> String {
+    let numbers: Vec<String> = [first, sectors]
+        .iter()
+        .chain(offsets)
+        .map(ToString::to_string)
+        .collect();
+    numbers.join(" ")
+}
+
+/// The first track and the offsets `DiscId::put` takes, the lead-out first
+fn parse_toc(body: &str) -> Result<(i32, Vec<i32>)> {
+    let numbers = body
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<i32>, _>>()?;
+    match numbers.split_first() {
+        Some((first, offsets)) if offsets.len() > 1 => Ok((*first, offsets.to_vec())),
+        _ => Err(anyhow!("the remote drive sent no TOC")),
+    }
+}
+
+/// `host:port` of the remote drive, with the default port when it has none
+fn address(remote: &str) -> String {
+    if remote.contains(':') {
+        remote.to_owned()
+    } else {
+        format!("{remote}:{DEFAULT_PORT}")
+    }
+}
+
+/// The URL of `path` on the remote drive
+fn url(remote: &str, path: &str) -> String {
+    format!("http://{}/{path}", address(remote))
+}
+
+/// Read the TOC of the disc in the remote drive
+pub fn read_toc(remote: &str, token: Option<&str>) -> Result<DiscId> {
+    let mut request = ureq::get(&url(remote, "toc"));
+    if let Some(token) = token {
+        request = request.set("Authorization", &authorization(token));
+    }
+    let body = request.call()?.into_string()?;
+    let (first, offsets) = parse_toc(&body)?;
+    Ok(DiscId::put(first, &offsets)?)
+}
+
+/// A source element with the raw audio of the track in the remote drive, named `cd_src` like
+/// the CD sources
+pub fn source(remote: &str, token: Option<&str>, track: &Track) -> Result<Element> {
+    let url = url(remote, &format!("track/{}", track.number));
+    let http = Element::make_from_uri(URIType::Src, &url, None)?;
+    if let Some(token) = token {
+        if !http.has_property("extra-headers", Some(Structure::static_type())) {
+            return Err(anyhow!(
+                "The HTTP source can't send the remote_token, install souphttpsrc (gst-plugins-good)"
+            ));
+        }
+        let headers = Structure::builder("extra-headers")
+            .field("Authorization", authorization(token))
+            .build();
+        http.set_property("extra-headers", headers);
+    }
+    raw_audio(http, Bin::builder().name("cd_src").build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        address, authorization, authorized, bearer_token, parse_request, parse_toc, read_head,
+        toc_body, url, Deadline, Refused, Request, MAX_HEADERS, MAX_HEAD_BYTES,
+    };
+    use std::{
+        io::{self, BufReader, ErrorKind, Read},
+        net::{TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    pub fn test_read_head() {
+        let head = read_head(
+            "GET /toc HTTP/1.1\r\nHost: a\r\nAuthorization: Bearer s3cret\r\n\r\n".as_bytes(),
+        );
+        assert_eq!(
+            Ok((
+                "GET /toc HTTP/1.1\r\n".to_owned(),
+                Some("s3cret".to_owned())
+            )),
+            head
+        );
+        let many = format!(
+            "GET /toc HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(Err(Refused::TooLarge), read_head(many.as_bytes()));
+        let long = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_HEAD_BYTES as usize)
+        );
+        assert_eq!(Err(Refused::TooLarge), read_head(long.as_bytes()));
+        assert_eq!(
+            Err(Refused::Malformed),
+            read_head("GET /toc HTTP/1.1\r\nHost".as_bytes())
+        );
+
+        struct Slow;
+        impl Read for Slow {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::from(ErrorKind::TimedOut))
+            }
+        }
+        assert_eq!(Err(Refused::TimedOut), read_head(BufReader::new(Slow)));
+    }
+
+    #[test]
+    pub fn test_deadline() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _client = TcpStream::connect(listener.local_addr()?)?;
+        let (stream, _) = listener.accept()?;
+        // the client sends nothing, the deadline ends the wait before READ_TIMEOUT
+        let started = Instant::now();
+        let deadline = Deadline {
+            stream: &stream,
+            until: started + Duration::from_millis(200),
+        };
+        assert_eq!(Err(Refused::TimedOut), read_head(BufReader::new(deadline)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_requests() {
+        assert_eq!(Some(Request::Toc), parse_request("GET /toc HTTP/1.1\r\n"));
+        assert_eq!(
+            Some(Request::Track(3)),
+            parse_request("GET /track/3?ignored HTTP/1.1\r\n")
+        );
+        assert_eq!(None, parse_request("GET /track/x HTTP/1.1\r\n"));
+        assert_eq!(None, parse_request("PUT /toc HTTP/1.1\r\n"));
+        assert_eq!(None, parse_request("GET /eject HTTP/1.1\r\n"));
+        assert_eq!(None, parse_request("GET\r\n"));
+        assert_eq!("http://laptop:4646/track/3", url("laptop", "track/3"));
+        assert_eq!(
+            Some("s3cret".to_owned()),
+            bearer_token(&format!("Authorization: {}\r\n", authorization("s3cret")))
+        );
+        assert_eq!(
+            Some("s3cret".to_owned()),
+            bearer_token("authorization:   bearer s3cret  \r\n")
+        );
+        assert_eq!(None, bearer_token("Authorization: Basic czNjcmV0\r\n"));
+        assert_eq!(None, bearer_token("X-Token: Bearer s3cret\r\n"));
+        assert_eq!(None, bearer_token("\r\n"));
+    }
+
+    #[test]
+    pub fn test_authorized() {
+        assert!(authorized(None, None));
+        assert!(authorized(None, Some("anything")));
+        assert!(authorized(Some("s3cret"), Some("s3cret")));
+        assert!(!authorized(Some("s3cret"), Some("s3cre")));
+        assert!(!authorized(Some("s3cret"), Some("S3cret")));
+        assert!(!authorized(Some("s3cret"), None));
+    }
+
+    #[test]
+    pub fn test_toc() {
+        let body = toc_body(1, 450, &[150, 300]);
+        assert_eq!("1 450 150 300", body);
+        assert_eq!((1, vec![450, 150, 300]), parse_toc(&body).unwrap());
+        assert!(parse_toc("").is_err());
+        assert!(parse_toc("1 450").is_err());
+        assert!(parse_toc("<html>").is_err());
+        assert_eq!("laptop:4646", address("laptop"));
+        assert_eq!("192.168.1.5:9000", address("192.168.1.5:9000"));
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Result};
+use gstreamer::{
+    prelude::*,
+    tags::{TrackGain, TrackPeak},
+    ClockTime, Element, ElementFactory, MessageView, Pipeline, State, TagList, TagMergeMode,
+};
+
+/// Peak lossy files are limited to, -1 dBFS: the encoders overshoot the peaks of the source
+/// and players without ReplayGain clip those
+const SAFE_PEAK: f64 = 0.891;
+
+/// The loudness of a track as measured by `rganalysis`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    /// dB to bring the track to the ReplayGain reference level
+    pub gain: f64,
+    /// the highest sample, 1.0 is full scale
+    pub peak: f64,
+}
+
+impl Gain {
+    /// The volume bringing the peak down to `SAFE_PEAK`, 1.0 when it's below already
+    pub fn safe_volume(&self) -> f64 {
+        if self.peak > SAFE_PEAK {
+            SAFE_PEAK / self.peak
+        } else {
+            1.0
+        }
+    }
+
+    /// The gain and peak of the track after its volume is changed
+    pub fn with_volume(&self, volume: f64) -> Gain {
+        Gain {
+            gain: self.gain - 20.0 * volume.log10(),
+            peak: self.peak * volume,
+        }
+    }
+
+    /// Add the REPLAYGAIN_TRACK_GAIN and REPLAYGAIN_TRACK_PEAK tags
+    pub fn add_tags(&self, tags: &mut TagList) -> Result<()> {
+        let tags = tags
+            .get_mut()
+            .ok_or(anyhow!("can not get mut".to_owned()))?;
+        tags.add::<TrackGain>(&self.gain, TagMergeMode::ReplaceAll);
+        tags.add::<TrackPeak>(&self.peak, TagMergeMode::ReplaceAll);
+        Ok(())
+    }
+}
+
+/// Measure the audio of `source`, e.g. the raw audio of a track that was read
+pub fn analyze(source: Element) -> Result<Gain> {
+    let convert = ElementFactory::make("audioconvert").build()?;
+    let analysis = ElementFactory::make("rganalysis").build()?;
+    let sink = ElementFactory::make("fakesink")
+        .property("sync", false)
+        .build()?;
+    let elements = [&source, &convert, &analysis, &sink];
+    let pipeline = Pipeline::new();
+    pipeline.add_many(elements)?;
+    Element::link_many(elements)?;
+    let gain = run_analysis(&pipeline);
+    pipeline.set_state(State::Null)?;
+    gain
+}
+
+/// Play the pipeline to the end, collecting the tags `rganalysis` sends downstream
+fn run_analysis(pipeline: &Pipeline) -> Result<Gain> {
+    let bus = pipeline.bus().ok_or(anyhow!("no bus".to_owned()))?;
+    pipeline.set_state(State::Playing)?;
+    let (mut gain, mut peak) = (None, None);
+    for msg in bus.iter_timed(ClockTime::NONE) {
+        match msg.view() {
+            MessageView::Tag(tag) => {
+                let tags = tag.tags();
+                gain = tags.get::<TrackGain>().map(|v| v.get()).or(gain);
+                peak = tags.get::<TrackPeak>().map(|v| v.get()).or(peak);
+            }
+            MessageView::Eos(..) => break,
+            MessageView::Error(err) => {
+                return Err(anyhow!("ReplayGain analysis failed: {}", err.error()));
+            }
+            _ => (),
+        }
+    }
+    match (gain, peak) {
+        (Some(gain), Some(peak)) => Ok(Gain { gain, peak }),
+        _ => Err(anyhow!("rganalysis found no track gain")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Gain;
+
+    #[test]
+    pub fn test_safe_volume() {
+        // a hot master, the peak is brought down to -1 dBFS and the tags follow
+        let hot = Gain {
+            gain: -9.5,
+            peak: 1.0,
+        };
+        let volume = hot.safe_volume();
+        assert!((volume - 0.891).abs() < 1e-9);
+        let limited = hot.with_volume(volume);
+        assert!((limited.peak - 0.891).abs() < 1e-9);
+        assert!((limited.gain + 8.5).abs() < 0.01);
+        // quiet tracks are left alone
+        let quiet = Gain {
+            gain: 2.0,
+            peak: 0.5,
+        };
+        assert!((quiet.safe_volume() - 1.0).abs() < f64::EPSILON);
+        assert_eq!(quiet, quiet.with_volume(1.0));
+    }
+}
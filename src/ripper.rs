@@ -1,277 +1,2545 @@
-use crate::data::{Config, Disc, Encoder, Track};
+use crate::{
+    accuraterip::{self, Checksum, Entry, Verdict},
+    automation::Automation,
+    cancel::CancelToken,
+    controller::send_status,
+    coverart::{fetch_cover, image_extension},
+    data::{
+        CdBackend, Comparison, Config, Disc, Encoded, Encoder, ErrorCorrection, ImageFormat,
+        Outcome, Profile, Quality, ReadErrors, ReplayGain, Segment, Status, Track, TrackState,
+    },
+    duration::{sectors_to_seconds, SAMPLE_RATE},
+    encoder::{backend, file_extension},
+    hooks::record_tracks,
+    library::Archive,
+    naming::{expand_template, name_rules, sanitize, template},
+    remote,
+    replaygain::{self, Gain},
+    tags::{add_chapters, image_tags, track_tags},
+    util::{cue_sheet, drive},
+};
 use anyhow::{anyhow, Result};
-use async_channel::Sender;
+use async_channel::{Receiver, Sender};
 use glib::ControlFlow;
 use gstreamer::{
     format::Percent,
-    glib,
-    glib::MainLoop,
+    glib::{self, subclass::SignalId, MainContext, MainLoop, Priority},
     prelude::*,
-    tags::{Album, Artist, Composer, Date, Duration, Title, TrackNumber},
-    ClockTime, Element, ElementFactory, Format, GenericFormattedValue, MessageView, Pipeline,
-    State, TagList, TagMergeMode, TagSetter, URIType,
+    Bin, ClockTime, Element, ElementFactory, Format, GenericFormattedValue, GhostPad, MessageView,
+    Pipeline, SeekFlags, SeekType, State, Structure, TagList, TagMergeMode, TagSetter, URIType,
 };
-use log::{debug, error};
+use log::{debug, error, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::{
-    path::Path,
-    sync::{Arc, RwLock},
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-/// Extract/Rip a `Disc` to MP3/OGG/FLAC
-pub fn extract(disc: &Disc, status: &Sender<String>, ripping: &Arc<RwLock<bool>>) -> Result<()> {
-    for t in &disc.tracks {
-        if !*ripping.read().expect("failed to get state") {
-            // ABORTED
-            break;
-        }
-        let pipeline = create_pipeline(t, disc)?;
-        if t.rip {
-            extract_track(pipeline, &t.title, status, ripping.clone())?;
+/// Makes the element reading a track, `make_source` for the drive
+type SourceMaker = fn(&Track, &Config) -> Result<Element>;
+
+/// Extract/Rip a `Disc` to MP3/OGG/FLAC
+/// The tracks are ripped in `order` (indexes into `Disc::tracks`), the tracks not in it are skipped
+/// Returns the encoded tracks for the summary
+pub fn extract(
+    disc: &Disc,
+    order: &[usize],
+    config: &Config,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+) -> Result<Vec<Encoded>> {
+    extract_from(disc, order, config, status, cancel, make_source)
+}
+
+/// `extract` with the tracks read by the elements of `source`
+fn extract_from(
+    disc: &Disc,
+    order: &[usize],
+    config: &Config,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+    source: SourceMaker,
+) -> Result<Vec<Encoded>> {
+    // the track format seeks of the image need a CD source
+    if config.disc_image && config.cd_backend == CdBackend::Remote {
+        return Err(anyhow!("Disc images can't be ripped from a remote drive"));
+    }
+    let fallback_available = config
+        .fallback_encoder
+        .is_some_and(|encoder| missing_elements(&encoder).is_empty());
+    let missing = if config.disc_image {
+        missing_image_elements(config.image_format)
+    } else if fallback_available {
+        // the tracks whose encoder can't be made are encoded to the fallback format
+        Vec::new()
+    } else {
+        let mut missing = Vec::new();
+        for encoder in track_encoders(disc, order, config) {
+            for element in missing_elements(&encoder) {
+                if !missing.contains(&element) {
+                    missing.push(element);
+                }
+            }
+        }
+        missing
+    };
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Missing GStreamer elements: {}",
+            describe_missing(&missing)
+        ));
+    }
+    let started = SystemTime::now();
+    // downloaded while the disc is read
+    let pressings = (config.secure_rip && !config.disc_image).then(|| {
+        let disc = disc.clone();
+        thread::spawn(move || fetch_pressings(&disc))
+    });
+    let mut ripped = if config.disc_image {
+        Ripped::from(extract_image(disc, order, config, status, cancel))
+    } else {
+        extract_tracks(disc, order, config, status, cancel, source)
+    };
+    let pressings = match pressings {
+        Some(fetch) if !ripped.verified.is_empty() => fetch.join().unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    for verified in &ripped.verified {
+        if let Some(encoded) = ripped.encoded.iter_mut().find(|e| e.path == verified.path) {
+            let verdict = accuraterip::verify(&pressings, verified.number, &verified.read.checksum);
+            encoded.accuraterip = Some(verdict);
+        }
+    }
+    if config.json_report {
+        // the report is written for failed rips as well, it doesn't fail the rip
+        if let Err(e) = write_report(disc, order, config, &ripped, &pressings, started, true) {
+            error!("Failed to write the rip report: {e}");
+        }
+    }
+    if let Some(e) = ripped.error {
+        return Err(e);
+    }
+    if let Some(folder) = ripped.verified.first().and_then(|v| v.path.parent()) {
+        let log = write_rip_log(disc, config, folder, &ripped.verified, &pressings)?;
+        set_permissions(&log, config.file_mode.as_deref(), config.group.as_deref())?;
+    }
+    let report = ripped.encoded;
+    let produced: Vec<PathBuf> = report.iter().map(|e| e.path.clone()).collect();
+    if let Some(folder) = produced.first().and_then(|l| l.parent()) {
+        if config.write_checksums {
+            let manifest = write_checksums(folder, &produced)?;
+            set_permissions(
+                &manifest,
+                config.file_mode.as_deref(),
+                config.group.as_deref(),
+            )?;
+        }
+        if config.symlink_views {
+            update_library_views(disc, config, folder)?;
+        }
+        if config.cover_art {
+            // a missing cover doesn't fail the rip
+            if let Err(e) = save_cover(disc, config, folder) {
+                error!("Failed to save the cover: {e}");
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Encode the FLAC files of a ripped disc to the format of the `profile`, without the disc
+/// The files go where a rip to that format would put them, the tags of the FLAC files are kept
+/// Returns the encoded files
+pub fn transcode(
+    archive: &Archive,
+    profile: &Profile,
+    config: &Config,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+) -> Result<Vec<Encoded>> {
+    let mut config = config.clone();
+    config.encoder = profile.encoder;
+    config.quality = profile.quality;
+    config.profiles.clear();
+    config.name_rules = name_rules(&config);
+    let missing = missing_elements(&profile.encoder);
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Missing GStreamer elements: {}",
+            describe_missing(&missing)
+        ));
+    }
+    let disc = &archive.disc;
+    let mut report = Vec::new();
+    for (track, flac) in disc.tracks.iter().zip(&archive.files) {
+        let location = output_location(track, disc, &config);
+        if location == *flac {
+            return Err(anyhow!("{} would be overwritten", flac.display()));
+        }
+        let partial = partial_location(&location);
+        let pipeline = create_pipeline(flac_source(flac)?, track, disc, &config, &partial, None)?;
+        if let Err(e) = extract_track(pipeline, track, status, cancel, progress_interval(&config)) {
+            fs::remove_file(&partial).ok();
+            return Err(e);
+        }
+        finalize_output(&partial, &location)?;
+        send_progress(status, track, 100.0, TrackState::Done);
+        set_permissions(
+            &location,
+            config.file_mode.as_deref(),
+            config.group.as_deref(),
+        )?;
+        report.push(Encoded {
+            size: fs::metadata(&location)?.len(),
+            path: location,
+            number: track.number,
+            title: track.title.clone(),
+            // the inventory doesn't have the lengths
+            bitrate: None,
+            expected_bitrate: backend(&profile.encoder).estimated_bitrate(&profile.quality),
+            read_errors: ReadErrors::default(),
+            accuraterip: None,
+        });
+    }
+    Ok(report)
+}
+
+/// The decoded audio of a FLAC file
+fn flac_source(location: &Path) -> Result<Element> {
+    let bin = Bin::new();
+    let file = ElementFactory::make("filesrc")
+        .property("location", location.to_str())
+        .build()?;
+    let parse = ElementFactory::make("flacparse").build()?;
+    let decode = ElementFactory::make("flacdec").build()?;
+    bin.add_many([&file, &parse, &decode])?;
+    Element::link_many([&file, &parse, &decode])?;
+    let pad = decode
+        .static_pad("src")
+        .ok_or(anyhow!("flacdec has no src pad"))?;
+    bin.add_pad(&GhostPad::with_target(&pad)?)?;
+    Ok(bin.upcast())
+}
+
+/// Read the tracks of an earlier rip from the disc again and compare them to the checksums in
+/// its JSON `report`, nothing is written but the raw audio of the track being checked
+/// Answers whether the old files are accurate, e.g. before the disc is sold
+pub fn verify_rip(
+    disc: &Disc,
+    report: &Path,
+    config: &Config,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+) -> Result<Vec<Comparison>> {
+    let report: Value = serde_json::from_str(&fs::read_to_string(report)?)?;
+    if let (Some(ripped), Some(discid)) = (report["disc"]["discid"].as_str(), &disc.discid) {
+        if ripped != discid {
+            return Err(anyhow!("The report is of another disc"));
+        }
+    }
+    let checksums = report_checksums(&report);
+    if checksums.is_empty() {
+        return Err(anyhow!(
+            "The report has no checksums, the disc was not ripped securely"
+        ));
+    }
+    let pressings = {
+        let disc = disc.clone();
+        thread::spawn(move || fetch_pressings(&disc))
+    };
+    let mut reads = Vec::new();
+    for (index, track) in disc.tracks.iter().enumerate() {
+        let Some(&(v1, v2)) = checksums.get(&track.number) else {
+            continue;
+        };
+        if cancel.is_cancelled() {
+            return Err(anyhow!("Verification stopped"));
+        }
+        let pcm = pcm_location(index);
+        let pipeline = create_read_pipeline(track, config, &pcm, make_source)?;
+        let read = watch_source(&pipeline, track, disc, status)?;
+        let interval = progress_interval(config);
+        let result = run_pipeline(
+            pipeline,
+            track,
+            TrackState::Reading,
+            status,
+            cancel,
+            interval,
+        )
+        .and_then(|()| {
+            let mut read = std::mem::take(&mut *read.write().map_err(|e| anyhow!("{e}"))?);
+            checksum_pcm(&pcm, &mut read.checksum)?;
+            Ok(read)
+        });
+        fs::remove_file(&pcm).ok();
+        let read = result?;
+        send_progress(status, track, 100.0, TrackState::Done);
+        let matches = read.checksum.v1() == v1 && read.checksum.v2() == v2;
+        reads.push((track, read, matches));
+    }
+    let pressings = pressings.join().unwrap_or_default();
+    Ok(reads
+        .into_iter()
+        .map(|(track, read, matches)| Comparison {
+            number: track.number,
+            title: track.title.clone(),
+            matches,
+            read_errors: read.errors,
+            accuraterip: accuraterip::verify(&pressings, track.number, &read.checksum),
+        })
+        .collect())
+}
+
+/// The AccurateRip v1 and v2 checksums of the tracks in a JSON rip report, by track number
+fn report_checksums(report: &Value) -> BTreeMap<u32, (u32, u32)> {
+    let crc = |value: &Value| {
+        value
+            .as_str()
+            .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+    };
+    report["tracks"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|track| {
+            let number = u32::try_from(track["number"].as_u64()?).ok()?;
+            Some((number, (crc(&track["crc_v1"])?, crc(&track["crc_v2"])?)))
+        })
+        .collect()
+}
+
+/// Rip each selected track (or each of its segments) to a file of its own
+/// The disc is read in this thread while the tracks read before are encoded by
+/// `Config::encoder_workers` others, the raw audio is passed on in a temporary file
+fn extract_tracks(
+    disc: &Disc,
+    order: &[usize],
+    config: &Config,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+    source: SourceMaker,
+) -> Ripped {
+    let parts: Vec<(Track, Option<Segment>)> = order
+        .iter()
+        .filter_map(|i| disc.tracks.get(*i))
+        .filter(|t| t.rip)
+        .flat_map(|t| {
+            track_parts(t)
+                .into_iter()
+                .map(|(part, segment)| (part, segment.cloned()))
+        })
+        .collect();
+    let workers = encoder_workers(config);
+    // stops the reader and the encoders when one of them failed, or the rip was cancelled
+    let stop = CancelToken::new();
+    let stop_clone = stop.clone();
+    let _cancel_guard = cancel.on_cancel(move || stop_clone.cancel());
+    let mut rip = Rip::new(disc, config, status, cancel, &stop);
+    rip.source = source;
+    // the reader is at most one track ahead of each encoder
+    let (jobs, queue) = async_channel::bounded(workers);
+    let mut done = thread::scope(|scope| {
+        let encoders: Vec<_> = (0..workers)
+            .map(|_| {
+                let queue = queue.clone();
+                let rip = &rip;
+                scope.spawn(move || rip.encode_tracks(&queue))
+            })
+            .collect();
+        let writer = config
+            .spool_dir
+            .is_some()
+            .then(|| scope.spawn(|| rip.write_spooled()));
+        rip.read_tracks(&parts, &jobs);
+        jobs.close();
+        let done = encoders
+            .into_iter()
+            .flat_map(|encoder| encoder.join().unwrap_or_default())
+            .collect::<Vec<EncodedTrack>>();
+        rip.spool.0.close();
+        if let Some(writer) = writer {
+            writer.join().ok();
+        }
+        done
+    });
+    if let Some(folder) = rip.spool_folder() {
+        fs::remove_dir(folder).ok();
+    }
+    let error = rip
+        .error
+        .into_inner()
+        .unwrap_or_else(|e| Some(anyhow!("{e}")));
+    let report = rip
+        .journal
+        .into_inner()
+        .ok()
+        .and_then(|journal| journal.report);
+    done.sort_by_key(|track| track.index);
+    let verified = done.iter_mut().filter_map(|t| t.verified.take()).collect();
+    Ripped {
+        encoded: done.into_iter().map(|track| track.encoded).collect(),
+        verified,
+        error,
+        report,
+    }
+}
+
+/// What a rip produced, also when it failed
+#[derive(Default)]
+struct Ripped {
+    encoded: Vec<Encoded>,
+    /// the tracks of a secure rip
+    verified: Vec<Verified>,
+    error: Option<anyhow::Error>,
+    /// the report written after each track, the one of the finished rip replaces it
+    report: Option<PathBuf>,
+}
+
+impl From<Result<Vec<Encoded>>> for Ripped {
+    fn from(result: Result<Vec<Encoded>>) -> Ripped {
+        match result {
+            Ok(encoded) => Ripped {
+                encoded,
+                ..Default::default()
+            },
+            Err(e) => Ripped {
+                error: Some(e),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// How many tracks are encoded at the same time
+fn encoder_workers(config: &Config) -> usize {
+    config.encoder_workers.max(1)
+}
+
+/// State shared by the reader and the encoders of `extract_tracks`
+struct Rip<'a> {
+    disc: &'a Disc,
+    config: &'a Config,
+    status: &'a Sender<Status>,
+    cancel: &'a CancelToken,
+    stop: &'a CancelToken,
+    /// the first error, the rip fails with it
+    error: Mutex<Option<anyhow::Error>>,
+    /// files could be written to the encode path, so it going away pauses the rip
+    target_seen: AtomicBool,
+    /// held by the encoder waiting for the encode path to come back
+    waiting: Mutex<()>,
+    /// the script of `Config::automation_script`
+    automation: Option<Arc<Automation>>,
+    /// the files encoded to the spool folder, for the writer
+    spool: (Sender<Spooled>, Receiver<Spooled>),
+    /// the tracks done so far, for the report written after each one
+    journal: Mutex<Ripped>,
+    started: SystemTime,
+    /// reads the tracks, from the drive unless a test reads a file
+    source: SourceMaker,
+}
+
+/// A track read from the disc, waiting for an encoder
+struct ReadTrack {
+    /// position in the rip order
+    index: usize,
+    part: Track,
+    location: PathBuf,
+    /// raw audio of the track
+    pcm: PathBuf,
+    read: SecureRead,
+    /// whether the track is checked with AccurateRip
+    verify: bool,
+    /// loudness of the track, when ReplayGain is on
+    gain: Option<Gain>,
+    /// the encode pipeline, made by the reader while the encoders are busy with the tracks
+    /// before, so an encoder can start on the track right away
+    pipeline: Option<Pipeline>,
+}
+
+impl ReadTrack {
+    /// The track as listed in the rip log, with the checksums of its raw audio
+    /// Computed by the encoder after the drive moved on, so the drive never waits for them
+    /// None when the raw audio can't be checksummed completely, the track is then left out of
+    /// the verification rather than judged by the checksum of a part of it
+    fn verified(&mut self) -> Option<Verified> {
+        if !self.verify {
+            return None;
+        }
+        if let Err(e) = checksum_pcm(&self.pcm, &mut self.read.checksum) {
+            warn!("Failed to verify track {}: {e}", self.part.number);
+            return None;
+        }
+        Some(Verified {
+            number: self.part.number,
+            path: self.location.clone(),
+            read: std::mem::take(&mut self.read),
+        })
+    }
+}
+
+impl Drop for ReadTrack {
+    /// A track dropped from the queue, e.g. after a failure, takes down its pipeline
+    fn drop(&mut self) {
+        if let Some(pipeline) = self.pipeline.take() {
+            pipeline.set_state(State::Null).ok();
+        }
+    }
+}
+
+/// A file the encoder wrote to the spool folder, to be moved to its location in the encode path
+struct Spooled {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+/// A track the encoders are done with
+struct EncodedTrack {
+    index: usize,
+    encoded: Encoded,
+    verified: Option<Verified>,
+}
+
+impl<'a> Rip<'a> {
+    fn new(
+        disc: &'a Disc,
+        config: &'a Config,
+        status: &'a Sender<Status>,
+        cancel: &'a CancelToken,
+        stop: &'a CancelToken,
+    ) -> Rip<'a> {
+        Rip {
+            disc,
+            config,
+            status,
+            cancel,
+            stop,
+            error: Mutex::new(None),
+            target_seen: AtomicBool::new(target_available(Path::new(&config.encode_path))),
+            waiting: Mutex::new(()),
+            automation: Automation::configured(config),
+            spool: async_channel::unbounded(),
+            journal: Mutex::default(),
+            started: SystemTime::now(),
+            source: make_source,
+        }
+    }
+
+    /// Write the inventory and the report again with a track that was encoded, so a crash or a
+    /// power loss in the middle of the disc leaves a record of the tracks that were done
+    fn record(&self, encoded: &Encoded, verified: Option<&Verified>) {
+        if !self.config.json_report && self.config.inventory_file.is_none() {
+            return;
+        }
+        let Ok(mut journal) = self.journal.lock() else {
+            return;
+        };
+        journal.encoded.push(encoded.clone());
+        journal.verified.extend(verified.cloned());
+        if let Err(e) = record_tracks(self.disc, &journal.encoded, self.config) {
+            warn!("Failed to add the tracks done so far to the inventory: {e}");
+        }
+        if !self.config.json_report {
+            return;
+        }
+        match write_report(
+            self.disc,
+            &[],
+            self.config,
+            &journal,
+            &[],
+            self.started,
+            false,
+        ) {
+            Ok(path) => journal.report = Some(path),
+            Err(e) => warn!("Failed to write the report of the tracks done so far: {e}"),
+        }
+    }
+
+    /// Remember the error and stop the others
+    /// Errors of tracks that were stopped because another one failed are left out
+    fn fail(&self, e: anyhow::Error) {
+        if !self.stop.is_cancelled() || self.cancel.is_cancelled() {
+            if let Ok(mut error) = self.error.lock() {
+                error.get_or_insert(e);
+            }
+        }
+        self.stop.cancel();
+    }
+
+    /// Read the tracks from the disc one by one and queue them for the encoders
+    fn read_tracks(&self, parts: &[(Track, Option<Segment>)], jobs: &Sender<ReadTrack>) {
+        for (index, (part, segment)) in parts.iter().enumerate() {
+            if self.stop.is_cancelled() {
+                break;
+            }
+            let mut part = part.clone();
+            if let Some(automation) = &self.automation {
+                automation.on_track(self.disc, &mut part);
+                if !part.rip {
+                    debug!("the script skipped track {}", part.number);
+                    continue;
+                }
+            }
+            match self.read_track(index, &part, segment.as_ref()) {
+                Ok(job) => {
+                    if let Err(e) = jobs.send_blocking(job) {
+                        fs::remove_file(&e.0.pcm).ok();
+                    }
+                }
+                Err(e) => self.fail(e),
+            }
+        }
+    }
+
+    fn read_track(
+        &self,
+        index: usize,
+        part: &Track,
+        segment: Option<&Segment>,
+    ) -> Result<ReadTrack> {
+        let location = output_location(part, self.disc, self.config);
+        let pcm = pcm_location(index);
+        let pipeline = create_read_pipeline(part, self.config, &pcm, self.source)?;
+        if let Some(segment) = segment {
+            seek_segment(&pipeline, segment)?;
+        }
+        let read = watch_source(&pipeline, part, self.disc, self.status)?;
+        let interval = progress_interval(self.config);
+        if let Err(e) = run_pipeline(
+            pipeline,
+            part,
+            TrackState::Reading,
+            self.status,
+            self.stop,
+            interval,
+        ) {
+            fs::remove_file(&pcm).ok();
+            if !self.stop.is_cancelled() {
+                send_progress(self.status, part, 0.0, TrackState::Failed);
+            }
+            return Err(e);
+        }
+        let read = std::mem::take(&mut *read.write().map_err(|e| anyhow!("{e}"))?);
+        // known before the encoder is made, so it goes in the tags of the track
+        let part = &self.with_condition(part, &read);
+        let gain = self.analyze(part, &pcm);
+        let output = self.encoder_output(index, &location);
+        let pipeline = self.prepare_encoder(part, &output, &pcm, gain.as_ref());
+        Ok(ReadTrack {
+            index,
+            part: part.clone(),
+            location,
+            pcm,
+            read,
+            // segments don't match the tracks in the AccurateRip database
+            verify: self.config.secure_rip && segment.is_none(),
+            gain,
+            pipeline,
+        })
+    }
+
+    /// The track with how it read added to its comment, when `Config::condition_comment` is on
+    /// and the source counts its read errors
+    fn with_condition(&self, part: &Track, read: &SecureRead) -> Track {
+        let mut part = part.clone();
+        if self.config.condition_comment && read.counted {
+            let note = track_condition(read.errors);
+            part.comment = Some(match part.comment.take() {
+                Some(comment) => format!("{comment}; {note}"),
+                None => note,
+            });
+        }
+        part
+    }
+
+    /// Measure the loudness of a track that was read, a failure only loses the tags
+    fn analyze(&self, part: &Track, pcm: &Path) -> Option<Gain> {
+        if self.config.replaygain == ReplayGain::Off {
+            return None;
+        }
+        match raw_source(pcm).and_then(replaygain::analyze) {
+            Ok(gain) => {
+                debug!("{}: {gain:?}", part.title);
+                Some(gain)
+            }
+            Err(e) => {
+                warn!("ReplayGain analysis of {} failed: {e}", part.title);
+                None
+            }
+        }
+    }
+
+    /// Make the encode pipeline of a track that was read and bring it to READY, `None` when
+    /// that fails, the encoder makes it again and reports the error
+    fn prepare_encoder(
+        &self,
+        part: &Track,
+        location: &Path,
+        pcm: &Path,
+        gain: Option<&Gain>,
+    ) -> Option<Pipeline> {
+        let started = Instant::now();
+        let partial = partial_location(location);
+        let prepared = raw_source(pcm)
+            .and_then(|source| {
+                create_pipeline(source, part, self.disc, self.config, &partial, gain)
+            })
+            .and_then(|pipeline| {
+                pipeline.set_state(State::Ready)?;
+                Ok(pipeline)
+            });
+        match prepared {
+            Ok(pipeline) => {
+                debug!("encoder of {} ready in {:?}", part.title, started.elapsed());
+                Some(pipeline)
+            }
+            Err(e) => {
+                debug!("encoder of {} not prepared: {e}", part.title);
+                None
+            }
+        }
+    }
+
+    /// Encode the queued tracks until the reader is done, the tracks queued after a failure
+    /// are dropped
+    fn encode_tracks(&self, queue: &Receiver<ReadTrack>) -> Vec<EncodedTrack> {
+        let mut done = Vec::new();
+        while let Ok(mut job) = queue.recv_blocking() {
+            if !self.stop.is_cancelled() {
+                let result = self.encode_with_retries(&mut job);
+                if let (Ok(encoded), Some(automation)) = (&result, &self.automation) {
+                    automation.on_encoded(self.disc, encoded);
+                }
+                match result {
+                    Ok(encoded) => {
+                        let verified = job.verified();
+                        self.record(&encoded, verified.as_ref());
+                        done.push(EncodedTrack {
+                            index: job.index,
+                            encoded,
+                            verified,
+                        });
+                    }
+                    Err(e) => {
+                        if !self.stop.is_cancelled() {
+                            send_progress(self.status, &job.part, 0.0, TrackState::Failed);
+                        }
+                        self.fail(e);
+                    }
+                }
+            }
+            fs::remove_file(&job.pcm).ok();
+        }
+        done
+    }
+
+    /// Encode a track that was read, from scratch with its raw audio when the encoder fails:
+    /// after the encode path came back, `Config::encode_retries` times, and then once with
+    /// `Config::fallback_encoder`, which changes the extension of the file
+    fn encode_with_retries(&self, job: &mut ReadTrack) -> Result<Encoded> {
+        let mut prepared = job.pipeline.take();
+        let mut retries = self.config.encode_retries;
+        let mut fallback = self
+            .config
+            .fallback_encoder
+            .filter(|encoder| *encoder != job.part.encoder(self.config));
+        loop {
+            let e = match self.encode_track(job, prepared.take()) {
+                Ok(encoded) => return Ok(encoded),
+                Err(e) => e,
+            };
+            if self.wait_for_target() {
+                continue;
+            }
+            if self.stop.is_cancelled() {
+                return Err(e);
+            }
+            if retries > 0 {
+                retries -= 1;
+                warn!("Encoding {} failed, trying again: {e}", job.part.title);
+            } else if let Some(encoder) = fallback.take() {
+                warn!(
+                    "Encoding {} failed, encoding it to {encoder:?}: {e}",
+                    job.part.title
+                );
+                job.part.encoder = Some(encoder);
+                job.location = output_location(&job.part, self.disc, self.config);
+            } else {
+                return Err(e);
+            }
+        }
+    }
+
+    fn encode_track(&self, job: &ReadTrack, prepared: Option<Pipeline>) -> Result<Encoded> {
+        let (config, part, location) = (self.config, &job.part, &job.location);
+        let output = self.encoder_output(job.index, location);
+        let partial = partial_location(&output);
+        let started = Instant::now();
+        let pipeline = match prepared {
+            Some(pipeline) => pipeline,
+            None => create_pipeline(
+                raw_source(&job.pcm)?,
+                part,
+                self.disc,
+                config,
+                &partial,
+                job.gain.as_ref(),
+            )?,
+        };
+        debug!("encoding {} after {:?}", part.title, started.elapsed());
+        if let Err(e) = extract_track(
+            pipeline,
+            part,
+            self.status,
+            self.stop,
+            progress_interval(config),
+        ) {
+            if self.cancel.is_cancelled() && config.keep_aborted_tracks {
+                move_file(&partial, &aborted_location(location)).ok();
+            } else {
+                fs::remove_file(&partial).ok();
+            }
+            return Err(e);
+        }
+        finalize_output(&partial, &output)?;
+        let size = fs::metadata(&output)?.len();
+        if output == *location {
+            self.target_seen.store(true, Ordering::SeqCst);
+            set_permissions(
+                location,
+                config.file_mode.as_deref(),
+                config.group.as_deref(),
+            )?;
+        } else {
+            let spooled = Spooled {
+                from: output,
+                to: location.clone(),
+            };
+            self.spool.0.send_blocking(spooled)?;
+        }
+        send_progress(self.status, part, 100.0, TrackState::Done);
+        let encoded = Encoded {
+            path: location.clone(),
+            number: part.number,
+            title: part.title.clone(),
+            size,
+            bitrate: actual_bitrate(size, part.duration),
+            expected_bitrate: backend(&part.encoder(config)).estimated_bitrate(&config.quality),
+            read_errors: job.read.errors,
+            accuraterip: None,
+        };
+        send_status(self.status, Status::Encoded(encoded.clone()));
+        Ok(encoded)
+    }
+
+    /// The folder on the fast local disk the encoders write to with `Config::spool_dir`, one per
+    /// process so its permissions can be set like those of an album folder
+    fn spool_folder(&self) -> Option<PathBuf> {
+        let spool = self.config.spool_dir.as_ref()?;
+        Some(Path::new(spool).join(format!("ripperx4-{}", std::process::id())))
+    }
+
+    /// Where the encoder writes the file of the `index`th track of the rip, in the spool folder
+    /// when there is one, the writer moves it to `location` afterwards
+    fn encoder_output(&self, index: usize, location: &Path) -> PathBuf {
+        let Some(folder) = self.spool_folder() else {
+            return location.to_owned();
+        };
+        let mut output = folder.join(index.to_string());
+        if let Some(extension) = location.extension() {
+            output.set_extension(extension);
+        }
+        output
+    }
+
+    /// Move the spooled files to the encode path as the encoders finish them, until the
+    /// encoders are done, so the drive never waits for a slow network share
+    /// Files finished before a failure are still moved
+    fn write_spooled(&self) {
+        while let Ok(spooled) = self.spool.1.recv_blocking() {
+            loop {
+                match self.write_out(&spooled) {
+                    Err(_) if self.wait_for_target() => continue,
+                    Err(e) => {
+                        fs::remove_file(&spooled.from).ok();
+                        self.fail(e);
+                    }
+                    Ok(()) => (),
+                }
+                break;
+            }
+        }
+    }
+
+    fn write_out(&self, spooled: &Spooled) -> Result<()> {
+        let config = self.config;
+        if let Some(folder) = spooled.to.parent() {
+            fs::create_dir_all(folder)?;
+            set_permissions(folder, config.dir_mode.as_deref(), config.group.as_deref())?;
+        }
+        move_file(&spooled.from, &spooled.to)?;
+        debug!(
+            "moved {} to {}",
+            spooled.from.display(),
+            spooled.to.display()
+        );
+        self.target_seen.store(true, Ordering::SeqCst);
+        set_permissions(
+            &spooled.to,
+            config.file_mode.as_deref(),
+            config.group.as_deref(),
+        )
+    }
+
+    /// After a failed track, wait for the encode path when it went away, e.g. a USB drive was
+    /// unplugged or a share unmounted, the user is told the rip is paused
+    /// Returns whether the track should be encoded again
+    fn wait_for_target(&self) -> bool {
+        let target = Path::new(&self.config.encode_path);
+        if !self.target_seen.load(Ordering::SeqCst)
+            || self.stop.is_cancelled()
+            || target_available(target)
+        {
+            return false;
+        }
+        // the other encoders fail as well, they wait here until the target is back
+        let Ok(_waiting) = self.waiting.lock() else {
+            return false;
+        };
+        if !target_available(target) {
+            warn!("{} is not available, pausing", target.display());
+            send_status(
+                self.status,
+                Status::Paused(format!("{} is not available", target.display())),
+            );
+            let (wake, woken) = mpsc::channel();
+            let _cancel_guard = self.stop.on_cancel(move || {
+                wake.send(()).ok();
+            });
+            while !target_available(target) {
+                if woken.recv_timeout(TARGET_POLL_INTERVAL).is_ok() {
+                    return false;
+                }
+            }
+            debug!("{} is back, resuming", target.display());
+            send_status(self.status, Status::Resumed);
+        }
+        !self.stop.is_cancelled()
+    }
+}
+
+/// How often the encode path is checked while the rip is paused
+const TARGET_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Whether a file can be created in the encode path
+fn target_available(target: &Path) -> bool {
+    let probe = target.join(format!(".ripperx4-{}", std::process::id()));
+    let created = File::create(&probe).is_ok();
+    fs::remove_file(&probe).ok();
+    created
+}
+
+/// What the rip saw of a track while reading it
+#[derive(Debug, Clone, Default)]
+struct SecureRead {
+    /// factory of the source element
+    source: String,
+    checksum: Checksum,
+    errors: ReadErrors,
+    /// the source reports its read errors, only cdparanoiasrc does
+    counted: bool,
+    /// failed reads per sector
+    attempts: HashMap<i32, u32>,
+}
+
+/// More sectors re-read than this make the corrections of a track heavy
+const HEAVY_CORRECTIONS: u32 = 100;
+
+const CLEAN_NOTE: &str = "clean rip, 0 corrections";
+
+/// How a track read, from its read errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    Clean,
+    Light,
+    Heavy,
+    Uncorrected,
+}
+
+impl Condition {
+    fn of(errors: ReadErrors) -> Condition {
+        if errors.skipped > 0 {
+            Condition::Uncorrected
+        } else if errors.rereads > HEAVY_CORRECTIONS {
+            Condition::Heavy
+        } else if errors.rereads > 0 {
+            Condition::Light
+        } else {
+            Condition::Clean
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Condition::Clean => "clean rip",
+            Condition::Light => "light corrections",
+            Condition::Heavy => "heavy corrections",
+            Condition::Uncorrected => "uncorrected errors",
+        }
+    }
+}
+
+/// The note on a track, e.g. "heavy corrections, 120 sectors re-read, 2 not corrected"
+fn track_condition(errors: ReadErrors) -> String {
+    let condition = Condition::of(errors);
+    if condition == Condition::Clean {
+        return CLEAN_NOTE.to_owned();
+    }
+    let mut note = format!("{}, {} sectors re-read", condition.label(), errors.rereads);
+    if errors.skipped > 0 {
+        note.push_str(&format!(", {} not corrected", errors.skipped));
+    }
+    note
+}
+
+/// The note on the disc from its tracks and their read errors, the worst first, e.g.
+/// "heavy corrections tracks 3–4, light corrections track 7"
+fn disc_condition(tracks: &[(u32, ReadErrors)]) -> String {
+    let notes: Vec<String> = [Condition::Uncorrected, Condition::Heavy, Condition::Light]
+        .into_iter()
+        .filter_map(|condition| {
+            let numbers: Vec<u32> = tracks
+                .iter()
+                .filter(|(_, errors)| Condition::of(*errors) == condition)
+                .map(|(number, _)| *number)
+                .collect();
+            (!numbers.is_empty())
+                .then(|| format!("{} {}", condition.label(), track_ranges(&numbers)))
+        })
+        .collect();
+    if notes.is_empty() {
+        CLEAN_NOTE.to_owned()
+    } else {
+        notes.join(", ")
+    }
+}
+
+/// The numbers with consecutive ones as a range, e.g. "track 7" or "tracks 3–4, 7"
+fn track_ranges(numbers: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &number in numbers {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == number => *last = number,
+            _ => ranges.push((number, number)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}–{last}")
+            }
+        })
+        .collect();
+    let noun = if numbers.len() == 1 {
+        "track"
+    } else {
+        "tracks"
+    };
+    format!("{noun} {}", ranges.join(", "))
+}
+
+impl SecureRead {
+    /// Count a read error cdparanoiasrc reported, `uncorrected` when it gave up on the sector
+    /// Returns the how manieth failed read of the sector it was
+    fn record_error(&mut self, sector: Option<i32>, uncorrected: bool) -> Option<u32> {
+        if uncorrected {
+            self.errors.skipped += 1;
+            return None;
+        }
+        self.errors.retries += 1;
+        let Some(sector) = sector else {
+            self.errors.rereads += 1;
+            return None;
+        };
+        let attempt = self.attempts.entry(sector).or_default();
+        *attempt += 1;
+        if *attempt == 1 {
+            self.errors.rereads += 1;
+        }
+        Some(*attempt)
+    }
+}
+
+/// A securely ripped track, as listed in the rip log
+#[derive(Debug, Clone)]
+struct Verified {
+    number: u32,
+    path: PathBuf,
+    read: SecureRead,
+}
+
+/// Follow the errors of the CD source in the pipeline, the checksums of the audio are computed
+/// later by `ReadTrack::verified`
+/// Sectors read again are reported as `Status::Recovering`, with the attempts on each sector
+fn watch_source(
+    pipeline: &Pipeline,
+    track: &Track,
+    disc: &Disc,
+    status: &Sender<Status>,
+) -> Result<Arc<RwLock<SecureRead>>> {
+    let source = pipeline
+        .by_name("cd_src")
+        .ok_or(anyhow!("no CD source in the pipeline"))?;
+    let first = disc
+        .tracks
+        .first()
+        .is_some_and(|t| t.number == track.number);
+    let last = disc.tracks.last().is_some_and(|t| t.number == track.number);
+    let read = Arc::new(RwLock::new(SecureRead {
+        source: source
+            .factory()
+            .map(|f| f.name().to_string())
+            .unwrap_or_default(),
+        checksum: Checksum::new(track.sectors, first, last),
+        counted: SignalId::lookup("uncorrected-error", source.type_()).is_some(),
+        ..Default::default()
+    }));
+    // only cdparanoiasrc tells which sectors it could not read, it doesn't pass on the jitter
+    // and fixup statistics of libcdio-paranoia's callback
+    for (signal, skipped) in [("transport-error", false), ("uncorrected-error", true)] {
+        if SignalId::lookup(signal, source.type_()).is_some() {
+            let errors = read.clone();
+            let status = status.clone();
+            let (number, title) = (track.number, track.title.clone());
+            source.connect(signal, false, move |args| {
+                let sector = args.get(1).and_then(|sector| sector.get::<i32>().ok());
+                let attempt = errors
+                    .write()
+                    .ok()
+                    .and_then(|mut read| read.record_error(sector, skipped));
+                if let (Some(sector), Some(attempt)) = (sector, attempt) {
+                    let recovering = Status::Recovering {
+                        number,
+                        title: title.clone(),
+                        sector,
+                        attempt,
+                    };
+                    send_status(&status, recovering);
+                }
+                None
+            });
+        }
+    }
+    Ok(read)
+}
+
+/// Bytes of raw audio checksummed at a time, a second of audio
+const VERIFY_CHUNK: usize = 4 * SAMPLE_RATE as usize;
+
+/// Add the raw audio in the file to the checksum, a chunk at a time, leaving the CPU to the
+/// reader and the encoders in between
+/// Fails when the file ends before the track, e.g. it was truncated
+fn checksum_pcm(pcm: &Path, checksum: &mut Checksum) -> Result<()> {
+    let mut file = File::open(pcm)?;
+    let mut chunk = vec![0; VERIFY_CHUNK];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            if !checksum.complete() {
+                return Err(anyhow!("{} is shorter than the track", pcm.display()));
+            }
+            return Ok(());
+        }
+        checksum.update(&chunk[..read]);
+        thread::yield_now();
+    }
+}
+
+/// The AccurateRip checksums of the disc, none when it's not in the database or can't be reached
+fn fetch_pressings(disc: &Disc) -> Vec<Vec<Entry>> {
+    match &disc.accuraterip_id {
+        Some(id) => accuraterip::fetch(id).unwrap_or_else(|e| {
+            error!("AccurateRip lookup failed: {e}");
+            Vec::new()
+        }),
+        None => Vec::new(),
+    }
+}
+
+/// Write `rip.log` with the AccurateRip results in the album folder
+fn write_rip_log(
+    disc: &Disc,
+    config: &Config,
+    album_folder: &Path,
+    verified: &[Verified],
+    pressings: &[Vec<Entry>],
+) -> Result<PathBuf> {
+    let date = glib::DateTime::now_local()
+        .and_then(|now| now.format("%Y-%m-%d %H:%M"))
+        .map(|date| format!(" from {date}"))
+        .unwrap_or_default();
+    let drive = drive(config);
+    let log = album_folder.join("rip.log");
+    write_safely(
+        &log,
+        &format!(
+            "ripperx4 {} extraction log{date}\n\n{}",
+            env!("CARGO_PKG_VERSION"),
+            rip_log(disc, &drive, verified, pressings)
+        ),
+    )?;
+    Ok(log)
+}
+
+/// Write the file next to it and rename it when it's on the disk, so a crash leaves the old
+/// or the new contents, never half of them
+pub fn write_safely(path: &Path, contents: &str) -> Result<()> {
+    let partial = partial_location(path);
+    let mut file = File::create(&partial)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+    // the rename is only on the disk once the folder is
+    #[cfg(unix)]
+    if let Some(folder) = path
+        .parent()
+        .filter(|folder| !folder.as_os_str().is_empty())
+    {
+        File::open(folder)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Write the JSON report of the rip to `Config::report_dir`, or as `rip.json` in the album folder
+/// A rip that isn't `finished` gets the `outcome` "incomplete", the report of the finished rip
+/// replaces it
+fn write_report(
+    disc: &Disc,
+    order: &[usize],
+    config: &Config,
+    ripped: &Ripped,
+    pressings: &[Vec<Entry>],
+    started: SystemTime,
+    finished: bool,
+) -> Result<PathBuf> {
+    let path = match (&ripped.report, &config.report_dir) {
+        // written during the rip
+        (Some(path), _) => path.clone(),
+        (None, Some(dir)) => {
+            let date = glib::DateTime::now_local()
+                .and_then(|now| now.format("%Y%m%d-%H%M%S"))
+                .map(|date| date.to_string())
+                .unwrap_or_default();
+            let name = sanitize(&format!("{} - {} {date}", disc.artist, disc.title));
+            Path::new(dir).join(format!("{name}.json"))
+        }
+        (None, None) => {
+            // a failed rip may not have produced a file yet
+            let first = ripped.encoded.first().map(|e| e.path.clone()).or_else(|| {
+                order
+                    .iter()
+                    .filter_map(|i| disc.tracks.get(*i))
+                    .find(|t| t.rip)
+                    .map(|t| output_location(t, disc, config))
+            });
+            first
+                .as_deref()
+                .and_then(Path::parent)
+                .ok_or(anyhow!("no album folder"))?
+                .join("rip.json")
+        }
+    };
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let started_at = started
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let seconds = started.elapsed().map(|d| d.as_secs()).unwrap_or_default();
+    let mut report = rip_report(disc, config, ripped, pressings, started_at, seconds);
+    if !finished {
+        report["success"] = json!(false);
+        report["outcome"] = json!("incomplete");
+    }
+    write_safely(&path, &serde_json::to_string_pretty(&report)?)?;
+    set_permissions(&path, config.file_mode.as_deref(), config.group.as_deref())?;
+    Ok(path)
+}
+
+/// The settings, the tracks with their checksums and AccurateRip results and the error of a rip
+fn rip_report(
+    disc: &Disc,
+    config: &Config,
+    ripped: &Ripped,
+    pressings: &[Vec<Entry>],
+    started_at: u64,
+    seconds: u64,
+) -> Value {
+    let tracks: Vec<Value> = ripped
+        .encoded
+        .iter()
+        .map(|encoded| {
+            let track = disc.tracks.iter().find(|t| t.number == encoded.number);
+            let mut entry = json!({
+                "number": encoded.number,
+                "title": encoded.title,
+                "path": encoded.path,
+                "encoder": track.map(|t| t.encoder(config)),
+                "duration": track.map(|t| t.duration),
+                "size": encoded.size,
+                "bitrate": encoded.bitrate,
+                "rereads": encoded.read_errors.rereads,
+                "retries": encoded.read_errors.retries,
+                "skipped": encoded.read_errors.skipped,
+            });
+            if let Some(verified) = ripped.verified.iter().find(|v| v.path == encoded.path) {
+                let checksum = &verified.read.checksum;
+                let accuraterip = match accuraterip::verify(pressings, verified.number, checksum) {
+                    Verdict::Accurate(confidence) => {
+                        json!({"result": "accurate", "confidence": confidence})
+                    }
+                    Verdict::Inaccurate => json!({"result": "inaccurate"}),
+                    Verdict::Unknown => json!({"result": "unknown"}),
+                };
+                entry["crc_v1"] = json!(format!("{:08X}", checksum.v1()));
+                entry["crc_v2"] = json!(format!("{:08X}", checksum.v2()));
+                entry["accuraterip"] = accuraterip;
+            }
+            entry
+        })
+        .collect();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "started_at": started_at,
+        "seconds": seconds,
+        "success": ripped.error.is_none(),
+        "outcome": Outcome::of(&ripped.encoded, ripped.error.is_some(), disc.lookup_error.is_some()).name(),
+        "lookup_error": disc.lookup_error,
+        "error": ripped.error.as_ref().map(ToString::to_string),
+        "disc": {
+            "artist": disc.artist,
+            "album": disc.title,
+            "year": disc.year,
+            "date": disc.date,
+            "genre": disc.genre,
+            "release_id": disc.release_id,
+            "discid": disc.discid,
+            "accuraterip_id": disc.accuraterip_id,
+            "in_accuraterip": !pressings.is_empty(),
+        },
+        "settings": {
+            "encoder": config.encoder,
+            "quality": config.quality,
+            "disc_image": config.disc_image,
+            "image_format": config.image_format,
+            "secure_rip": config.secure_rip,
+            "cd_backend": config.cd_backend,
+            "error_correction": config.error_correction,
+            "replaygain": config.replaygain,
+            "drive": drive(config),
+            "encoder_workers": encoder_workers(config),
+            "name_template": template(config, disc),
+        },
+        "tracks": tracks,
+    })
+}
+
+/// The drive, the read errors and AccurateRip results of the tracks, after EAC's log
+fn rip_log(disc: &Disc, drive: &str, verified: &[Verified], pressings: &[Vec<Entry>]) -> String {
+    let mut log = format!("{} / {}\n\n", disc.artist, disc.title);
+    log.push_str(&format!("Used drive  : {drive}\n"));
+    if let Some(read) = verified.first().map(|v| &v.read) {
+        log.push_str(&format!("CD source   : {}\n", read.source));
+    }
+    let database = match (&disc.accuraterip_id, pressings.is_empty()) {
+        (Some(id), false) => id.rsplit('/').next().unwrap_or(id).to_owned(),
+        (Some(_), true) => "the disc is not in the database".to_owned(),
+        (None, _) => "unknown disc".to_owned(),
+    };
+    log.push_str(&format!("AccurateRip : {database}\n"));
+    log.push_str("Read offset : not corrected\n");
+    if !verified.is_empty() && verified.iter().all(|track| track.read.counted) {
+        let tracks: Vec<(u32, ReadErrors)> = verified
+            .iter()
+            .map(|track| (track.number, track.read.errors))
+            .collect();
+        log.push_str(&format!("Condition   : {}\n", disc_condition(&tracks)));
+    }
+    let mut accurate = 0;
+    let mut errors = ReadErrors::default();
+    for track in verified {
+        let checksum = &track.read.checksum;
+        let verdict = match accuraterip::verify(pressings, track.number, checksum) {
+            Verdict::Accurate(confidence) => {
+                accurate += 1;
+                format!("Accurately ripped (confidence {confidence})")
+            }
+            Verdict::Inaccurate => "Not matching the AccurateRip database".to_owned(),
+            Verdict::Unknown => "Not verified".to_owned(),
+        };
+        let read = track.read.errors;
+        errors.rereads += read.rereads;
+        errors.retries += read.retries;
+        errors.skipped += read.skipped;
+        // sectors that took more than one read again
+        let retries = if read.retries > read.rereads {
+            format!(" ({} reads)", read.retries)
+        } else {
+            String::new()
+        };
+        log.push_str(&format!(
+            "\nTrack {:2}\n\n     Filename    {}\n     Re-read     {} sectors{retries}\n     Uncorrected {} sectors\n     AccurateRip v1 {:08X}, v2 {:08X}\n     {verdict}\n",
+            track.number,
+            track.path.display(),
+            read.rereads,
+            read.skipped,
+            checksum.v1(),
+            checksum.v2(),
+        ));
+    }
+    if !pressings.is_empty() {
+        log.push_str(&format!(
+            "\n{accurate} of {} tracks accurately ripped\n",
+            verified.len()
+        ));
+    }
+    if errors == ReadErrors::default() {
+        log.push_str("\nNo errors occurred\n");
+    } else {
+        log.push_str(&format!(
+            "\n{} sectors were re-read, {} could not be corrected\n",
+            errors.rereads, errors.skipped
+        ));
+    }
+    log
+}
+
+/// Rip the selected tracks to one continuous file, with a CUE sheet next to it
+/// The image runs from the first to the last selected track of the disc, tracks in between are
+/// included even if they were deselected so the CUE sheet matches the disc
+fn extract_image(
+    disc: &Disc,
+    order: &[usize],
+    config: &Config,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+) -> Result<Vec<Encoded>> {
+    let selected = || {
+        order
+            .iter()
+            .copied()
+            .filter(|i| disc.tracks.get(*i).is_some_and(|t| t.rip))
+    };
+    let (Some(first), Some(last)) = (selected().min(), selected().max()) else {
+        return Ok(Vec::new());
+    };
+    let tracks: Vec<&Track> = disc.tracks[first..=last].iter().collect();
+    let extension = config.image_format.extension();
+    let name = sanitize(&format!("{} - {}", disc.artist, disc.title));
+    let folder = output_location(tracks[0], disc, config)
+        .parent()
+        .map(Path::to_path_buf)
+        .ok_or(anyhow!("no album folder"))?;
+    let location = folder.join(format!("{name}.{extension}"));
+    let partial = partial_location(&location);
+    // the progress of the image is sent as a track without number
+    let image = Track {
+        title: disc.title.clone(),
+        duration: sectors_to_seconds(tracks.iter().map(|t| t.sectors).sum()),
+        ..Default::default()
+    };
+
+    let pipeline = create_image_pipeline(&tracks, disc, config, &partial)?;
+    let end = (last + 1 < disc.tracks.len()).then_some(last + 1);
+    seek_tracks(&pipeline, first, end)?;
+    let read = watch_source(&pipeline, tracks[0], disc, status)?;
+    if let Err(e) = extract_track(pipeline, &image, status, cancel, progress_interval(config)) {
+        fs::remove_file(&partial).ok();
+        if !cancel.is_cancelled() {
+            send_progress(status, &image, 0.0, TrackState::Failed);
+        }
+        return Err(e);
+    }
+    finalize_output(&partial, &location)?;
+    send_progress(status, &image, 100.0, TrackState::Done);
+    let cue = location.with_extension("cue");
+    fs::write(
+        &cue,
+        cue_sheet(disc, &tracks, &format!("{name}.{extension}")),
+    )?;
+    for file in [&location, &cue] {
+        set_permissions(file, config.file_mode.as_deref(), config.group.as_deref())?;
+    }
+    let size = fs::metadata(&location)?.len();
+    let encoded = Encoded {
+        path: location,
+        number: tracks[0].number,
+        title: disc.title.clone(),
+        size,
+        bitrate: actual_bitrate(size, image.duration),
+        expected_bitrate: image_bitrate(config),
+        read_errors: read.read().map(|read| read.errors).unwrap_or_default(),
+        accuraterip: None,
+    };
+    send_status(status, Status::Encoded(encoded.clone()));
+    Ok(vec![encoded])
+}
+
+/// Limit the continuous stream of the CD source to the tracks from `first` up to `end`, both
+/// index the tracks of the disc, without an end the stream runs to the end of the disc
+fn seek_tracks(pipeline: &Pipeline, first: usize, end: Option<usize>) -> Result<()> {
+    // the CD sources register a format counting tracks from 0
+    let track_format = Format::by_nick("track");
+    pipeline.set_state(State::Paused)?;
+    pipeline.state(ClockTime::from_seconds(10)).0?;
+    let stop_type = if end.is_some() {
+        SeekType::Set
+    } else {
+        SeekType::None
+    };
+    pipeline.seek(
+        1.0,
+        SeekFlags::FLUSH | SeekFlags::ACCURATE,
+        SeekType::Set,
+        GenericFormattedValue::new(track_format, i64::try_from(first)?),
+        stop_type,
+        GenericFormattedValue::new(track_format, i64::try_from(end.unwrap_or_default())?),
+    )?;
+    Ok(())
+}
+
+/// Create the pipeline ripping the tracks of the disc to one file
+fn create_image_pipeline(
+    tracks: &[&Track],
+    disc: &Disc,
+    config: &Config,
+    location: &Path,
+) -> Result<Pipeline> {
+    let extractor = make_source(tracks[0], config)?;
+    // one stream for the whole disc instead of one per track
+    extractor.set_property_from_str("mode", "continuous");
+
+    let folder = location
+        .parent()
+        .ok_or(anyhow!("failed to create folder".to_owned()))?;
+    std::fs::create_dir_all(folder)?;
+    set_permissions(folder, config.dir_mode.as_deref(), config.group.as_deref())?;
+    let sink = ElementFactory::make("filesink").build()?;
+    sink.set_property("location", location.to_str());
+
+    let mut elements = vec![extractor];
+    match image_encoder(config.image_format) {
+        Some(encoder) => {
+            let encoding = backend(&encoder).elements(&config.quality)?;
+            if let Some(tagger) = encoding.iter().find(|e| e.is::<TagSetter>()) {
+                let mut tags = image_tags(disc)?;
+                if config.image_format == ImageFormat::Opus {
+                    add_chapters(&mut tags, tracks)?;
+                }
+                merge_tags(tagger, &tags)?;
+            }
+            elements.extend(encoding);
+        }
+        None => elements.push(ElementFactory::make("wavenc").build()?),
+    }
+    elements.push(sink);
+
+    let pipeline = Pipeline::new();
+    pipeline.add_many(&elements)?;
+    Element::link_many(&elements)?;
+
+    Ok(pipeline)
+}
+
+/// The GStreamer elements the disc image format needs and aren't installed
+fn missing_image_elements(format: ImageFormat) -> Vec<(&'static str, &'static str)> {
+    let required = match image_encoder(format) {
+        Some(encoder) => backend(&encoder).required_elements(),
+        None => vec![("wavenc", "gst-plugins-good")],
+    };
+    required
+        .into_iter()
+        .filter(|(element, _)| ElementFactory::find(element).is_none())
+        .collect()
+}
+
+/// The encoder of the disc image format, none for WAV
+fn image_encoder(format: ImageFormat) -> Option<Encoder> {
+    match format {
+        ImageFormat::Flac => Some(Encoder::FLAC),
+        ImageFormat::Wav => None,
+        ImageFormat::Opus => Some(Encoder::OPUS),
+        ImageFormat::M4b => Some(Encoder::AAC),
+    }
+}
+
+/// Approximate average bitrate in kbit/s of a disc image
+fn image_bitrate(config: &Config) -> u64 {
+    match image_encoder(config.image_format) {
+        Some(encoder) => backend(&encoder).estimated_bitrate(&config.quality),
+        // 44.1 kHz, 16 bit stereo
+        None => 1411,
+    }
+}
+
+/// Save the front cover as `cover.jpg` (or `.png`) in the album folder
+/// Uses the cover found by the lookup, otherwise tries again as the metadata may have been edited
+fn save_cover(disc: &Disc, config: &Config, album_folder: &Path) -> Result<()> {
+    let fetched;
+    let image = match &disc.cover {
+        Some(cover) => cover,
+        None => {
+            fetched = fetch_cover(disc, config)?;
+            &fetched
+        }
+    };
+    let extension = image_extension(image).ok_or(anyhow!("cover is not a JPEG or PNG"))?;
+    let path = album_folder.join(format!("cover.{extension}"));
+    fs::write(&path, image)?;
+    set_permissions(&path, config.file_mode.as_deref(), config.group.as_deref())
+}
+
+/// What is ripped to separate files: the whole track, or each of its segments
+fn track_parts(track: &Track) -> Vec<(Track, Option<&Segment>)> {
+    if track.segments.is_empty() {
+        return vec![(track.clone(), None)];
+    }
+    track
+        .segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let title = if segment.title.is_empty() {
+                format!("{} ({})", track.title, i + 1)
+            } else {
+                segment.title.clone()
+            };
+            let end = segment.end.unwrap_or(track.duration);
+            let part = Track {
+                title,
+                duration: end.saturating_sub(segment.start),
+                segments: Vec::new(),
+                latin_title: None,
+                ..track.clone()
+            };
+            (part, Some(segment))
+        })
+        .collect()
+}
+
+/// Limit the pipeline to the segment, the source seeks to its start and ends the stream at its end
+fn seek_segment(pipeline: &Pipeline, segment: &Segment) -> Result<()> {
+    pipeline.set_state(State::Paused)?;
+    pipeline.state(ClockTime::from_seconds(10)).0?;
+    let stop_type = if segment.end.is_some() {
+        SeekType::Set
+    } else {
+        SeekType::None
+    };
+    pipeline.seek(
+        1.0,
+        SeekFlags::FLUSH | SeekFlags::ACCURATE,
+        SeekType::Set,
+        Some(ClockTime::from_seconds(segment.start)),
+        stop_type,
+        segment.end.map(ClockTime::from_seconds),
+    )?;
+    Ok(())
+}
+
+/// Average bitrate in kbit/s of a file of `size` bytes lasting `duration` seconds
+fn actual_bitrate(size: u64, duration: u64) -> Option<u64> {
+    (duration > 0).then(|| size * 8 / duration / 1000)
+}
+
+/// Add the given files to the `checksums.sha256` manifest in the album folder
+/// The manifest uses the `sha256sum` format, so it can be checked with `sha256sum -c`
+fn write_checksums(album_folder: &Path, files: &[PathBuf]) -> Result<PathBuf> {
+    let manifest = album_folder.join("checksums.sha256");
+    let mut entries: BTreeMap<String, String> = fs::read_to_string(&manifest)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, name)| (name.to_owned(), hash.to_owned()))
+        .collect();
+    for file in files {
+        let name = file
+            .file_name()
+            .ok_or(anyhow!("invalid file name {}", file.display()))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(file)?, &mut hasher)?;
+        entries.insert(
+            name.to_string_lossy().into_owned(),
+            format!("{:x}", hasher.finalize()),
+        );
+    }
+    let contents: String = entries
+        .iter()
+        .map(|(name, hash)| format!("{hash}  {name}\n"))
+        .collect();
+    fs::write(&manifest, contents)?;
+    Ok(manifest)
+}
+
+/// Link the album folder into the "By Genre" and "By Year" trees under the encode path
+/// The links made before, e.g. under the genre the disc had then, are removed first
+#[cfg(unix)]
+fn update_library_views(disc: &Disc, config: &Config, album_folder: &Path) -> Result<()> {
+    let name = album_folder
+        .file_name()
+        .ok_or(anyhow!("invalid album folder"))?;
+    let target = fs::canonicalize(album_folder)?;
+    let views = [
+        ("By Genre", disc.genre.clone()),
+        ("By Year", disc.year.map(|y| y.to_string())),
+    ];
+    for (view, key) in views {
+        let view = Path::new(&config.encode_path).join(view);
+        remove_view_links(&view, &target)?;
+        let Some(key) = key.filter(|k| !k.is_empty()) else {
+            continue;
+        };
+        let folder = view.join(key.replace('/', "-"));
+        fs::create_dir_all(&folder)?;
+        let link = folder.join(name);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link)?;
+        }
+        std::os::unix::fs::symlink(&target, &link)?;
+        debug!("linked {} -> {}", link.display(), target.display());
+    }
+    Ok(())
+}
+
+/// Remove the links to `target` from the folders of the view, and the folders left empty
+#[cfg(unix)]
+fn remove_view_links(view: &Path, target: &Path) -> Result<()> {
+    let Ok(folders) = fs::read_dir(view) else {
+        return Ok(());
+    };
+    for folder in folders {
+        let folder = folder?.path();
+        if !folder.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&folder)? {
+            let link = entry?.path();
+            if fs::read_link(&link).is_ok_and(|linked| linked == target) {
+                fs::remove_file(&link)?;
+                debug!("removed {}", link.display());
+            }
+        }
+        // only succeeds when it's empty
+        fs::remove_dir(&folder).ok();
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn update_library_views(_disc: &Disc, _config: &Config, _album_folder: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Returns the final path of the encoded file for the `Track`
+/// `Config::name_rules` is resolved once per rip, `NameRules::Auto` looks up the file system
+fn output_location(track: &Track, disc: &Disc, config: &Config) -> PathBuf {
+    PathBuf::from(format!(
+        "{}/{}{}",
+        config.encode_path,
+        expand_template(
+            template(config, disc),
+            track,
+            disc,
+            config.latin_file_names,
+            name_rules(config)
+        ),
+        file_extension(&track.encoder(config), config)
+    ))
+}
+
+/// The formats the selected tracks are encoded to, without duplicates
+fn track_encoders(disc: &Disc, order: &[usize], config: &Config) -> Vec<Encoder> {
+    let mut encoders = Vec::new();
+    for track in order.iter().filter_map(|i| disc.tracks.get(*i)) {
+        let encoder = track.encoder(config);
+        if track.rip && !encoders.contains(&encoder) {
+            encoders.push(encoder);
+        }
+    }
+    encoders
+}
+
+/// The raw audio of the `index`th track of the rip is kept here between reading and encoding it,
+/// outside the encode path so it isn't lost when that goes away
+fn pcm_location(index: usize) -> PathBuf {
+    env::temp_dir().join(format!("ripperx4-{}-{index}.pcm", std::process::id()))
+}
+
+/// The encoder writes to this file, it is only renamed to the final location when encoding succeeded
+fn partial_location(location: &Path) -> PathBuf {
+    let mut partial = location.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// Where the finalized part of a track is kept when the rip was stopped, `title (aborted).ext`
+fn aborted_location(location: &Path) -> PathBuf {
+    let stem = location.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match location.extension() {
+        Some(extension) => format!("{stem} (aborted).{}", extension.to_string_lossy()),
+        None => format!("{stem} (aborted)"),
+    };
+    location.with_file_name(name)
+}
+
+/// Rename the file, or copy it when it's on another file system, e.g. from the spool folder to a
+/// network share
+/// The copy is a `.partial` file until it's complete
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let partial = partial_location(to);
+    if let Err(e) = fs::copy(from, &partial).and_then(|_| fs::rename(&partial, to)) {
+        fs::remove_file(&partial).ok();
+        return Err(e.into());
+    }
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Verify the encoded `.partial` file and move it to its final location
+/// A missing or empty file is removed and reported as an error
+fn finalize_output(partial: &Path, location: &Path) -> Result<()> {
+    let size = fs::metadata(partial).map(|m| m.len()).unwrap_or(0);
+    if size == 0 {
+        fs::remove_file(partial).ok();
+        return Err(anyhow!("no output written for {}", location.display()));
+    }
+    fs::rename(partial, location)?;
+    debug!("wrote {}", location.display());
+    Ok(())
+}
+
+/// Apply the configured mode bits and group ownership to a created file or directory
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: Option<&str>, group: Option<&str>) -> Result<()> {
+    use std::os::unix::fs::{chown, PermissionsExt};
+
+    if let Some(mode) = mode {
+        let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .map_err(|_| anyhow!("invalid mode {mode}"))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    if let Some(group) = group {
+        chown(path, None, Some(lookup_gid(group)?))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _mode: Option<&str>, _group: Option<&str>) -> Result<()> {
+    Ok(())
+}
+
+/// Resolve a group name (or numeric gid) to a gid using `/etc/group`
+#[cfg(unix)]
+fn lookup_gid(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    fs::read_to_string("/etc/group")?
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(':');
+            if fields.next() == Some(group) {
+                fields.nth(1).and_then(|gid| gid.parse().ok())
+            } else {
+                None
+            }
+        })
+        .ok_or(anyhow!("unknown group {group}"))
+}
+
+/// Name of the application message posted on the bus when the rip is cancelled
+const CANCEL_MESSAGE: &str = "ripperx4-cancel";
+/// How long to wait for the encoder to finish the file after the rip was cancelled
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the progress is sent, at most 10 times per second
+fn progress_interval(config: &Config) -> Duration {
+    Duration::from_millis(config.progress_interval_ms.max(100))
+}
+
+/// Rip one `Track`
+/// The progress is sent every `interval`, when it changed
+fn extract_track(
+    pipeline: Pipeline,
+    track: &Track,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+    interval: Duration,
+) -> Result<()> {
+    run_pipeline(
+        pipeline,
+        track,
+        TrackState::Encoding,
+        status,
+        cancel,
+        interval,
+    )
+}
+
+/// Run the pipeline of a `Track` to the end, the progress is sent with the given state
+fn run_pipeline(
+    pipeline: Pipeline,
+    track: &Track,
+    state: TrackState,
+    status: &Sender<Status>,
+    cancel: &CancelToken,
+    interval: Duration,
+) -> Result<()> {
+    let title = &track.title;
+    send_progress(status, track, 0.0, state);
+
+    // a context of its own, so rips (and tests) in other threads don't share the default one
+    let context = MainContext::new();
+    let main_loop = MainLoop::new(Some(&context), false);
+    let main_loop_clone = main_loop.clone();
+
+    pipeline.set_state(State::Playing)?;
+    let status = status.clone();
+    let working = Arc::new(RwLock::new(true));
+    let failed = Arc::new(RwLock::new(false));
+    let failed_clone = failed.clone();
+    let progress = track.clone();
+    handle_progress(
+        move |percent| send_progress(&status, &progress, percent, state),
+        pipeline.clone(),
+        cancel.clone(),
+        working.clone(),
+        interval,
+        &context,
+    );
+
+    let bus = pipeline.bus().ok_or(anyhow!("no bus".to_owned()))?;
+    // wake up the bus watch right away instead of finishing the track
+    let pipeline_clone = pipeline.clone();
+    let cancel_guard = cancel.on_cancel(move || {
+        let msg = gstreamer::message::Application::new(Structure::new_empty(CANCEL_MESSAGE));
+        pipeline_clone.post_message(msg).ok();
+    });
+
+    let flush_context = context.clone();
+    // the watch is attached to the thread default context
+    let guard = context.with_thread_default(|| {
+        bus.add_watch(move |_, msg| {
+            let main_loop = &main_loop_clone;
+            match msg.view() {
+                MessageView::Eos(..) => {
+                    debug!("Eos");
+                    let mut w = working.write().expect("failed to get state");
+                    *w = false;
+                    pipeline.set_state(State::Null).ok();
+                    main_loop.quit();
+                }
+                MessageView::Application(app)
+                    if app.structure().is_some_and(|s| s.name() == CANCEL_MESSAGE) =>
+                {
+                    // let the encoder and muxer finish the file (headers, last frames) so it's
+                    // playable, the Eos arrives when it's done
+                    debug!("Cancelled, flushing");
+                    pipeline.send_event(gstreamer::event::Eos::new());
+                    let pipeline = pipeline.clone();
+                    let main_loop = main_loop.clone();
+                    let timeout = glib::timeout_source_new(
+                        FLUSH_TIMEOUT,
+                        None,
+                        Priority::DEFAULT,
+                        move || {
+                            pipeline.set_state(State::Null).ok();
+                            main_loop.quit();
+                            ControlFlow::Break
+                        },
+                    );
+                    timeout.attach(Some(&flush_context));
+                }
+                MessageView::Error(err) => {
+                    debug!("Error");
+                    let mut w = working.write().expect("failed to get state");
+                    *w = false;
+                    *failed_clone.write().expect("failed to get state") = true;
+                    error!(
+                        "Error from {:?}: {} ({:?})",
+                        err.src().map(gstreamer::prelude::GstObjectExt::path_string),
+                        err.error(),
+                        err.debug()
+                    );
+                    pipeline.set_state(State::Null).ok();
+                    main_loop.quit();
+                }
+                _ => (),
+            }
+            ControlFlow::Continue
+        })
+    })??;
+    main_loop.run();
+    drop(guard);
+    drop(cancel_guard);
+    if *failed.read().expect("failed to get state") {
+        return Err(anyhow!("failed to encode {title}"));
+    }
+    if cancel.is_cancelled() {
+        return Err(anyhow!("stopped while ripping {title}"));
+    }
+    debug!("done with {title}");
+    Ok(())
+}
+
+fn send_progress(status: &Sender<Status>, track: &Track, percent: f64, state: TrackState) {
+    send_status(
+        status,
+        Status::Progress {
+            number: track.number,
+            title: track.title.clone(),
+            percent,
+            state,
+        },
+    );
+}
+
+/// Pass the progress of the pipeline to `report` every `interval`, until it's done
+fn handle_progress(
+    report: impl Fn(f64) + Send + 'static,
+    pipeline_clone: Pipeline,
+    cancel: CancelToken,
+    working: Arc<RwLock<bool>>,
+    interval: Duration,
+    context: &MainContext,
+) {
+    let mut last_percent = 0;
+    let timeout = glib::timeout_source_new(interval, None, Priority::DEFAULT, move || {
+        let pipeline = &pipeline_clone;
+        if cancel.is_cancelled() || !*working.read().expect("failed to get state") {
+            return ControlFlow::Break;
+        }
+        let zero = GenericFormattedValue::Percent(Some(Percent::from_percent(0)));
+        let one = GenericFormattedValue::Percent(Some(Percent::from_percent(1)));
+        let pos = pipeline
+            .query_position_generic(Format::Percent)
+            .unwrap_or(zero);
+        let dur = pipeline
+            .query_duration_generic(Format::Percent)
+            .unwrap_or(one);
+        let perc = pos.value() as f64 / dur.value() as f64 * 100.0;
+        // the progress bar shows whole percents, don't send the same one again
+        let whole = perc as i64;
+        if whole == last_percent {
+            return ControlFlow::Continue;
+        }
+        last_percent = whole;
+        report(perc);
+
+        ControlFlow::Continue
+    });
+    timeout.attach(Some(context));
+}
+
+/// Estimate the total size in bytes of the selected tracks when encoded with the given settings
+/// Tracks with a format of their own are estimated in that format
+pub fn estimate_size(disc: &Disc, encoder: &Encoder, quality: &Quality) -> u64 {
+    let kbits: u64 = disc
+        .tracks
+        .iter()
+        .filter(|t| t.rip)
+        .map(|t| t.duration * backend(&t.encoder.unwrap_or(*encoder)).estimated_bitrate(quality))
+        .sum();
+    kbits * 1000 / 8
+}
+
+/// An existing file is only reported larger when it's this much larger than the estimate, the
+/// estimates of VBR encoders are rough
+const LARGER_MARGIN: f64 = 1.25;
+
+/// An existing file a rip would replace that may be better than the new one
+#[derive(Debug, PartialEq)]
+pub struct Overwrite {
+    /// index of the track in the disc
+    pub index: usize,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// The files of the selected tracks that exist already and are larger than the estimate of the
+/// new ones, e.g. a previous rip at a higher quality, or were changed after they were ripped,
+/// e.g. tags fixed by hand
+/// The config must have the route, folder settings and profile of the rip applied
+pub fn risky_overwrites(disc: &Disc, config: &Config) -> Vec<Overwrite> {
+    // a disc image is named after the disc, not the template
+    if config.disc_image {
+        return Vec::new();
+    }
+    let mut existing = Vec::new();
+    for (index, track) in disc.tracks.iter().enumerate().filter(|(_, t)| t.rip) {
+        for (part, _) in track_parts(track) {
+            let path = output_location(&part, disc, config);
+            if let Ok(metadata) = fs::metadata(&path) {
+                existing.push((index, part, path, metadata));
+            }
+        }
+    }
+    if existing.is_empty() {
+        return Vec::new();
+    }
+    let mut folders: Vec<&Path> = existing
+        .iter()
+        .filter_map(|(_, _, path, _)| path.parent())
+        .collect();
+    folders.dedup();
+    let recorded = recorded_files(config, &folders);
+    let mut risky = Vec::new();
+    for (index, part, path, metadata) in existing {
+        let encoder = part.encoder(config);
+        let estimate = part.duration * backend(&encoder).estimated_bitrate(&config.quality) * 125;
+        if let Some(reason) = overwrite_reason(&metadata, estimate, recorded.get(&path)) {
+            risky.push(Overwrite {
+                index,
+                path,
+                reason,
+            });
+        }
+    }
+    risky
+}
+
+/// The size and the time of the last change of a file when it was ripped
+#[derive(Debug, Default, Clone, Copy)]
+struct Recorded {
+    size: Option<u64>,
+    modified: Option<u64>,
+}
+
+/// The files of earlier rips as the `rip.json` reports in `folders` and the inventory recorded
+/// them, the inventory has the older rips too
+fn recorded_files(config: &Config, folders: &[&Path]) -> HashMap<PathBuf, Recorded> {
+    let mut recorded = HashMap::new();
+    let mut add = |file: &Value| {
+        if let Some(path) = file["path"].as_str() {
+            let record = Recorded {
+                size: file["size"].as_u64(),
+                modified: file["modified"].as_u64(),
+            };
+            recorded.insert(PathBuf::from(path), record);
+        }
+    };
+    for folder in folders {
+        let report = fs::read_to_string(folder.join("rip.json")).unwrap_or_default();
+        if let Ok(report) = serde_json::from_str::<Value>(&report) {
+            report["tracks"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .for_each(&mut add);
+        }
+    }
+    let inventory = config
+        .inventory_file
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    // the later lines are the later rips of the file
+    for entry in inventory
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+    {
+        entry["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .for_each(&mut add);
+    }
+    recorded
+}
+
+/// When the file was changed last, in seconds since the epoch
+pub fn modified_at(metadata: &fs::Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    modified
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Why the existing file may be better than the new one, after its size and what was recorded
+/// of it when it was ripped
+fn overwrite_reason(
+    metadata: &fs::Metadata,
+    estimate: u64,
+    recorded: Option<&Recorded>,
+) -> Option<String> {
+    if metadata.len() as f64 > estimate as f64 * LARGER_MARGIN {
+        return Some(format!(
+            "{:.1} MB, the new file ≈ {:.1} MB",
+            metadata.len() as f64 / 1_000_000.0,
+            estimate as f64 / 1_000_000.0
+        ));
+    }
+    // a file ripped before the size was recorded can't be told apart from a changed one
+    let recorded = recorded?;
+    let resized = recorded.size.is_some_and(|size| size != metadata.len());
+    let touched = recorded
+        .modified
+        .zip(modified_at(metadata))
+        .is_some_and(|(then, now)| now > then);
+    (resized || touched).then(|| "changed after it was ripped".to_owned())
+}
+
+/// Elements needed to hand the audio from the reader to the encoders
+const READ_ELEMENTS: &[(&str, &str)] = &[("rawaudioparse", "gst-plugins-base")];
+
+/// Returns the required elements for the `Encoder` that are not installed
+pub fn missing_elements(encoder: &Encoder) -> Vec<(&'static str, &'static str)> {
+    let required = backend(encoder)
+        .required_elements()
+        .into_iter()
+        .chain(READ_ELEMENTS.iter().copied());
+    required
+        .filter(|(element, _)| ElementFactory::find(element).is_none())
+        .collect()
+}
+
+/// Format missing elements as "element (package), ..."
+pub fn describe_missing(missing: &[(&str, &str)]) -> String {
+    missing
+        .iter()
+        .map(|(element, package)| format!("{element} ({package})"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns the CD source backends that are installed, in order of preference
+pub fn available_backends() -> Vec<CdBackend> {
+    let mut backends = Vec::new();
+    for backend in [CdBackend::Cdio, CdBackend::Cdparanoia] {
+        if backend_element(backend).is_some_and(|e| ElementFactory::find(e).is_some()) {
+            backends.push(backend);
         }
     }
-    Ok(())
+    if Element::make_from_uri(URIType::Src, "cdda://1", None).is_ok() {
+        backends.push(CdBackend::CddaUri);
+    }
+    backends
 }
 
-/// Rip one `Track`
-fn extract_track(
-    pipeline: Pipeline,
-    title: &str,
-    status: &Sender<String>,
-    ripping: Arc<RwLock<bool>>,
-) -> Result<()> {
-    let status_message = format!("Encoding {title}");
-    status.send_blocking(status_message.clone()).ok();
+/// The element factory name for a backend, if it maps to a specific element
+fn backend_element(backend: CdBackend) -> Option<&'static str> {
+    match backend {
+        CdBackend::Cdio => Some("cdiocddasrc"),
+        CdBackend::Cdparanoia => Some("cdparanoiasrc"),
+        CdBackend::Auto | CdBackend::CddaUri | CdBackend::Remote => None,
+    }
+}
 
-    let main_loop = MainLoop::new(None, false);
-    let main_loop_clone = main_loop.clone();
+/// Create the source element reading the `Track` from the CD with the configured backend
+pub(crate) fn make_source(track: &Track, config: &Config) -> Result<Element> {
+    if config.cd_backend == CdBackend::Remote {
+        let remote = config
+            .remote_drive
+            .as_deref()
+            .ok_or(anyhow!("No remote drive configured"))?;
+        return remote::source(remote, config.remote_token.as_deref(), track);
+    }
+    let source = make_cd_source(track, config.cd_backend, config.error_correction)?;
+    source.set_property("read-speed", 0_i32);
+    if source.has_property("paranoia-mode", None) {
+        source.set_property_from_str("paranoia-mode", paranoia_mode(config.error_correction));
+    }
+    // rip from the drive that was scanned
+    source.set_property("device", drive(config));
+    Ok(source)
+}
 
-    pipeline.set_state(State::Playing)?;
-    let status = status.clone();
-    let working = Arc::new(RwLock::new(true));
-    handle_progress(
-        status_message,
-        pipeline.clone(),
-        ripping,
-        status.clone(),
-        working.clone(),
-    );
+/// The `paranoia-mode` flags of cdparanoiasrc
+fn paranoia_mode(correction: ErrorCorrection) -> &'static str {
+    match correction {
+        ErrorCorrection::Off => "disable",
+        ErrorCorrection::Fast => "fragment+overlap",
+        ErrorCorrection::Full => "full",
+    }
+}
 
-    let bus = pipeline.bus().ok_or(anyhow!("no bus".to_owned()))?;
+/// The backend used for `CdBackend::Auto`: the first available one, cdparanoia when errors
+/// are corrected, as the others don't
+fn auto_backend(available: &[CdBackend], correction: ErrorCorrection) -> Option<CdBackend> {
+    if correction != ErrorCorrection::Off && available.contains(&CdBackend::Cdparanoia) {
+        return Some(CdBackend::Cdparanoia);
+    }
+    available.first().copied()
+}
 
-    let guard = bus.add_watch(move |_, msg| {
-        let main_loop = &main_loop_clone;
-        match msg.view() {
-            MessageView::Eos(..) => {
-                debug!("Eos");
-                let mut w = working.write().expect("failed to get state");
-                *w = false;
-                pipeline.set_state(State::Null).ok();
-                main_loop.quit();
-            }
-            MessageView::Error(err) => {
-                debug!("Error");
-                status.send_blocking("aborted".to_owned()).ok();
-                let mut w = working.write().expect("failed to get state");
-                *w = false;
-                error!(
-                    "Error from {:?}: {} ({:?})",
-                    err.src().map(gstreamer::prelude::GstObjectExt::path_string),
-                    err.error(),
-                    err.debug()
-                );
-                pipeline.set_state(State::Null).ok();
-                main_loop.quit();
-            }
-            _ => (),
+fn make_cd_source(
+    track: &Track,
+    backend: CdBackend,
+    correction: ErrorCorrection,
+) -> Result<Element> {
+    let backend = match backend {
+        CdBackend::Auto => auto_backend(&available_backends(), correction).ok_or(anyhow!(
+            "No CD source available, install the GStreamer cdio (gst-plugins-ugly) or cdparanoia (gst-plugins-base) plugin"
+        ))?,
+        backend => backend,
+    };
+    debug!("using CD backend {backend:?}");
+    if let Some(name) = backend_element(backend) {
+        if ElementFactory::find(name).is_none() {
+            return Err(anyhow!(
+                "The {name} element is not installed, choose another CD source in the configuration"
+            ));
         }
-        ControlFlow::Continue
-    })?;
-    main_loop.run();
-    drop(guard);
-    debug!("done with {title}");
+        Ok(ElementFactory::make(name)
+            .name("cd_src")
+            .property("track", track.number)
+            .build()?)
+    } else {
+        let cdda = format!("cdda://{}", track.number);
+        Ok(Element::make_from_uri(URIType::Src, &cdda, Some("cd_src"))?)
+    }
+}
+
+fn merge_tags(element: &Element, tags: &TagList) -> Result<()> {
+    let tagsetter = element
+        .dynamic_cast_ref::<TagSetter>()
+        .ok_or(anyhow!("failed to cast"))?;
+    tagsetter.merge_tags(tags, TagMergeMode::ReplaceAll);
     Ok(())
 }
 
-fn handle_progress(
-    status_message: String,
-    pipeline_clone: Pipeline,
-    ripping: Arc<RwLock<bool>>,
-    status: Sender<String>,
-    working: Arc<RwLock<bool>>,
-) {
-    glib::timeout_add(std::time::Duration::from_millis(1000), move || {
-        let pipeline = &pipeline_clone;
-        if !*ripping.read().expect("failed to get state")
-            || !*working.read().expect("failed to get state")
-        {
-            return ControlFlow::Break;
-        }
-        let zero = GenericFormattedValue::Percent(Some(Percent::from_percent(0)));
-        let one = GenericFormattedValue::Percent(Some(Percent::from_percent(1)));
-        let pos = pipeline
-            .query_position_generic(Format::Percent)
-            .unwrap_or(zero);
-        let dur = pipeline
-            .query_duration_generic(Format::Percent)
-            .unwrap_or(one);
-        let perc = pos.value() as f64 / dur.value() as f64 * 100.0;
-        let status_message_perc = format!("{status_message} : {perc:.0} %");
-        status.send_blocking(status_message_perc.clone()).ok();
+/// Create a gstreamer pipeline reading the `Track` from the disc to a file of raw audio
+fn create_read_pipeline(
+    track: &Track,
+    config: &Config,
+    location: &Path,
+    source: SourceMaker,
+) -> Result<Pipeline> {
+    let extractor = source(track, config)?;
+    let folder = location
+        .parent()
+        .ok_or(anyhow!("failed to create folder".to_owned()))?;
+    std::fs::create_dir_all(folder)?;
+    set_permissions(folder, config.dir_mode.as_deref(), config.group.as_deref())?;
+    let sink = ElementFactory::make("filesink").build()?;
+    sink.set_property("location", location.to_str());
 
-        ControlFlow::Continue
-    });
+    let pipeline = Pipeline::new();
+    pipeline.add_many([&extractor, &sink])?;
+    extractor.link(&sink)?;
+    Ok(pipeline)
 }
 
-/// Create a gstreamer pipeline for extracting/encoding the `Track`
-/// Returns a linked `Pipeline`
-fn create_pipeline(track: &Track, disc: &Disc) -> Result<Pipeline> {
-    let config: Config = confy::load("ripperx4", None)?;
-
-    gstreamer::init()?;
+/// A source for the raw audio written by the read pipeline, in the format of a CD
+fn raw_source(location: &Path) -> Result<Element> {
+    let file = ElementFactory::make("filesrc")
+        .property("location", location.to_str())
+        .build()?;
+    raw_audio(file, Bin::new())
+}
 
-    let cdda = format!("cdda://{}", track.number);
-    let extractor = Element::make_from_uri(URIType::Src, &cdda, Some("cd_src"))?;
-    extractor.set_property("read-speed", 0_i32);
+/// Parse the raw audio `file` reads in the format of a CD, in `bin`
+pub(crate) fn raw_audio(file: Element, bin: Bin) -> Result<Element> {
+    let parse = ElementFactory::make("rawaudioparse")
+        .property("sample-rate", i32::try_from(SAMPLE_RATE)?)
+        .property("num-channels", 2_i32)
+        .build()?;
+    parse.set_property_from_str("pcm-format", "s16le");
+    bin.add_many([&file, &parse])?;
+    file.link(&parse)?;
+    let pad = parse
+        .static_pad("src")
+        .ok_or(anyhow!("rawaudioparse has no src pad"))?;
+    bin.add_pad(&GhostPad::with_target(&pad)?)?;
+    Ok(bin.upcast())
+}
 
-    let id3 = ElementFactory::make("id3v2mux").build()?;
-    let mut tags = TagList::new();
-    {
-        let tags = tags
-            .get_mut()
-            .ok_or(anyhow!("can not get mut".to_owned()))?;
-        tags.add::<Title>(&track.title.as_str(), TagMergeMode::ReplaceAll);
-        tags.add::<Artist>(&track.artist.as_str(), TagMergeMode::ReplaceAll);
-        tags.add::<TrackNumber>(&track.number, TagMergeMode::ReplaceAll);
-        tags.add::<Album>(&disc.title.as_str(), TagMergeMode::ReplaceAll);
-        if let Some(year) = disc.year {
-            let date = glib::Date::from_dmy(1, glib::DateMonth::January, year)?;
-            tags.add::<Date>(&date, TagMergeMode::ReplaceAll);
-        }
-        tags.add::<Duration>(
-            &(ClockTime::SECOND * track.duration),
-            TagMergeMode::ReplaceAll,
-        );
-        if let Some(composer) = track.composer.clone() {
-            tags.add::<Composer>(&composer.as_str(), TagMergeMode::ReplaceAll);
+/// The tag muxer, `id3mux` (gst-plugins-bad) instead of `id3v2mux` for MP3 files that also get
+/// an ID3v1 tag, for car stereos that don't read ID3v2
+/// ID3v2.4 frames are written in UTF-8, ID3v2.3 ones in UTF-16
+fn make_muxer(muxer: &str, mp3: bool, id3v2_version: u32, id3v1: bool) -> Result<Element> {
+    if muxer != "id3v2mux" {
+        return Ok(ElementFactory::make(muxer).build()?);
+    }
+    let version = i32::try_from(id3v2_version)?;
+    if mp3 && id3v1 {
+        match ElementFactory::make("id3mux")
+            .property("write-v1", true)
+            .property("v2-version", version)
+            .build()
+        {
+            Ok(mux) => return Ok(mux),
+            Err(_) => warn!("id3mux is not installed, MP3 files get no ID3v1 tag"),
         }
     }
+    let mux = ElementFactory::make(muxer).build()?;
+    mux.set_property("v2-version", version);
+    Ok(mux)
+}
 
-    let extension = match config.encoder {
-        Encoder::MP3 => ".mp3",
-        Encoder::FLAC => ".flac",
-        Encoder::OGG | Encoder::OPUS => ".ogg",
-    };
+/// Create a gstreamer pipeline encoding the audio of `source` for the `Track`, `gain` is its
+/// measured loudness
+/// Returns a linked `Pipeline`
+fn create_pipeline(
+    source: Element,
+    track: &Track,
+    disc: &Disc,
+    config: &Config,
+    location: &Path,
+    gain: Option<&Gain>,
+) -> Result<Pipeline> {
+    let extractor = source;
 
-    let location = format!(
-        "{}/{}-{}/{}{}",
-        config.encode_path, disc.artist, disc.title, track.title, extension
-    );
     //ensure folder exists
-    std::fs::create_dir_all(
-        Path::new(&location)
-            .parent()
-            .ok_or(anyhow!("failed to create folder".to_owned()))?,
-    )?;
+    let folder = location
+        .parent()
+        .ok_or(anyhow!("failed to create folder".to_owned()))?;
+    std::fs::create_dir_all(folder)?;
+    set_permissions(folder, config.dir_mode.as_deref(), config.group.as_deref())?;
     let sink = ElementFactory::make("filesink").build()?;
-    sink.set_property("location", location);
-
-    let pipeline = Pipeline::new();
-    match config.encoder {
-        Encoder::MP3 => {
-            let enc = ElementFactory::make("lamemp3enc").build()?;
-            let quality = match config.quality {
-                crate::data::Quality::Low => 9_f32,
-                crate::data::Quality::Medium => 5_f32,
-                crate::data::Quality::High => 0_f32,
-            };
-            enc.set_property("quality", quality);
-
-            let tagsetter = &id3
-                .dynamic_cast_ref::<TagSetter>()
-                .ok_or(anyhow!("failed to cast"))?;
-            tagsetter.merge_tags(&tags, TagMergeMode::ReplaceAll);
-
-            let elements = &[&extractor, &enc, &id3, &sink];
-            pipeline.add_many(elements)?;
-            Element::link_many(elements)?;
-        }
-        Encoder::OGG => {
-            let convert = ElementFactory::make("audioconvert").build()?;
-            let vorbis = ElementFactory::make("vorbisenc").build()?;
-            let quality = match config.quality {
-                crate::data::Quality::Low => 0.2_f32,
-                crate::data::Quality::Medium => 0.5_f32,
-                crate::data::Quality::High => 0.9_f32,
-            };
-            vorbis.set_property("quality", quality);
-            let mux = ElementFactory::make("oggmux").build()?;
-
-            let tagsetter = &vorbis
-                .dynamic_cast_ref::<TagSetter>()
-                .ok_or(anyhow!("failed to cast"))?;
-            tagsetter.merge_tags(&tags, TagMergeMode::ReplaceAll);
-
-            let elements = &[&extractor, &convert, &vorbis, &mux, &sink];
-            pipeline.add_many(elements)?;
-            Element::link_many(elements)?;
-        }
-        Encoder::FLAC => {
-            let enc = ElementFactory::make("flacenc").build()?;
-            let elements = &[&extractor, &enc, &id3, &sink];
-            let quality = match config.quality {
-                crate::data::Quality::Low => "2",
-                crate::data::Quality::Medium => "5",
-                crate::data::Quality::High => "8",
-            };
-            enc.set_property_from_str("quality", quality);
-
-            let tagsetter = &id3
-                .dynamic_cast_ref::<TagSetter>()
-                .ok_or(anyhow!("failed to cast"))?;
-            tagsetter.merge_tags(&tags, TagMergeMode::ReplaceAll);
-
-            pipeline.add_many(elements)?;
-            Element::link_many(elements)?;
-        }
-        Encoder::OPUS => {
-            let convert = ElementFactory::make("audioconvert").build()?;
-            let resample = ElementFactory::make("audioresample").build()?;
-            let opus = ElementFactory::make("opusenc").build()?;
-            let mux = ElementFactory::make("oggmux").build()?;
-
-            let bitrate = match config.quality {
-                crate::data::Quality::Low => 64_000_i32,
-                crate::data::Quality::Medium => 128_000_i32,
-                crate::data::Quality::High => 256_000_i32,
-            };
-            opus.set_property("bitrate", bitrate);
-            let tagsetter = &opus
-                .dynamic_cast_ref::<TagSetter>()
-                .ok_or(anyhow!("failed to cast"))?;
-            tagsetter.merge_tags(&tags, TagMergeMode::ReplaceAll);
+    sink.set_property("location", location.to_str());
 
-            let elements = &[&extractor, &convert, &resample, &opus, &mux, &sink];
-            pipeline.add_many(elements)?;
-            Element::link_many(elements)?;
+    let encoder = backend(&track.encoder(config));
+    let mut elements = vec![extractor];
+    let gain = match gain {
+        Some(gain) if config.replaygain == ReplayGain::Limit && encoder.lossy() => {
+            let volume = gain.safe_volume();
+            if volume < 1.0 {
+                debug!("lowering the volume of {} to {volume:.3}", track.title);
+                let element = ElementFactory::make("volume")
+                    .property("volume", volume)
+                    .build()?;
+                elements.push(element);
+            }
+            // the tags describe the file, not the disc
+            Some(gain.with_volume(volume))
         }
+        gain => gain.copied(),
     };
+    elements.extend(encoder.elements(&config.quality)?);
+    // after the elements are made, so the tag library registered the cover image type
+    let id3v2_version = config.id3v2_version.clamp(3, 4);
+    let muxer = encoder.tag_strategy().muxer();
+    // ID3v2.3 has a year frame only
+    let full_date = encoder.full_date() && (muxer != Some("id3v2mux") || id3v2_version == 4);
+    let mut tags = track_tags(disc, track, full_date)?;
+    if let Some(gain) = gain {
+        gain.add_tags(&mut tags)?;
+    }
+    match muxer {
+        Some(muxer) => {
+            // flacenc also writes the tags natively, including the cover as a PICTURE block
+            if let Some(tagger) = elements.iter().find(|e| e.is::<TagSetter>()) {
+                merge_tags(tagger, &tags)?;
+            }
+            let mp3 = track.encoder(config) == Encoder::MP3;
+            let mux = make_muxer(muxer, mp3, id3v2_version, config.id3v1_tags)?;
+            merge_tags(&mux, &tags)?;
+            elements.push(mux);
+        }
+        None => {
+            let tagger = elements
+                .iter()
+                .find(|e| e.is::<TagSetter>())
+                .ok_or(anyhow!("no element accepts tags"))?;
+            merge_tags(tagger, &tags)?;
+        }
+    }
+    elements.push(sink);
+
+    let pipeline = Pipeline::new();
+    pipeline.add_many(&elements)?;
+    Element::link_many(&elements)?;
 
     Ok(pipeline)
 }
 
 #[cfg(test)]
 mod test {
-    use anyhow::Result;
-    use gstreamer::{prelude::*, Element, ElementFactory, Pipeline};
+    use anyhow::{anyhow, Result};
+    use gstreamer::{prelude::*, Bin, Element, ElementFactory, GhostPad, PadDirection, Pipeline};
+    use serde_json::json;
     use serial_test::serial;
     use std::{
         env,
-        fs::remove_file,
-        path::Path,
-        sync::{Arc, RwLock},
+        fs::{self, remove_file},
+        path::{Path, PathBuf},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{
+        aborted_location, actual_bitrate, auto_backend, checksum_pcm, disc_condition,
+        encoder_workers, estimate_size, extract_from, extract_track, finalize_output, lookup_gid,
+        missing_elements, output_location, partial_location, progress_interval, report_checksums,
+        rip_log, rip_report, risky_overwrites, target_available, track_condition, track_parts,
+        update_library_views, write_checksums, write_report, ReadTrack, Rip, Ripped, SecureRead,
+        Spooled, Verified,
+    };
+    use crate::{
+        accuraterip::{Checksum, Entry},
+        cancel::CancelToken,
+        data::{
+            CdBackend, Config, Disc, Encoded, Encoder, ErrorCorrection, Quality, ReadErrors,
+            Segment, Status, Track, TrackState,
+        },
+        encoder::backend,
     };
 
-    use super::extract_track;
+    /// WAV file ripped instead of the CD by `fixture_source`
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/resources/test/file_example_WAV_1MG.wav"
+    );
+
+    /// A virtual drive: every track reads the decoded WAV file
+    fn fixture_source(_track: &Track, _config: &Config) -> Result<Element> {
+        let bin = Bin::builder().name("cd_src").build();
+        let file = ElementFactory::make("filesrc")
+            .property("location", FIXTURE)
+            .build()?;
+        let parse = ElementFactory::make("wavparse").build()?;
+        bin.add_many([&file, &parse])?;
+        file.link(&parse)?;
+        // wavparse only adds its source pad once it has read the header
+        let src = GhostPad::builder(PadDirection::Src).name("src").build();
+        bin.add_pad(&src)?;
+        parse.connect_pad_added(move |_, pad| {
+            if let Err(e) = src.set_target(Some(pad)) {
+                log::error!("Failed to link the fixture: {e}");
+            }
+        });
+        Ok(bin.upcast())
+    }
+
+    fn test_track() -> Track {
+        Track {
+            number: 1,
+            title: "track".to_owned(),
+            ..Default::default()
+        }
+    }
 
     #[test]
     #[serial]
@@ -289,8 +2557,13 @@ mod test {
         pipeline.add_many(elements)?;
         Element::link_many(elements)?;
         let (tx, _rx) = async_channel::unbounded();
-        let ripping = Arc::new(RwLock::new(true));
-        let result = extract_track(pipeline, "track", &tx, ripping);
+        let result = extract_track(
+            pipeline,
+            &test_track(),
+            &tx,
+            &CancelToken::new(),
+            Duration::from_secs(1),
+        );
         assert!(result.is_err());
         Ok(())
     }
@@ -315,8 +2588,13 @@ mod test {
         pipeline.add_many(elements)?;
         Element::link_many(elements)?;
         let (tx, _rx) = async_channel::unbounded();
-        let ripping = Arc::new(RwLock::new(true));
-        extract_track(pipeline, "track", &tx, ripping)?;
+        extract_track(
+            pipeline,
+            &test_track(),
+            &tx,
+            &CancelToken::new(),
+            Duration::from_secs(1),
+        )?;
         assert!(Path::new(dest).exists());
         assert!(Path::new(dest).is_file());
         remove_file(dest)?;
@@ -341,8 +2619,13 @@ mod test {
         pipeline.add_many(elements)?;
         Element::link_many(elements)?;
         let (tx, _rx) = async_channel::unbounded();
-        let ripping = Arc::new(RwLock::new(true));
-        extract_track(pipeline, "track", &tx, ripping)?;
+        extract_track(
+            pipeline,
+            &test_track(),
+            &tx,
+            &CancelToken::new(),
+            Duration::from_secs(1),
+        )?;
         assert!(Path::new(dest).exists());
         assert!(Path::new(dest).is_file());
         remove_file(dest)?;
@@ -369,14 +2652,94 @@ mod test {
         pipeline.add_many(elements)?;
         Element::link_many(elements)?;
         let (tx, _rx) = async_channel::unbounded();
-        let ripping = Arc::new(RwLock::new(true));
-        extract_track(pipeline, "track", &tx, ripping)?;
+        extract_track(
+            pipeline,
+            &test_track(),
+            &tx,
+            &CancelToken::new(),
+            Duration::from_secs(1),
+        )?;
         assert!(Path::new(dest).exists());
         assert!(Path::new(dest).is_file());
         remove_file(dest)?;
         Ok(())
     }
 
+    /// Encode the test file with the chain of the backend
+    fn encode_test_file(encoder: &Encoder, dest: &str) -> Result<Vec<u8>> {
+        gstreamer::init()?;
+        let mut path = env::var("CARGO_MANIFEST_DIR")?;
+        path.push_str("/resources/test/file_example_WAV_1MG.wav");
+        let file = ElementFactory::make("filesrc").build()?;
+        file.set_property("location", &path);
+        let wav = ElementFactory::make("wavparse").build()?;
+        let sink = ElementFactory::make("filesink").build()?;
+        sink.set_property("location", dest);
+        let mut elements = vec![file, wav];
+        elements.extend(backend(encoder).elements(&Quality::Medium)?);
+        elements.push(sink);
+        let pipeline = Pipeline::new();
+        pipeline.add_many(&elements)?;
+        Element::link_many(&elements)?;
+        let (tx, _rx) = async_channel::unbounded();
+        extract_track(
+            pipeline,
+            &test_track(),
+            &tx,
+            &CancelToken::new(),
+            Duration::from_secs(1),
+        )?;
+        let contents = fs::read(dest)?;
+        remove_file(dest)?;
+        Ok(contents)
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_m4a_wavpack() -> Result<()> {
+        gstreamer::init()?;
+        for (encoder, magic, offset) in [
+            (Encoder::AAC, b"ftyp", 4),
+            (Encoder::ALAC, b"ftyp", 4),
+            (Encoder::WAVPACK, b"wvpk", 0),
+        ] {
+            // gst-libav and the WavPack plugin are optional
+            if !missing_elements(&encoder).is_empty() {
+                continue;
+            }
+            let contents = encode_test_file(&encoder, "/tmp/ripperx4_format")?;
+            assert_eq!(magic, &contents[offset..offset + 4], "{encoder:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_gapless_mp3() -> Result<()> {
+        gstreamer::init()?;
+        if ElementFactory::find("xingmux").is_none() {
+            return Ok(());
+        }
+        let mp3 = encode_test_file(&Encoder::MP3, "/tmp/ripperx4_gapless.mp3")?;
+        // the Xing frame is the first frame of the stream
+        assert!(mp3[..4096].windows(4).any(|w| w == b"Xing" || w == b"Info"));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_gapless_opus() -> Result<()> {
+        let opus = encode_test_file(&Encoder::OPUS, "/tmp/ripperx4_gapless.opus")?;
+        // the pre-skip in the OpusHead tells the player how many samples of encoder delay to drop
+        let head = opus
+            .windows(8)
+            .position(|w| w == b"OpusHead")
+            .expect("no OpusHead");
+        let pre_skip = u16::from_le_bytes([opus[head + 10], opus[head + 11]]);
+        assert!(pre_skip > 0);
+        Ok(())
+    }
+
     #[test]
     #[serial]
     pub fn test_ogg() -> Result<()> {
@@ -397,11 +2760,775 @@ mod test {
         pipeline.add_many(elements)?;
         Element::link_many(elements)?;
         let (tx, _rx) = async_channel::unbounded();
-        let ripping = Arc::new(RwLock::new(true));
-        extract_track(pipeline, "track", &tx, ripping)?;
+        extract_track(
+            pipeline,
+            &test_track(),
+            &tx,
+            &CancelToken::new(),
+            Duration::from_secs(1),
+        )?;
         assert!(Path::new(dest).exists());
         assert!(Path::new(dest).is_file());
         remove_file(dest)?;
         Ok(())
     }
+
+    #[test]
+    pub fn test_finalize_output() -> Result<()> {
+        let dest = Path::new("/tmp/ripperx4_finalize.mp3");
+        let partial = partial_location(dest);
+        assert_eq!(Path::new("/tmp/ripperx4_finalize.mp3.partial"), partial);
+
+        std::fs::write(&partial, b"")?;
+        assert!(finalize_output(&partial, dest).is_err());
+        assert!(!partial.exists());
+        assert!(!dest.exists());
+
+        std::fs::write(&partial, b"encoded")?;
+        finalize_output(&partial, dest)?;
+        assert!(!partial.exists());
+        assert!(dest.is_file());
+        remove_file(dest)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_lookup_gid() -> Result<()> {
+        assert_eq!(0, lookup_gid("root")?);
+        assert_eq!(100, lookup_gid("100")?);
+        assert!(lookup_gid("no such group").is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_update_library_views() -> Result<()> {
+        let root = Path::new("/tmp/ripperx4_views");
+        let album = root.join("Dire Straits-Money for Nothing");
+        std::fs::create_dir_all(&album)?;
+        let config = Config {
+            encode_path: root.display().to_string(),
+            ..Default::default()
+        };
+        let disc = Disc {
+            year: Some(1988),
+            genre: Some("Blues/Rock".to_owned()),
+            ..Default::default()
+        };
+        update_library_views(&disc, &config, &album)?;
+        // updating again replaces the existing links
+        update_library_views(&disc, &config, &album)?;
+        let album = std::fs::canonicalize(album)?;
+        let link = root.join("By Genre/Blues-Rock/Dire Straits-Money for Nothing");
+        assert_eq!(album, std::fs::read_link(link)?);
+        let link = root.join("By Year/1988/Dire Straits-Money for Nothing");
+        assert_eq!(album, std::fs::read_link(link)?);
+        // another genre moves the link, other albums of the old genre keep theirs
+        let other = root.join("By Genre/Blues-Rock/Dire Straits-Alchemy");
+        std::os::unix::fs::symlink(root.join("Dire Straits-Alchemy"), &other)?;
+        let disc = Disc {
+            genre: Some("Rock".to_owned()),
+            ..disc
+        };
+        update_library_views(&disc, &config, &album)?;
+        let link = root.join("By Genre/Rock/Dire Straits-Money for Nothing");
+        assert_eq!(album, std::fs::read_link(link)?);
+        assert!(std::fs::symlink_metadata(
+            root.join("By Genre/Blues-Rock/Dire Straits-Money for Nothing")
+        )
+        .is_err());
+        assert!(std::fs::symlink_metadata(&other).is_ok());
+        // without a genre the folder of the last one goes away
+        std::fs::remove_file(&other)?;
+        let disc = Disc {
+            genre: None,
+            ..disc
+        };
+        update_library_views(&disc, &config, &album)?;
+        assert!(!root.join("By Genre/Rock").exists());
+        assert!(root.join("By Year/1988").exists());
+        std::fs::remove_dir_all(root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_write_checksums() -> Result<()> {
+        let root = Path::new("/tmp/ripperx4_checksums");
+        std::fs::create_dir_all(root)?;
+        let abc = root.join("abc.flac");
+        let empty = root.join("empty.flac");
+        std::fs::write(&abc, b"abc")?;
+        std::fs::write(&empty, b"")?;
+        write_checksums(root, std::slice::from_ref(&abc))?;
+        let manifest = write_checksums(root, &[empty, abc])?;
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  abc.flac\n\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty.flac\n",
+            std::fs::read_to_string(manifest)?
+        );
+        std::fs::remove_dir_all(root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_estimate_size() {
+        let mut disc = Disc::with_tracks(2);
+        disc.tracks[0].duration = 60;
+        disc.tracks[1].duration = 600;
+        disc.tracks[1].rip = false;
+        assert_eq!(
+            960_000,
+            estimate_size(&disc, &Encoder::OPUS, &Quality::Medium)
+        );
+        disc.tracks[1].rip = true;
+        assert_eq!(
+            10_560_000,
+            estimate_size(&disc, &Encoder::OPUS, &Quality::Medium)
+        );
+        // 10 minutes of FLAC instead of Opus
+        disc.tracks[1].encoder = Some(Encoder::FLAC);
+        let flac = backend(&Encoder::FLAC).estimated_bitrate(&Quality::Medium);
+        assert_eq!(
+            960_000 + 600 * flac * 1000 / 8,
+            estimate_size(&disc, &Encoder::OPUS, &Quality::Medium)
+        );
+    }
+
+    #[test]
+    pub fn test_risky_overwrites() -> Result<()> {
+        let root = env::temp_dir().join("ripperx4_overwrites");
+        let inventory = root.join("inventory.jsonl");
+        let config = Config {
+            encode_path: root.display().to_string(),
+            inventory_file: Some(inventory.display().to_string()),
+            encoder: Encoder::OPUS,
+            quality: Quality::Medium,
+            ..Default::default()
+        };
+        let mut disc = Disc::with_tracks(3);
+        for track in &mut disc.tracks {
+            track.duration = 60;
+            track.title = format!("Track {}", track.number);
+        }
+        // 60 seconds of Opus is about 960 kB
+        let write = |track: &Track, size: usize| -> Result<()> {
+            let path = output_location(track, &disc, &config);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, vec![0; size])?;
+            Ok(())
+        };
+        write(&disc.tracks[0], 3_000_000)?;
+        write(&disc.tracks[1], 900_000)?;
+        write(&disc.tracks[2], 900_000)?;
+        let risky = risky_overwrites(&disc, &config);
+        assert_eq!(1, risky.len());
+        assert_eq!(0, risky[0].index);
+        assert_eq!(
+            output_location(&disc.tracks[0], &disc, &config),
+            risky[0].path
+        );
+        assert_eq!("3.0 MB, the new file ≈ 1.0 MB", risky[0].reason);
+        // unselected tracks aren't overwritten
+        disc.tracks[0].rip = false;
+        assert!(risky_overwrites(&disc, &config).is_empty());
+        // the inventory has the size of the files when they were ripped
+        let file = |track: &Track, size: u64| json!({"path": output_location(track, &disc, &config), "size": size});
+        let entry =
+            json!({"files": [file(&disc.tracks[1], 800_000), file(&disc.tracks[2], 900_000)]});
+        std::fs::write(&inventory, format!("{entry}\n"))?;
+        let risky = risky_overwrites(&disc, &config);
+        assert_eq!(1, risky.len());
+        assert_eq!(1, risky[0].index);
+        assert_eq!("changed after it was ripped", risky[0].reason);
+        std::fs::remove_dir_all(root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_track_parts() {
+        let mut track = test_track();
+        track.duration = 600;
+        assert_eq!(1, track_parts(&track).len());
+        track.segments = vec![
+            Segment {
+                title: "Intro".to_owned(),
+                start: 0,
+                end: Some(90),
+            },
+            Segment {
+                title: String::new(),
+                start: 90,
+                end: None,
+            },
+        ];
+        let parts = track_parts(&track);
+        assert_eq!(2, parts.len());
+        assert_eq!("Intro", parts[0].0.title);
+        assert_eq!(90, parts[0].0.duration);
+        assert_eq!("track (2)", parts[1].0.title);
+        assert_eq!(510, parts[1].0.duration);
+        assert!(parts[1].0.segments.is_empty());
+    }
+
+    #[test]
+    pub fn test_aborted_location() {
+        assert_eq!(
+            Path::new("/music/a-b/Intro (aborted).flac"),
+            aborted_location(Path::new("/music/a-b/Intro.flac"))
+        );
+    }
+
+    #[test]
+    pub fn test_progress_interval() {
+        let mut config = Config::default();
+        assert_eq!(Duration::from_secs(1), progress_interval(&config));
+        config.progress_interval_ms = 0;
+        assert_eq!(Duration::from_millis(100), progress_interval(&config));
+    }
+
+    #[test]
+    pub fn test_extract_fixture() -> Result<()> {
+        gstreamer::init()?;
+        let root = env::temp_dir().join("ripperx4_extract_fixture");
+        let _ = fs::remove_dir_all(&root);
+        let config = Config {
+            encode_path: root.display().to_string(),
+            encoder: Encoder::FLAC,
+            write_checksums: true,
+            secure_rip: true,
+            cover_art: false,
+            ..Default::default()
+        };
+        let mut disc = Disc::with_tracks(2);
+        disc.artist = "Band".to_owned();
+        disc.title = "Album".to_owned();
+        disc.tracks[0].title = "First".to_owned();
+        disc.tracks[0].duration = 6;
+        disc.tracks[0].sectors = 6 * 75;
+        disc.tracks[1].rip = false;
+        let (tx, rx) = async_channel::unbounded();
+
+        let report = extract_from(
+            &disc,
+            &[0, 1],
+            &config,
+            &tx,
+            &CancelToken::new(),
+            fixture_source,
+        )?;
+        assert_eq!(1, report.len());
+        let album = root.join("Band-Album");
+        assert_eq!(album.join("First.flac"), report[0].path);
+        let contents = fs::read(&report[0].path)?;
+        assert!(contents.windows(4).any(|w| w == b"fLaC"));
+        assert!(contents.windows(5).any(|w| w == b"First"));
+        let checksums = fs::read_to_string(album.join("checksums.sha256"))?;
+        assert!(checksums.contains("First.flac"));
+        let log = fs::read_to_string(album.join("rip.log"))?;
+        assert!(log.contains("Band / Album"));
+        assert!(log.contains("Uncorrected 0 sectors"));
+        let mut states = Vec::new();
+        let mut files = Vec::new();
+        while let Ok(status) = rx.try_recv() {
+            match status {
+                Status::Progress { number, state, .. } => states.push((number, state)),
+                Status::Encoded(encoded) => files.push(encoded.path),
+                _ => (),
+            }
+        }
+        assert_eq!(Some(&(1, TrackState::Done)), states.last());
+        assert_eq!(vec![album.join("First.flac")], files);
+        fs::remove_dir_all(root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_parallel_extract_track() -> Result<()> {
+        gstreamer::init()?;
+        let rip = || -> Result<()> {
+            let src = ElementFactory::make("fakesrc")
+                .property("num-buffers", 100_i32)
+                .build()?;
+            let sink = ElementFactory::make("fakesink").build()?;
+            let pipeline = Pipeline::new();
+            pipeline.add_many([&src, &sink])?;
+            src.link(&sink)?;
+            let (tx, _rx) = async_channel::unbounded();
+            let interval = Duration::from_millis(10);
+            extract_track(pipeline, &test_track(), &tx, &CancelToken::new(), interval)
+        };
+        // every rip runs its own main loop, they must not steal each other's messages
+        let rips: Vec<_> = (0..4).map(|_| thread::spawn(rip)).collect();
+        for rip in rips {
+            rip.join().expect("rip panicked")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_condition() {
+        let errors = |rereads, skipped| ReadErrors {
+            rereads,
+            retries: rereads,
+            skipped,
+        };
+        assert_eq!("clean rip, 0 corrections", track_condition(errors(0, 0)));
+        assert_eq!(
+            "light corrections, 12 sectors re-read",
+            track_condition(errors(12, 0))
+        );
+        assert_eq!(
+            "uncorrected errors, 150 sectors re-read, 2 not corrected",
+            track_condition(errors(150, 2))
+        );
+        assert_eq!(
+            "clean rip, 0 corrections",
+            disc_condition(&[(1, errors(0, 0)), (2, errors(0, 0))])
+        );
+        assert_eq!(
+            "heavy corrections tracks 3–4, light corrections tracks 1, 7",
+            disc_condition(&[
+                (1, errors(5, 0)),
+                (2, errors(0, 0)),
+                (3, errors(200, 0)),
+                (4, errors(101, 0)),
+                (7, errors(1, 0)),
+            ])
+        );
+    }
+
+    #[test]
+    pub fn test_rip_log() {
+        let mut disc = Disc::with_tracks(2);
+        disc.artist = "Band".to_owned();
+        disc.title = "Album".to_owned();
+        let mut checksum = Checksum::new(1, false, false);
+        checksum.update(&0xabcd_u32.to_le_bytes());
+        let verified: Vec<Verified> = (1..=2)
+            .map(|number| Verified {
+                number,
+                path: PathBuf::from(format!("/music/Band-Album/{number}.flac")),
+                read: SecureRead {
+                    source: "cdparanoiasrc".to_owned(),
+                    checksum: checksum.clone(),
+                    errors: ReadErrors {
+                        rereads: number,
+                        retries: number,
+                        skipped: number - 1,
+                    },
+                    counted: true,
+                    ..Default::default()
+                },
+            })
+            .collect();
+        let log = rip_log(&disc, "/dev/cdrom", &verified, &[]);
+        assert!(log.starts_with("Band / Album\n"));
+        assert!(
+            log.contains("Condition   : uncorrected errors track 2, light corrections track 1\n")
+        );
+        assert!(log.contains("Used drive  : /dev/cdrom\nCD source   : cdparanoiasrc\n"));
+        assert!(log.contains("AccurateRip : unknown disc\n"));
+        assert!(log.contains(
+            "Filename    /music/Band-Album/2.flac\n     Re-read     2 sectors\n     Uncorrected 1 sectors\n"
+        ));
+        assert!(log.contains("AccurateRip v1 0000ABCD, v2 0000ABCD\n     Not verified\n"));
+        assert!(log.ends_with("3 sectors were re-read, 1 could not be corrected\n"));
+
+        disc.accuraterip_id = Some("d/c/b/dBAR-002-00000bcd-00001234-0a00b602.bin".to_owned());
+        let pressings = vec![vec![
+            Entry {
+                confidence: 4,
+                crc: 0xabcd,
+            },
+            Entry {
+                confidence: 4,
+                crc: 0x1234,
+            },
+        ]];
+        let log = rip_log(&disc, "/dev/cdrom", &verified, &pressings);
+        assert!(log.contains("AccurateRip : dBAR-002-00000bcd-00001234-0a00b602.bin\n"));
+        assert!(log.contains("Accurately ripped (confidence 4)"));
+        assert!(log.contains("Not matching the AccurateRip database"));
+        assert!(log.contains("1 of 2 tracks accurately ripped"));
+    }
+
+    #[test]
+    pub fn test_record_error() {
+        let mut read = SecureRead::default();
+        assert_eq!(Some(1), read.record_error(Some(1200), false));
+        assert_eq!(Some(2), read.record_error(Some(1200), false));
+        assert_eq!(Some(1), read.record_error(Some(1300), false));
+        assert_eq!(None, read.record_error(Some(1300), true));
+        // a source that doesn't say which sector
+        assert_eq!(None, read.record_error(None, false));
+        assert_eq!(
+            ReadErrors {
+                rereads: 3,
+                retries: 4,
+                skipped: 1,
+            },
+            read.errors
+        );
+
+        let verified = [Verified {
+            number: 1,
+            path: PathBuf::from("/music/Band-Album/1.flac"),
+            read,
+        }];
+        let log = rip_log(&Disc::with_tracks(1), "/dev/cdrom", &verified, &[]);
+        assert!(log.contains("Re-read     3 sectors (4 reads)\n"));
+    }
+
+    #[test]
+    pub fn test_rip_report() {
+        let mut disc = Disc::with_tracks(2);
+        disc.title = "Album".to_owned();
+        disc.tracks[0].duration = 240;
+        let config = Config {
+            encoder: Encoder::FLAC,
+            secure_rip: true,
+            device: Some("/dev/sr1".to_owned()),
+            ..Default::default()
+        };
+        let path = PathBuf::from("/music/Unknown-Album/Unknown.flac");
+        let mut checksum = Checksum::new(1, false, false);
+        checksum.update(&0xabcd_u32.to_le_bytes());
+        let ripped = Ripped {
+            encoded: vec![Encoded {
+                path: path.clone(),
+                number: 1,
+                title: "Unknown".to_owned(),
+                size: 1000,
+                bitrate: Some(850),
+                expected_bitrate: 850,
+                read_errors: ReadErrors {
+                    rereads: 2,
+                    retries: 2,
+                    skipped: 0,
+                },
+                accuraterip: None,
+            }],
+            verified: vec![Verified {
+                number: 1,
+                path,
+                read: SecureRead {
+                    checksum,
+                    ..Default::default()
+                },
+            }],
+            error: Some(anyhow!("failed to encode Unknown")),
+            report: None,
+        };
+        let pressings = vec![vec![Entry {
+            confidence: 5,
+            crc: 0xabcd,
+        }]];
+        let report = rip_report(&disc, &config, &ripped, &pressings, 1_700_000_000, 300);
+        assert_eq!(false, report["success"]);
+        assert_eq!("failed to encode Unknown", report["error"]);
+        assert_eq!("rip_failed", report["outcome"]);
+        assert_eq!("Album", report["disc"]["album"]);
+        assert_eq!("FLAC", report["settings"]["encoder"]);
+        assert_eq!("/dev/sr1", report["settings"]["drive"]);
+        let track = &report["tracks"][0];
+        assert_eq!(240, track["duration"]);
+        assert_eq!(2, track["rereads"]);
+        assert_eq!(0, track["skipped"]);
+        assert_eq!("0000ABCD", track["crc_v1"]);
+        assert_eq!("accurate", track["accuraterip"]["result"]);
+        assert_eq!(5, track["accuraterip"]["confidence"]);
+        // read back to verify the rip against the disc
+        let checksums = report_checksums(&report);
+        assert_eq!(vec![1], checksums.keys().copied().collect::<Vec<u32>>());
+        assert_eq!(0xabcd, checksums[&1].0);
+    }
+
+    #[test]
+    pub fn test_auto_backend() {
+        let available = [CdBackend::Cdio, CdBackend::Cdparanoia];
+        assert_eq!(
+            Some(CdBackend::Cdparanoia),
+            auto_backend(&available, ErrorCorrection::Fast)
+        );
+        assert_eq!(
+            Some(CdBackend::Cdio),
+            auto_backend(&available, ErrorCorrection::Off)
+        );
+        assert_eq!(
+            Some(CdBackend::CddaUri),
+            auto_backend(&[CdBackend::CddaUri], ErrorCorrection::Full)
+        );
+        assert_eq!(None, auto_backend(&[], ErrorCorrection::Full));
+    }
+
+    #[test]
+    pub fn test_rip_fail() -> Result<()> {
+        let disc = Disc::default();
+        let config = Config::default();
+        let (tx, _rx) = async_channel::unbounded();
+        let (cancel, stop) = (CancelToken::new(), CancelToken::new());
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        rip.fail(anyhow!("encoder failed"));
+        assert!(stop.is_cancelled());
+        // the tracks stopped because of it don't replace the error
+        rip.fail(anyhow!("stopped while ripping"));
+        let error = rip.error.into_inner().map_err(|e| anyhow!("{e}"))?;
+        assert_eq!("encoder failed", error.expect("error").to_string());
+        assert_eq!(
+            1,
+            encoder_workers(&Config {
+                encoder_workers: 0,
+                ..config
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_spool() -> Result<()> {
+        let root = env::temp_dir().join("ripperx4_spool");
+        let config = Config {
+            encode_path: root.join("music").display().to_string(),
+            spool_dir: Some(root.join("spool").display().to_string()),
+            ..Default::default()
+        };
+        let disc = Disc::default();
+        let (tx, _rx) = async_channel::unbounded();
+        let (cancel, stop) = (CancelToken::new(), CancelToken::new());
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        let location = root.join("music/Band-Album/Song.flac");
+        let output = rip.encoder_output(3, &location);
+        let folder = rip.spool_folder().expect("spool folder");
+        assert_eq!(folder.join("3.flac"), output);
+        fs::create_dir_all(&folder)?;
+        fs::write(&output, "flac")?;
+        rip.spool.0.send_blocking(Spooled {
+            from: output.clone(),
+            to: location.clone(),
+        })?;
+        rip.spool.0.close();
+        rip.write_spooled();
+        assert_eq!("flac", fs::read_to_string(&location)?);
+        assert!(!output.exists());
+        assert!(rip.error.lock().map_err(|e| anyhow!("{e}"))?.is_none());
+        fs::remove_dir_all(&root)?;
+
+        let config = Config::default();
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        assert_eq!(location, rip.encoder_output(3, &location));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_encode_retries() -> Result<()> {
+        gstreamer::init()?;
+        let available: Vec<Encoder> = crate::encoder::backends()
+            .iter()
+            .map(|b| b.encoder())
+            .filter(|encoder| missing_elements(encoder).is_empty())
+            .collect();
+        let [first, second, ..] = available[..] else {
+            return Ok(());
+        };
+        let root = env::temp_dir().join("ripperx4_encode_retries");
+        fs::create_dir_all(&root)?;
+        let pcm = root.join("track.pcm");
+        let mut config = Config {
+            encode_path: root.display().to_string(),
+            encoder: first,
+            encode_retries: 0,
+            device: Some("/dev/sr0".to_owned()),
+            ..Default::default()
+        };
+        let disc = Disc::with_tracks(1);
+        let (tx, _rx) = async_channel::unbounded();
+        let (cancel, stop) = (CancelToken::new(), CancelToken::new());
+        // the prepared pipeline fails, a retry starts from the raw audio
+        let job = |config: &Config| -> Result<ReadTrack> {
+            fs::write(&pcm, vec![0; 4 * 44100])?;
+            let file = ElementFactory::make("filesrc")
+                .property("location", "/nonexistent.pcm")
+                .build()?;
+            let sink = ElementFactory::make("fakesink").build()?;
+            let failing = Pipeline::new();
+            failing.add_many([&file, &sink])?;
+            file.link(&sink)?;
+            let part = disc.tracks[0].clone();
+            Ok(ReadTrack {
+                index: 0,
+                location: output_location(&part, &disc, config),
+                part,
+                pcm: pcm.clone(),
+                read: SecureRead::default(),
+                verify: false,
+                gain: None,
+                pipeline: Some(failing),
+            })
+        };
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        assert!(rip.encode_with_retries(&mut job(&config)?).is_err());
+
+        config.encode_retries = 1;
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        let encoded = rip.encode_with_retries(&mut job(&config)?)?;
+        assert_eq!(
+            output_location(&disc.tracks[0], &disc, &config),
+            encoded.path
+        );
+
+        config.encode_retries = 0;
+        config.fallback_encoder = Some(second);
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        let mut job = job(&config)?;
+        let encoded = rip.encode_with_retries(&mut job)?;
+        assert_eq!(Some(second), job.part.encoder);
+        assert!(encoded.path.is_file());
+        assert_eq!(output_location(&job.part, &disc, &config), encoded.path);
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_record() -> Result<()> {
+        let root = env::temp_dir().join("ripperx4_record");
+        let config = Config {
+            json_report: true,
+            report_dir: Some(root.display().to_string()),
+            device: Some("/dev/sr0".to_owned()),
+            ..Default::default()
+        };
+        let disc = Disc::with_tracks(2);
+        let (tx, _rx) = async_channel::unbounded();
+        let (cancel, stop) = (CancelToken::new(), CancelToken::new());
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        let mut encoded = Encoded {
+            path: root.join("Unknown-Unknown/Unknown.mp3"),
+            number: 1,
+            title: "Unknown".to_owned(),
+            size: 1000,
+            bitrate: None,
+            expected_bitrate: 128,
+            read_errors: ReadErrors::default(),
+            accuraterip: None,
+        };
+        rip.record(&encoded, None);
+        encoded.number = 2;
+        rip.record(&encoded, None);
+        let journal = rip.journal.into_inner().map_err(|e| anyhow!("{e}"))?;
+        let path = journal.report.clone().expect("report written");
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!("incomplete", report["outcome"]);
+        assert_eq!(2, report["tracks"].as_array().map_or(0, Vec::len));
+        assert!(!partial_location(&path).exists());
+        // the finished rip replaces it
+        write_report(&disc, &[], &config, &journal, &[], SystemTime::now(), true)?;
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!("success", report["outcome"]);
+        assert_eq!(1, fs::read_dir(&root)?.count());
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_wait_for_target() -> Result<()> {
+        let root = env::temp_dir().join("ripperx4_wait_for_target");
+        fs::create_dir_all(&root)?;
+        let config = Config {
+            encode_path: root.display().to_string(),
+            ..Default::default()
+        };
+        let disc = Disc::default();
+        let (tx, rx) = async_channel::unbounded();
+        let (cancel, stop) = (CancelToken::new(), CancelToken::new());
+        let rip = Rip::new(&disc, &config, &tx, &cancel, &stop);
+        assert!(target_available(&root));
+        // the track failed for another reason
+        assert!(!rip.wait_for_target());
+
+        fs::remove_dir(&root)?;
+        assert!(!target_available(&root));
+        let remount = thread::spawn({
+            let root = root.clone();
+            move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::create_dir(root)
+            }
+        });
+        assert!(rip.wait_for_target());
+        remount.join().map_err(|_| anyhow!("thread panicked"))??;
+        assert!(matches!(rx.try_recv()?, Status::Paused(_)));
+        assert!(matches!(rx.try_recv()?, Status::Resumed));
+
+        // stopping ends the wait
+        fs::remove_dir(&root)?;
+        let stopper = stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            stopper.cancel();
+        });
+        assert!(!rip.wait_for_target());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_actual_bitrate() {
+        // 4 minutes at 256 kbit/s
+        assert_eq!(Some(256), actual_bitrate(7_680_000, 240));
+        assert_eq!(None, actual_bitrate(7_680_000, 0));
+    }
+
+    #[test]
+    pub fn test_checksum_pcm() -> Result<()> {
+        // a bit over 2 seconds, so the last chunk is a partial one
+        let pcm: Vec<u8> = (0..400_003_u32).map(|i| (i * 7 % 251) as u8).collect();
+        let path = env::temp_dir().join("ripperx4_checksum.pcm");
+        fs::write(&path, &pcm)?;
+        let mut inline = Checksum::new(170, true, false);
+        inline.update(&pcm);
+        let mut background = Checksum::new(170, true, false);
+        checksum_pcm(&path, &mut background)?;
+        // the file ends before the 200 sectors of the track
+        assert!(checksum_pcm(&path, &mut Checksum::new(200, true, false)).is_err());
+        remove_file(&path)?;
+        assert_eq!(inline.v1(), background.v1());
+        assert_eq!(inline.v2(), background.v2());
+        assert!(checksum_pcm(&path, &mut background).is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_verified_partial_pcm() -> Result<()> {
+        let pcm = env::temp_dir().join("ripperx4_verified.pcm");
+        let mut job = ReadTrack {
+            index: 0,
+            part: Track {
+                number: 1,
+                sectors: 10,
+                ..Default::default()
+            },
+            location: PathBuf::from("/music/01.flac"),
+            pcm: pcm.clone(),
+            read: SecureRead::default(),
+            verify: true,
+            gain: None,
+            pipeline: None,
+        };
+        let sector = vec![1; 2352];
+        // truncated halfway
+        fs::write(&pcm, sector.repeat(5))?;
+        job.read.checksum = Checksum::new(10, false, false);
+        assert!(job.verified().is_none());
+        fs::write(&pcm, sector.repeat(10))?;
+        job.read.checksum = Checksum::new(10, false, false);
+        let verified = job
+            .verified()
+            .ok_or(anyhow!("the whole track is verified"))?;
+        assert_eq!(1, verified.number);
+        assert_ne!(0, verified.read.checksum.v1());
+        // gone
+        remove_file(&pcm)?;
+        job.read.checksum = Checksum::new(10, false, false);
+        assert!(job.verified().is_none());
+        Ok(())
+    }
 }
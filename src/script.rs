@@ -0,0 +1,53 @@
+/// The writing system (ISO 15924 code) most letters of the text are in, e.g. "Latn" or "Jpan"
+/// Japanese mixes kana with Han characters, any kana makes the text Japanese
+pub fn detect(text: &str) -> Option<&'static str> {
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+    let mut kana = false;
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        let script = script_of(c);
+        kana |= matches!(c, '\u{3040}'..='\u{30ff}');
+        match counts.iter_mut().find(|(s, _)| *s == script) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((script, 1)),
+        }
+    }
+    if kana {
+        return Some("Jpan");
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(script, _)| script)
+}
+
+fn script_of(c: char) -> &'static str {
+    match c {
+        '\u{0000}'..='\u{024f}' | '\u{1e00}'..='\u{1eff}' | '\u{ff21}'..='\u{ff5a}' => "Latn",
+        '\u{0370}'..='\u{03ff}' | '\u{1f00}'..='\u{1fff}' => "Grek",
+        '\u{0400}'..='\u{052f}' => "Cyrl",
+        '\u{0590}'..='\u{05ff}' => "Hebr",
+        '\u{0600}'..='\u{06ff}' | '\u{0750}'..='\u{077f}' => "Arab",
+        '\u{0e00}'..='\u{0e7f}' => "Thai",
+        '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => "Hang",
+        '\u{3040}'..='\u{30ff}' | '\u{ff66}'..='\u{ff9f}' => "Jpan",
+        '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => "Hani",
+        _ => "Zyyy",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::detect;
+
+    #[test]
+    pub fn test_detect() {
+        assert_eq!(Some("Latn"), detect("Sultans of Swing"));
+        assert_eq!(Some("Latn"), detect("Björk: Vespertine"));
+        assert_eq!(Some("Jpan"), detect("宇多田ヒカル"));
+        assert_eq!(Some("Hani"), detect("王菲"));
+        assert_eq!(Some("Cyrl"), detect("Кино - Группа крови"));
+        assert_eq!(Some("Hang"), detect("방탄소년단"));
+        assert_eq!(None, detect("1999 - 2000"));
+        assert_eq!(Some("Cyrl"), detect("Группа крови (Remastered)"));
+    }
+}
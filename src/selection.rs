@@ -0,0 +1,79 @@
+use crate::data::{Config, Disc};
+use confy::ConfyError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The tracks that were deselected per disc id, so a disc inserted again gets the same selection
+/// Stored as `selections.toml` in the config directory
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Selections {
+    pub discs: BTreeMap<String, Vec<u32>>,
+}
+
+impl Selections {
+    pub fn load() -> Self {
+        Config::load_file("selections").unwrap_or_default()
+    }
+
+    pub fn store(&self) -> Result<(), ConfyError> {
+        Config::store_file("selections", self)
+    }
+
+    /// Deselect the tracks that were deselected the last time this disc was in the drive
+    pub fn apply(&self, disc: &mut Disc) {
+        let Some(deselected) = disc.discid.as_ref().and_then(|id| self.discs.get(id)) else {
+            return;
+        };
+        for track in &mut disc.tracks {
+            track.rip = !deselected.contains(&track.number);
+        }
+    }
+
+    /// Remember the current selection of the disc, nothing is kept when all tracks are selected
+    pub fn remember(&mut self, disc: &Disc) {
+        let Some(id) = &disc.discid else {
+            return;
+        };
+        let deselected: Vec<u32> = disc
+            .tracks
+            .iter()
+            .filter(|t| !t.rip)
+            .map(|t| t.number)
+            .collect();
+        if deselected.is_empty() {
+            self.discs.remove(id);
+        } else {
+            self.discs.insert(id.clone(), deselected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Selections;
+    use crate::data::Disc;
+
+    #[test]
+    pub fn test_remember_and_apply() {
+        let mut disc = Disc::with_tracks(3);
+        disc.discid = Some("xA3p59dQpJpDXZYHz1SSQ491oaU-".to_owned());
+        disc.tracks[1].rip = false;
+        let mut selections = Selections::default();
+        selections.remember(&disc);
+        assert_eq!(
+            Some(&vec![2]),
+            selections.discs.get("xA3p59dQpJpDXZYHz1SSQ491oaU-")
+        );
+
+        let mut inserted_again = Disc::with_tracks(3);
+        inserted_again.discid = disc.discid.clone();
+        selections.apply(&mut inserted_again);
+        let rip: Vec<bool> = inserted_again.tracks.iter().map(|t| t.rip).collect();
+        assert_eq!(vec![true, false, true], rip);
+
+        disc.tracks[1].rip = true;
+        selections.remember(&disc);
+        assert!(selections.discs.is_empty());
+    }
+}
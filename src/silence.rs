@@ -0,0 +1,169 @@
+use crate::{
+    data::{Config, Segment, Track},
+    player::loudest_db,
+    ripper::make_source,
+};
+use anyhow::{anyhow, Result};
+use gstreamer::{prelude::*, ClockTime, Element, ElementFactory, MessageView, Pipeline, State};
+
+/// Audio below -60 dBFS RMS counts as silence, the noise floor of CD audio is well below
+const SILENCE_DB: f64 = -60.0;
+/// A pause in the music is shorter, the silence before a hidden track usually runs for minutes
+const MIN_SILENCE: u64 = 20;
+/// How often the `level` element measures
+const LEVEL_INTERVAL: ClockTime = ClockTime::from_mseconds(500);
+/// Title of the part after the silence
+const HIDDEN_TITLE: &str = "Hidden track";
+
+/// A silence in a track, in seconds from its start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Silence {
+    /// where the music stops, rounded up
+    pub start: u64,
+    /// where the hidden track starts, rounded down
+    pub end: u64,
+}
+
+/// Read the track from the disc and find the silence before a hidden track in it
+/// `None` when the track has no long silence with music after it
+pub fn find_hidden_track(track: &Track, config: &Config) -> Result<Option<Silence>> {
+    let levels = measure(make_source(track, config)?)?;
+    Ok(hidden_track(&levels))
+}
+
+/// The segments that split the track at the silence, the silence itself isn't ripped
+pub fn hidden_track_segments(title: &str, silence: Silence) -> Vec<Segment> {
+    vec![
+        Segment {
+            title: title.to_owned(),
+            start: 0,
+            end: Some(silence.start),
+        },
+        Segment {
+            title: HIDDEN_TITLE.to_owned(),
+            start: silence.end,
+            end: None,
+        },
+    ]
+}
+
+/// The start of each interval the `level` element measured and the RMS of its loudest channel
+fn measure(source: Element) -> Result<Vec<(ClockTime, f64)>> {
+    let convert = ElementFactory::make("audioconvert").build()?;
+    let level = ElementFactory::make("level")
+        .property("interval", LEVEL_INTERVAL.nseconds())
+        .build()?;
+    let sink = ElementFactory::make("fakesink")
+        .property("sync", false)
+        .build()?;
+    let elements = [&source, &convert, &level, &sink];
+    let pipeline = Pipeline::new();
+    pipeline.add_many(elements)?;
+    Element::link_many(elements)?;
+    let levels = collect_levels(&pipeline);
+    pipeline.set_state(State::Null)?;
+    levels
+}
+
+fn collect_levels(pipeline: &Pipeline) -> Result<Vec<(ClockTime, f64)>> {
+    let bus = pipeline.bus().ok_or(anyhow!("no bus".to_owned()))?;
+    pipeline.set_state(State::Playing)?;
+    let mut levels = Vec::new();
+    for msg in bus.iter_timed(ClockTime::NONE) {
+        match msg.view() {
+            MessageView::Element(element) => {
+                let Some(structure) = element.structure() else {
+                    continue;
+                };
+                if let (Some(rms), Ok(timestamp)) = (
+                    loudest_db(structure, "rms"),
+                    structure.get::<u64>("timestamp"),
+                ) {
+                    levels.push((ClockTime::from_nseconds(timestamp), rms));
+                }
+            }
+            MessageView::Eos(..) => return Ok(levels),
+            MessageView::Error(err) => {
+                return Err(anyhow!("Reading the track failed: {}", err.error()));
+            }
+            _ => (),
+        }
+    }
+    Ok(levels)
+}
+
+/// The longest silence of at least `MIN_SILENCE` with music after it
+fn hidden_track(levels: &[(ClockTime, f64)]) -> Option<Silence> {
+    let mut longest: Option<(ClockTime, ClockTime)> = None;
+    let mut quiet_since = None;
+    for (time, rms) in levels {
+        match (*rms < SILENCE_DB, quiet_since) {
+            (true, None) => quiet_since = Some(*time),
+            (false, Some(start)) => {
+                if longest.is_none_or(|(s, e)| *time - start > e - s) {
+                    longest = Some((start, *time));
+                }
+                quiet_since = None;
+            }
+            _ => (),
+        }
+    }
+    // silence at the very start, e.g. a fade in, isn't before a hidden track
+    let (start, end) = longest.filter(|(start, _)| *start > ClockTime::ZERO)?;
+    let silence = Silence {
+        start: start.nseconds().div_ceil(ClockTime::SECOND.nseconds()),
+        end: end.seconds(),
+    };
+    (silence.end >= silence.start + MIN_SILENCE).then_some(silence)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hidden_track, hidden_track_segments, Silence};
+    use crate::data::Segment;
+    use gstreamer::ClockTime;
+
+    /// Half second levels: the song, the silence, then the hidden track
+    fn levels(song: u64, silence: u64, hidden: u64) -> Vec<(ClockTime, f64)> {
+        (0..2 * (song + silence + hidden))
+            .map(|half| {
+                let rms = if (2 * song..2 * (song + silence)).contains(&half) {
+                    -90.0
+                } else {
+                    -18.0
+                };
+                (ClockTime::from_mseconds(half * 500), rms)
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_hidden_track() {
+        let silence = Silence {
+            start: 240,
+            end: 840,
+        };
+        // 4 minutes of song, 10 minutes of silence, the hidden track
+        assert_eq!(Some(silence), hidden_track(&levels(240, 600, 180)));
+        // a pause in the music
+        assert_eq!(None, hidden_track(&levels(240, 5, 180)));
+        // silence up to the end of the track, nothing is hidden after it
+        assert_eq!(None, hidden_track(&levels(240, 600, 0)));
+        assert_eq!(None, hidden_track(&[]));
+        assert_eq!(
+            vec![
+                Segment {
+                    title: "Outro".to_owned(),
+                    start: 0,
+                    end: Some(240),
+                },
+                Segment {
+                    title: "Hidden track".to_owned(),
+                    start: 840,
+                    end: None,
+                },
+            ],
+            hidden_track_segments("Outro", silence)
+        );
+    }
+}
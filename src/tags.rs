@@ -0,0 +1,378 @@
+use crate::{
+    coverart::image_extension,
+    data::{Disc, Track},
+    duration::sectors_to_time,
+};
+use anyhow::{anyhow, Result};
+use gstreamer::{
+    glib,
+    tags::{
+        Album, AlbumArtist, AlbumArtistSortname, AlbumSortname, AlbumVolumeCount,
+        AlbumVolumeNumber, Artist, ArtistSortname, Comment, Composer, Date, DateTime, Duration,
+        ExtendedComment, Genre, Image, Title, TitleSortname, TrackCount, TrackNumber,
+    },
+    Buffer, Caps, ClockTime, Sample, Structure, TagList, TagMergeMode,
+};
+use log::{debug, warn};
+
+/// Build the complete set of tags written to the file of a `Track`
+/// With `full_date` the release date is a `DateTime` with the precision that is known, otherwise a
+/// `Date` for the tag writers that only take that
+pub fn track_tags(disc: &Disc, track: &Track, full_date: bool) -> Result<TagList> {
+    let mut tags = TagList::new();
+    {
+        let tags = tags
+            .get_mut()
+            .ok_or(anyhow!("can not get mut".to_owned()))?;
+        tags.add::<Title>(&track.title.as_str(), TagMergeMode::ReplaceAll);
+        tags.add::<Artist>(&track.artist.as_str(), TagMergeMode::ReplaceAll);
+        tags.add::<TrackNumber>(&track.number, TagMergeMode::ReplaceAll);
+        let count = u32::try_from(disc.tracks.len())?;
+        tags.add::<TrackCount>(&count, TagMergeMode::ReplaceAll);
+        tags.add::<Album>(&disc.title.as_str(), TagMergeMode::ReplaceAll);
+        if let (Some(number), Some(count)) = (disc.disc_number, disc.disc_count) {
+            tags.add::<AlbumVolumeNumber>(&number, TagMergeMode::ReplaceAll);
+            tags.add::<AlbumVolumeCount>(&count, TagMergeMode::ReplaceAll);
+        }
+        if !disc.artist.is_empty() {
+            tags.add::<AlbumArtist>(&disc.artist.as_str(), TagMergeMode::ReplaceAll);
+        }
+        // discs in other scripts keep the original names, the Latin ones go to the sort tags
+        // (ARTISTSORT and the like, TSOP/TSOA/TSOT frames in ID3v2)
+        if let Some(title) = &track.latin_title {
+            tags.add::<TitleSortname>(&title.as_str(), TagMergeMode::ReplaceAll);
+        }
+        if let Some(artist) = &track.latin_artist {
+            tags.add::<ArtistSortname>(&artist.as_str(), TagMergeMode::ReplaceAll);
+        }
+        if let Some(album) = &disc.latin_title {
+            tags.add::<AlbumSortname>(&album.as_str(), TagMergeMode::ReplaceAll);
+        }
+        if let Some(artist) = disc
+            .latin_artist
+            .as_ref()
+            .filter(|_| !disc.artist.is_empty())
+        {
+            tags.add::<AlbumArtistSortname>(&artist.as_str(), TagMergeMode::ReplaceAll);
+        }
+        if let Some(date) = release_date(disc) {
+            if full_date {
+                tags.add::<DateTime>(&date, TagMergeMode::ReplaceAll);
+            } else {
+                tags.add::<Date>(&calendar_date(&date)?, TagMergeMode::ReplaceAll);
+            }
+        }
+        if let Some(genre) = &disc.genre {
+            tags.add::<Genre>(&genre.as_str(), TagMergeMode::ReplaceAll);
+        }
+        // exact from the TOC, split tracks only have the seconds
+        if track.sectors > 0 && track.segments.is_empty() {
+            tags.add::<Duration>(&sectors_to_time(track.sectors), TagMergeMode::ReplaceAll);
+        } else if track.duration > 0 {
+            tags.add::<Duration>(
+                &(ClockTime::SECOND * track.duration),
+                TagMergeMode::ReplaceAll,
+            );
+        }
+        if let Some(composer) = &track.composer {
+            tags.add::<Composer>(&composer.as_str(), TagMergeMode::ReplaceAll);
+        }
+        // there are no standard GStreamer tags for these, muxers write extended comments as
+        // KEY=value vorbis comments (Ogg, Opus, FLAC) or id3 comment frames
+        let comments = [
+            ("WORK", &track.work),
+            ("MOVEMENTNAME", &track.movement),
+            ("MUSICBRAINZ_ALBUMID", &disc.release_id),
+            ("MUSICBRAINZ_RELEASEGROUPID", &disc.release_group_id),
+            ("MUSICBRAINZ_TRACKID", &track.recording_id),
+            ("BARCODE", &disc.barcode),
+            ("CATALOGNUMBER", &disc.catalog_number),
+        ];
+        for (key, value) in comments {
+            if let Some(value) = value {
+                let comment = format!("{key}={value}");
+                tags.add::<ExtendedComment>(&comment.as_str(), TagMergeMode::Append);
+            }
+        }
+        let notes: Vec<&str> = [&disc.comment, &track.comment]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !notes.is_empty() {
+            tags.add::<Comment>(&notes.join("; ").as_str(), TagMergeMode::ReplaceAll);
+        }
+        // written as an APIC frame by id3v2mux, as METADATA_BLOCK_PICTURE by the Ogg
+        // encoders and as a PICTURE block by flacenc
+        if let Some(cover) = &disc.cover {
+            match cover_sample(cover) {
+                Ok(sample) => tags.add::<Image>(&sample, TagMergeMode::ReplaceAll),
+                Err(e) => warn!("Cover not embedded: {e}"),
+            }
+        }
+    }
+    Ok(tags)
+}
+
+/// The tags of a disc image file, titled after the album
+pub fn image_tags(disc: &Disc) -> Result<TagList> {
+    let album = Track {
+        title: disc.title.clone(),
+        artist: disc.artist.clone(),
+        latin_title: disc.latin_title.clone(),
+        latin_artist: disc.latin_artist.clone(),
+        ..Default::default()
+    };
+    let mut tags = track_tags(disc, &album, true)?;
+    let tags_mut = tags
+        .get_mut()
+        .ok_or(anyhow!("can not get mut".to_owned()))?;
+    tags_mut.remove::<TrackNumber>();
+    tags_mut.remove::<TrackCount>();
+    Ok(tags)
+}
+
+/// Add a chapter per track as Vorbis comments, `CHAPTER001=00:00:00.000` and
+/// `CHAPTER001NAME=<title>`, the start of a chapter is the length of the tracks before it
+pub fn add_chapters(tags: &mut TagList, tracks: &[&Track]) -> Result<()> {
+    let tags = tags
+        .get_mut()
+        .ok_or(anyhow!("can not get mut".to_owned()))?;
+    let mut start = 0;
+    for (i, track) in tracks.iter().enumerate() {
+        let ms = sectors_to_time(start).mseconds();
+        let time = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        );
+        let chapter = format!("CHAPTER{:03}", i + 1);
+        tags.add::<ExtendedComment>(&format!("{chapter}={time}").as_str(), TagMergeMode::Append);
+        tags.add::<ExtendedComment>(
+            &format!("{chapter}NAME={}", track.title).as_str(),
+            TagMergeMode::Append,
+        );
+        start += track.sectors;
+    }
+    Ok(())
+}
+
+/// The release date of the disc, as precise as `Disc::date` has it, or else the year
+fn release_date(disc: &Disc) -> Option<gstreamer::DateTime> {
+    let date = disc.date.as_deref().unwrap_or_default();
+    let mut parts = date.split('-').map(str::parse::<i32>);
+    let date = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) => {
+            gstreamer::DateTime::from_ymd(year, month, day)
+        }
+        (Some(Ok(year)), Some(Ok(month)), None) => gstreamer::DateTime::from_ym(year, month),
+        (Some(Ok(year)), None, None) => gstreamer::DateTime::from_y(year),
+        _ => gstreamer::DateTime::from_y(disc.year?.into()),
+    };
+    date.ok()
+}
+
+/// The date as a calendar day, the first of the year or month when they are all that is known
+fn calendar_date(date: &gstreamer::DateTime) -> Result<glib::Date> {
+    let year = u16::try_from(date.year())?;
+    let mut day = glib::Date::from_dmy(1, glib::DateMonth::January, year)?;
+    day.add_months(u32::try_from(date.month().unwrap_or(1) - 1)?)?;
+    day.add_days(u32::try_from(date.day().unwrap_or(1) - 1)?)?;
+    Ok(day)
+}
+
+/// Wrap the cover in a sample with the caps the muxers need to tell JPEG from PNG
+fn cover_sample(image: &[u8]) -> Result<Sample> {
+    let mime = match image_extension(image) {
+        Some("png") => "image/png",
+        Some(_) => "image/jpeg",
+        None => return Err(anyhow!("cover is not a JPEG or PNG")),
+    };
+    let buffer = Buffer::from_slice(image.to_vec());
+    let caps = Caps::builder(mime).build();
+    let mut sample = Sample::builder().buffer(&buffer).caps(&caps);
+    // the image type enum is registered by the tag library, which the muxers load, without it
+    // the picture is written as "other"
+    match "GstTagImageInfo, image-type=(GstTagImageType)front-cover".parse::<Structure>() {
+        Ok(info) => sample = sample.info(info),
+        Err(_) => debug!("GstTagImageType is not registered, cover type not set"),
+    }
+    Ok(sample.build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{add_chapters, image_tags, track_tags};
+    use crate::data::Disc;
+    use anyhow::Result;
+    use gstreamer::{
+        glib,
+        tags::{
+            Album, AlbumArtist, AlbumSortname, AlbumVolumeCount, AlbumVolumeNumber, Artist,
+            ArtistSortname, Comment, Composer, Date, DateTime, Duration, ExtendedComment, Genre,
+            Image, Title, TitleSortname, TrackCount,
+        },
+        ClockTime, TagList,
+    };
+
+    #[test]
+    pub fn test_track_tags() -> Result<()> {
+        gstreamer::init()?;
+        let mut disc = Disc::with_tracks(3);
+        disc.title = "Album".to_owned();
+        disc.artist = "Band".to_owned();
+        disc.year = Some(1988);
+        disc.genre = Some("Rock".to_owned());
+        disc.release_id = Some("a541c6e6".to_owned());
+        disc.disc_number = Some(2);
+        disc.disc_count = Some(3);
+        disc.tracks[1].title = "Song".to_owned();
+        disc.tracks[1].duration = 300;
+        disc.tracks[1].composer = Some("Writer".to_owned());
+        disc.tracks[1].work = Some("Suite".to_owned());
+        disc.tracks[1].movement = Some("Prelude".to_owned());
+        disc.tracks[1].recording_id = Some("5e26f77d".to_owned());
+        disc.comment = Some("scratched library copy".to_owned());
+        disc.tracks[1].comment = Some("skips at 2:10".to_owned());
+
+        let tags = track_tags(&disc, &disc.tracks[1], false)?;
+        assert_eq!("Song", tags.get::<Title>().expect("title").get());
+        assert_eq!(3, tags.get::<TrackCount>().expect("count").get());
+        assert_eq!("Album", tags.get::<Album>().expect("album").get());
+        assert_eq!("Band", tags.get::<AlbumArtist>().expect("artist").get());
+        assert_eq!("Rock", tags.get::<Genre>().expect("genre").get());
+        assert_eq!("Writer", tags.get::<Composer>().expect("composer").get());
+        assert_eq!(1988, tags.get::<Date>().expect("date").get().year());
+        assert!(tags.get::<DateTime>().is_none());
+        assert_eq!(2, tags.get::<AlbumVolumeNumber>().expect("disc").get());
+        assert_eq!(3, tags.get::<AlbumVolumeCount>().expect("discs").get());
+        assert_eq!(
+            "scratched library copy; skips at 2:10",
+            tags.get::<Comment>().expect("comment").get()
+        );
+        let comments: Vec<&str> = tags
+            .iter_tag::<ExtendedComment>()
+            .map(|c| c.get())
+            .collect();
+        assert_eq!(
+            vec![
+                "WORK=Suite",
+                "MOVEMENTNAME=Prelude",
+                "MUSICBRAINZ_ALBUMID=a541c6e6",
+                "MUSICBRAINZ_TRACKID=5e26f77d"
+            ],
+            comments
+        );
+        assert_eq!(
+            ClockTime::from_seconds(300),
+            tags.get::<Duration>().expect("duration").get()
+        );
+
+        // the date keeps the precision it has
+        let tags = track_tags(&disc, &disc.tracks[1], true)?;
+        let date = tags.get::<DateTime>().expect("date time");
+        assert_eq!("1988", date.get().to_iso8601_string()?);
+        assert!(tags.get::<Date>().is_none());
+        disc.date = Some("1988-11-30".to_owned());
+        let tags = track_tags(&disc, &disc.tracks[1], true)?;
+        let date = tags.get::<DateTime>().expect("date time");
+        assert_eq!("1988-11-30", date.get().to_iso8601_string()?);
+        let tags = track_tags(&disc, &disc.tracks[1], false)?;
+        let date = tags.get::<Date>().expect("date");
+        assert_eq!(30, date.get().day());
+        assert_eq!(glib::DateMonth::November, date.get().month());
+
+        let tags = track_tags(&disc, &disc.tracks[0], true)?;
+        assert_eq!(
+            "scratched library copy",
+            tags.get::<Comment>().expect("comment").get()
+        );
+
+        // unknown values are left out
+        disc.release_id = None;
+        disc.comment = None;
+        let tags = track_tags(&disc, &disc.tracks[0], true)?;
+        assert!(tags.get::<Comment>().is_none());
+        assert!(tags.get::<Composer>().is_none());
+        assert!(tags.get::<Duration>().is_none());
+        assert!(tags.get::<ExtendedComment>().is_none());
+        assert!(tags.get::<Image>().is_none());
+        assert!(tags.get::<ArtistSortname>().is_none());
+
+        let png = b"\x89PNG\r\n\x1a\n".to_vec();
+        disc.cover = Some(png.clone());
+        let tags = track_tags(&disc, &disc.tracks[0], true)?;
+        let image = tags.get::<Image>().expect("image");
+        let image = image.get();
+        let caps = image.caps().expect("caps");
+        assert_eq!("image/png", caps.structure(0).expect("structure").name());
+        let buffer = image.buffer().expect("buffer").map_readable()?;
+        assert_eq!(png, buffer.as_slice());
+
+        disc.cover = Some(b"GIF89a".to_vec());
+        let tags = track_tags(&disc, &disc.tracks[0], true)?;
+        assert!(tags.get::<Image>().is_none());
+
+        let tags = image_tags(&disc)?;
+        assert_eq!("Album", tags.get::<Title>().expect("title").get());
+        assert!(tags.get::<TrackCount>().is_none());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_chapters() -> Result<()> {
+        gstreamer::init()?;
+        let mut disc = Disc::with_tracks(3);
+        disc.tracks[0].title = "Chapter One".to_owned();
+        disc.tracks[0].sectors = 26_000;
+        disc.tracks[1].title = "Chapter Two".to_owned();
+        disc.tracks[1].sectors = 300_000;
+        disc.tracks[2].title = "Epilogue".to_owned();
+        let tracks: Vec<_> = disc.tracks.iter().collect();
+        let mut tags = TagList::new();
+        add_chapters(&mut tags, &tracks)?;
+        let comments: Vec<_> = tags
+            .iter_tag::<ExtendedComment>()
+            .map(|c| c.get())
+            .collect();
+        assert_eq!(
+            vec![
+                "CHAPTER001=00:00:00.000",
+                "CHAPTER001NAME=Chapter One",
+                "CHAPTER002=00:05:46.666",
+                "CHAPTER002NAME=Chapter Two",
+                "CHAPTER003=01:12:26.666",
+                "CHAPTER003NAME=Epilogue",
+            ],
+            comments
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_latin_sort_tags() -> Result<()> {
+        gstreamer::init()?;
+        let mut disc = Disc::with_tracks(1);
+        disc.title = "初恋".to_owned();
+        disc.artist = "宇多田ヒカル".to_owned();
+        disc.latin_title = Some("Hatsukoi".to_owned());
+        disc.latin_artist = Some("Utada Hikaru".to_owned());
+        disc.tracks[0].title = "誓い".to_owned();
+        disc.tracks[0].artist = "宇多田ヒカル".to_owned();
+        disc.tracks[0].latin_title = Some("Chikai".to_owned());
+        disc.tracks[0].latin_artist = Some("Utada Hikaru".to_owned());
+        let tags = track_tags(&disc, &disc.tracks[0], true)?;
+        assert_eq!("誓い", tags.get::<Title>().expect("title").get());
+        assert_eq!("宇多田ヒカル", tags.get::<Artist>().expect("artist").get());
+        assert_eq!("Chikai", tags.get::<TitleSortname>().expect("title").get());
+        let artist = tags.get::<ArtistSortname>().expect("artist");
+        assert_eq!("Utada Hikaru", artist.get());
+        assert_eq!(
+            "Hatsukoi",
+            tags.get::<AlbumSortname>().expect("album").get()
+        );
+        Ok(())
+    }
+}
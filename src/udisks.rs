@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Result};
+use gtk::{
+    gio::{self, BusType, Cancellable, DBusCallFlags, DBusConnection},
+    glib::{Variant, VariantTy},
+    prelude::*,
+};
+use log::debug;
+use std::{collections::HashMap, fs};
+
+const UDISKS: &str = "org.freedesktop.UDisks2";
+const DRIVE_INTERFACE: &str = "org.freedesktop.UDisks2.Drive";
+const BLOCK_INTERFACE: &str = "org.freedesktop.UDisks2.Block";
+const TIMEOUT_MS: i32 = 5000;
+
+/// An optical drive as udisks2 lists it
+struct OpticalDrive {
+    /// object path of the drive on the bus
+    object: String,
+    /// the block device, e.g. "/dev/sr0", then its links, e.g. "/dev/cdrom"
+    devices: Vec<String>,
+    /// whether there is a disc in the drive
+    media: bool,
+}
+
+/// The block devices of the optical drives, e.g. `/dev/sr0` and `/dev/sr1`
+/// Works without access to sysfs or the devices, e.g. inside a Flatpak sandbox
+pub fn cd_devices() -> Result<Vec<String>> {
+    let bus = gio::bus_get_sync(BusType::System, Cancellable::NONE)?;
+    let mut devices: Vec<String> = optical_drives(&bus)?
+        .into_iter()
+        .filter_map(|drive| drive.devices.into_iter().next())
+        .collect();
+    devices.sort();
+    Ok(devices)
+}
+
+/// Whether there is a disc in the drive of `device`, without reading it
+pub fn has_media(device: &str) -> Result<bool> {
+    let bus = gio::bus_get_sync(BusType::System, Cancellable::NONE)?;
+    Ok(drive_of(&bus, device)?.media)
+}
+
+/// Eject the disc from the drive of `device`, e.g. "/dev/sr1", through udisks2
+/// This works without access to the raw device, e.g. inside a Flatpak sandbox
+pub fn eject_disc(device: &str) -> Result<()> {
+    let bus = gio::bus_get_sync(BusType::System, Cancellable::NONE)?;
+    let drive = drive_of(&bus, device)?;
+    if !drive.media {
+        return Err(anyhow!("there is no disc in {device}"));
+    }
+    debug!("ejecting {}", drive.object);
+    let options = HashMap::<String, Variant>::new().to_variant();
+    bus.call_sync(
+        Some(UDISKS),
+        &drive.object,
+        DRIVE_INTERFACE,
+        "Eject",
+        Some(&Variant::tuple_from_iter([options])),
+        None,
+        DBusCallFlags::NONE,
+        TIMEOUT_MS,
+        Cancellable::NONE,
+    )?;
+    Ok(())
+}
+
+/// The optical drive of `device`, matched by the name of its block device or one of its links
+fn drive_of(bus: &DBusConnection, device: &str) -> Result<OpticalDrive> {
+    let canonical = fs::canonicalize(device)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| device.to_owned());
+    optical_drives(bus)?
+        .into_iter()
+        .find(|drive| {
+            drive
+                .devices
+                .iter()
+                .any(|name| *name == device || *name == canonical)
+        })
+        .ok_or(anyhow!("{device} is no optical drive"))
+}
+
+/// The optical drives with their block devices
+fn optical_drives(bus: &DBusConnection) -> Result<Vec<OpticalDrive>> {
+    let reply = bus.call_sync(
+        Some(UDISKS),
+        "/org/freedesktop/UDisks2",
+        "org.freedesktop.DBus.ObjectManager",
+        "GetManagedObjects",
+        None,
+        Some(VariantTy::new("(a{oa{sa{sv}}})")?),
+        DBusCallFlags::NONE,
+        TIMEOUT_MS,
+        Cancellable::NONE,
+    )?;
+    let mut drives = Vec::new();
+    // drive object path -> names of its block device
+    let mut blocks: HashMap<String, Vec<String>> = HashMap::new();
+    // a{oa{sa{sv}}}: object path -> interface -> property -> value
+    for object in reply.child_value(0).iter() {
+        let Some(path) = object.child_value(0).str().map(str::to_owned) else {
+            continue;
+        };
+        for interface in object.child_value(1).iter() {
+            let properties: HashMap<String, Variant> =
+                interface.child_value(1).get().unwrap_or_default();
+            match interface.child_value(0).str() {
+                Some(DRIVE_INTERFACE) => {
+                    let flag = |name: &str| {
+                        properties
+                            .get(name)
+                            .and_then(Variant::get::<bool>)
+                            .unwrap_or_default()
+                    };
+                    // `Optical` is only set while there is a disc in the drive
+                    if flag("Optical") || is_optical(&properties) {
+                        drives.push(OpticalDrive {
+                            object: path.clone(),
+                            devices: Vec::new(),
+                            media: flag("MediaAvailable"),
+                        });
+                    }
+                }
+                Some(BLOCK_INTERFACE) => {
+                    let Some(drive) = properties.get("Drive").and_then(|d| d.str()) else {
+                        continue;
+                    };
+                    // byte strings with a trailing NUL, the device before its links
+                    let mut names: Vec<Vec<u8>> = properties
+                        .get("Device")
+                        .and_then(Variant::get::<Vec<u8>>)
+                        .into_iter()
+                        .collect();
+                    names.extend(
+                        properties
+                            .get("Symlinks")
+                            .and_then(Variant::get::<Vec<Vec<u8>>>)
+                            .unwrap_or_default(),
+                    );
+                    blocks
+                        .entry(drive.to_owned())
+                        .or_default()
+                        .extend(names.iter().map(|name| {
+                            String::from_utf8_lossy(name)
+                                .trim_end_matches('\0')
+                                .to_owned()
+                        }));
+                }
+                _ => (),
+            }
+        }
+    }
+    for drive in &mut drives {
+        drive.devices = blocks.remove(&drive.object).unwrap_or_default();
+    }
+    Ok(drives)
+}
+
+/// Whether the drive takes CDs, after the media it's compatible with, e.g. "optical_cd"
+fn is_optical(properties: &HashMap<String, Variant>) -> bool {
+    properties
+        .get("MediaCompatibility")
+        .and_then(Variant::get::<Vec<String>>)
+        .unwrap_or_default()
+        .iter()
+        .any(|media| media.starts_with("optical_cd"))
+}
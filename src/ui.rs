@@ -1,25 +1,234 @@
 use crate::{
-    data::{Config, Data, Encoder, Quality},
-    ripper::extract,
-    util::{lookup_disc, scan_disc},
+    cache::{self, MetadataCache},
+    cancel::CancelToken,
+    config,
+    controller::{prepare_config, start_rip, RipEvent, RipState, STATUS_CAPACITY},
+    data::{Config, Data, Disc, DiscSettings, Encoded, Quality, Status, TrackState},
+    dbus::StatusExporter,
+    encoder::{backend, backends, EncoderBackend},
+    gsettings,
+    launcher::{open_folder, reveal_file},
+    library::{
+        export_csv, find_duplicates, flac_archives, plan_reorganize, reorganize, statistics,
+    },
+    musicbrainz::{self, Release},
+    player::{is_clipping, meter_value, Player},
+    preferences::{settings, Editor, Page, Setting},
+    profile,
+    ripper::{
+        available_backends, describe_missing, estimate_size, missing_elements, risky_overwrites,
+        transcode, Overwrite,
+    },
+    selection::Selections,
+    silence::{find_hidden_track, hidden_track_segments},
+    util::{
+        cd_devices, disc_from_lookup, disc_present, format_segments, lookup_releases, parse_cue,
+        parse_segments, parse_track_list, scan_disc, scan_hook, toc_warnings, Found, Toc,
+    },
 };
+use anyhow::Result;
+use discid::DiscId;
 use glib::Type;
 use gtk::{
-    prelude::*, Align, Application, ApplicationWindow, Box, Builder, Button, ButtonsType, Dialog,
-    DropDown, Frame, ListStore, MessageDialog, MessageType, Orientation, Separator, Statusbar,
-    TextView, TreeView,
+    gdk::{self, Texture},
+    gio,
+    prelude::*,
+    Align, Application, ApplicationWindow, Box, Builder, Button, ButtonsType, CheckButton, Dialog,
+    DropDown, Entry, FileChooserAction, FileChooserNative, FileFilter, Frame, Grid, Label,
+    LevelBar, ListBox, ListStore, MessageDialog, MessageType, Orientation, Picture, ProgressBar,
+    ResponseType, Revealer, ScrolledWindow, Stack, StackSidebar, TextView, ToggleButton, TreeView,
 };
-use log::debug;
+use log::{debug, error};
 use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    path::Path,
+    rc::Rc,
     sync::{Arc, RwLock},
     thread,
+    time::Duration,
 };
 
-pub fn build(app: &Application) {
+/// Hidden column of the track list holding the index of the row's track in `Disc::tracks`
+/// Rows must never be mapped to tracks by their displayed number
+const TRACK_ID_COLUMN: u32 = 5;
+/// Columns of the track list with the rip progress of the row's track, in percent and as text
+const PROGRESS_COLUMN: u32 = 6;
+const PROGRESS_TEXT_COLUMN: u32 = 7;
+/// Column of the track list with the format of the row's track, `DEFAULT_FORMAT` for the
+/// configured one
+const FORMAT_COLUMN: u32 = 8;
+/// Column of the track list with the user's note on the row's track
+const COMMENT_COLUMN: u32 = 9;
+const DEFAULT_FORMAT: &str = "default";
+/// How long a toast stays on the window
+const TOAST_SECONDS: u32 = 4;
+/// How often the Auto mode checks the drive for the next disc
+const AUTO_POLL_SECONDS: u32 = 5;
+/// How long the metadata cache waits for the next keystroke before it's written
+const REMEMBER_DELAY: Duration = Duration::from_millis(800);
+
+/// What the lookup of the scanned disc found
+/// Only sent once from the worker thread, the size of the disc doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Lookup {
+    /// the metadata, completed for the disc
+    Found(Completed),
+    /// MusicBrainz knows several releases with the disc id, the user picks one
+    Releases(Toc, Vec<Release>),
+}
+
+/// The metadata of a disc completed by `disc_from_lookup`, with why none was found
+type Completed = Result<(Disc, Option<String>)>;
+
+/// The rip state shared by the Go, Stop, Scan and Retry buttons, they are only sensitive in the
+/// states they apply to
+#[derive(Clone)]
+struct RipControl {
+    state: Rc<Cell<RipState>>,
+    builder: Builder,
+    data: Arc<RwLock<Data>>,
+    /// the Auto button is down: discs are scanned, ripped and ejected without asking
+    auto: Rc<Cell<bool>>,
+    /// id of the last disc the Auto mode scanned, so it isn't ripped again when it stays in
+    /// the drive
+    auto_disc: Rc<RefCell<Option<String>>>,
+}
+
+impl RipControl {
+    fn new(builder: &Builder, data: Arc<RwLock<Data>>) -> Self {
+        RipControl {
+            state: Rc::default(),
+            builder: builder.clone(),
+            data,
+            auto: Rc::default(),
+            auto_disc: Rc::default(),
+        }
+    }
+
+    fn is_auto(&self) -> bool {
+        self.auto.get()
+    }
+
+    /// Leave the Auto mode, e.g. for a disc that needs the user
+    fn stop_auto(&self) {
+        let button: ToggleButton = self
+            .builder
+            .object("auto_button")
+            .expect("Failed to get widget");
+        button.set_active(false);
+    }
+
+    /// Move on to the state after the event, false when the event doesn't apply to the current
+    /// state and must be ignored
+    fn handle(&self, event: RipEvent) -> bool {
+        let Some(state) = self.state.get().next(event) else {
+            debug!("{event:?} ignored while {:?}", self.state.get());
+            return false;
+        };
+        self.state.set(state);
+        self.update_buttons();
+        true
+    }
+
+    /// Make the buttons sensitive for the current state, Go and Retry also need a disc
+    fn update_buttons(&self) {
+        let state = self.state.get();
+        let idle = state == RipState::Idle;
+        let has_disc = self.data.read().is_ok_and(|d| d.disc.is_some());
+        let button =
+            |id: &str| -> Button { self.builder.object(id).expect("Failed to get widget") };
+        button("go_button").set_sensitive(idle && has_disc);
+        button("stop_button").set_sensitive(state == RipState::Ripping);
+        button("scan_button").set_sensitive(idle);
+        button("retry_lookup_button").set_sensitive(idle && has_disc);
+        // the tracks can be previewed from the CD, the encoded files also while ripping
+        if idle && has_disc {
+            button("play_button").set_sensitive(true);
+        }
+    }
+}
+
+/// Sort the text column by the collation rules of the user's locale when its header is clicked
+fn sort_collated(store: &ListStore, column: &gtk::TreeViewColumn, index: u32) {
+    store.set_sort_func(gtk::SortColumn::Index(index), move |model, a, b| {
+        let key = |iter| {
+            let text = model
+                .get_value(iter, index as i32)
+                .get::<String>()
+                .unwrap_or_default();
+            glib::CollationKey::from(text)
+        };
+        key(a).cmp(&key(b)).into()
+    });
+    column.set_sort_column_id(index as i32);
+}
+
+/// Look up the track index stored in the row
+fn track_id(model: &impl IsA<gtk::TreeModel>, iter: &gtk::TreeIter) -> usize {
+    model
+        .get_value(iter, TRACK_ID_COLUMN as i32)
+        .get::<u32>()
+        .expect("Failed to get value") as usize
+}
+
+/// The track ids in the order of the rows
+fn row_order(model: &gtk::TreeModel) -> Vec<usize> {
+    let mut order = Vec::new();
+    if let Some(iter) = model.iter_first() {
+        loop {
+            order.push(track_id(model, &iter));
+            if !model.iter_next(&iter) {
+                break;
+            }
+        }
+    }
+    order
+}
+
+/// Move the selected row one up or down, to reorder the tracks without dragging them
+fn move_selected_row(tree: &TreeView, store: &ListStore, up: bool) {
+    let Some((_, iter)) = tree.selection().selected() else {
+        return;
+    };
+    let neighbour = iter;
+    if up {
+        if store.iter_previous(&neighbour) {
+            store.move_before(&iter, Some(&neighbour));
+        }
+    } else if store.iter_next(&neighbour) {
+        store.move_after(&iter, Some(&neighbour));
+    }
+}
+
+/// Raise the existing window, only build the UI the first time the application is activated
+/// `gstreamer_error` is why GStreamer failed to initialize, the window can't work without it
+pub fn activate(app: &Application, gstreamer_error: Option<&str>) {
+    if let Some(window) = app.active_window() {
+        window.present();
+    } else if let Some(e) = gstreamer_error {
+        let dialog = MessageDialog::builder()
+            .title("Error")
+            .modal(true)
+            .buttons(ButtonsType::Close)
+            .message_type(MessageType::Error)
+            .text(format!(
+                "GStreamer could not be initialized, discs can't be ripped:\n\n{e}"
+            ))
+            .application(app)
+            .build();
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.present();
+    } else {
+        build(app);
+    }
+}
+
+fn build(app: &Application) {
     let data = Arc::new(RwLock::new(Data {
         ..Default::default()
     }));
-    let ripping = Arc::new(RwLock::new(false));
+    let cancel = CancelToken::new();
 
     let builder = Builder::new();
     builder
@@ -36,75 +245,609 @@ pub fn build(app: &Application) {
         window.close();
     });
 
-    handle_disc(data.clone(), &builder);
+    let comment_changed = handle_disc(data.clone(), &builder);
+    let banner_close: Button = builder
+        .object("banner_close")
+        .expect("Failed to get widget");
+    let b = builder.clone();
+    banner_close.connect_clicked(move |_| show_banner(&b, None));
 
-    handle_scan(data.clone(), &builder, &window_clone);
+    let control = RipControl::new(&builder, data.clone());
+    control.update_buttons();
+    handle_scan(
+        data.clone(),
+        &builder,
+        &window_clone,
+        control.clone(),
+        comment_changed,
+    );
+    handle_auto(&builder, control.clone());
 
     let config_button: Button = builder
         .object("config_button")
         .expect("Failed to get widget");
-    handle_config(&config_button, &window_clone);
+    let format_dropdown: DropDown = builder
+        .object("format_dropdown")
+        .expect("Failed to get widget");
+    let quality_dropdown: DropDown = builder
+        .object("quality_dropdown")
+        .expect("Failed to get widget");
+    let disc_only: CheckButton = builder
+        .object("disc_only_check")
+        .expect("Failed to get widget");
+    let track_list: TreeView = builder
+        .object("track_listview")
+        .expect("Failed to get widget");
+    let touch_builder = builder.clone();
+    handle_format(data.clone(), &builder, &window_clone);
+    handle_library(data.clone(), &builder);
+    handle_config(
+        &config_button,
+        &window_clone,
+        glib::clone!(@weak format_dropdown, @weak quality_dropdown, @weak disc_only, @weak track_list => move || {
+            // the disc keeps its own format
+            if !disc_only.is_active() {
+                show_format(&format_dropdown, &quality_dropdown);
+            }
+            let config = Config::load().unwrap_or_default();
+            style_track_list(&track_list, &config);
+            show_touch_mode(&touch_builder, config.touch_mode);
+        }),
+    );
 
-    let stop_button: Button = builder.object("stop_button").expect("Failed to get widget");
-    stop_button.set_sensitive(false);
-    handle_stop(ripping.clone(), &builder);
+    let reorganize_button: Button = builder
+        .object("reorganize_button")
+        .expect("Failed to get widget");
+    handle_reorganize(&reorganize_button, &window_clone);
+
+    let export_button: Button = builder
+        .object("export_button")
+        .expect("Failed to get widget");
+    handle_export(&export_button, &window_clone);
+
+    let statistics_button: Button = builder
+        .object("statistics_button")
+        .expect("Failed to get widget");
+    handle_statistics(&statistics_button, &window_clone);
+
+    let transcode_button: Button = builder
+        .object("transcode_button")
+        .expect("Failed to get widget");
+    handle_transcode(&transcode_button, &window_clone);
+
+    handle_stop(cancel.clone(), &builder, control.clone());
+
+    let exporter =
+        app.dbus_connection()
+            .and_then(|connection| match StatusExporter::register(connection) {
+                Ok(exporter) => Some(exporter),
+                Err(e) => {
+                    debug!("Failed to export status on D-Bus: {e}");
+                    None
+                }
+            });
+    // the files of the last rip, for the play button
+    let encoded = Rc::new(RefCell::new(Vec::new()));
+    handle_play(&builder, &data, encoded.clone(), control.clone());
+    handle_reveal(&builder, &data, encoded.clone());
+    handle_go(cancel, data, &builder, exporter, encoded, control);
+
+    // "app.scan" is triggered by the --scan command line option
+    let scan_button: Button = builder.object("scan_button").expect("Failed to get widget");
+    let scan_action = gio::SimpleAction::new("scan", None);
+    scan_action.connect_activate(move |_, _| {
+        if scan_button.is_sensitive() {
+            scan_button.emit_clicked();
+        }
+    });
+    app.add_action(&scan_action);
+
+    let touch_css = gtk::CssProvider::new();
+    touch_css.load_from_resource("/touch.css");
+    gtk::style_context_add_provider_for_display(
+        &WidgetExt::display(&window_clone),
+        &touch_css,
+        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
+    show_touch_mode(&builder, Config::load().unwrap_or_default().touch_mode);
+
+    check_plugins(&window_clone);
+}
+
+/// The buttons that are hidden in touch mode
+const TOUCH_HIDDEN: &[&str] = &[
+    "reorganize_button",
+    "export_button",
+    "statistics_button",
+    "transcode_button",
+    "auto_button",
+];
+/// The rows of the disc that are hidden in touch mode, by a widget in them
+const TOUCH_HIDDEN_ROWS: &[&str] = &["barcode", "disc_comment", "format_dropdown"];
+
+/// Switch between the full window and the one for a touch screen: large buttons, text and
+/// progress, only what's needed to scan, check the disc and rip it
+fn show_touch_mode(builder: &Builder, touch: bool) {
+    let window: ApplicationWindow = builder.object("window").expect("Failed to get widget");
+    if touch {
+        window.add_css_class("touch");
+    } else {
+        window.remove_css_class("touch");
+    }
+    for &id in TOUCH_HIDDEN {
+        let widget: gtk::Widget = builder.object(id).expect("Failed to get widget");
+        widget.set_visible(!touch);
+    }
+    for &id in TOUCH_HIDDEN_ROWS {
+        let widget: gtk::Widget = builder.object(id).expect("Failed to get widget");
+        if let Some(row) = widget.parent() {
+            row.set_visible(!touch);
+        }
+    }
+}
+
+/// Warn about encoders that can't be used because GStreamer plugins are missing
+fn check_plugins(window: &ApplicationWindow) {
+    let missing: Vec<String> = backends()
+        .iter()
+        .filter_map(|backend| {
+            let missing = missing_elements(&backend.encoder());
+            (!missing.is_empty()).then(|| {
+                format!(
+                    "{}: {}",
+                    backend.settings().name,
+                    describe_missing(&missing)
+                )
+            })
+        })
+        .collect();
+    if !missing.is_empty() {
+        let message = format!(
+            "Some GStreamer plugins are missing, encoding to these formats will fail until the packages are installed:\n\n{}",
+            missing.join("\n")
+        );
+        show_message(&message, MessageType::Warning, window);
+    }
+}
+
+/// Show the estimated output size of the selected tracks with the configured encoder, or the one
+/// of the disc
+fn update_size_estimate(data: &Arc<RwLock<Data>>, label: &Label) {
+    let Ok(data) = data.read() else {
+        return;
+    };
+    if let Some(disc) = data.disc.as_ref() {
+        let mut config: Config = Config::load().unwrap_or_default();
+        if let Some(settings) = &data.disc_settings {
+            settings.apply(&mut config);
+        }
+        let bytes = estimate_size(disc, &config.encoder, &config.quality);
+        label.set_text(&format!(
+            "≈ {:.0} MB {:?}",
+            bytes as f64 / 1_000_000.0,
+            config.encoder
+        ));
+    } else {
+        label.set_text("");
+    }
+}
+
+/// Format and quality selectors on the main window, changes are saved to the config right away
+/// With "This disc only" they, and the folder picked, only apply to the scanned disc
+fn handle_format(data: Arc<RwLock<Data>>, builder: &Builder, window: &ApplicationWindow) {
+    let format: DropDown = builder
+        .object("format_dropdown")
+        .expect("Failed to get widget");
+    let quality: DropDown = builder
+        .object("quality_dropdown")
+        .expect("Failed to get widget");
+    let disc_only: CheckButton = builder
+        .object("disc_only_check")
+        .expect("Failed to get widget");
+    let folder_button: Button = builder
+        .object("disc_folder_button")
+        .expect("Failed to get widget");
+    let size_label: Label = builder.object("size_label").expect("Failed to get widget");
+    let names: Vec<&str> = backends().iter().map(|b| b.settings().name).collect();
+    format.set_model(Some(&gtk::StringList::new(&names)));
+    show_format(&format, &quality);
+    let d_clone = data.clone();
+    format.connect_selected_notify(
+        glib::clone!(@weak quality, @weak disc_only, @weak size_label => move |format| {
+            set_quality_labels(&quality, selected_backend(format));
+            store_format(&d_clone, &disc_only, format, &quality);
+            update_size_estimate(&d_clone, &size_label);
+        }),
+    );
+    let d_clone = data.clone();
+    quality.connect_selected_notify(
+        glib::clone!(@weak format, @weak disc_only, @weak size_label => move |quality| {
+            store_format(&d_clone, &disc_only, &format, quality);
+            update_size_estimate(&d_clone, &size_label);
+        }),
+    );
+    let d_clone = data.clone();
+    disc_only.connect_toggled(
+        glib::clone!(@weak format, @weak quality, @weak folder_button, @weak size_label => move |check| {
+            let settings = check.is_active().then(|| DiscSettings {
+                encoder: selected_backend(&format).encoder(),
+                quality: selected_quality(&quality),
+                encode_path: Config::load().unwrap_or_default().encode_path,
+            });
+            folder_button.set_sensitive(settings.is_some());
+            folder_button.set_tooltip_text(Some(&match &settings {
+                Some(settings) => format!("Folder of this disc: {}", settings.encode_path),
+                None => "Folder of this disc".to_owned(),
+            }));
+            let restore = settings.is_none();
+            if let Ok(mut data) = d_clone.write() {
+                data.disc_settings = settings;
+            }
+            // back to the configured format
+            if restore {
+                show_format(&format, &quality);
+            }
+            update_size_estimate(&d_clone, &size_label);
+        }),
+    );
+    let chooser: Rc<RefCell<Option<FileChooserNative>>> = Rc::default();
+    let window = window.clone();
+    folder_button.connect_clicked(move |button| {
+        let native = FileChooserNative::new(
+            Some("Folder of this disc"),
+            Some(&window),
+            FileChooserAction::SelectFolder,
+            None,
+            None,
+        );
+        let data = data.clone();
+        native.connect_response(glib::clone!(@weak button => move |native, response| {
+            if response != ResponseType::Accept {
+                return;
+            }
+            let Some(path) = native.file().and_then(|file| file.path()) else {
+                return;
+            };
+            if let Ok(mut data) = data.write() {
+                if let Some(settings) = data.disc_settings.as_mut() {
+                    settings.encode_path = path.display().to_string();
+                    let tooltip = format!("Folder of this disc: {}", settings.encode_path);
+                    button.set_tooltip_text(Some(&tooltip));
+                }
+            }
+        }));
+        native.show();
+        chooser.replace(Some(native));
+    });
+}
+
+/// Keep the selected format and quality for the disc with "This disc only", or else save them
+fn store_format(
+    data: &Arc<RwLock<Data>>,
+    disc_only: &CheckButton,
+    format: &DropDown,
+    quality: &DropDown,
+) {
+    if !disc_only.is_active() {
+        save_format(format, quality);
+        return;
+    }
+    if let Ok(mut data) = data.write() {
+        if let Some(settings) = data.disc_settings.as_mut() {
+            settings.encoder = selected_backend(format).encoder();
+            settings.quality = selected_quality(quality);
+        }
+    }
+}
+
+/// Pick the library of `Config::routes` the disc is ripped to, only shown when there are some
+fn handle_library(data: Arc<RwLock<Data>>, builder: &Builder) {
+    let library: DropDown = builder
+        .object("library_dropdown")
+        .expect("Failed to get widget");
+    let routes = Config::load().unwrap_or_default().routes;
+    if routes.is_empty() {
+        return;
+    }
+    let names: Vec<&str> = std::iter::once("By genre")
+        .chain(routes.iter().map(|route| route.name.as_str()))
+        .collect();
+    library.set_model(Some(&gtk::StringList::new(&names)));
+    library.set_visible(true);
+    library.connect_selected_notify(move |library| {
+        let route = (library.selected() as usize)
+            .checked_sub(1)
+            .and_then(|index| routes.get(index))
+            .map(|route| route.name.clone());
+        if let Ok(mut data) = data.write() {
+            data.route = route;
+        }
+    });
+}
+
+/// Select the configured format and quality
+fn show_format(format: &DropDown, quality: &DropDown) {
+    let config: Config = Config::load().unwrap_or_default();
+    let selected = backends()
+        .iter()
+        .position(|b| b.encoder() == config.encoder)
+        .unwrap_or_default();
+    format.set_selected(selected as u32);
+    set_quality_labels(quality, backend(&config.encoder));
+    quality.set_selected(quality_index(&config.quality));
+}
+
+/// Store the selected format and quality when they differ from the config
+fn save_format(format: &DropDown, quality: &DropDown) {
+    let Ok(config) = Config::load() else {
+        debug!("Failed to load config");
+        return;
+    };
+    let encoder = selected_backend(format).encoder();
+    let quality = selected_quality(quality);
+    if config.encoder != encoder || config.quality != quality {
+        let stored = config::manager().update_later(|config| {
+            config.encoder = encoder;
+            config.quality = quality;
+        });
+        if let Err(e) = stored {
+            error!("Failed to store the configuration: {e}");
+        }
+    }
+}
+
+/// Show the quality descriptions of the backend, keeping the selection
+fn set_quality_labels(quality: &DropDown, backend: &dyn EncoderBackend) {
+    let selected = quality.selected().min(2);
+    quality.set_model(Some(&gtk::StringList::new(&backend.settings().qualities)));
+    quality.set_selected(selected);
+}
+
+fn quality_index(quality: &Quality) -> u32 {
+    match quality {
+        Quality::Low => 0,
+        Quality::Medium => 1,
+        Quality::High => 2,
+    }
+}
+
+fn selected_quality(quality: &DropDown) -> Quality {
+    match quality.selected() {
+        0 => Quality::Low,
+        1 => Quality::Medium,
+        _ => Quality::High,
+    }
+}
+
+/// A widget of the preferences window, edits a `Setting`
+enum Field {
+    Check(CheckButton),
+    Choice(DropDown),
+    Line(Entry),
+    Text(TextView),
+}
+
+impl Field {
+    /// Add the widget for the setting to the page, showing its value in the configuration
+    fn add(setting: &Setting, config: &Config, page: &Box) -> Field {
+        let label = || Label::builder().label(&setting.label).xalign(0.0).build();
+        let row = || {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(10)
+                .build();
+            row.append(&label());
+            page.append(&row);
+            row
+        };
+        let field = match &setting.editor {
+            Editor::Check(get, _) => {
+                let check = CheckButton::builder()
+                    .label(&setting.label)
+                    .active(get(config))
+                    .build();
+                page.append(&check);
+                Field::Check(check)
+            }
+            Editor::Choice { options, get, .. } => {
+                let options: Vec<&str> = options.iter().map(String::as_str).collect();
+                let combo = DropDown::from_strings(&options);
+                combo.set_selected(get(config) as u32);
+                combo.set_hexpand(true);
+                row().append(&combo);
+                Field::Choice(combo)
+            }
+            Editor::Line(get, _) => {
+                let entry = Entry::builder().text(get(config)).hexpand(true).build();
+                if let Some(hint) = setting.hint {
+                    entry.set_placeholder_text(Some(hint));
+                }
+                row().append(&entry);
+                Field::Line(entry)
+            }
+            Editor::Text(get, _) => {
+                page.append(&label());
+                let text = TextView::builder().hexpand(true).build();
+                text.buffer().set_text(&get(config));
+                page.append(&text);
+                Field::Text(text)
+            }
+        };
+        if let (Some(hint), false) = (setting.hint, matches!(field, Field::Line(_))) {
+            field.widget().set_tooltip_text(Some(hint));
+        }
+        field
+    }
+
+    fn widget(&self) -> gtk::Widget {
+        match self {
+            Field::Check(check) => check.clone().upcast(),
+            Field::Choice(combo) => combo.clone().upcast(),
+            Field::Line(entry) => entry.clone().upcast(),
+            Field::Text(text) => text.clone().upcast(),
+        }
+    }
+
+    /// Bind the widget to the GSettings key of the setting, so a value changed elsewhere (e.g. in
+    /// dconf-editor) shows right away. The mappings go through the editor of the setting, on
+    /// `config` with the value of the key
+    fn bind(
+        &self,
+        live: &gio::Settings,
+        settings: &Rc<Vec<Setting>>,
+        index: usize,
+        config: &Config,
+    ) {
+        let key = gsettings::key(settings[index].key);
+        if !live
+            .settings_schema()
+            .is_some_and(|schema| schema.has_key(&key))
+        {
+            return;
+        }
+        let (object, property): (glib::Object, &str) = match self {
+            Field::Check(check) => (check.clone().upcast(), "active"),
+            Field::Choice(combo) => (combo.clone().upcast(), "selected"),
+            Field::Line(entry) => (entry.clone().upcast(), "text"),
+            Field::Text(text) => (text.buffer().upcast(), "text"),
+        };
+        let (get_settings, get_config, get_key) = (settings.clone(), config.clone(), key.clone());
+        let (settings, config, set_key) = (settings.clone(), config.clone(), key.clone());
+        live.bind(&key, &object, property)
+            .mapping(move |value, _| {
+                let config = gsettings::with_value(&get_config, &get_key, value)?;
+                Some(match &get_settings[index].editor {
+                    Editor::Check(get, _) => get(&config).to_value(),
+                    Editor::Choice { get, .. } => (get(&config) as u32).to_value(),
+                    Editor::Line(get, _) | Editor::Text(get, _) => get(&config).to_value(),
+                })
+            })
+            .set_mapping(move |value, ty| {
+                let mut config = config.clone();
+                match &settings[index].editor {
+                    Editor::Check(_, set) => set(&mut config, value.get().ok()?),
+                    Editor::Choice { set, .. } => {
+                        set(&mut config, value.get::<u32>().ok()? as usize)
+                    }
+                    Editor::Line(_, set) | Editor::Text(_, set) => {
+                        set(&mut config, &value.get::<String>().ok()?);
+                    }
+                }
+                gsettings::value(&config, &set_key, &ty)
+            })
+            .build();
+    }
 
-    handle_go(ripping, data, &builder);
+    /// Write the value of the widget to the configuration
+    fn store(&self, setting: &Setting, config: &mut Config) {
+        match (self, &setting.editor) {
+            (Field::Check(check), Editor::Check(_, set)) => set(config, check.is_active()),
+            (Field::Choice(combo), Editor::Choice { set, .. }) => {
+                set(config, combo.selected() as usize);
+            }
+            (Field::Line(entry), Editor::Line(_, set)) => set(config, &entry.text()),
+            (Field::Text(text), Editor::Text(_, set)) => {
+                let buf = text.buffer();
+                set(config, &buf.text(&buf.start_iter(), &buf.end_iter(), false));
+            }
+            _ => error!("the widget of {} doesn't match its setting", setting.key),
+        }
+    }
 }
 
-fn handle_config(config_button: &Button, window: &ApplicationWindow) {
+/// Open the preferences window, a page per `Page` with the widgets of the `settings`
+fn handle_config(
+    config_button: &Button,
+    window: &ApplicationWindow,
+    on_saved: impl Fn() + Clone + 'static,
+) {
     let window = window.clone();
     config_button.connect_clicked(move |_| {
-        let cfg: Config = confy::load("ripperx4", None).expect("Failed to load config");
-        let config = Arc::new(RwLock::new(cfg));
+        // a config file that doesn't parse (edited by hand, from a newer version) is shown with
+        // the defaults, Ok replaces it
+        let loaded = Config::load();
+        let broken = loaded.as_ref().err().map(ToString::to_string);
+        let config = loaded.unwrap_or_default();
+        let settings = Rc::new(settings(&config, &cd_devices(), &available_backends()));
+        let stack = Stack::builder().hexpand(true).vexpand(true).build();
+        let pages: HashMap<Page, Box> = Page::ALL
+            .into_iter()
+            .map(|page| {
+                let child = Box::builder()
+                    .orientation(Orientation::Vertical)
+                    .spacing(10)
+                    .margin_start(10)
+                    .margin_end(10)
+                    .margin_top(10)
+                    .build();
+                stack.add_titled(&child, Some(page.title()), page.title());
+                (page, child)
+            })
+            .collect();
+        let fields: Vec<Field> = settings
+            .iter()
+            .map(|setting| Field::add(setting, &config, &pages[&setting.page]))
+            .collect();
+        let field = |key: &str| {
+            settings
+                .iter()
+                .position(|s| s.key == key)
+                .and_then(|i| fields.get(i))
+        };
+        // changes wait in the delayed settings until Ok
+        let live = gsettings::open();
+        if let Some(live) = &live {
+            live.delay();
+            for (index, field) in fields.iter().enumerate() {
+                field.bind(live, &settings, index, &config);
+            }
+        }
+        for (setting, widget) in settings.iter().zip(&fields) {
+            if let Some(Field::Check(check)) = setting.enabled_by.and_then(field) {
+                widget.widget().set_sensitive(check.is_active());
+                check
+                    .bind_property("active", &widget.widget(), "sensitive")
+                    .build();
+            }
+            if let (Some((key, options)), Field::Choice(combo)) = (setting.options_by, widget) {
+                if let Some(Field::Choice(source)) = field(key) {
+                    source.connect_selected_notify(glib::clone!(@weak combo => move |source| {
+                        let selected = combo.selected();
+                        let labels = options(source.selected() as usize);
+                        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+                        combo.set_model(Some(&gtk::StringList::new(&labels)));
+                        combo.set_selected(selected);
+                    }));
+                }
+            }
+        }
+        let sidebar = StackSidebar::builder().stack(&stack).build();
+        let pane = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(10)
+            .build();
+        pane.append(&sidebar);
+        pane.append(&stack);
         let child = Box::builder()
             .orientation(Orientation::Vertical)
             .spacing(10)
-            .hexpand(true)
-            .vexpand(true)
-            .build();
-        let frame = Frame::builder()
-            .child(&child)
-            .label("Configuration")
-            .hexpand(true)
-            .vexpand(true)
+            .margin_bottom(10)
+            .margin_end(10)
             .build();
-        let path = TextView::builder().visible(true).hexpand(true).build();
-        let options = ["mp3", "ogg", "flac", "opus"];
-        let combo = DropDown::from_strings(&options);
-        if let Ok(c) = config.read() {
-            path.buffer().set_text(&c.encode_path);
-            child.append(&path);
-            let selected = match c.encoder {
-                Encoder::MP3 => 0,
-                Encoder::OGG => 1,
-                Encoder::FLAC => 2,
-                Encoder::OPUS => 3,
-            };
-            combo.set_selected(selected);
-        } else {
-            debug!("Failed to read config");
-        }
-        child.append(&combo);
-        // quality
-        let quality_options = ["low", "medium", "high"];
-        let quality_combo = DropDown::from_strings(&quality_options);
-        if let Ok(c) = config.read() {
-            path.buffer().set_text(&c.encode_path);
-            child.append(&path);
-            let selected = match c.quality {
-                Quality::Low => 0,
-                Quality::Medium => 1,
-                Quality::High => 2,
-            };
-            quality_combo.set_selected(selected);
-        } else {
-            debug!("Failed to read config");
+        if let Some(e) = &broken {
+            let label = Label::builder()
+                .label(format!(
+                    "The config file can't be read ({e}), these are the defaults. Ok replaces it."
+                ))
+                .wrap(true)
+                .xalign(0.0)
+                .margin_start(10)
+                .margin_top(10)
+                .build();
+            label.add_css_class("error");
+            child.append(&label);
         }
-        child.append(&quality_combo);
-
-        let separator = Separator::builder().vexpand(true).build();
-        child.append(&separator);
+        child.append(&pane);
         let button_box = Box::builder()
             .orientation(Orientation::Horizontal)
             .spacing(10)
@@ -117,112 +860,233 @@ fn handle_config(config_button: &Button, window: &ApplicationWindow) {
         child.append(&button_box);
 
         let dialog = Dialog::builder()
-            .title("Configuration")
+            .title("Preferences")
             .modal(true)
-            .child(&frame)
-            .width_request(300)
+            .child(&child)
+            .width_request(600)
+            .height_request(400)
             .transient_for(&window)
             .build();
+        let on_saved = on_saved.clone();
+        let cancelled = live.clone();
         ok_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
-            let buf = path.buffer();
-            let new_path = path
-                .buffer()
-                .text(&buf.start_iter(), &buf.end_iter(), false);
-            if let Ok(mut config) = config.write() {
-                config.encode_path = new_path.to_string();
-                let c = combo.selected();
-                config.encoder = match c {
-                    0 => Encoder::MP3,
-                    1 => Encoder::OGG,
-                    2 => Encoder::FLAC,
-                    3 => Encoder::OPUS,
-                    _ => panic!("invalid value"),
-                };
-                let c = quality_combo.selected();
-                config.quality = match c {
-                    0 => Quality::Low,
-                    1 => Quality::Medium,
-                    2 => Quality::High,
-                    _ => panic!("invalid value"),
-                };
-                confy::store("ripperx4", None, &*config).ok();
+            if let Some(live) = &live {
+                live.apply();
+            }
+            let store = |config: &mut Config| {
+                for (setting, field) in settings.iter().zip(&fields) {
+                    field.store(setting, config);
+                }
+            };
+            // onto the current configuration, a rip may have changed it since the dialog opened
+            let stored = if broken.is_some() {
+                let mut config = config.clone();
+                store(&mut config);
+                config::manager().replace(config)
             } else {
-                debug!("Failed to write config");
+                config::manager().update(store)
+            };
+            if let Err(e) = stored {
+                error!("Failed to store the configuration: {e}");
             }
+            on_saved();
             dialog.close();
         }));
         cancel_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+            if let Some(live) = &cancelled {
+                live.revert();
+            }
             dialog.close();
         }));
         dialog.show();
     });
 }
 
-fn handle_disc(data: Arc<RwLock<Data>>, builder: &Builder) {
+/// The encoder backend selected in the format drop down
+fn selected_backend(combo: &DropDown) -> &'static dyn EncoderBackend {
+    backends()
+        .get(combo.selected() as usize)
+        .copied()
+        .expect("invalid value")
+}
+
+/// Writes the metadata of a disc edited in a text field to the cache once the typing stopped,
+/// rather than the whole cache file on every keystroke
+#[derive(Clone, Default)]
+struct RememberLater(Rc<RefCell<Option<glib::SourceId>>>);
+
+impl RememberLater {
+    /// Remember the disc as it is now after `REMEMBER_DELAY`, unless it's edited again
+    /// The disc is copied, the edit is kept when another disc is scanned in the meantime
+    fn remember(&self, disc: &Disc) {
+        if let Some(pending) = self.0.take() {
+            pending.remove();
+        }
+        let disc = disc.clone();
+        let pending = self.0.clone();
+        self.0.replace(Some(glib::timeout_add_local_once(
+            REMEMBER_DELAY,
+            move || {
+                pending.take();
+                cache::remember(&disc);
+            },
+        )));
+    }
+}
+
+/// Returns the handler of the disc's note, blocked while the metadata of a disc is filled in
+fn handle_disc(data: Arc<RwLock<Data>>, builder: &Builder) -> glib::SignalHandlerId {
+    let later = RememberLater::default();
     let title_text: TextView = builder.object("disc_title").expect("Failed to get widget");
     let artist_text: TextView = builder.object("disc_artist").expect("Failed to get widget");
     let title_buffer = title_text.buffer();
     let data_title = data.clone();
+    let title_later = later.clone();
     title_buffer.connect_changed(move |s| {
         if let Ok(mut data) = data_title.write() {
             if data.disc.is_some() {
                 let new_title = s.text(&s.start_iter(), &s.end_iter(), false);
                 if let Some(disc) = data.disc.as_mut() {
-                    disc.title = new_title.to_string();
+                    if disc.title != new_title {
+                        disc.title = new_title.to_string();
+                        title_later.remember(disc);
+                    }
                 }
             }
         }
     });
     let artist_buffer = artist_text.buffer();
-    let data_artist = data;
+    let data_artist = data.clone();
+    let artist_later = later.clone();
     artist_buffer.connect_changed(move |s| {
         if let Ok(mut data) = data_artist.write() {
             if data.disc.is_some() {
                 let new_artist = s.text(&s.start_iter(), &s.end_iter(), false);
                 if let Some(disc) = data.disc.as_mut() {
-                    disc.artist = new_artist.to_string();
+                    if disc.artist != new_artist {
+                        disc.artist = new_artist.to_string();
+                        artist_later.remember(disc);
+                    }
                 }
             }
         }
     });
+    let year_text: TextView = builder.object("year").expect("Failed to get widget");
+    let data_year = data.clone();
+    let year_later = later.clone();
+    year_text.buffer().connect_changed(move |s| {
+        if let Ok(mut data) = data_year.write() {
+            if let Some(disc) = data.disc.as_mut() {
+                let text = s.text(&s.start_iter(), &s.end_iter(), false);
+                let year = text.trim().parse().ok();
+                if disc.year != year {
+                    disc.set_year(year);
+                    year_later.remember(disc);
+                }
+            }
+        }
+    });
+    let comment_entry: Entry = builder
+        .object("disc_comment")
+        .expect("Failed to get widget");
+    comment_entry.connect_changed(move |entry| {
+        if let Ok(mut data) = data.write() {
+            if let Some(disc) = data.disc.as_mut() {
+                let text = entry.text();
+                let comment = Some(text.trim().to_owned()).filter(|c| !c.is_empty());
+                if disc.comment != comment {
+                    disc.comment = comment;
+                    later.remember(disc);
+                }
+            }
+        }
+    })
 }
 
-fn handle_stop(ripping: Arc<RwLock<bool>>, builder: &Builder) {
-    let builder = builder.clone();
+/// Stopping cancels the rip, the buttons wait until the rip thread has finished off the tracks
+fn handle_stop(cancel: CancelToken, builder: &Builder, control: RipControl) {
     let stop_button: Button = builder.object("stop_button").expect("Failed to get widget");
     stop_button.connect_clicked(move |_| {
         debug!("stop");
-        if let Ok(mut ripping) = ripping.write() {
-            *ripping = false;
-            let stop_button: Button = builder.object("stop_button").expect("Failed to get widget");
-            stop_button.set_sensitive(false);
-            let go_button: Button = builder.object("go_button").expect("Failed to get widget");
-            go_button.set_sensitive(true); //
-            let scan_button: Button = builder.object("scan_button").expect("Failed to get widget");
-            scan_button.set_sensitive(true);
+        if control.handle(RipEvent::Stop) {
+            cancel.cancel();
         }
     });
 }
 
-fn handle_scan(data: Arc<RwLock<Data>>, builder: &Builder, window: &ApplicationWindow) {
+fn handle_scan(
+    data: Arc<RwLock<Data>>,
+    builder: &Builder,
+    window: &ApplicationWindow,
+    control: RipControl,
+    comment_changed: glib::SignalHandlerId,
+) {
     let window = window.clone();
     let title_text: TextView = builder.object("disc_title").expect("Failed to get widget");
     let artist_text: TextView = builder.object("disc_artist").expect("Failed to get widget");
     let year_text: TextView = builder.object("year").expect("Failed to get widget");
     let genre_text: TextView = builder.object("genre").expect("Failed to get widget");
-    let go_button: Button = builder.object("go_button").expect("Failed to get widget");
+    let barcode_entry: Entry = builder.object("barcode").expect("Failed to get widget");
+    let release_entry: Entry = builder.object("release_id").expect("Failed to get widget");
+    let comment_entry: Entry = builder
+        .object("disc_comment")
+        .expect("Failed to get widget");
+    let catalog_entry: Entry = builder
+        .object("catalog_number")
+        .expect("Failed to get widget");
+    let cover_image: Picture = builder.object("cover_image").expect("Failed to get widget");
+    let paste_button: Button = builder
+        .object("paste_button")
+        .expect("Failed to get widget");
+    let disc_only: CheckButton = builder
+        .object("disc_only_check")
+        .expect("Failed to get widget");
+    let library: DropDown = builder
+        .object("library_dropdown")
+        .expect("Failed to get widget");
+    let size_label: Label = builder.object("size_label").expect("Failed to get widget");
+    let banner_builder = builder.clone();
     // build treeview
     let tree: TreeView = builder
         .object("track_listview")
         .expect("Failed to get widget");
-    let store = ListStore::new(&[Type::BOOL, Type::U32, Type::STRING, Type::STRING]);
+    let store = ListStore::new(&[
+        Type::BOOL,
+        Type::U32,
+        Type::STRING,
+        Type::STRING,
+        Type::STRING,
+        Type::U32,
+        Type::I32,
+        Type::STRING,
+        Type::STRING,
+        Type::STRING,
+    ]);
     tree.set_model(Some(&store));
+    // drag rows, or move the selected one with Alt+Up and Alt+Down, to change the order the
+    // tracks are ripped in
+    tree.set_reorderable(true);
+    let keys = gtk::EventControllerKey::new();
+    let (t, s) = (tree.clone(), store.clone());
+    keys.connect_key_pressed(move |_, key, _, modifier| {
+        if !modifier.contains(gdk::ModifierType::ALT_MASK) {
+            return glib::Propagation::Proceed;
+        }
+        match key {
+            gdk::Key::Up => move_selected_row(&t, &s, true),
+            gdk::Key::Down => move_selected_row(&t, &s, false),
+            _ => return glib::Propagation::Proceed,
+        }
+        glib::Propagation::Stop
+    });
+    tree.add_controller(keys);
     let bool_renderer = gtk::CellRendererToggle::new();
     bool_renderer.set_property("activatable", true);
     let t = tree.clone();
     let m = t.model().expect("Failed to get model");
     let s = store.clone();
     let d_clone = data.clone();
+    let label = size_label.clone();
     bool_renderer.connect_toggled(move |_, path| {
         let iter = m.iter(&path).expect("Failed to get iter");
         let old = s
@@ -237,18 +1101,23 @@ fn handle_scan(data: Arc<RwLock<Data>>, builder: &Builder, window: &ApplicationW
             .disc
             .as_mut()
         {
-            let num = m
-                .get_value(&iter, 1)
-                .get::<u8>()
-                .expect("Failed to get value");
-            d.tracks[num as usize - 1].rip = new;
+            if let Some(track) = d.tracks.get_mut(track_id(&m, &iter)) {
+                track.rip = new;
+            }
+            let mut selections = Selections::load();
+            selections.remember(d);
+            if let Err(e) = selections.store() {
+                error!("Failed to store the track selection: {e}");
+            }
         }
+        update_size_estimate(&d_clone, &label);
     });
     let column = gtk::TreeViewColumn::with_attributes("Encode", &bool_renderer, &[("active", 0)]);
     tree.append_column(&column);
 
     let renderer = gtk::CellRendererText::new();
     let column = gtk::TreeViewColumn::with_attributes("Track", &renderer, &[("text", 1)]);
+    column.set_sort_column_id(1);
     tree.append_column(&column);
 
     let renderer = gtk::CellRendererText::new();
@@ -266,14 +1135,14 @@ fn handle_scan(data: Arc<RwLock<Data>>, builder: &Builder, window: &ApplicationW
             .disc
             .as_mut()
         {
-            let num = m
-                .get_value(&iter, 1)
-                .get::<u8>()
-                .expect("Failed to get value");
-            d.tracks[num as usize - 1].title = new_text.to_string();
+            if let Some(track) = d.tracks.get_mut(track_id(&m, &iter)) {
+                track.title = new_text.to_string();
+            }
+            cache::remember(d);
         };
     });
     let column = gtk::TreeViewColumn::with_attributes("Title", &renderer, &[("text", 2)]);
+    sort_collated(&store, &column, 2);
     tree.append_column(&column);
 
     let renderer = gtk::CellRendererText::new();
@@ -291,130 +1160,1799 @@ fn handle_scan(data: Arc<RwLock<Data>>, builder: &Builder, window: &ApplicationW
             .disc
             .as_mut()
         {
-            let num = m
-                .get_value(&iter, 1)
-                .get::<u8>()
-                .expect("Failed to get value");
-            d.tracks[num as usize - 1].artist = new_text.to_string();
+            if let Some(track) = d.tracks.get_mut(track_id(&m, &iter)) {
+                track.artist = new_text.to_string();
+            }
+            cache::remember(d);
         };
     });
     let column = gtk::TreeViewColumn::with_attributes("Artist", &renderer, &[("text", 3)]);
+    sort_collated(&store, &column, 3);
     tree.append_column(&column);
 
-    let scan_button: Button = builder.object("scan_button").expect("Failed to get widget");
-    scan_button.connect_clicked(move |_| {
-        debug!("Scan");
-        if let Ok(discid) = scan_disc() {
-            debug!("Scanned: {discid:?}");
-            debug!("id={}", discid.id());
-            let disc = lookup_disc(&discid);
-            debug!("disc:{}", disc.title);
-            // store.clear();
-            title_text.buffer().set_text(&disc.title);
-            artist_text.buffer().set_text(&disc.artist);
-            if let Some(year) = disc.year {
-                year_text.buffer().set_text(&(year.to_string()));
-            }
-            if let Some(genre) = &disc.genre {
-                genre_text.buffer().set_text(&genre.clone());
-            }
-            let tracks = disc.tracks.len();
-            // panic if we can't get a write lock
-            data.write()
-                .expect("Failed to aquire write lock on data")
-                .disc = Some(disc);
-            // here we know how many tracks there are
-            for i in 0..tracks {
-                let iter = store.append();
-                if let Ok(r) = data.read() {
-                    if let Some(d) = r.disc.as_ref() {
-                        let num = d.tracks[i].number;
-                        let title = &d.tracks[i].title.clone();
-                        let artist = &d.tracks[i].artist.clone();
-                        debug!("{}: {} - {}", num, title, artist);
-                        store.set(&iter, &[(0, &true), (1, &num), (2, &title), (3, &artist)]);
-                    }
-                }
+    let renderer = gtk::CellRendererText::new();
+    renderer.set_property("editable", true);
+    let t = tree.clone();
+    let m = t.model().expect("Failed to get model");
+    let s = store.clone();
+    let d_clone = data.clone();
+    renderer.connect_edited(move |_, path, new_text| {
+        let iter = m.iter(&path).expect("Failed to get iter");
+        s.set_value(&iter, 4, &new_text.to_value());
+        if let Some(d) = d_clone
+            .write()
+            .expect("Failed to aquire write lock on data")
+            .disc
+            .as_mut()
+        {
+            if let Some(track) = d.tracks.get_mut(track_id(&m, &iter)) {
+                let composer = new_text.trim();
+                track.composer = (!composer.is_empty()).then(|| composer.to_owned());
             }
-            go_button.set_sensitive(true);
-        } else {
-            show_message("Failed to scan disc", MessageType::Error, &window);
+            cache::remember(d);
+        };
+    });
+    let column = gtk::TreeViewColumn::with_attributes("Composer", &renderer, &[("text", 4)]);
+    sort_collated(&store, &column, 4);
+    tree.append_column(&column);
+    // a sorted store puts dragged rows back in their sorted place, so a sort by a column header
+    // becomes the order of the rows, which can then be dragged again and set the rip order
+    store.connect_sort_column_changed(|store| {
+        if store.sort_column_id().is_some() {
+            let store = store.clone();
+            glib::idle_add_local_once(move || store.set_unsorted());
         }
     });
-}
 
-fn show_message(message: &str, typ: MessageType, window: &ApplicationWindow) {
-    let dialog = MessageDialog::builder()
-        .title("Error")
-        .modal(true)
-        .buttons(ButtonsType::Ok)
-        .message_type(typ)
-        .text(message)
-        .transient_for(window)
-        .width_request(300)
-        .build();
-    dialog.connect_response(glib::clone!(@weak dialog => move |_, _| {
-        dialog.close();
-    }));
-    dialog.show();
-}
+    let renderer = gtk::CellRendererText::new();
+    renderer.set_property("editable", true);
+    let t = tree.clone();
+    let m = t.model().expect("Failed to get model");
+    let s = store.clone();
+    let d_clone = data.clone();
+    renderer.connect_edited(move |_, path, new_text| {
+        let iter = m.iter(&path).expect("Failed to get iter");
+        s.set_value(&iter, COMMENT_COLUMN, &new_text.to_value());
+        if let Some(d) = d_clone
+            .write()
+            .expect("Failed to aquire write lock on data")
+            .disc
+            .as_mut()
+        {
+            if let Some(track) = d.tracks.get_mut(track_id(&m, &iter)) {
+                let comment = new_text.trim();
+                track.comment = (!comment.is_empty()).then(|| comment.to_owned());
+            }
+            cache::remember(d);
+        };
+    });
+    let column = gtk::TreeViewColumn::with_attributes(
+        "Comment",
+        &renderer,
+        &[("text", COMMENT_COLUMN as i32)],
+    );
+    tree.append_column(&column);
 
-fn handle_go(ripping_arc: Arc<RwLock<bool>>, data: Arc<RwLock<Data>>, builder: &Builder) {
-    let builder = builder.clone();
-    let go_button: Button = builder.object("go_button").expect("Failed to get widget");
-    go_button.set_sensitive(false);
-    let status: Statusbar = builder.object("statusbar").expect("Failed to get widget");
-    let stop_button: Button = builder.object("stop_button").expect("Failed to get widget");
-    go_button.connect_clicked(glib::clone!(@weak status => move |_| {
-        if let Ok(mut ripping) = ripping_arc.write() {
-            stop_button.set_sensitive(true);
-            let go_button: Button = builder.object("go_button").expect("Failed to get widget");
-            go_button.set_sensitive(false);
-            let scan_button: Button = builder.object("scan_button").expect("Failed to get widget");
-            scan_button.set_sensitive(false);
-            *ripping = true;
-            let context_id = status.context_id("foo");
-            let (tx, rx) = async_channel::unbounded();
-            let ripping_clone3 = ripping_arc.clone();
-            thread::spawn(glib::clone!(@weak data => move || {
-                if let Ok(data_go) = data.clone().read() {
-                    if let Some(disc) = &data_go.disc {
-                        match extract(disc, &tx, &ripping_clone3) {
-                            Ok(()) => {
-                                debug!("done");
-                                tx.send_blocking("done".to_owned()).ok();
-                            }
-                            Err(e) => {
-                                let msg = format!("Error: {e}");
-                                debug!("{msg}");
-                                tx.send_blocking("aborted".to_owned()).ok();
-                            }
-                        }
+    let formats = ListStore::new(&[Type::STRING]);
+    let names = backends().iter().map(|b| b.settings().name);
+    for name in std::iter::once(DEFAULT_FORMAT).chain(names) {
+        formats.set(&formats.append(), &[(0, &name)]);
+    }
+    let renderer = gtk::CellRendererCombo::new();
+    renderer.set_property("model", &formats);
+    renderer.set_property("text-column", 0);
+    renderer.set_property("has-entry", false);
+    renderer.set_property("editable", true);
+    let t = tree.clone();
+    let m = t.model().expect("Failed to get model");
+    let s = store.clone();
+    let d_clone = data.clone();
+    let label = size_label.clone();
+    renderer.connect_edited(move |_, path, new_text| {
+        let iter = m.iter(&path).expect("Failed to get iter");
+        s.set_value(&iter, FORMAT_COLUMN, &new_text.to_value());
+        if let Some(d) = d_clone
+            .write()
+            .expect("Failed to aquire write lock on data")
+            .disc
+            .as_mut()
+        {
+            if let Some(track) = d.tracks.get_mut(track_id(&m, &iter)) {
+                track.encoder = backends()
+                    .iter()
+                    .find(|b| b.settings().name == new_text)
+                    .map(|b| b.encoder());
+            }
+        };
+        update_size_estimate(&d_clone, &label);
+    });
+    let column = gtk::TreeViewColumn::with_attributes(
+        "Format",
+        &renderer,
+        &[("text", FORMAT_COLUMN as i32)],
+    );
+    tree.append_column(&column);
+
+    let renderer = gtk::CellRendererProgress::new();
+    let column = gtk::TreeViewColumn::with_attributes(
+        "Progress",
+        &renderer,
+        &[
+            ("value", PROGRESS_COLUMN as i32),
+            ("text", PROGRESS_TEXT_COLUMN as i32),
+        ],
+    );
+    tree.append_column(&column);
+    style_track_list(&tree, &Config::load().unwrap_or_default());
+
+    handle_paste(data.clone(), &paste_button, &window, &store);
+    let segments_button: Button = builder
+        .object("segments_button")
+        .expect("Failed to get widget");
+    handle_segments(data.clone(), &segments_button, &window, &tree, &control);
+
+    let scanned: Rc<RefCell<Option<DiscId>>> = Rc::new(RefCell::new(None));
+    let go_button: Button = builder.object("go_button").expect("Failed to get widget");
+    let auto = control.clone();
+    // fills in the metadata looked up for the scanned disc
+    let window_clone = window.clone();
+    let disc_id = scanned.clone();
+    let barcode = barcode_entry.clone();
+    let catalog_number = catalog_entry.clone();
+    let release = release_entry.clone();
+    let comment = comment_entry.clone();
+    let fill = Rc::new(move |completed: Completed| {
+        let (mut disc, error) = match completed {
+            Ok(completed) => completed,
+            Err(e) => {
+                error!("Failed to look up the disc: {e}");
+                return;
+            }
+        };
+        // the lookup of a disc scanned before finished after the scan of this one
+        if disc_id.borrow().as_ref().map(DiscId::id) != disc.discid {
+            debug!("dropping the metadata of disc {:?}", disc.discid);
+            return;
+        }
+        debug!("disc:{}", disc.title);
+        Selections::load().apply(&mut disc);
+        let duplicates = find_duplicates(&disc, &Config::load().unwrap_or_default());
+        if !duplicates.is_empty() {
+            let message = format!(
+                "This disc seems to be ripped already:\n\n{}",
+                duplicates.join("\n")
+            );
+            show_message(&message, MessageType::Warning, &window_clone);
+        }
+        show_banner(&banner_builder, error.as_deref());
+        // the Auto mode goes on, the user checks the titles later
+        if let Some(warning) = &disc.lookup_warning {
+            show_message(warning, MessageType::Warning, &window_clone);
+        }
+        // the format of the previous disc isn't kept
+        disc_only.set_active(false);
+        library.set_selected(0);
+        store.clear();
+        // the previous disc must not pick up the texts of this one
+        data.write()
+            .expect("Failed to aquire write lock on data")
+            .disc = None;
+        title_text.buffer().set_text(&disc.title);
+        artist_text.buffer().set_text(&disc.artist);
+        year_text
+            .buffer()
+            .set_text(&disc.year.map(|year| year.to_string()).unwrap_or_default());
+        if let Some(genre) = &disc.genre {
+            genre_text.buffer().set_text(&genre.clone());
+        }
+        barcode.set_text(disc.barcode.as_deref().unwrap_or_default());
+        catalog_number.set_text(disc.catalog_number.as_deref().unwrap_or_default());
+        release.set_text(disc.release_id.as_deref().unwrap_or_default());
+        comment.block_signal(&comment_changed);
+        comment.set_text(disc.comment.as_deref().unwrap_or_default());
+        comment.unblock_signal(&comment_changed);
+        barcode.set_sensitive(true);
+        catalog_number.set_sensitive(true);
+        release.set_sensitive(true);
+        comment.set_sensitive(true);
+        show_cover(&cover_image, disc.cover.as_deref());
+        let tracks = disc.tracks.len();
+        // panic if we can't get a write lock
+        data.write()
+            .expect("Failed to aquire write lock on data")
+            .disc = Some(disc);
+        // here we know how many tracks there are
+        for i in 0..tracks {
+            let id = i as u32;
+            let iter = store.append();
+            if let Ok(r) = data.read() {
+                if let Some(d) = r.disc.as_ref() {
+                    let rip = d.tracks[i].rip;
+                    let num = d.tracks[i].number;
+                    let title = &d.tracks[i].title.clone();
+                    let artist = &d.tracks[i].artist.clone();
+                    let composer = &d.tracks[i].composer.clone().unwrap_or_default();
+                    let comment = &d.tracks[i].comment.clone().unwrap_or_default();
+                    let format = d.tracks[i]
+                        .encoder
+                        .map_or(DEFAULT_FORMAT, |e| backend(&e).settings().name);
+                    debug!("{}: {} - {}", num, title, artist);
+                    store.set(
+                        &iter,
+                        &[
+                            (0, &rip),
+                            (1, &num),
+                            (2, &title),
+                            (3, &artist),
+                            (4, &composer),
+                            (TRACK_ID_COLUMN, &id),
+                            (FORMAT_COLUMN, &format),
+                            (COMMENT_COLUMN, &comment),
+                        ],
+                    );
+                }
+            }
+        }
+        update_size_estimate(&data, &size_label);
+        control.update_buttons();
+        paste_button.set_sensitive(true);
+        segments_button.set_sensitive(true);
+        if control.is_auto() {
+            // a disc without metadata or ripped before is left for the user
+            if error.is_some() || !duplicates.is_empty() {
+                control.stop_auto();
+            } else {
+                go_button.emit_clicked();
+            }
+        }
+    });
+    // looks up the release with the barcode and catalog number entered, e.g. to pick the right
+    // edition when the disc id matches the wrong one
+    let disc_id = scanned.clone();
+    let search_window = window.clone();
+    let search_fill = fill.clone();
+    let toast_builder = builder.clone();
+    let search = Rc::new(move |barcode: &Entry, catalog_number: &Entry| {
+        let Some(toc) = disc_id.borrow().as_ref().map(Toc::of) else {
+            return;
+        };
+        let (barcode, catalog_number) = (barcode.text(), catalog_number.text());
+        let optional = |text: &str| Some(text.trim().to_owned()).filter(|t| !t.is_empty());
+        let (barcode, catalog_number) = (optional(&barcode), optional(&catalog_number));
+        debug!("Search {barcode:?} {catalog_number:?}");
+        let (window, fill, toast_builder) = (
+            search_window.clone(),
+            search_fill.clone(),
+            toast_builder.clone(),
+        );
+        in_background(
+            move || musicbrainz::search_releases(barcode.as_deref(), catalog_number.as_deref()),
+            move |releases| match releases {
+                Ok(releases) if releases.len() > 1 => {
+                    choose_release(&window, releases, move |release| {
+                        let fill = fill.clone();
+                        lookup_release(release.id.clone(), toc.clone(), move |c| fill(c));
+                    });
+                }
+                Ok(releases) => {
+                    lookup_release(releases[0].id.clone(), toc, move |c| fill(c));
+                }
+                Err(e) => show_toast(&toast_builder, &format!("No release found: {e}")),
+            },
+        );
+    });
+    for entry in [&barcode_entry, &catalog_entry] {
+        let search = search.clone();
+        let (barcode, catalog_number) = (barcode_entry.clone(), catalog_entry.clone());
+        entry.connect_activate(move |_| search(&barcode, &catalog_number));
+    }
+    // looks up the scanned disc, also used to retry the lookup without rescanning
+    // when the disc id matches several releases the user picks one
+    // the cached metadata is used unless `refresh`, or when no source has the disc
+    let disc_id = scanned.clone();
+    let chooser_window = window.clone();
+    let release_fill = fill.clone();
+    let show_lookup = Rc::new(move |refresh: bool| {
+        let scanned = disc_id.borrow();
+        let Some(discid) = scanned.as_ref() else {
+            return;
+        };
+        debug!("id={}", discid.id());
+        // the Auto mode takes the first release
+        let (toc, choose) = (Toc::of(discid), !auto.is_auto());
+        let (fill, chooser_window) = (fill.clone(), chooser_window.clone());
+        in_background(
+            move || lookup_sources(&toc, refresh, choose),
+            move |found| match found {
+                Lookup::Found(completed) => fill(completed),
+                Lookup::Releases(toc, releases) => {
+                    choose_release(&chooser_window, releases, move |release| {
+                        let fill = fill.clone();
+                        lookup_release(release.id.clone(), toc.clone(), move |c| fill(c));
+                    });
+                }
+            },
+        );
+    });
+
+    // a release pasted from the browser, the disc id may not be added to it on MusicBrainz yet
+    // clearing it looks up the releases of the disc id again
+    let disc_id = scanned.clone();
+    let toast_builder = builder.clone();
+    let lookup = show_lookup.clone();
+    release_entry.connect_activate(move |entry| {
+        let Some(toc) = disc_id.borrow().as_ref().map(Toc::of) else {
+            return;
+        };
+        let id = disc_id
+            .borrow()
+            .as_ref()
+            .map(DiscId::id)
+            .unwrap_or_default();
+        if entry.text().trim().is_empty() {
+            cache::unpin_release(&id);
+            lookup(true);
+            return;
+        }
+        let Some(release) = musicbrainz::parse_release_id(&entry.text()) else {
+            show_toast(&toast_builder, "Not a MusicBrainz release ID or URL");
+            return;
+        };
+        let (fill, toast_builder) = (release_fill.clone(), toast_builder.clone());
+        in_background(
+            move || {
+                let config = Config::load().unwrap_or_default();
+                let disc = musicbrainz::lookup_release(&release, &id, &config)?;
+                cache::pin_release(&id, &release);
+                complete(&toc, |_| Ok(disc))
+            },
+            move |completed| match completed {
+                Ok(completed) => fill(Ok(completed)),
+                Err(e) => show_toast(&toast_builder, &format!("Release not found: {e}")),
+            },
+        );
+    });
+
+    let retry_button: Button = builder
+        .object("retry_lookup_button")
+        .expect("Failed to get widget");
+    let lookup = show_lookup.clone();
+    retry_button.connect_clicked(move |_| {
+        debug!("Retry lookup");
+        lookup(true);
+    });
+
+    let scan_button: Button = builder.object("scan_button").expect("Failed to get widget");
+    scan_button.connect_clicked(move |_| {
+        debug!("Scan");
+        if let Ok(discid) = scan_disc() {
+            debug!("Scanned: {discid:?}");
+            scan_hook(&discid);
+            debug!("id={}", discid.id());
+            let warnings = toc_warnings(&discid);
+            if !warnings.is_empty() {
+                let message = format!(
+                    "The table of contents of this disc looks wrong, it may be copy protected or damaged:\n\n{}\n\nIf ripping fails, select the cdparanoia CD source in the configuration, it retries and corrects read errors.",
+                    warnings.join("\n")
+                );
+                show_message(&message, MessageType::Warning, &window);
+            }
+            scanned.replace(Some(discid));
+            show_lookup(false);
+            retry_button.set_sensitive(true);
+        } else {
+            show_message("Failed to scan disc", MessageType::Error, &window);
+        }
+    });
+}
+
+/// Look up the disc in the configured sources, see `lookup_releases`, and complete the metadata
+/// The cached metadata is used unless `refresh`, or when no source has the disc
+fn lookup_sources(toc: &Toc, refresh: bool, choose: bool) -> Lookup {
+    let discid = match toc.discid() {
+        Ok(discid) => discid,
+        Err(e) => return Lookup::Found(Err(e)),
+    };
+    let cached = MetadataCache::load().get(&discid.id());
+    let metadata = match cached {
+        Some(disc) if !refresh => Ok(disc),
+        cached => match lookup_releases(&discid, &Config::load().unwrap_or_default(), choose) {
+            Ok(Found::Disc(disc)) => Ok(disc),
+            Ok(Found::Releases(releases)) => return Lookup::Releases(toc.clone(), releases),
+            Err(e) => cached.ok_or(e),
+        },
+    };
+    Lookup::Found(Ok(disc_from_lookup(&discid, metadata)))
+}
+
+/// Complete the metadata `lookup` finds for the disc with the TOC, see `disc_from_lookup`
+/// The cover and the Latin names are fetched, on a worker thread
+fn complete(toc: &Toc, lookup: impl FnOnce(&DiscId) -> Result<Disc>) -> Completed {
+    let discid = toc.discid()?;
+    let metadata = lookup(&discid);
+    Ok(disc_from_lookup(&discid, metadata))
+}
+
+/// Look up the release the user picked on a worker thread, for `fill` on the main thread
+fn lookup_release(release: String, toc: Toc, fill: impl FnOnce(Completed) + 'static) {
+    in_background(
+        move || {
+            complete(&toc, |discid| {
+                let config = Config::load().unwrap_or_default();
+                musicbrainz::lookup_release(&release, &discid.id(), &config)
+            })
+        },
+        fill,
+    );
+}
+
+/// Run `work` on a worker thread, e.g. a lookup on the network that would freeze the window,
+/// and hand its result to `done` on the main thread
+fn in_background<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+    done: impl FnOnce(T) + 'static,
+) {
+    let (tx, rx) = async_channel::bounded(1);
+    thread::spawn(move || {
+        tx.send_blocking(work()).ok();
+    });
+    glib::spawn_future_local(async move {
+        match rx.recv().await {
+            Ok(result) => done(result),
+            Err(_) => error!("The background work panicked"),
+        }
+    });
+}
+
+/// The Auto button: while it's down, every disc found in the drive is scanned, looked up and
+/// ripped, the scan and Go buttons do the work
+fn handle_auto(builder: &Builder, control: RipControl) {
+    let auto_button: ToggleButton = builder.object("auto_button").expect("Failed to get widget");
+    let scan_button: Button = builder.object("scan_button").expect("Failed to get widget");
+    let poll_control = control.clone();
+    // a read of the drive that didn't finish yet, the next poll waits for it
+    let polling = Rc::new(Cell::new(false));
+    // the disc in the drive was read, it's only read again after udisks2 saw it go
+    let known = Rc::new(Cell::new(false));
+    let poll_known = known.clone();
+    let poll = Rc::new(move || {
+        if polling.get()
+            || poll_control.state.get() != RipState::Idle
+            || !scan_button.is_sensitive()
+        {
+            return;
+        }
+        polling.set(true);
+        let (control, scan_button, polling, known) = (
+            poll_control.clone(),
+            scan_button.clone(),
+            polling.clone(),
+            poll_known.clone(),
+        );
+        let was_known = known.get();
+        // reading the TOC takes a while, from a remote drive even over the network
+        in_background(
+            move || match disc_present() {
+                Some(false) => Polled::Empty,
+                Some(true) if was_known => Polled::Unchanged,
+                _ => scan_disc().map_or(Polled::Empty, |discid| Polled::Disc(discid.id())),
+            },
+            move |polled| {
+                polling.set(false);
+                let id = match polled {
+                    Polled::Empty => {
+                        known.set(false);
+                        return;
                     }
+                    Polled::Unchanged => return,
+                    Polled::Disc(id) => id,
+                };
+                known.set(true);
+                if !control.is_auto() || control.state.get() != RipState::Idle {
+                    return;
+                }
+                let mut last = control.auto_disc.borrow_mut();
+                if last.as_deref() != Some(id.as_str()) {
+                    debug!("Auto: disc {id}");
+                    *last = Some(id);
+                    drop(last);
+                    scan_button.emit_clicked();
+                }
+            },
+        );
+    });
+    let timer: RefCell<Option<glib::SourceId>> = RefCell::new(None);
+    auto_button.connect_toggled(move |button| {
+        let active = button.is_active();
+        control.auto.set(active);
+        if let Some(timer) = timer.take() {
+            timer.remove();
+        }
+        if !active {
+            return;
+        }
+        // the disc in the drive is ripped, also when it was scanned before
+        control.auto_disc.replace(None);
+        known.set(false);
+        poll();
+        let poll = poll.clone();
+        timer.replace(Some(glib::timeout_add_seconds_local(
+            AUTO_POLL_SECONDS,
+            move || {
+                poll();
+                glib::ControlFlow::Continue
+            },
+        )));
+    });
+}
+
+/// What the Auto mode found in the drive
+enum Polled {
+    /// no disc, or one that can't be read
+    Empty,
+    /// the disc that was read before, udisks2 didn't see it go
+    Unchanged,
+    /// the id of the disc that was read
+    Disc(String),
+}
+
+/// Edit the segments of the selected track, as `start-end title` lines
+fn handle_segments(
+    data: Arc<RwLock<Data>>,
+    segments_button: &Button,
+    window: &ApplicationWindow,
+    tree: &TreeView,
+    control: &RipControl,
+) {
+    let window = window.clone();
+    let tree = tree.clone();
+    let control = control.clone();
+    segments_button.connect_clicked(move |_| {
+        let Some((model, iter)) = tree.selection().selected() else {
+            show_message("Select a track first", MessageType::Info, &window);
+            return;
+        };
+        let index = track_id(&model, &iter);
+        let Some((track, segments, last)) = data.read().ok().and_then(|d| {
+            let tracks = &d.disc.as_ref()?.tracks;
+            let track = tracks.get(index)?;
+            Some((track.clone(), format_segments(&track.segments), index + 1 == tracks.len()))
+        }) else {
+            return;
+        };
+        let title = track.title.clone();
+        let child = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(10)
+            .margin_start(10)
+            .margin_end(10)
+            .build();
+        let help = Label::builder()
+            .label("One part per line as start-end title, e.g. 3:25-7:02 Part two.\nLeave out the end to rip up to the end of the track, or give only the start time of\neach part (cue points). Clear the text to rip the whole track.")
+            .xalign(0.0)
+            .build();
+        child.append(&help);
+        let text = TextView::builder().hexpand(true).build();
+        text.buffer().set_text(&segments);
+        let scroll = ScrolledWindow::builder()
+            .child(&text)
+            .min_content_height(200)
+            .vexpand(true)
+            .build();
+        child.append(&scroll);
+        let button_box = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(10)
+            .halign(Align::End)
+            .build();
+        // the classic hidden track after minutes of silence at the end of the disc
+        let hidden_button = Button::builder()
+            .label("Find hidden track")
+            .tooltip_text("Read the track and split it at a long silence")
+            .visible(last)
+            .sensitive(control.state.get() == RipState::Idle)
+            .build();
+        button_box.append(&hidden_button);
+        let import_button = Button::builder().label("Import CUE sheet").build();
+        button_box.append(&import_button);
+        let ok_button = Button::builder().label("Ok").build();
+        button_box.append(&ok_button);
+        let cancel_button = Button::builder().label("Cancel").build();
+        button_box.append(&cancel_button);
+        child.append(&button_box);
+
+        let dialog = Dialog::builder()
+            .title(format!("Segments of {title}"))
+            .modal(true)
+            .child(&child)
+            .width_request(500)
+            .transient_for(&window)
+            .build();
+        // native dialogs are not kept alive by GTK
+        let chooser: Rc<RefCell<Option<FileChooserNative>>> = Rc::default();
+        import_button.connect_clicked(glib::clone!(@weak dialog, @weak text => move |_| {
+            let native = FileChooserNative::new(
+                Some("Import CUE sheet"),
+                Some(&dialog),
+                FileChooserAction::Open,
+                None,
+                None,
+            );
+            let filter = FileFilter::new();
+            filter.set_name(Some("CUE sheets"));
+            filter.add_pattern("*.cue");
+            native.add_filter(&filter);
+            native.connect_response(glib::clone!(@weak text => move |native, response| {
+                if response != ResponseType::Accept {
+                    return;
+                }
+                let Some(path) = native.file().and_then(|file| file.path()) else {
+                    return;
+                };
+                match std::fs::read_to_string(&path) {
+                    Ok(cue) => text.buffer().set_text(&format_segments(&parse_cue(&cue))),
+                    Err(e) => error!("Failed to read {}: {e}", path.display()),
                 }
             }));
-            let scan_button_clone = scan_button;
-            let go_button_clone = go_button;
-            let stop_button_clone = stop_button.clone();
+            native.show();
+            chooser.replace(Some(native));
+        }));
+        let parent = window.clone();
+        hidden_button.connect_clicked(glib::clone!(@weak text => move |button| {
+            button.set_sensitive(false);
+            button.set_label("Searching…");
+            let (tx, rx) = async_channel::bounded(1);
+            let track = track.clone();
+            thread::spawn(move || {
+                let config = Config::load().unwrap_or_default();
+                tx.send_blocking(find_hidden_track(&track, &config)).ok();
+            });
+            let (button, title, parent) = (button.clone(), title.clone(), parent.clone());
             glib::spawn_future_local(async move {
-                while let Ok(value) =rx.recv().await {
-                    let s = value.clone();
-                    status.remove_all(context_id);
-                    status.push(context_id, &s);
-                    if s == "aborted" {
-                        scan_button_clone.set_sensitive(true);
-                        go_button_clone.set_sensitive(true);
-                        stop_button_clone.set_sensitive(false);
-                        break;
+                let Ok(result) = rx.recv().await else {
+                    return;
+                };
+                button.set_label("Find hidden track");
+                button.set_sensitive(true);
+                match result {
+                    Ok(Some(silence)) => text
+                        .buffer()
+                        .set_text(&format_segments(&hidden_track_segments(&title, silence))),
+                    Ok(None) => show_message("No hidden track found", MessageType::Info, &parent),
+                    Err(e) => show_message(
+                        &format!("Failed to read the track: {e}"),
+                        MessageType::Error,
+                        &parent,
+                    ),
+                }
+            });
+        }));
+        let data = data.clone();
+        let window = window.clone();
+        ok_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+            let buf = text.buffer();
+            let segments = match parse_segments(&buf.text(&buf.start_iter(), &buf.end_iter(), false)) {
+                Ok(segments) => segments,
+                Err(e) => {
+                    show_message(&e, MessageType::Error, &window);
+                    return;
+                }
+            };
+            if let Some(track) = data
+                .write()
+                .expect("Failed to aquire write lock on data")
+                .disc
+                .as_mut()
+                .and_then(|d| d.tracks.get_mut(index))
+            {
+                track.segments = segments;
+            }
+            dialog.close();
+        }));
+        cancel_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+            dialog.close();
+        }));
+        dialog.show();
+    });
+}
+
+fn handle_paste(
+    data: Arc<RwLock<Data>>,
+    paste_button: &Button,
+    window: &ApplicationWindow,
+    store: &ListStore,
+) {
+    let window = window.clone();
+    let store = store.clone();
+    paste_button.connect_clicked(move |_| {
+        let window_clone = window.clone();
+        let store = store.clone();
+        let data = data.clone();
+        window
+            .clipboard()
+            .read_text_async(gio::Cancellable::NONE, move |text| {
+                if let Ok(Some(text)) = text {
+                    let titles = parse_track_list(&text);
+                    show_paste_preview(titles, data, &store, &window_clone);
+                } else {
+                    show_message(
+                        "The clipboard does not contain text",
+                        MessageType::Error,
+                        &window_clone,
+                    );
+                }
+            });
+    });
+}
+
+/// Show how the pasted titles map onto the tracks and apply them when confirmed
+fn show_paste_preview(
+    titles: Vec<String>,
+    data: Arc<RwLock<Data>>,
+    store: &ListStore,
+    window: &ApplicationWindow,
+) {
+    let grid = Grid::builder()
+        .row_spacing(5)
+        .column_spacing(10)
+        .margin_start(10)
+        .margin_end(10)
+        .build();
+    for (column, heading) in ["Track", "Current title", "New title"].iter().enumerate() {
+        let label = Label::builder().label(*heading).xalign(0.0).build();
+        grid.attach(&label, column as i32, 0, 1, 1);
+    }
+    let mut tracks = 0;
+    if let Some(disc) = data
+        .read()
+        .expect("Failed to aquire read lock on data")
+        .disc
+        .as_ref()
+    {
+        tracks = disc.tracks.len();
+        for (row, track) in disc.tracks.iter().enumerate() {
+            let new_title = titles.get(row).map_or("(unchanged)", String::as_str);
+            let cells = [
+                track.number.to_string(),
+                track.title.clone(),
+                new_title.to_owned(),
+            ];
+            for (column, text) in cells.iter().enumerate() {
+                let label = Label::builder().label(text).xalign(0.0).build();
+                grid.attach(&label, column as i32, row as i32 + 1, 1, 1);
+            }
+        }
+    }
+    let child = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(10)
+        .build();
+    let scroll = ScrolledWindow::builder()
+        .child(&grid)
+        .min_content_height(300)
+        .vexpand(true)
+        .build();
+    child.append(&scroll);
+    if titles.len() > tracks {
+        let extra = titles.len() - tracks;
+        let label = Label::new(Some(&format!("{extra} extra line(s) will be ignored")));
+        child.append(&label);
+    }
+    let button_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(10)
+        .halign(Align::End)
+        .build();
+    let ok_button = Button::builder().label("Ok").build();
+    button_box.append(&ok_button);
+    let cancel_button = Button::builder().label("Cancel").build();
+    button_box.append(&cancel_button);
+    child.append(&button_box);
+
+    let dialog = Dialog::builder()
+        .title("Paste track list")
+        .modal(true)
+        .child(&child)
+        .width_request(500)
+        .transient_for(window)
+        .build();
+    ok_button.connect_clicked(glib::clone!(@weak dialog, @weak store => move |_| {
+        if let Some(disc) = data
+            .write()
+            .expect("Failed to aquire write lock on data")
+            .disc
+            .as_mut()
+        {
+            for (track, title) in disc.tracks.iter_mut().zip(&titles) {
+                track.title = title.clone();
+            }
+            cache::remember(disc);
+        }
+        if let Some(iter) = store.iter_first() {
+            loop {
+                if let Some(title) = titles.get(track_id(&store, &iter)) {
+                    store.set_value(&iter, 2, &title.to_value());
+                }
+                if !store.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        dialog.close();
+    }));
+    cancel_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+        dialog.close();
+    }));
+    dialog.show();
+}
+
+/// Preview moving the ripped files to where the current naming template puts them, then move them
+fn handle_reorganize(button: &Button, window: &ApplicationWindow) {
+    let window = window.clone();
+    button.connect_clicked(move |_| {
+        let config = Config::load().unwrap_or_default();
+        let moves = match plan_reorganize(&config) {
+            Ok(moves) => moves,
+            Err(e) => {
+                let message = format!("Can't reorganize the library: {e}");
+                show_message(&message, MessageType::Error, &window);
+                return;
+            }
+        };
+        if moves.is_empty() {
+            show_message(
+                "All ripped files are named after the template",
+                MessageType::Info,
+                &window,
+            );
+            return;
+        }
+        let frame = Frame::builder()
+            .margin_top(10)
+            .margin_bottom(10)
+            .margin_start(10)
+            .margin_end(10)
+            .build();
+        let child = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(10)
+            .build();
+        frame.set_child(Some(&child));
+        let label = Label::builder()
+            .label(format!("{} file(s) will be moved:", moves.len()))
+            .xalign(0.0)
+            .build();
+        child.append(&label);
+        let preview: Vec<String> = moves
+            .iter()
+            .map(|m| format!("{}\n    → {}", m.from.display(), m.to.display()))
+            .collect();
+        let text = TextView::builder().editable(false).monospace(true).build();
+        text.buffer().set_text(&preview.join("\n"));
+        let scroll = ScrolledWindow::builder()
+            .child(&text)
+            .min_content_height(300)
+            .min_content_width(600)
+            .vexpand(true)
+            .build();
+        child.append(&scroll);
+        let button_box = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(10)
+            .halign(Align::End)
+            .build();
+        let ok_button = Button::builder().label("Move").build();
+        button_box.append(&ok_button);
+        let cancel_button = Button::builder().label("Cancel").build();
+        button_box.append(&cancel_button);
+        child.append(&button_box);
+
+        let dialog = Dialog::builder()
+            .title("Reorganize library")
+            .modal(true)
+            .child(&frame)
+            .transient_for(&window)
+            .build();
+        let window = window.clone();
+        ok_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+            dialog.close();
+            if let Err(e) = reorganize(&moves, &config) {
+                let message = format!("Reorganizing the library stopped: {e}");
+                show_message(&message, MessageType::Error, &window);
+            }
+        }));
+        cancel_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+            dialog.close();
+        }));
+        dialog.present();
+    });
+}
+
+/// Save the inventory of ripped discs as a CSV file where the user picks
+fn handle_export(button: &Button, window: &ApplicationWindow) {
+    let chooser: Rc<RefCell<Option<FileChooserNative>>> = Rc::default();
+    let window = window.clone();
+    button.connect_clicked(move |_| {
+        let native = FileChooserNative::new(
+            Some("Export the ripped discs"),
+            Some(&window),
+            FileChooserAction::Save,
+            None,
+            None,
+        );
+        native.set_current_name("inventory.csv");
+        let window = window.clone();
+        native.connect_response(move |native, response| {
+            if response != ResponseType::Accept {
+                return;
+            }
+            let Some(path) = native.file().and_then(|file| file.path()) else {
+                return;
+            };
+            let config = Config::load().unwrap_or_default();
+            match export_csv(&config, &path) {
+                Ok(discs) => {
+                    let message = format!("Exported {discs} disc(s) to {}", path.display());
+                    show_message(&message, MessageType::Info, &window);
+                }
+                Err(e) => {
+                    let message = format!("Can't export the ripped discs: {e}");
+                    show_message(&message, MessageType::Error, &window);
+                }
+            }
+        });
+        native.show();
+        chooser.replace(Some(native));
+    });
+}
+
+/// Show the totals of the ripped discs and how many were ripped on the last days
+fn handle_statistics(button: &Button, window: &ApplicationWindow) {
+    let window = window.clone();
+    button.connect_clicked(move |_| {
+        let config = Config::load().unwrap_or_default();
+        match statistics(&config) {
+            Ok(statistics) => {
+                let message = format!("Ripped so far:\n{statistics}");
+                show_message(&message, MessageType::Info, &window);
+            }
+            Err(e) => {
+                let message = format!("Can't count the ripped discs: {e}");
+                show_message(&message, MessageType::Error, &window);
+            }
+        }
+    });
+}
+
+/// Encode a disc of the inventory ripped to FLAC to the format of a profile, e.g. Opus for a
+/// phone, without the disc
+fn handle_transcode(button: &Button, window: &ApplicationWindow) {
+    let window = window.clone();
+    button.connect_clicked(move |_| {
+        let config = Config::load().unwrap_or_default();
+        let archives = match flac_archives(&config) {
+            Ok(archives) => archives,
+            Err(e) => {
+                let message = format!("Can't list the discs ripped to FLAC: {e}");
+                show_message(&message, MessageType::Error, &window);
+                return;
+            }
+        };
+        if archives.is_empty() {
+            show_message(
+                "There are no discs ripped to FLAC whose files are all still there",
+                MessageType::Info,
+                &window,
+            );
+            return;
+        }
+        let targets = profile::transcode_targets(&config);
+        if targets.is_empty() {
+            show_message(
+                "All profiles rip to FLAC, add one with another format",
+                MessageType::Info,
+                &window,
+            );
+            return;
+        }
+        let frame = Frame::builder()
+            .margin_top(10)
+            .margin_bottom(10)
+            .margin_start(10)
+            .margin_end(10)
+            .build();
+        let grid = Grid::builder().row_spacing(10).column_spacing(10).build();
+        frame.set_child(Some(&grid));
+        let names: Vec<String> = archives.iter().map(ToString::to_string).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let album = DropDown::from_strings(&names);
+        album.set_enable_search(true);
+        album.set_hexpand(true);
+        let target_names: Vec<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+        let target = DropDown::from_strings(&target_names);
+        grid.attach(
+            &Label::builder().label("Disc").xalign(0.0).build(),
+            0,
+            0,
+            1,
+            1,
+        );
+        grid.attach(&album, 1, 0, 1, 1);
+        grid.attach(
+            &Label::builder().label("Profile").xalign(0.0).build(),
+            0,
+            1,
+            1,
+            1,
+        );
+        grid.attach(&target, 1, 1, 1, 1);
+        let progress = ProgressBar::builder()
+            .show_text(true)
+            .visible(false)
+            .build();
+        grid.attach(&progress, 0, 2, 2, 1);
+        let button_box = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(10)
+            .halign(Align::End)
+            .build();
+        let encode_button = Button::builder().label("Encode").build();
+        button_box.append(&encode_button);
+        let close_button = Button::builder().label("Close").build();
+        button_box.append(&close_button);
+        grid.attach(&button_box, 0, 3, 2, 1);
+
+        let dialog = Dialog::builder()
+            .title("Encode from FLAC")
+            .modal(true)
+            .child(&frame)
+            .default_width(500)
+            .transient_for(&window)
+            .build();
+        let cancel = CancelToken::new();
+        // closing the dialog any way stops the transcode, there is nothing left to show it
+        dialog.connect_close_request(glib::clone!(@strong cancel => move |_| {
+            cancel.cancel();
+            glib::Propagation::Proceed
+        }));
+        close_button.connect_clicked(glib::clone!(@weak dialog => move |_| dialog.close()));
+        let window = window.clone();
+        encode_button.connect_clicked(glib::clone!(@weak dialog => move |button| {
+            let (Some(archive), Some(profile)) = (
+                archives.get(album.selected() as usize).cloned(),
+                targets.get(target.selected() as usize).cloned(),
+            ) else {
+                return;
+            };
+            button.set_sensitive(false);
+            progress.set_fraction(0.0);
+            progress.set_visible(true);
+            let tracks = archive.files.len().max(1) as f64;
+            let (tx, rx) = async_channel::bounded(STATUS_CAPACITY);
+            let (done_tx, done_rx) = async_channel::bounded(1);
+            let (config, cancel) = (config.clone(), cancel.clone());
+            thread::spawn(move || {
+                let result = transcode(&archive, &profile, &config, &tx, &cancel);
+                drop(tx);
+                done_tx.send_blocking((result, cancel.is_cancelled())).ok();
+            });
+            let (button, progress, window) = (button.clone(), progress.clone(), window.clone());
+            glib::spawn_future_local(async move {
+                let mut finished = 0.0;
+                while let Ok(status) = rx.recv().await {
+                    if let Status::Progress { title, percent, state, .. } = status {
+                        let current = if state == TrackState::Done {
+                            finished += 1.0;
+                            0.0
+                        } else {
+                            percent / 100.0
+                        };
+                        progress.set_fraction(((finished + current) / tracks).min(1.0));
+                        progress.set_text(Some(&title));
                     }
-                    if s == "done" {
-                        scan_button_clone.set_sensitive(true);
-                        go_button_clone.set_sensitive(true);
-                        stop_button_clone.set_sensitive(false);
-                        break;
+                }
+                button.set_sensitive(true);
+                match done_rx.recv().await {
+                    Ok((Ok(report), _)) => {
+                        dialog.close();
+                        let folder = report
+                            .first()
+                            .and_then(|encoded| encoded.path.parent())
+                            .map(|folder| folder.display().to_string())
+                            .unwrap_or_default();
+                        let message = format!("Encoded {} file(s) to {folder}", report.len());
+                        show_message(&message, MessageType::Info, &window);
+                    }
+                    // closed by the user
+                    Ok((Err(_), true)) | Err(_) => (),
+                    Ok((Err(e), false)) => {
+                        let message = format!("Encoding stopped: {e}");
+                        show_message(&message, MessageType::Error, &window);
                     }
                 }
             });
+        }));
+        dialog.present();
+    });
+}
+
+/// Let the user pick the release of the disc, `on_chosen` is only called when confirmed
+fn choose_release(
+    window: &ApplicationWindow,
+    releases: Vec<Release>,
+    on_chosen: impl Fn(&Release) + 'static,
+) {
+    let frame = Frame::builder()
+        .margin_top(10)
+        .margin_bottom(10)
+        .margin_start(10)
+        .margin_end(10)
+        .build();
+    let child = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(10)
+        .build();
+    frame.set_child(Some(&child));
+    let label = Label::builder()
+        .label("This disc matches several releases, choose the one you have:")
+        .xalign(0.0)
+        .build();
+    child.append(&label);
+    let list = ListBox::new();
+    for release in &releases {
+        let row = Label::builder()
+            .label(release.to_string())
+            .xalign(0.0)
+            .margin_top(5)
+            .margin_bottom(5)
+            .build();
+        list.append(&row);
+    }
+    list.select_row(list.row_at_index(0).as_ref());
+    let scroll = ScrolledWindow::builder()
+        .child(&list)
+        .min_content_height(200)
+        .vexpand(true)
+        .build();
+    child.append(&scroll);
+    let button_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(10)
+        .halign(Align::End)
+        .build();
+    let ok_button = Button::builder().label("Ok").build();
+    button_box.append(&ok_button);
+    let cancel_button = Button::builder().label("Cancel").build();
+    button_box.append(&cancel_button);
+    child.append(&button_box);
+
+    let dialog = Dialog::builder()
+        .title("Choose the release")
+        .modal(true)
+        .child(&frame)
+        .width_request(500)
+        .transient_for(window)
+        .build();
+    ok_button.connect_clicked(glib::clone!(@weak dialog, @weak list => move |_| {
+        let selected = list
+            .selected_row()
+            .and_then(|row| releases.get(usize::try_from(row.index()).ok()?));
+        dialog.close();
+        if let Some(release) = selected {
+            on_chosen(release);
+        }
+    }));
+    cancel_button.connect_clicked(glib::clone!(@weak dialog => move |_| {
+        dialog.close();
+    }));
+    dialog.present();
+}
+
+/// Show the front cover next to the disc title, hide the picture when there is none
+fn show_cover(picture: &Picture, cover: Option<&[u8]>) {
+    let texture = cover.and_then(
+        |cover| match Texture::from_bytes(&glib::Bytes::from(cover)) {
+            Ok(texture) => Some(texture),
+            Err(e) => {
+                debug!("Failed to load the cover: {e}");
+                None
+            }
+        },
+    );
+    picture.set_paintable(texture.as_ref());
+    picture.set_visible(texture.is_some());
+}
+
+/// Show a passing event at the bottom of the window, it goes away by itself
+fn show_toast(builder: &Builder, message: &str) {
+    let toast: Revealer = builder.object("toast").expect("Failed to get widget");
+    let label: Label = builder.object("toast_label").expect("Failed to get widget");
+    label.set_text(message);
+    toast.set_reveal_child(true);
+    let message = message.to_owned();
+    glib::timeout_add_seconds_local_once(TOAST_SECONDS, move || {
+        // a later toast gets its own time
+        if label.text() == message {
+            toast.set_reveal_child(false);
+        }
+    });
+}
+
+/// Show an error above the disc until it's dismissed or cleared with `None`
+fn show_banner(builder: &Builder, message: Option<&str>) {
+    let banner: Revealer = builder.object("banner").expect("Failed to get widget");
+    let label: Label = builder
+        .object("banner_label")
+        .expect("Failed to get widget");
+    if let Some(message) = message {
+        label.set_text(message);
+    }
+    banner.set_reveal_child(message.is_some());
+}
+
+fn show_message(message: &str, typ: MessageType, window: &ApplicationWindow) {
+    let title = match typ {
+        MessageType::Warning => "Warning",
+        MessageType::Info => "Information",
+        _ => "Error",
+    };
+    let dialog = MessageDialog::builder()
+        .title(title)
+        .modal(true)
+        .buttons(ButtonsType::Ok)
+        .message_type(typ)
+        .text(message)
+        .transient_for(window)
+        .width_request(300)
+        .build();
+    dialog.connect_response(glib::clone!(@weak dialog => move |_, _| {
+        dialog.close();
+    }));
+    dialog.show();
+}
+
+/// The rip works on a copy of the disc, tell the user edits don't change the running rip
+fn show_snapshot_label(builder: &Builder, ripping: bool) {
+    let label: Label = builder
+        .object("snapshot_label")
+        .expect("Failed to get widget");
+    label.set_visible(ripping);
+}
+
+/// Apply the text size and density of the preferences to the track list
+fn style_track_list(tree: &TreeView, config: &Config) {
+    let padding = if config.compact_track_list { 0 } else { 2 };
+    for column in tree.columns() {
+        for cell in column.cells() {
+            cell.set_padding(cell.padding().0, padding);
+            if cell.find_property("size-points").is_none() {
+                continue;
+            }
+            if config.track_list_font_size > 0 {
+                cell.set_property("size-points", f64::from(config.track_list_font_size));
+            } else {
+                // back to the size of the theme
+                cell.set_property("size-set", false);
+            }
+        }
+    }
+    tree.columns_autosize();
+}
+
+/// Show the progress of the track in its row of the track list
+/// `numbers` are the numbers of the tracks of the disc ripped, by track id
+fn show_track_progress(
+    tree: &TreeView,
+    numbers: &[u32],
+    number: u32,
+    percent: f64,
+    state: TrackState,
+) {
+    let Some(store) = tree.model().and_downcast::<ListStore>() else {
+        return;
+    };
+    let (value, text) = match state {
+        TrackState::Reading => (percent as i32, format!("reading {percent:.0} %")),
+        TrackState::Encoding => (percent as i32, format!("{percent:.0} %")),
+        TrackState::Done => (100, "done".to_owned()),
+        TrackState::Failed => (0, "failed".to_owned()),
+    };
+    store.foreach(|model, _, iter| {
+        let found = numbers.get(track_id(model, iter)) == Some(&number);
+        if found {
+            store.set(
+                iter,
+                &[(PROGRESS_COLUMN, &value), (PROGRESS_TEXT_COLUMN, &text)],
+            );
+        }
+        found
+    });
+}
+
+/// Clear the progress of all tracks before a rip
+fn reset_progress(tree: &TreeView) {
+    let Some(store) = tree.model().and_downcast::<ListStore>() else {
+        return;
+    };
+    store.foreach(|_, _, iter| {
+        store.set(iter, &[(PROGRESS_COLUMN, &0), (PROGRESS_TEXT_COLUMN, &"")]);
+        false
+    });
+}
+
+/// Send a desktop notification with the outcome of the rip
+fn notify_finished(window: &ApplicationWindow, album: &str, status: &Status, failed: usize) {
+    let Some(app) = window.application() else {
+        return;
+    };
+    let notification = match status {
+        Status::Done(report) => {
+            let notification = gio::Notification::new("Rip finished");
+            let mut body = format!("{album}: {} track(s) encoded", report.len());
+            if failed > 0 {
+                body.push_str(&format!(", {failed} failed"));
+            }
+            notification.set_body(Some(&body));
+            notification
+        }
+        _ => {
+            let notification = gio::Notification::new("Rip aborted");
+            notification.set_body(Some(album));
+            notification
         }
+    };
+    app.send_notification(Some("rip-finished"), &notification);
+}
+
+/// Tell the user the rip waits for the encode path, also when the window is hidden
+fn notify_paused(window: &ApplicationWindow, album: &str, reason: &str) {
+    let message = format!("Ripping {album} is paused, {reason}. It continues when it's back.");
+    if window.is_visible() {
+        show_message(&message, MessageType::Warning, window);
+    } else if let Some(app) = window.application() {
+        let notification = gio::Notification::new("Rip paused");
+        notification.set_body(Some(&message));
+        app.send_notification(Some("rip-paused"), &notification);
+    }
+}
+
+/// Play the encoded file of the selected track, or the last one encoded, a second click stops
+/// A track that isn't encoded is previewed from the CD while no rip is running
+/// The level bar shows the peaks played, the clip label that they reached full scale
+fn handle_play(
+    builder: &Builder,
+    data: &Arc<RwLock<Data>>,
+    encoded: Rc<RefCell<Vec<Encoded>>>,
+    control: RipControl,
+) {
+    let data = data.clone();
+    let play_button: Button = builder.object("play_button").expect("Failed to get widget");
+    let level_bar: LevelBar = builder.object("level_bar").expect("Failed to get widget");
+    let clip_label: Label = builder.object("clip_label").expect("Failed to get widget");
+    let tree: TreeView = builder
+        .object("track_listview")
+        .expect("Failed to get widget");
+    let window: ApplicationWindow = builder.object("window").expect("Failed to get widget");
+    let player = Player::new(
+        glib::clone!(@weak play_button, @weak level_bar => move || {
+            play_button.set_icon_name("media-playback-start");
+            level_bar.set_value(0.0);
+        }),
+        glib::clone!(@weak level_bar, @weak clip_label => move |peak| {
+            level_bar.set_value(meter_value(peak));
+            if is_clipping(peak) {
+                clip_label.set_visible(true);
+            }
+        }),
+    )
+    .map_err(|e| debug!("Playback is not available: {e}"))
+    .ok();
+    play_button.connect_clicked(move |button| {
+        let Some(player) = &player else {
+            let message = "Playing the files needs the playbin element (gst-plugins-base)";
+            show_message(message, MessageType::Error, &window);
+            return;
+        };
+        if player.is_playing() {
+            player.stop();
+            button.set_icon_name("media-playback-start");
+            level_bar.set_value(0.0);
+            return;
+        }
+        // the drive is busy while ripping
+        let preview = selected_track(&tree, &data)
+            .filter(|number| !encoded.borrow().iter().any(|e| e.number == *number))
+            .filter(|_| control.state.get() == RipState::Idle);
+        let track = preview.and_then(|number| {
+            let data = data.read().ok()?;
+            data.disc
+                .as_ref()?
+                .tracks
+                .iter()
+                .find(|t| t.number == number)
+                .cloned()
+        });
+        let (played, name) = if let Some(track) = track {
+            let config = Config::load().unwrap_or_default();
+            (
+                player.preview(&track, &config),
+                format!("track {}", track.number),
+            )
+        } else if let Some(file) = selected_file(&tree, &data, &encoded.borrow()) {
+            (player.play(&file.path), file.path.display().to_string())
+        } else {
+            return;
+        };
+        clip_label.set_visible(false);
+        match played {
+            Ok(()) => button.set_icon_name("media-playback-stop"),
+            Err(e) => {
+                let message = format!("Failed to play {name}: {e}");
+                show_message(&message, MessageType::Error, &window);
+            }
+        }
+    });
+}
+
+/// The number of the selected track
+fn selected_track(tree: &TreeView, data: &Arc<RwLock<Data>>) -> Option<u32> {
+    let (model, iter) = tree.selection().selected()?;
+    let id = track_id(&model, &iter);
+    let data = data.read().ok()?;
+    data.disc.as_ref()?.tracks.get(id).map(|track| track.number)
+}
+
+/// The encoded file of the selected track, or the last one encoded
+fn selected_file(
+    tree: &TreeView,
+    data: &Arc<RwLock<Data>>,
+    encoded: &[Encoded],
+) -> Option<Encoded> {
+    selected_track(tree, data)
+        .and_then(|number| encoded.iter().rev().find(|e| e.number == number))
+        .or(encoded.last())
+        .cloned()
+}
+
+/// Show the encoded file of the selected track in the file manager
+fn handle_reveal(builder: &Builder, data: &Arc<RwLock<Data>>, encoded: Rc<RefCell<Vec<Encoded>>>) {
+    let data = data.clone();
+    let reveal_button: Button = builder
+        .object("reveal_button")
+        .expect("Failed to get widget");
+    let tree: TreeView = builder
+        .object("track_listview")
+        .expect("Failed to get widget");
+    let window: ApplicationWindow = builder.object("window").expect("Failed to get widget");
+    reveal_button.connect_clicked(move |_| {
+        let Some(file) = selected_file(&tree, &data, &encoded.borrow()) else {
+            return;
+        };
+        if let Err(e) = reveal_file(&file.path) {
+            let message = format!("Failed to show {}: {e}", file.path.display());
+            show_message(&message, MessageType::Error, &window);
+        }
+    });
+}
+
+/// The tracks encoded, with a button to open the album folder
+fn show_rip_report(report: &[Encoded], window: &ApplicationWindow) {
+    let lines: Vec<String> = report.iter().map(ToString::to_string).collect();
+    let message = format!("Encoded {} track(s):\n\n{}", report.len(), lines.join("\n"));
+    let dialog = MessageDialog::builder()
+        .title("Information")
+        .modal(true)
+        .buttons(ButtonsType::Close)
+        .message_type(MessageType::Info)
+        .text(message)
+        .transient_for(window)
+        .width_request(300)
+        .build();
+    let folder = report
+        .first()
+        .and_then(|encoded| encoded.path.parent())
+        .map(Path::to_path_buf);
+    if folder.is_some() {
+        dialog.add_button("Open album folder", ResponseType::Accept);
+    }
+    dialog.connect_response(glib::clone!(@weak window => move |dialog, response| {
+        dialog.close();
+        if let (ResponseType::Accept, Some(folder)) = (response, &folder) {
+            if let Err(e) = open_folder(folder) {
+                let message = format!("Failed to open {}: {e}", folder.display());
+                show_message(&message, MessageType::Error, &window);
+            }
+        }
+    }));
+    dialog.show();
+}
+
+/// The configuration with the library and the format picked for the scanned disc
+fn disc_config(data: &Arc<RwLock<Data>>) -> Config {
+    let mut config: Config = Config::load().unwrap_or_default();
+    if let Some(route) = data.read().ok().and_then(|d| d.route.clone()) {
+        profile::choose_route(&mut config, &route);
+    }
+    if let Some(settings) = data.read().ok().and_then(|d| d.disc_settings.clone()) {
+        settings.apply(&mut config);
+    }
+    config
+}
+
+/// Ask whether to overwrite files that may be better than the new ones, or skip their tracks
+/// The rip starts with the answer by clicking the button again
+fn confirm_overwrites(
+    window: &ApplicationWindow,
+    risky: &[Overwrite],
+    button: &Button,
+    decided: &Rc<RefCell<Option<Vec<usize>>>>,
+) {
+    let files: Vec<String> = risky
+        .iter()
+        .map(|o| format!("{}: {}", o.path.display(), o.reason))
+        .collect();
+    let dialog = MessageDialog::builder()
+        .title("Overwrite files?")
+        .modal(true)
+        .message_type(MessageType::Warning)
+        .text(
+            "These files may be better than the new ones, e.g. a previous rip at a higher quality",
+        )
+        .secondary_text(files.join("\n"))
+        .transient_for(window)
+        .width_request(300)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Skip these tracks", ResponseType::Reject);
+    dialog.add_button("Overwrite", ResponseType::Accept);
+    let skipped: Vec<usize> = risky.iter().map(|o| o.index).collect();
+    let decided = decided.clone();
+    dialog.connect_response(glib::clone!(@weak button => move |dialog, response| {
+        dialog.close();
+        let answer = match response {
+            ResponseType::Accept => Vec::new(),
+            ResponseType::Reject => skipped.clone(),
+            _ => return,
+        };
+        decided.replace(Some(answer));
+        button.emit_clicked();
+    }));
+    dialog.show();
+}
+
+/// Ask before ripping in touch mode, there is no other step to check the disc
+fn confirm_rip(
+    window: &ApplicationWindow,
+    disc: &Disc,
+    button: &Button,
+    confirmed: &Rc<Cell<bool>>,
+) {
+    let tracks = disc.tracks.iter().filter(|t| t.rip).count();
+    let dialog = MessageDialog::builder()
+        .title("Rip the disc?")
+        .modal(true)
+        .message_type(MessageType::Question)
+        .text(format!(
+            "Rip {tracks} tracks of {} - {}?",
+            disc.artist, disc.title
+        ))
+        .transient_for(window)
+        .build();
+    dialog.add_css_class("touch");
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Rip", ResponseType::Accept);
+    let confirmed = confirmed.clone();
+    dialog.connect_response(glib::clone!(@weak button => move |dialog, response| {
+        dialog.close();
+        if response == ResponseType::Accept {
+            confirmed.set(true);
+            button.emit_clicked();
+        }
+    }));
+    dialog.show();
+}
+
+fn handle_go(
+    cancel: CancelToken,
+    data: Arc<RwLock<Data>>,
+    builder: &Builder,
+    exporter: Option<StatusExporter>,
+    encoded: Rc<RefCell<Vec<Encoded>>>,
+    control: RipControl,
+) {
+    let builder = builder.clone();
+    let go_button: Button = builder.object("go_button").expect("Failed to get widget");
+    let play_button: Button = builder.object("play_button").expect("Failed to get widget");
+    let reveal_button: Button = builder
+        .object("reveal_button")
+        .expect("Failed to get widget");
+    let progress_bar: ProgressBar = builder
+        .object("rip_progress")
+        .expect("Failed to get widget");
+    let window: ApplicationWindow = builder.object("window").expect("Failed to get widget");
+    // the tracks to leave out, once the user was asked about overwriting better files
+    let decided: Rc<RefCell<Option<Vec<usize>>>> = Rc::default();
+    // the rip was confirmed in touch mode
+    let confirmed: Rc<Cell<bool>> = Rc::default();
+    go_button.connect_clicked(glib::clone!(@weak progress_bar, @weak window => move |button| {
+        let Some(mut disc) = data.read().ok().and_then(|d| d.disc.clone()) else {
+            return;
+        };
+        if decided.borrow().is_none()
+            && !control.is_auto()
+            && Config::load().unwrap_or_default().touch_mode
+            && !confirmed.replace(false)
+        {
+            confirm_rip(&window, &disc, button, &confirmed);
+            return;
+        }
+        let skipped = match decided.take() {
+            Some(skipped) => skipped,
+            None => {
+                let mut config = disc_config(&data);
+                prepare_config(&mut config, &disc);
+                let risky = risky_overwrites(&disc, &config);
+                if risky.is_empty() {
+                    Vec::new()
+                } else if control.is_auto() {
+                    // nobody to ask, the files that may be better are kept
+                    show_toast(&builder, "Tracks ripped before were skipped");
+                    risky.iter().map(|o| o.index).collect()
+                } else {
+                    confirm_overwrites(&window, &risky, button, &decided);
+                    return;
+                }
+            }
+        };
+        for index in skipped {
+            if let Some(track) = disc.tracks.get_mut(index) {
+                track.rip = false;
+            }
+        }
+        // a click that got through before the button was made insensitive is ignored
+        if !control.handle(RipEvent::Start) {
+            return;
+        }
+        cancel.reset();
+        encoded.borrow_mut().clear();
+        play_button.set_sensitive(false);
+        reveal_button.set_sensitive(false);
+        show_snapshot_label(&builder, true);
+        let mut config = disc_config(&data);
+        // the Auto mode waits for the next disc
+        config.eject_when_done |= control.is_auto();
+        window.set_hide_on_close(config.background);
+        let album = disc.title.clone();
+        let notify = config.notify_when_done;
+        let tree: TreeView = builder
+            .object("track_listview")
+            .expect("Failed to get widget");
+        let order = tree.model().map(|m| row_order(&m)).unwrap_or_default();
+        let numbers: Vec<u32> = disc.tracks.iter().map(|track| track.number).collect();
+        // a disc image is reported as one track
+        let total = if config.disc_image {
+            1.0
+        } else {
+            order
+                .iter()
+                .filter(|i| disc.tracks.get(**i).is_some_and(|t| t.rip))
+                .count()
+                .max(1) as f64
+        };
+        // tracks are read from the disc before they are encoded, a disc image in one go
+        let read_share = if config.disc_image { 0.0 } else { 0.5 };
+        let mut done = HashSet::new();
+        let mut failed = 0;
+        // the tracks being read or encoded, with how far they are
+        let mut busy = HashMap::new();
+        reset_progress(&tree);
+        progress_bar.set_fraction(0.0);
+        progress_bar.set_text(None);
+        progress_bar.set_visible(true);
+        let exporter = exporter.clone();
+        let encoded = encoded.clone();
+        let play_button = play_button.clone();
+        let reveal_button = reveal_button.clone();
+        let rx = start_rip(disc, order, config, cancel.clone());
+        let control = control.clone();
+        let builder = builder.clone();
+        glib::spawn_future_local(async move {
+            loop {
+                // the channel closes without an outcome when the rip thread panicked
+                let mut value = rx.recv().await.unwrap_or_else(|_| {
+                    error!("The rip ended without an outcome");
+                    Status::Aborted
+                });
+                // only the latest progress matters when the UI fell behind
+                while matches!(
+                    value,
+                    Status::Progress {
+                        state: TrackState::Reading | TrackState::Encoding,
+                        ..
+                    }
+                ) {
+                    match rx.try_recv() {
+                        Ok(next) => value = next,
+                        Err(_) => break,
+                    }
+                }
+                if let Status::Progress { number, percent, state, .. } = &value {
+                    show_track_progress(&tree, &numbers, *number, *percent, *state);
+                    match state {
+                        TrackState::Reading => {
+                            busy.insert(*number, percent / 100.0 * read_share);
+                        }
+                        TrackState::Encoding => {
+                            busy.insert(*number, read_share + percent / 100.0 * (1.0 - read_share));
+                        }
+                        TrackState::Done => {
+                            busy.remove(number);
+                            done.insert(*number);
+                        }
+                        TrackState::Failed => {
+                            busy.remove(number);
+                            failed += 1;
+                            show_toast(&builder, &format!("Track {number} failed"));
+                        }
+                    }
+                    let current: f64 = busy.values().sum();
+                    let fraction = ((done.len() as f64 + current) / total).min(1.0);
+                    progress_bar.set_fraction(fraction);
+                    progress_bar.set_text(Some(&value.to_string()));
+                    window.set_title(Some(&format!("{:.0}% - ripperX", fraction * 100.0)));
+                }
+                if matches!(value, Status::Recovering { .. } | Status::Paused(_) | Status::Resumed) {
+                    progress_bar.set_text(Some(&value.to_string()));
+                }
+                if let Status::Paused(reason) = &value {
+                    show_banner(&builder, Some(&format!("Ripping paused: {reason}")));
+                    notify_paused(&window, &album, reason);
+                }
+                if value == Status::Resumed {
+                    show_banner(&builder, None);
+                    show_toast(&builder, "Ripping resumed");
+                }
+                if let Status::Encoded(file) = &value {
+                    encoded.borrow_mut().push(file.clone());
+                    play_button.set_sensitive(true);
+                    reveal_button.set_sensitive(true);
+                }
+                if let Some(exporter) = &exporter {
+                    exporter.update(&album, &value);
+                }
+                if matches!(value, Status::Aborted | Status::Done(_)) {
+                    control.handle(RipEvent::Ended);
+                    progress_bar.set_text(Some(&value.to_string()));
+                    // the rip may have ended while paused
+                    show_banner(&builder, None);
+                    if value == Status::Aborted {
+                        show_toast(&builder, "Ripping stopped");
+                        control.stop_auto();
+                    }
+                    show_snapshot_label(&builder, false);
+                    window.set_title(None);
+                    window.set_hide_on_close(false);
+                    if !window.is_visible() {
+                        // running in the background, tell the user and quit once the mail, the
+                        // post rip hooks and the eject are done
+                        notify_finished(&window, &album, &value, failed);
+                        while rx.recv().await.is_ok() {}
+                        window.close();
+                        break;
+                    }
+                    if notify {
+                        notify_finished(&window, &album, &value, failed);
+                    }
+                    if let Status::Done(report) = &value {
+                        if control.is_auto() {
+                            show_toast(&builder, &format!("{album} ripped, insert the next disc"));
+                        } else if !report.is_empty() {
+                            show_rip_report(report, &window);
+                        }
+                    }
+                    break;
+                }
+            }
+        });
     }));
 }
@@ -1,36 +1,647 @@
-use discid::{DiscError, DiscId};
-use log::debug;
+use anyhow::{anyhow, Result};
+use discid::DiscId;
+use log::{debug, error, warn};
+use std::{fs, path::Path, thread};
 
-use crate::data::{Config, Disc};
+use crate::{
+    accuraterip::disc_id,
+    automation::Automation,
+    cache::{remember, MetadataCache},
+    coverart::fetch_cover,
+    data::{CdBackend, Config, Disc, Segment, Track},
+    duration::{sectors_to_msf, sectors_to_seconds, toc_sectors, SECTORS_PER_SECOND},
+    genre::GenreMap,
+    musicbrainz::{latin_names, Release},
+    remote::read_toc,
+    script::detect,
+    udisks,
+};
 
-pub fn scan_disc() -> Result<DiscId, DiscError> {
-    let config: Config = confy::load("ripperx4", None).expect("failed to load config");
+/// Difference between the length of a track in the metadata and on the disc that is still the
+/// same track, the TOC counts the pregap of the next track
+const LENGTH_TOLERANCE_MS: u64 = 5000;
+/// The gap between the audio and the data session of an Enhanced CD, the TOC counts it in the
+/// last audio track (152 seconds)
+const DATA_SESSION_GAP_MS: u64 = 11_400 * 1000 / SECTORS_PER_SECOND;
+
+/// The configured CD drive, or the default one
+pub fn drive(config: &Config) -> String {
+    config.device.clone().unwrap_or_else(DiscId::default_device)
+}
+
+/// The CD drives of the system, e.g. `/dev/sr0` and `/dev/sr1`, or `D:` on Windows
+/// From udisks2 when it's running, else from sysfs
+pub fn cd_devices() -> Vec<String> {
+    if cfg!(target_os = "linux") {
+        match udisks::cd_devices() {
+            Ok(devices) if !devices.is_empty() => devices,
+            _ => cd_devices_in(Path::new("/sys/class/block"), Path::new("/dev")),
+        }
+    } else {
+        let devices = windows_cd_drives();
+        if devices.is_empty() {
+            vec![DiscId::default_device()]
+        } else {
+            devices
+        }
+    }
+}
+
+/// The letters of the optical drives, as libcdio finds them: the drives Windows reports as
+/// `DRIVE_CDROM`
+#[cfg(windows)]
+fn windows_cd_drives() -> Vec<String> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetLogicalDrives() -> u32;
+        fn GetDriveTypeW(root: *const u16) -> u32;
+    }
+    const DRIVE_CDROM: u32 = 5;
+    // SAFETY: takes no arguments, a bit mask of the drive letters in use
+    let mask = unsafe { GetLogicalDrives() };
+    drive_letters(mask, |drive| {
+        let root: Vec<u16> = format!("{drive}\\").encode_utf16().chain([0]).collect();
+        // SAFETY: `root` is a NUL terminated wide string that outlives the call
+        unsafe { GetDriveTypeW(root.as_ptr()) == DRIVE_CDROM }
+    })
+}
+
+#[cfg(not(windows))]
+fn windows_cd_drives() -> Vec<String> {
+    Vec::new()
+}
+
+/// The drives of `mask` (bit 0 is `A:`) that `is_cd` takes, e.g. `D:`
+#[cfg(any(windows, test))]
+fn drive_letters(mask: u32, is_cd: impl Fn(&str) -> bool) -> Vec<String> {
+    (b'A'..=b'Z')
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, letter)| format!("{}:", letter as char))
+        .filter(|drive| is_cd(drive))
+        .collect()
+}
+
+/// The optical drives listed in `blocks` (sysfs), as devices in `dev`
+fn cd_devices_in(blocks: &Path, dev: &Path) -> Vec<String> {
+    let mut devices: Vec<String> = fs::read_dir(blocks)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with("sr"))
+                .map(|name| dev.join(name).display().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    devices.sort();
+    devices
+}
+
+/// Whether there is a disc in the configured drive, from udisks2 without reading the drive
+/// `None` when udisks2 can't tell, e.g. for a remote drive
+pub fn disc_present() -> Option<bool> {
+    let config = Config::load().ok()?;
+    if config.fake_cdrom
+        || matches!(
+            (config.cd_backend, &config.remote_drive),
+            (CdBackend::Remote, Some(_))
+        )
+    {
+        return None;
+    }
+    udisks::has_media(&drive(&config)).ok()
+}
+
+pub fn scan_disc() -> Result<DiscId> {
+    let config = Config::load()?;
     debug!("fake={}", config.fake_cdrom);
-    match DiscId::read(Some(&DiscId::default_device())) {
-        Ok(discid) => Ok(discid),
+    let read = match (config.cd_backend, &config.remote_drive) {
+        (CdBackend::Remote, Some(remote)) => read_toc(remote, config.remote_token.as_deref()),
+        _ => DiscId::read(Some(&drive(&config))).map_err(Into::into),
+    };
+    let discid = match read {
+        Ok(discid) => discid,
         Err(e) => {
             if config.fake_cdrom {
                 debug!("fake_cdrom is set, using hardcoded offsets");
                 // for testing on machine without CDROM drive: hardcode offsets of a dire straits disc
-                Ok(fake_discid())
+                fake_discid()
             } else {
-                Err(e)
+                return Err(e);
             }
         }
+    };
+    Ok(discid)
+}
+
+/// Run the `on_scan` hook of the configured script for the disc the user scanned to rip it
+pub fn scan_hook(discid: &DiscId) {
+    if let Some(automation) = Config::load()
+        .ok()
+        .as_ref()
+        .and_then(Automation::configured)
+    {
+        automation.on_scan(&discid.id());
+    }
+}
+
+/// Look up the metadata of the disc
+/// When no metadata is found an unknown `Disc` is returned with the reason for the user
+pub fn lookup_disc(discid: &DiscId) -> (Disc, Option<String>) {
+    let id = discid.id();
+    debug!("id={id}");
+    // discs looked up or edited before don't need the network
+    let metadata = match MetadataCache::load().get(&id) {
+        Some(disc) => Ok(disc),
+        None => lookup_metadata(discid, &Config::load().unwrap_or_default()),
+    };
+    disc_from_lookup(discid, metadata)
+}
+
+/// Look up the disc in one of the `Config::metadata_sources`
+fn lookup_source(source: &str, discid: &DiscId, config: &Config) -> Result<Disc> {
+    match source {
+        "musicbrainz" => crate::musicbrainz::lookup(&discid.id(), config),
+        "gnudb" => crate::gnudb::lookup(discid, config),
+        "cdtext" => crate::cdtext::lookup(config, u32::try_from(discid.tracks().count())?),
+        _ => Err(anyhow!("unknown metadata source {source}")),
+    }
+}
+
+/// The TOC of a disc, a `DiscId` can't be sent to another thread, the copy can
+#[derive(Clone, Debug)]
+pub struct Toc {
+    first: i32,
+    /// the lead-out, then the offset of each track
+    offsets: Vec<i32>,
+}
+
+impl Toc {
+    pub fn of(discid: &DiscId) -> Toc {
+        Toc {
+            first: discid.first_track_num(),
+            offsets: std::iter::once(discid.sectors())
+                .chain(discid.tracks().map(|t| t.offset))
+                .collect(),
+        }
+    }
+
+    /// A `DiscId` with the same TOC, and so the same ids
+    pub fn discid(&self) -> Result<DiscId> {
+        Ok(DiscId::put(self.first, &self.offsets)?)
     }
 }
 
+/// Look up the disc in all the configured sources at once, the first one in order that knows it
+/// wins
+/// Fails with the errors of all sources
+pub fn lookup_metadata(discid: &DiscId, config: &Config) -> Result<Disc> {
+    // each lookup gets its own copy of the TOC
+    let toc = Toc::of(discid);
+    first_found(&config.metadata_sources, |source| {
+        lookup_source(source, &toc.discid()?, config)
+    })
+}
+
+/// What the lookup of a disc in the sources found
+/// Returned once per lookup, the size of the disc doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum Found {
+    Disc(Disc),
+    /// MusicBrainz knows several releases with the disc id, the user picks one
+    Releases(Vec<Release>),
+}
+
+/// Look up the disc in all the configured sources at once like `lookup_metadata`, except that
+/// when MusicBrainz knows several releases and `choose`, they are returned instead of the first
+/// The release the user picked before is looked up without asking again
+pub fn lookup_releases(discid: &DiscId, config: &Config, choose: bool) -> Result<Found> {
+    let toc = Toc::of(discid);
+    let id = discid.id();
+    let pinned = MetadataCache::load().release(&id).is_some();
+    first_found(&config.metadata_sources, |source| {
+        if source == "musicbrainz" && choose && !pinned {
+            let releases = crate::musicbrainz::releases(&id, config)?;
+            return match releases.as_slice() {
+                [release] => {
+                    crate::musicbrainz::lookup_release(&release.id, &id, config).map(Found::Disc)
+                }
+                [] => Err(anyhow!("failed to get release")),
+                _ => Ok(Found::Releases(releases)),
+            };
+        }
+        lookup_source(source, &toc.discid()?, config).map(Found::Disc)
+    })
+}
+
+/// Run the lookup of all sources concurrently, the result of the first source in order that
+/// found the disc
+fn first_found<T: Send>(
+    sources: &[String],
+    lookup: impl Fn(&str) -> Result<T> + Sync,
+) -> Result<T> {
+    let results: Vec<Result<T>> = thread::scope(|scope| {
+        let lookups: Vec<_> = sources
+            .iter()
+            .map(|source| scope.spawn(|| lookup(source)))
+            .collect();
+        lookups
+            .into_iter()
+            .map(|lookup| {
+                lookup
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("lookup panicked")))
+            })
+            .collect()
+    });
+    let mut errors = Vec::new();
+    for (source, result) in sources.iter().zip(results) {
+        match result {
+            Ok(found) => return Ok(found),
+            Err(e) => {
+                debug!("{source} lookup failed: {e}");
+                errors.push(format!("{source}: {e}"));
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Err(anyhow!("no metadata source configured"));
+    }
+    Err(anyhow!(errors.join(", ")))
+}
+
+/// Complete the metadata looked up for the scanned disc, or an empty disc if the lookup failed
+/// The message describes why no metadata was found, `Disc::lookup_warning` warns that the
+/// metadata doesn't match the disc; metadata that was found is cached
 #[allow(clippy::cast_sign_loss)]
-pub fn lookup_disc(discid: &DiscId) -> Disc {
-    debug!("id={}", discid.id());
-    if let Ok(disc) = crate::musicbrainz::lookup(&discid.id()) {
-        disc
+pub fn disc_from_lookup(discid: &DiscId, metadata: Result<Disc>) -> (Disc, Option<String>) {
+    let last = discid.last_track_num() as u32;
+    let first = discid.first_track_num() as u32;
+    let num: u32 = last.saturating_sub(first) + 1;
+    let (mut disc, error) = match metadata {
+        Ok(disc) => (disc, None),
+        Err(e) => {
+            debug!("MusicBrainz lookup failed: {e}");
+            let message = format!("No metadata found: {e}");
+            (Disc::with_tracks(num), Some(message))
+        }
+    };
+    let mismatch = align_tracks(&mut disc, num);
+    set_durations(&mut disc, discid);
+    // lengths of tracks assigned by position say nothing
+    let mismatch = mismatch.or_else(|| length_mismatch(&disc));
+    if disc.script.is_none() && error.is_none() {
+        let titles: Vec<&str> = disc.tracks.iter().map(|t| t.title.as_str()).collect();
+        disc.script = detect(&format!("{} {}", disc.title, titles.join(" "))).map(str::to_owned);
+    }
+    disc.discid = Some(discid.id());
+    disc.lookup_error.clone_from(&error);
+    disc.lookup_warning = mismatch;
+    let offsets: Vec<i32> = discid.tracks().map(|t| t.offset).collect();
+    disc.accuraterip_id = Some(disc_id(&offsets, discid.sectors(), &discid.freedb_id()));
+    disc.genre = disc
+        .genre
+        .and_then(|genre| GenreMap::load().normalize(&genre));
+    if let Ok(config) = Config::load() {
+        if config.classical {
+            for track in &mut disc.tracks {
+                track.title = track.classical_title();
+            }
+        }
+        let other_script = disc.script.as_deref().is_some_and(|s| s != "Latn");
+        // the Latin names and the cover come from different servers, both are fetched at once
+        let (latin, cover) = thread::scope(|scope| {
+            let cover = scope.spawn(|| config.cover_art.then(|| fetch_cover(&disc, &config)));
+            // for the sort tags, also when the files are named in the original script
+            let latin = (other_script && disc.latin_title.is_none()).then(|| {
+                let mut latin = disc.clone();
+                latin_names(&mut latin).map(|()| latin)
+            });
+            let cover = cover.join().unwrap_or_else(|_| {
+                error!("The cover art lookup panicked");
+                None
+            });
+            (latin, cover)
+        });
+        match latin {
+            Some(Ok(latin)) => disc = latin,
+            Some(Err(e)) => debug!("No Latin names: {e}"),
+            None => (),
+        }
+        match cover {
+            Some(Ok(cover)) => disc.cover = Some(cover),
+            Some(Err(e)) => debug!("No cover art: {e}"),
+            None => (),
+        }
+    }
+    if error.is_none() {
+        remember(&disc);
+    }
+    // after remembering, so the script changes the looked up metadata every time
+    if let Some(automation) = Config::load()
+        .ok()
+        .as_ref()
+        .and_then(Automation::configured)
+    {
+        automation.on_lookup(&mut disc);
+    }
+    (disc, error)
+}
+
+/// Match the tracks of the metadata to the `count` tracks of the TOC by index, the tracks the
+/// disc doesn't have are dropped and the missing ones get placeholders
+/// Returns a warning when they didn't match
+fn align_tracks(disc: &mut Disc, count: u32) -> Option<String> {
+    let found = disc.tracks.len();
+    if found == count as usize {
+        return None;
+    }
+    disc.tracks.truncate(count as usize);
+    let placeholders = Disc::with_tracks(count).tracks.into_iter().skip(found);
+    disc.tracks.extend(placeholders.map(|mut track| {
+        track.artist.clone_from(&disc.artist);
+        track
+    }));
+    Some(format!(
+        "The metadata has {found} tracks, the disc {count}: titles may belong to other tracks, check them before ripping"
+    ))
+}
+
+/// Warn about the tracks the metadata gives another length than the TOC, by more than
+/// `LENGTH_TOLERANCE_MS`: the release found is almost always another one than the disc
+/// The last track of an Enhanced CD is as much longer as the gap before the data session
+fn length_mismatch(disc: &Disc) -> Option<String> {
+    let last = disc.tracks.len().saturating_sub(1);
+    let numbers: Vec<String> = disc
+        .tracks
+        .iter()
+        .enumerate()
+        .filter(|(index, track)| {
+            let toc_ms = track.sectors * 1000 / SECTORS_PER_SECOND;
+            let differs = |length: u64, toc_ms: u64| length.abs_diff(toc_ms) > LENGTH_TOLERANCE_MS;
+            track.sectors > 0
+                && track.length_ms.is_some_and(|length| {
+                    differs(length, toc_ms)
+                        && (*index != last
+                            || differs(length, toc_ms.saturating_sub(DATA_SESSION_GAP_MS)))
+                })
+        })
+        .map(|(_, track)| track)
+        .map(|track| track.number.to_string())
+        .collect();
+    (!numbers.is_empty()).then(|| {
+        format!(
+            "The metadata gives track {} another length than the disc: it may be another release, check it before ripping",
+            numbers.join(", ")
+        )
+    })
+}
+
+/// Fill in the track durations (in seconds) from the TOC
+fn set_durations(disc: &mut Disc, discid: &DiscId) {
+    for (track, toc) in disc.tracks.iter_mut().zip(discid.tracks()) {
+        // unknown, the track is ripped to its end anyway
+        track.sectors = toc_sectors(toc.sectors).unwrap_or_else(|| {
+            warn!("track {} can't be {} sectors long", toc.number, toc.sectors);
+            0
+        });
+        track.duration = sectors_to_seconds(track.sectors);
+    }
+}
+
+/// Look for signs of copy protection or a damaged disc in the TOC
+/// Returns a description of each problem found
+pub fn toc_warnings(discid: &DiscId) -> Vec<String> {
+    let tracks: Vec<(i32, i32, i32)> = discid
+        .tracks()
+        .map(|t| (t.number, t.offset, t.sectors))
+        .collect();
+    check_toc(&tracks, discid.sectors())
+}
+
+/// Check the (number, offset, sectors) of each track against the Red Book limits
+fn check_toc(tracks: &[(i32, i32, i32)], leadout: i32) -> Vec<String> {
+    // 2 seconds of pregap, tracks of at least 4 seconds, at most 99:59:74 in total
+    const MIN_OFFSET: i32 = 150;
+    const MIN_SECTORS: i32 = 4 * SECTORS_PER_SECOND as i32;
+    const MAX_LEADOUT: i32 = 100 * 60 * SECTORS_PER_SECOND as i32;
+    let mut warnings = Vec::new();
+    if tracks
+        .first()
+        .is_some_and(|(_, offset, _)| *offset < MIN_OFFSET)
+    {
+        warnings.push("the first track starts before the 2 second pregap".to_owned());
+    }
+    for (i, (number, offset, sectors)) in tracks.iter().enumerate() {
+        if *sectors < MIN_SECTORS {
+            warnings.push(format!(
+                "track {number} is shorter than 4 seconds ({sectors} sectors)"
+            ));
+        }
+        let next = tracks.get(i + 1).map_or(leadout, |(_, offset, _)| *offset);
+        if offset + sectors > next {
+            warnings.push(format!("track {number} overlaps the next track"));
+        }
+    }
+    if leadout > MAX_LEADOUT {
+        warnings.push(format!(
+            "the disc claims to be longer than 100 minutes ({leadout} sectors)"
+        ));
+    }
+    warnings
+}
+
+/// Split a pasted track list into titles, one per non-empty line
+/// Leading track numbers ("1.", "01 -", "3)") and trailing durations ("5:48") are stripped
+pub fn parse_track_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| strip_duration(strip_track_number(line.trim())).to_owned())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Strip a leading track number, but only when followed by a separator so "99 Luftballons" stays intact
+fn strip_track_number(line: &str) -> &str {
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == line.len() {
+        return line;
+    }
+    if let Some(rest) = rest.strip_prefix('\t') {
+        return rest.trim_start();
+    }
+    rest.trim_start_matches(' ')
+        .strip_prefix(['.', ')', '-', ':'])
+        .map_or(line, str::trim_start)
+}
+
+/// Strip a trailing duration like "5:48" or "(5:48)"
+fn strip_duration(line: &str) -> &str {
+    let Some((title, last)) = line.rsplit_once(char::is_whitespace) else {
+        return line;
+    };
+    let last = last.trim_start_matches('(').trim_end_matches(')');
+    let is_duration = last.split_once(':').is_some_and(|(min, sec)| {
+        !min.is_empty()
+            && sec.len() == 2
+            && min.chars().chain(sec.chars()).all(|c| c.is_ascii_digit())
+    });
+    if is_duration {
+        title.trim_end()
     } else {
-        let last = discid.last_track_num() as u32;
-        let first = discid.first_track_num() as u32;
-        let num: u32 = last.saturating_sub(first) + 1;
-        Disc::with_tracks(num)
+        line
+    }
+}
+
+/// Parse the segments of a track, one per line as `start-end title`, e.g. `3:25-7:02 Part two`
+/// The end can be left out for the last segment, to rip up to the end of the track
+/// A line with only a start time, e.g. `3:25 Part two`, is a cue point: it runs up to the next line
+pub fn parse_segments(text: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut cue_points = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (range, title) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let (start, end) = range.split_once('-').unwrap_or((range, ""));
+        let start = parse_time(start).ok_or(format!("invalid start time in \"{line}\""))?;
+        let end = match end {
+            "" => None,
+            end => Some(parse_time(end).ok_or(format!("invalid end time in \"{line}\""))?),
+        };
+        cue_points.push(!range.contains('-'));
+        segments.push(Segment {
+            title: title.trim().to_owned(),
+            start,
+            end,
+        });
+    }
+    let next_starts: Vec<u64> = segments.iter().skip(1).map(|s| s.start).collect();
+    for ((segment, next_start), cue_point) in segments.iter_mut().zip(next_starts).zip(cue_points) {
+        if cue_point {
+            segment.end = Some(next_start);
+        }
     }
+    if let Some(segment) = segments
+        .iter()
+        .find(|s| s.end.is_some_and(|end| end <= s.start))
+    {
+        return Err(format!(
+            "\"{}\" ends before it starts, at {}",
+            segment.title,
+            format_time(segment.start)
+        ));
+    }
+    Ok(segments)
+}
+
+/// Segments from the tracks of a CUE sheet, the times are relative to the start of its file
+/// Each track starts at its `INDEX 01` and runs up to the next one
+pub fn parse_cue(text: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut title = None;
+    let mut indexed = false;
+    for line in text.lines().map(str::trim) {
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command.to_ascii_uppercase().as_str() {
+            "TRACK" => {
+                title = Some(String::new());
+                indexed = false;
+            }
+            "TITLE" if title.is_some() => {
+                let value = rest.trim_matches('"').to_owned();
+                match segments.last_mut() {
+                    Some(segment) if indexed => segment.title = value,
+                    _ => title = Some(value),
+                }
+            }
+            "INDEX" if title.is_some() && !indexed => {
+                let time = rest.strip_prefix("01").map(str::trim);
+                if let Some(start) = time.and_then(parse_cue_time) {
+                    segments.push(Segment {
+                        title: title.clone().unwrap_or_default(),
+                        start,
+                        end: None,
+                    });
+                    indexed = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    let next_starts: Vec<u64> = segments.iter().skip(1).map(|s| s.start).collect();
+    for (segment, next_start) in segments.iter_mut().zip(next_starts) {
+        segment.end = Some(next_start);
+    }
+    segments
+}
+
+/// Seconds from a CUE `mm:ss:ff` time, the frames (75 per second) are rounded
+fn parse_cue_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(minutes * 60 + seconds + u64::from(frames * 2 >= 75))
+}
+
+/// A CUE sheet for the tracks ripped to one image file, the tracks follow each other in the file
+pub fn cue_sheet(disc: &Disc, tracks: &[&Track], file_name: &str) -> String {
+    // quotes can't be escaped in a CUE sheet
+    let quote = |value: &str| format!("\"{}\"", value.replace('"', "'"));
+    let mut cue = String::new();
+    if let Some(genre) = &disc.genre {
+        cue.push_str(&format!("REM GENRE {}\n", quote(genre)));
+    }
+    if let Some(year) = disc.year {
+        cue.push_str(&format!("REM DATE {year}\n"));
+    }
+    if let Some(discid) = &disc.discid {
+        cue.push_str(&format!("REM MUSICBRAINZ_DISCID {discid}\n"));
+    }
+    cue.push_str(&format!("PERFORMER {}\n", quote(&disc.artist)));
+    cue.push_str(&format!("TITLE {}\n", quote(&disc.title)));
+    cue.push_str(&format!("FILE {} WAVE\n", quote(file_name)));
+    let mut start = 0;
+    for (i, track) in tracks.iter().enumerate() {
+        cue.push_str(&format!("  TRACK {:02} AUDIO\n", i + 1));
+        cue.push_str(&format!("    TITLE {}\n", quote(&track.title)));
+        cue.push_str(&format!("    PERFORMER {}\n", quote(&track.artist)));
+        if let Some(composer) = &track.composer {
+            cue.push_str(&format!("    SONGWRITER {}\n", quote(composer)));
+        }
+        let (minutes, seconds, frames) = sectors_to_msf(start);
+        cue.push_str(&format!(
+            "    INDEX 01 {minutes:02}:{seconds:02}:{frames:02}\n"
+        ));
+        start += track.sectors;
+    }
+    cue
+}
+
+/// The segments in the format `parse_segments` reads
+pub fn format_segments(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            let end = segment.end.map(format_time).unwrap_or_default();
+            format!("{}-{end} {}\n", format_time(segment.start), segment.title)
+        })
+        .collect()
+}
+
+/// Seconds from `m:ss` or `h:mm:ss`, the minutes and seconds after the first part are below 60
+fn parse_time(time: &str) -> Option<u64> {
+    if time.split(':').count() > 3 {
+        return None;
+    }
+    let mut parts = time.split(':');
+    let first = parts.next()?.parse::<u64>().ok()?;
+    parts.try_fold(first, |total, part| {
+        let part = part.parse::<u64>().ok().filter(|part| *part < 60)?;
+        total.checked_mul(60)?.checked_add(part)
+    })
+}
+
+pub fn format_time(seconds: u64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
 }
 
 fn fake_discid() -> DiscId {
@@ -44,6 +655,13 @@ fn fake_discid() -> DiscId {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Mutex,
+        },
+        time::Duration,
+    };
 
     fn bad_discid() -> DiscId {
         let offsets = [450, 150, 300];
@@ -52,14 +670,18 @@ mod test {
 
     #[test]
     fn test_lookup_disc_dire_straits() {
-        let disc = lookup_disc(&fake_discid());
+        let (disc, error) = lookup_disc(&fake_discid());
+        assert!(error.is_none());
         assert_eq!(disc.tracks.len(), 12);
         assert_eq!(disc.title, "Money for Nothing");
+        // (26155 - 183) / 75
+        assert_eq!(disc.tracks[0].duration, 346);
     }
 
     #[test]
     fn test_lookup_disc_bad_discid() {
-        let disc = lookup_disc(&bad_discid());
+        let (disc, error) = lookup_disc(&bad_discid());
+        assert!(error.is_some());
         assert_eq!(disc.tracks.len(), 2);
         assert_eq!(disc.title, "Unknown");
         assert_eq!(disc.artist, "Unknown");
@@ -68,4 +690,261 @@ mod test {
         assert_eq!(disc.tracks[1].title, "Unknown");
         assert_eq!(disc.tracks[1].artist, "Unknown");
     }
+
+    #[test]
+    fn test_first_found() {
+        let sources = vec!["first".to_owned(), "second".to_owned(), "none".to_owned()];
+        let calls = AtomicUsize::new(0);
+        let (done, second_done) = mpsc::channel();
+        let second_done = Mutex::new(second_done);
+        let lookup = |source: &str| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            match source {
+                "none" => return Err(anyhow!("not found")),
+                "second" => done.send(()).expect("first gave up"),
+                // only when the sources are asked at the same time
+                _ => second_done
+                    .lock()
+                    .expect("poisoned")
+                    .recv_timeout(Duration::from_secs(10))
+                    .map_err(|_| anyhow!("second wasn't asked"))?,
+            }
+            let mut disc = Disc::with_tracks(1);
+            disc.title = source.to_owned();
+            Ok(disc)
+        };
+        // the second source is done first, the order decides
+        assert_eq!("first", first_found(&sources, lookup).unwrap().title);
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+        let error = first_found(&sources[2..], lookup).unwrap_err();
+        assert_eq!("none: not found", error.to_string());
+        assert!(first_found(&[], lookup).is_err());
+    }
+
+    #[test]
+    fn test_align_tracks() {
+        let mut disc = Disc::with_tracks(3);
+        disc.artist = "Dire Straits".to_owned();
+        disc.tracks[0].title = "Sultans of Swing".to_owned();
+        assert_eq!(None, align_tracks(&mut disc, 3));
+
+        let warning = align_tracks(&mut disc, 5).expect("no warning");
+        assert!(warning.starts_with("The metadata has 3 tracks, the disc 5"));
+        assert_eq!(5, disc.tracks.len());
+        assert_eq!("Sultans of Swing", disc.tracks[0].title);
+        assert_eq!(4, disc.tracks[3].number);
+        assert_eq!("Unknown", disc.tracks[4].title);
+        assert_eq!("Dire Straits", disc.tracks[4].artist);
+
+        assert!(align_tracks(&mut disc, 2).is_some());
+        assert_eq!(2, disc.tracks.len());
+        assert_eq!("Sultans of Swing", disc.tracks[0].title);
+    }
+
+    #[test]
+    fn test_length_mismatch() {
+        let mut disc = Disc::with_tracks(3);
+        for track in &mut disc.tracks {
+            // 3 minutes
+            track.sectors = 13_500;
+        }
+        // no lengths, nothing to compare
+        assert_eq!(None, length_mismatch(&disc));
+        disc.tracks[0].length_ms = Some(182_000);
+        disc.tracks[1].length_ms = Some(174_000);
+        disc.tracks[2].length_ms = Some(240_000);
+        let warning = length_mismatch(&disc).expect("no warning");
+        assert!(warning.starts_with("The metadata gives track 2, 3 another length"));
+        // the last track of an Enhanced CD, with the gap before the data session
+        disc.tracks[1].length_ms = Some(176_000);
+        disc.tracks[2].length_ms = Some(28_000);
+        assert_eq!(None, length_mismatch(&disc));
+        disc.tracks[2].sectors = 0;
+        assert_eq!(None, length_mismatch(&disc));
+    }
+
+    #[test]
+    fn test_parse_track_list() {
+        let text = "1. Sultans of Swing 5:48\n\n02 - Down to the Waterline\n3) Portobello Belle (4:31)\n4\tTwisting by the Pool\t3:30\n99 Luftballons\n  Romeo and Juliet  \n";
+        assert_eq!(
+            vec![
+                "Sultans of Swing",
+                "Down to the Waterline",
+                "Portobello Belle",
+                "Twisting by the Pool",
+                "99 Luftballons",
+                "Romeo and Juliet",
+            ],
+            parse_track_list(text)
+        );
+    }
+
+    #[test]
+    fn test_check_toc() {
+        let good = [(1, 150, 20000), (2, 20150, 30000)];
+        assert!(check_toc(&good, 50150).is_empty());
+
+        let bad = [(1, 0, 100), (2, 100, 60000)];
+        let warnings = check_toc(&bad, 50100);
+        assert_eq!(3, warnings.len());
+        assert!(warnings[0].contains("pregap"));
+        assert!(warnings[1].contains("track 1 is shorter"));
+        assert!(warnings[2].contains("track 2 overlaps"));
+
+        assert_eq!(1, check_toc(&good, 500_000).len());
+    }
+
+    #[test]
+    fn test_parse_segments() {
+        let segments =
+            parse_segments("0:00-3:25 Intro\n\n 3:25-1:02:03 Main part \n1:02:03- Outro\n")
+                .expect("valid segments");
+        assert_eq!(
+            vec![
+                Segment {
+                    title: "Intro".to_owned(),
+                    start: 0,
+                    end: Some(205),
+                },
+                Segment {
+                    title: "Main part".to_owned(),
+                    start: 205,
+                    end: Some(3723),
+                },
+                Segment {
+                    title: "Outro".to_owned(),
+                    start: 3723,
+                    end: None,
+                },
+            ],
+            segments
+        );
+        assert_eq!(
+            "0:00-3:25 Intro\n3:25-62:03 Main part\n62:03- Outro\n",
+            format_segments(&segments)
+        );
+        assert!(parse_segments("Intro").is_err());
+        assert!(parse_segments("").expect("no segments").is_empty());
+        assert!(parse_segments("1:00-0:30 Backwards").is_err());
+        assert!(parse_segments("a:00-0:30 Bad").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(Some(205), parse_time("3:25"));
+        assert_eq!(Some(3723), parse_time("1:02:03"));
+        assert_eq!(Some(3723), parse_time("62:03"));
+        assert_eq!(None, parse_time("3:60"));
+        assert_eq!(None, parse_time("1:60:00"));
+        assert_eq!(None, parse_time("1:00:00:00"));
+        assert_eq!(None, parse_time("99999999999999999999:00"));
+        assert_eq!(None, parse_time(&format!("{}:00", u64::MAX / 10)));
+    }
+
+    #[test]
+    fn test_parse_cue_points() {
+        let segments = parse_segments("0:00 Intro\n2:10 Drop\n5:00-6:00 Break").expect("valid");
+        assert_eq!(Some(130), segments[0].end);
+        assert_eq!(Some(300), segments[1].end);
+        assert_eq!(Some(360), segments[2].end);
+        assert!(parse_segments("2:00 Late\n1:00 Early").is_err());
+    }
+
+    #[test]
+    fn test_cue_sheet() {
+        let mut disc = Disc::with_tracks(3);
+        disc.artist = "Band".to_owned();
+        disc.title = "Live \"at\" home".to_owned();
+        disc.year = Some(1999);
+        disc.tracks[1].title = "Opening".to_owned();
+        disc.tracks[1].sectors = 75 * 62 + 10;
+        disc.tracks[2].title = "Closing".to_owned();
+        disc.tracks[2].composer = Some("Writer".to_owned());
+        let tracks = [&disc.tracks[1], &disc.tracks[2]];
+        let cue = cue_sheet(&disc, &tracks, "Band - Live.flac");
+        assert_eq!(
+            r#"REM DATE 1999
+PERFORMER "Band"
+TITLE "Live 'at' home"
+FILE "Band - Live.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Opening"
+    PERFORMER "Unknown"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Closing"
+    PERFORMER "Unknown"
+    SONGWRITER "Writer"
+    INDEX 01 01:02:10
+"#,
+            cue
+        );
+        let segments = parse_cue(&cue);
+        assert_eq!(2, segments.len());
+        assert_eq!("Closing", segments[1].title);
+        assert_eq!(62, segments[1].start);
+    }
+
+    #[test]
+    fn test_parse_cue() {
+        let cue = r#"PERFORMER "DJ"
+TITLE "Mix"
+FILE "mix.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "Opening"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 04:58:00
+    INDEX 01 05:01:40
+    TITLE "Second"
+  TRACK 03 AUDIO
+    TITLE "Closing"
+    INDEX 01 61:10:10
+"#;
+        let segments = parse_cue(cue);
+        assert_eq!(
+            vec![
+                Segment {
+                    title: "Opening".to_owned(),
+                    start: 0,
+                    end: Some(302),
+                },
+                Segment {
+                    title: "Second".to_owned(),
+                    start: 302,
+                    end: Some(3670),
+                },
+                Segment {
+                    title: "Closing".to_owned(),
+                    start: 3670,
+                    end: None,
+                },
+            ],
+            segments
+        );
+    }
+
+    #[test]
+    fn test_cd_devices() -> Result<()> {
+        let blocks = std::env::temp_dir().join("ripperx4_cd_devices");
+        let _ = fs::remove_dir_all(&blocks);
+        for name in ["sr1", "sda", "sr0", "loop0"] {
+            fs::create_dir_all(blocks.join(name))?;
+        }
+        assert_eq!(
+            vec!["/dev/sr0", "/dev/sr1"],
+            cd_devices_in(&blocks, Path::new("/dev"))
+        );
+        fs::remove_dir_all(&blocks)?;
+        assert!(cd_devices_in(&blocks, Path::new("/dev")).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_drive_letters() {
+        // C:, D: and E:, the last two optical
+        let mask = 0b11100;
+        assert_eq!(vec!["D:", "E:"], drive_letters(mask, |drive| drive != "C:"));
+        assert!(drive_letters(0, |_| true).is_empty());
+    }
 }